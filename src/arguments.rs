@@ -0,0 +1,1606 @@
+use std::{fs, path::Path, str::FromStr};
+
+use log::{warn, LevelFilter};
+use serde::Deserialize;
+
+use crate::{math::Matrix4f, output, pipeline::{self, AddressMode, ColorSpace, PhaseFunction, ToneMapOperator, VolumeFormat}, sweep::{self, SweepParameter}, volume::{VolumeDataType, VolumeShape}};
+
+pub struct Arguments {
+    pub volume: String,
+    pub volume_dimensions: Option<[u32; 3]>,
+    pub volume_format: VolumeFormat,
+    pub volume_dtype: VolumeDataType,
+    pub normalize: bool,
+    pub window: Option<[u8; 2]>,
+    /// Source axis of every axis of the reoriented volume
+    pub axes: [usize; 3],
+    pub flip: [bool; 3],
+    pub labels: Option<String>,
+    pub show_labels: Option<Vec<u8>>,
+    pub voxel_spacing: Option<[f32; 3]>,
+    pub transfer_function: Option<String>,
+    /// Second transfer function, mixed into the first one by tf_blend
+    pub blend_transfer_function: Option<String>,
+    pub tf_blend: f32,
+    pub tf_colorspace: ColorSpace,
+    pub camera_position: [f32; 3],
+    pub look_at: [f32; 3],
+    pub up: Option<[f32; 3]>,
+    pub mvp_matrix: Option<[f32; 16]>,
+    pub shader_dir: Option<String>,
+    pub output_resolution: [u32; 2],
+    /// Square resolution that is rendered and then upscaled to the output resolution
+    pub preview_resolution: Option<u32>,
+    pub output: String,
+    pub output_dir: Option<String>,
+    /// File the accumulated state is written to, and how often
+    pub checkpoint: Option<String>,
+    pub checkpoint_every: Option<u32>,
+    /// Checkpoint whose state the render continues
+    pub resume: Option<String>,
+    /// Template of the output file name, with keys of output::TEMPLATE_KEYS in braces
+    pub output_name: Option<String>,
+    pub output_bit_depth: u32,
+    pub depth: Option<String>,
+    /// Path of the radiance statistics, JSON or CSV
+    pub stats: Option<String>,
+    /// Draw the bounding box of the volume over the image
+    pub show_axes: bool,
+    /// Write the rows of the output images from the bottom one
+    pub flip_y: bool,
+    pub albedo: bool,
+    pub normal: bool,
+    pub steps: Option<u32>,
+    pub steps_schedule: Option<Vec<u32>>,
+    pub anisotropy: f32,
+    pub phase: PhaseFunction,
+    pub phase_params: [f32; 3],
+    pub clip: [[f32; 2]; 3],
+    pub clip_plane: Option<[f32; 4]>,
+    pub roi: Option<[[f32; 3]; 2]>,
+    pub volume_origin: [f32; 3],
+    pub invert_clip_plane: bool,
+    pub gradient_shading: bool,
+    pub light_dir: [f32; 3],
+    pub light_position: Option<[f32; 3]>,
+    pub light_intensity: f32,
+    pub nee: bool,
+    pub envmap: Option<String>,
+    pub extinction: f32,
+    pub bounces: u32,
+    pub bounces_schedule: Option<Vec<u32>>,
+    pub rr_start: Option<u32>,
+    pub max_sample_radiance: Option<f32>,
+    pub reset_radiance: [f32; 3],
+    pub linear: bool,
+    pub tricubic: bool,
+    pub linear_tf: bool,
+    pub address_mode: AddressMode,
+    pub iterations: u32,
+    pub tile: Option<u32>,
+    /// Budget of GPU memory in megabytes
+    pub max_memory: Option<u64>,
+    pub iterations_per_submit: u32,
+    pub noise_threshold: Option<f32>,
+    pub denoise: bool,
+    pub cpu: bool,
+    pub focal_length: f32,
+    pub pixel_aspect: f32,
+    pub tones: [f32; 3],
+    pub saturation: f32,
+    pub gamma: f32,
+    pub output_colorspace: Option<ColorSpace>,
+    pub tonemap: ToneMapOperator,
+    pub dump_matrix: bool,
+    pub dump_gbuffer: bool,
+    pub dry_run: bool,
+    /// Only read with the gui feature, parsing rejects it otherwise
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub preview: bool,
+    pub benchmark: bool,
+    pub gpu_timing: bool,
+    /// Split the rows of the image among all GPU adapters
+    pub multi_gpu: bool,
+    pub histogram: bool,
+    pub info: bool,
+    pub list_backends: bool,
+    /// Graphics APIs the GPU adapter is chosen from
+    pub backends: wgpu::Backends,
+    pub generate: Option<VolumeShape>,
+    pub gen_dims: [u32; 3],
+    pub gen_out: String,
+    pub seed: u64,
+    pub accumulate: Option<Vec<String>>,
+    pub accumulate_weights: Option<Vec<f32>>,
+    /// Values of every parameter of --sweep, in the order they were given
+    pub sweep: Option<Vec<(SweepParameter, Vec<f32>)>>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFileFormat {
+    output: Option<String>,
+    output_dir: Option<String>,
+    checkpoint: Option<String>,
+    checkpoint_every: Option<u32>,
+    resume: Option<String>,
+    output_name: Option<String>,
+    out_resolution: Option<Vec<u32>>,
+    preview_resolution: Option<u32>,
+    output_bit_depth: Option<u32>,
+    depth: Option<String>,
+    stats: Option<String>,
+    show_axes: Option<bool>,
+    flip_y: Option<bool>,
+    aov: Option<Vec<String>>,
+    data: Option<ConfigFileData>,
+    rendering: Option<ConfigFileRendering>,
+    tone_mapping: Option<ConfigFileToneMapping>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFileData {
+    volume: Option<String>,
+    volume_dimensions: Option<Vec<u32>>,
+    volume_format: Option<String>,
+    volume_dtype: Option<String>,
+    normalize: Option<bool>,
+    window: Option<Vec<u8>>,
+    flip: Option<Vec<String>>,
+    swap_axes: Option<Vec<String>>,
+    labels: Option<String>,
+    show_labels: Option<Vec<u8>>,
+    voxel_spacing: Option<Vec<f32>>,
+    transfer_function: Option<String>,
+    transfer_function2: Option<String>,
+    tf_blend: Option<f32>,
+    tf_colorspace: Option<String>,
+    envmap: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFileRendering {
+    camera_position: Option<Vec<f32>>,
+    #[serde(alias = "target")]
+    look_at: Option<Vec<f32>>,
+    up: Option<Vec<f32>>,
+    mvp_matrix: Option<Vec<f32>>,
+    steps: Option<u32>,
+    steps_schedule: Option<Vec<u32>>,
+    anisotropy: Option<f32>,
+    phase: Option<String>,
+    phase_params: Option<Vec<f32>>,
+    clip_x: Option<Vec<f32>>,
+    clip_y: Option<Vec<f32>>,
+    clip_z: Option<Vec<f32>>,
+    clip_plane: Option<Vec<f32>>,
+    invert_clip_plane: Option<bool>,
+    roi: Option<Vec<f32>>,
+    volume_origin: Option<Vec<f32>>,
+    gradient_shading: Option<bool>,
+    light_dir: Option<Vec<f32>>,
+    light_position: Option<Vec<f32>>,
+    light_intensity: Option<f32>,
+    nee: Option<bool>,
+    extinction: Option<f32>,
+    bounces: Option<u32>,
+    bounces_schedule: Option<Vec<u32>>,
+    rr_start: Option<u32>,
+    max_sample_radiance: Option<f32>,
+    reset_radiance: Option<Vec<f32>>,
+    linear: Option<bool>,
+    tricubic: Option<bool>,
+    linear_tf: Option<bool>,
+    address_mode: Option<String>,
+    iterations: Option<u32>,
+    tile: Option<u32>,
+    max_memory: Option<u64>,
+    iterations_per_submit: Option<u32>,
+    noise_threshold: Option<f32>,
+    denoise: Option<bool>,
+    cpu: Option<bool>,
+    multi_gpu: Option<bool>,
+    focal_length: Option<f32>,
+    pixel_aspect: Option<f32>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFileToneMapping {
+    tones: Option<Vec<f32>>,
+    saturation: Option<f32>,
+    gamma: Option<f32>,
+    gamma_only: Option<bool>,
+    tonemap: Option<String>,
+    output_colorspace: Option<String>
+}
+
+fn parse_color_space(value: &str, of: &str) -> Result<ColorSpace, String> {
+    return match value {
+        "srgb" => Ok(ColorSpace::Srgb),
+        "linear" => Ok(ColorSpace::Linear),
+        _ => Err(format!("Unknown {} color space {:?} (expected srgb or linear)", of, value))
+    };
+}
+
+fn parse_tonemap(value: &str) -> Result<ToneMapOperator, String> {
+    return match value {
+        "linear" => Ok(ToneMapOperator::Linear),
+        "reinhard" => Ok(ToneMapOperator::Reinhard),
+        "aces" => Ok(ToneMapOperator::Aces),
+        _ => Err(format!("Unknown tone mapping operator {:?} (expected linear, reinhard or aces)", value))
+    };
+}
+
+fn parse_address_mode(value: &str) -> Result<AddressMode, String> {
+    return match value {
+        "clamp" => Ok(AddressMode::Clamp),
+        "repeat" => Ok(AddressMode::Repeat),
+        "mirror" => Ok(AddressMode::Mirror),
+        _ => Err(format!("Unknown address mode {:?} (expected clamp, repeat or mirror)", value))
+    };
+}
+
+fn parse_backend(value: &str) -> Result<wgpu::Backends, String> {
+    return match pipeline::BACKENDS.iter().find(|(name, _)| *name == value) {
+        Some((_, backends)) => Ok(*backends),
+        None => {
+            let names: Vec<&str> = pipeline::BACKENDS.iter().map(|(name, _)| *name).collect();
+            Err(format!("Unknown backend {:?} (expected {} or {})", value, names[..names.len() - 1].join(", "), names[names.len() - 1]))
+        }
+    };
+}
+
+fn parse_volume_format(value: &str) -> Result<VolumeFormat, String> {
+    return match value {
+        "r8" => Ok(VolumeFormat::R8),
+        "rgba8" => Ok(VolumeFormat::Rgba8),
+        _ => Err(format!("Unknown volume format {:?} (expected r8 or rgba8)", value))
+    };
+}
+
+fn parse_volume_data_type(value: &str) -> Result<VolumeDataType, String> {
+    return match value {
+        "u8" => Ok(VolumeDataType::U8),
+        "i16le" => Ok(VolumeDataType::I16Le),
+        "i16be" => Ok(VolumeDataType::I16Be),
+        "u16le" => Ok(VolumeDataType::U16Le),
+        "u16be" => Ok(VolumeDataType::U16Be),
+        "f32le" => Ok(VolumeDataType::F32Le),
+        "f32be" => Ok(VolumeDataType::F32Be),
+        _ => Err(format!("Unknown volume data type {:?} (expected u8, i16le, i16be, u16le, u16be, f32le or f32be)", value))
+    };
+}
+
+fn parse_volume_shape(value: &str) -> Result<VolumeShape, String> {
+    return match value {
+        "sphere" => Ok(VolumeShape::Sphere),
+        "cube" => Ok(VolumeShape::Cube),
+        "gradient" => Ok(VolumeShape::Gradient),
+        "noise" => Ok(VolumeShape::Noise),
+        _ => Err(format!("Unknown volume shape {:?} (expected sphere, cube, gradient or noise)", value))
+    };
+}
+
+fn parse_phase_function(value: &str) -> Result<PhaseFunction, String> {
+    return match value {
+        "hg" => Ok(PhaseFunction::HenyeyGreenstein),
+        "double-hg" => Ok(PhaseFunction::DoubleHenyeyGreenstein),
+        "rayleigh" => Ok(PhaseFunction::Rayleigh),
+        _ => Err(format!("Unknown phase function {:?} (expected hg, double-hg or rayleigh)", value))
+    };
+}
+
+fn parse_floats(value: &str) -> Result<Vec<f32>, String> {
+    let mut floats = Vec::new();
+    for v in value.split(',') {
+        match v.trim().parse::<f32>() {
+            Ok(f) => floats.push(f),
+            Err(_) => return Err(format!("{:?} is not a number", v))
+        }
+    }
+    return Ok(floats);
+}
+
+/// Parses the number given to a flag
+fn parse_number<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    return value.parse::<T>().map_err(|_| format!("Invalid value {:?} for {}, expected a number", value, flag));
+}
+
+fn parse_labels(value: &str) -> Result<Vec<u8>, String> {
+    let mut labels = Vec::new();
+    for label in value.split(',') {
+        match label.trim().parse::<u8>() {
+            Ok(l) => labels.push(l),
+            Err(_) => return Err(format!("Label {:?} is not an integer between 0 and 255", label))
+        }
+    }
+    return Ok(labels);
+}
+
+/// Returns which of the albedo and normal images are requested
+fn parse_aovs<'a>(names: impl Iterator<Item = &'a str>) -> Result<(bool, bool), String> {
+    let mut albedo = false;
+    let mut normal = false;
+    for name in names {
+        match name.trim() {
+            "albedo" => albedo = true,
+            "normal" => normal = true,
+            _ => return Err(format!("Unknown AOV {:?} (expected albedo or normal)", name))
+        }
+    }
+    return Ok((albedo, normal));
+}
+
+fn parse_axis(name: char) -> Result<usize, String> {
+    return match name {
+        'x' => Ok(0),
+        'y' => Ok(1),
+        'z' => Ok(2),
+        _ => Err(format!("Unknown axis {:?} (expected x, y or z)", name))
+    };
+}
+
+fn parse_flip<'a>(names: impl Iterator<Item = &'a str>) -> Result<[bool; 3], String> {
+    let mut flip = [false; 3];
+    for name in names {
+        let name = name.trim();
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(axis), None) => flip[parse_axis(axis)?] = true,
+            _ => return Err(format!("Unknown axis {:?} (expected x, y or z)", name))
+        }
+    }
+    return Ok(flip);
+}
+
+/// Composes the swaps of pairs of axes, such as "xz", into the source axis
+/// of every axis of the reoriented volume
+fn parse_swap_axes<'a>(pairs: impl Iterator<Item = &'a str>) -> Result<[usize; 3], String> {
+    let mut axes = [0, 1, 2];
+    for pair in pairs {
+        let pair = pair.trim();
+        let mut chars = pair.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some(a), Some(b), None) => axes.swap(parse_axis(a)?, parse_axis(b)?),
+            _ => return Err(format!("Invalid axis pair {:?} (expected two of x, y and z, e.g. xz)", pair))
+        }
+    }
+    return Ok(axes);
+}
+
+/// Outcome of successfully parsing the arguments
+pub enum ParsedArguments {
+    Run(Box<Arguments>),
+    /// Only print the text and exit, e.g. for --help
+    Exit(String)
+}
+
+/// Level of the messages of the renderer that are logged, chosen before the
+/// other arguments are parsed so that their warnings are already logged.
+/// Later flags take precedence.
+pub fn log_level(args: &[String]) -> LevelFilter {
+    let mut level = LevelFilter::Info;
+    for arg in args {
+        match arg.as_str() {
+            "--quiet" => level = LevelFilter::Warn,
+            "-v" => level = LevelFilter::Debug,
+            "-vv" => level = LevelFilter::Trace,
+            _ => {}
+        }
+    }
+    return level;
+}
+
+pub fn parse_arguments(args: &[String]) -> Result<ParsedArguments, String> {
+    let mut volume = String::new();
+    let mut volume_dimensions = None;
+    let mut volume_format = VolumeFormat::R8;
+    let mut volume_dtype = VolumeDataType::U8;
+    let mut normalize = false;
+    let mut window = None;
+    let mut axes = [0, 1, 2];
+    let mut flip = [false; 3];
+    let mut labels = None;
+    let mut show_labels = None;
+    let mut voxel_spacing = None;
+    let mut transfer_function = None;
+    let mut blend_transfer_function = None;
+    let mut tf_blend = None;
+    let mut tf_colorspace = ColorSpace::Srgb;
+    let mut camera_position = [-1.0, -1.0, 1.0];
+    let mut look_at = [0.0; 3];
+    let mut up = None;
+    let mut mvp_matrix = None;
+    let mut shader_dir = None;
+    let mut output_resolution = [512, 512];
+    let mut preview_resolution = None;
+    let mut output_bit_depth = 8;
+    let mut output = "output.ppm".to_string();
+    let mut output_dir = None;
+    let mut checkpoint = None;
+    let mut checkpoint_every = None;
+    let mut resume = None;
+    let mut output_name = None;
+    let mut depth = None;
+    let mut stats = None;
+    let mut albedo = false;
+    let mut normal = false;
+    let mut steps = None;
+    let mut steps_schedule = None;
+    let mut anisotropy = 0.0;
+    let mut phase = PhaseFunction::HenyeyGreenstein;
+    let mut phase_params = None;
+    let mut clip = [[0.0, 1.0]; 3];
+    let mut clip_plane = None;
+    let mut roi = None;
+    let mut volume_origin = [0.5; 3];
+    let mut invert_clip_plane = false;
+    let mut gradient_shading = false;
+    let mut light_dir = [-1.0, -1.0, 1.0];
+    let mut light_position = None;
+    let mut light_intensity = 1.0;
+    let mut nee = false;
+    let mut envmap = None;
+    let mut extinction = 100.0;
+    let mut bounces = 8;
+    let mut bounces_schedule = None;
+    let mut rr_start = None;
+    let mut max_sample_radiance = None;
+    let mut reset_radiance = [0.0; 3];
+    let mut linear = false;
+    let mut tricubic = false;
+    let mut linear_tf = true;
+    let mut address_mode = AddressMode::Clamp;
+    let mut iterations = 1;
+    let mut tile = None;
+    let mut max_memory = None;
+    let mut iterations_per_submit = 64;
+    let mut noise_threshold = None;
+    let mut denoise = false;
+    let mut cpu = false;
+    let mut focal_length = 2.0;
+    let mut pixel_aspect = 1.0;
+    let mut tones = [0.0, 0.5, 1.0];
+    let mut saturation = 1.0;
+    let mut gamma = 2.2;
+    let mut tonemap = ToneMapOperator::Linear;
+    let mut output_colorspace = None;
+    let mut gamma_only = false;
+    let mut dump_matrix = false;
+    let mut show_axes = false;
+    let mut flip_y = false;
+    let mut dump_gbuffer = false;
+    let mut dry_run = false;
+    let mut preview = false;
+    let mut benchmark = false;
+    let mut gpu_timing = false;
+    let mut multi_gpu = false;
+    let mut histogram = false;
+    let mut info = false;
+    let mut list_backends = false;
+    let mut backends = wgpu::Backends::all();
+    let mut generate = None;
+    let mut gen_dims = [64, 64, 64];
+    let mut gen_out = "volume.raw".to_string();
+    let mut seed = 0;
+    let mut accumulate = None;
+    let mut accumulate_weights = None;
+    let mut sweep = None;
+    let mut config_volume = false;
+    let mut stdin_volume = false;
+
+    // Config files are applied first, in the order they are given, so that
+    // command line options always override them regardless of their position
+    let config_files: Vec<&String> = args.windows(2).filter(|w| w[0] == "--config").map(|w| &w[1]).collect();
+    for config_file in config_files {
+        match fs::read_to_string(config_file) {
+            Ok(s) => {
+                match toml::from_str::<ConfigFileFormat>(&s) {
+                    Ok(config) => {
+                        if let Some(x) = config.output {
+                            output = x;
+                        }
+                        if config.output_dir.is_some() {
+                            output_dir = config.output_dir;
+                        }
+                        if config.checkpoint.is_some() {
+                            checkpoint = config.checkpoint;
+                        }
+                        if config.checkpoint_every.is_some() {
+                            checkpoint_every = config.checkpoint_every;
+                        }
+                        if config.resume.is_some() {
+                            resume = config.resume;
+                        }
+                        if config.output_name.is_some() {
+                            output_name = config.output_name;
+                        }
+                        if let Some(x) = config.out_resolution {
+                            output_resolution = [x[0], x[1]];
+                        }
+                        if config.preview_resolution.is_some() {
+                            preview_resolution = config.preview_resolution;
+                        }
+                        if let Some(x) = config.output_bit_depth {
+                            output_bit_depth = x;
+                        }
+                        if config.depth.is_some() {
+                            depth = config.depth;
+                        }
+                        if config.stats.is_some() {
+                            stats = config.stats;
+                        }
+                        if let Some(x) = config.show_axes {
+                            show_axes = x;
+                        }
+                        if let Some(x) = config.flip_y {
+                            flip_y = x;
+                        }
+                        if let Some(x) = config.aov {
+                            (albedo, normal) = parse_aovs(x.iter().map(|v| v.as_str()))?;
+                        }
+                        if let Some(x) = config.data {
+                            if let Some(y) = x.volume {
+                                volume = y;
+                                config_volume = true;
+                            }
+                            if let Some(y) = x.volume_dimensions {
+                                volume_dimensions = Some([y[0], y[1], y[2]])
+                            }
+                            if let Some(y) = x.volume_format {
+                                volume_format = parse_volume_format(&y)?;
+                            }
+                            if let Some(y) = x.volume_dtype {
+                                volume_dtype = parse_volume_data_type(&y)?;
+                            }
+                            if let Some(y) = x.normalize {
+                                normalize = y;
+                            }
+                            if let Some(y) = x.window {
+                                if y.len() != 2 {
+                                    return Err("The density window in the config file needs two values, low and high!".to_string());
+                                }
+                                window = Some([y[0], y[1]]);
+                            }
+                            if let Some(y) = x.flip {
+                                flip = parse_flip(y.iter().map(|v| v.as_str()))?;
+                            }
+                            if let Some(y) = x.swap_axes {
+                                axes = parse_swap_axes(y.iter().map(|v| v.as_str()))?;
+                            }
+                            if x.labels.is_some() {
+                                labels = x.labels;
+                            }
+                            if x.show_labels.is_some() {
+                                show_labels = x.show_labels;
+                            }
+                            if let Some(y) = x.voxel_spacing {
+                                voxel_spacing = Some([y[0], y[1], y[2]]);
+                            }
+                            if x.transfer_function.is_some() {
+                                transfer_function = x.transfer_function;
+                            }
+                            if x.transfer_function2.is_some() {
+                                blend_transfer_function = x.transfer_function2;
+                            }
+                            if x.tf_blend.is_some() {
+                                tf_blend = x.tf_blend;
+                            }
+                            if let Some(y) = x.tf_colorspace {
+                                tf_colorspace = parse_color_space(&y, "transfer function")?;
+                            }
+                            if x.envmap.is_some() {
+                                envmap = x.envmap;
+                            }
+                        }
+                        if let Some(x) = config.rendering {
+                            if let Some(y) = x.anisotropy {
+                                anisotropy = y;
+                            }
+                            if let Some(y) = x.phase {
+                                phase = parse_phase_function(&y)?;
+                            }
+                            if x.phase_params.is_some() {
+                                phase_params = x.phase_params;
+                            }
+                            for (axis, bounds) in [x.clip_x, x.clip_y, x.clip_z].into_iter().enumerate() {
+                                if let Some(y) = bounds {
+                                    clip[axis] = [y[0], y[1]];
+                                }
+                            }
+                            if let Some(y) = x.clip_plane {
+                                clip_plane = Some([y[0], y[1], y[2], y[3]]);
+                            }
+                            if let Some(y) = x.invert_clip_plane {
+                                invert_clip_plane = y;
+                            }
+                            if let Some(y) = x.roi {
+                                if y.len() != 6 {
+                                    return Err("The region of interest in the config file needs six values, the minimum and maximum corner!".to_string());
+                                }
+                                roi = Some([[y[0], y[1], y[2]], [y[3], y[4], y[5]]]);
+                            }
+                            if let Some(y) = x.volume_origin {
+                                volume_origin = [y[0], y[1], y[2]];
+                            }
+                            if let Some(y) = x.gradient_shading {
+                                gradient_shading = y;
+                            }
+                            if let Some(y) = x.light_dir {
+                                light_dir = [y[0], y[1], y[2]];
+                            }
+                            if let Some(y) = x.light_position {
+                                light_position = Some([y[0], y[1], y[2]]);
+                            }
+                            if let Some(y) = x.light_intensity {
+                                light_intensity = y;
+                            }
+                            if let Some(y) = x.nee {
+                                nee = y;
+                            }
+                            if let Some(y) = x.bounces {
+                                bounces = y;
+                            }
+                            if x.rr_start.is_some() {
+                                rr_start = x.rr_start;
+                            }
+                            if x.max_sample_radiance.is_some() {
+                                max_sample_radiance = x.max_sample_radiance;
+                            }
+                            if let Some(y) = x.reset_radiance {
+                                reset_radiance = [y[0], y[1], y[2]];
+                            }
+                            if let Some(y) = x.camera_position {
+                                camera_position = [y[0], y[1], y[2]];
+                            }
+                            if let Some(y) = x.look_at {
+                                look_at = [y[0], y[1], y[2]];
+                            }
+                            if let Some(y) = x.up {
+                                up = Some([y[0], y[1], y[2]]);
+                            }
+                            if let Some(y) = x.extinction {
+                                extinction = y;
+                            }
+                            if let Some(y) = x.focal_length {
+                                focal_length = y;
+                            }
+                            if let Some(y) = x.pixel_aspect {
+                                pixel_aspect = y;
+                            }
+                            if let Some(y) = x.iterations {
+                                iterations = y;
+                            }
+                            if let Some(y) = x.linear {
+                                linear = y;
+                            }
+                            if let Some(y) = x.tricubic {
+                                tricubic = y;
+                            }
+                            if let Some(y) = x.linear_tf {
+                                linear_tf = y;
+                            }
+                            if let Some(y) = x.address_mode {
+                                address_mode = parse_address_mode(&y)?;
+                            }
+                            if let Some(y) = x.mvp_matrix {
+                                mvp_matrix = Some([y[0],y[1],y[2],y[3],y[4],y[5],y[6],y[7],y[8],y[9],y[10],y[11],y[12],y[13],y[14],y[15]]);
+                            }
+                            if x.steps.is_some() {
+                                steps = x.steps;
+                            }
+                            if x.tile.is_some() {
+                                tile = x.tile;
+                            }
+                            if x.max_memory.is_some() {
+                                max_memory = x.max_memory;
+                            }
+                            if let Some(y) = x.iterations_per_submit {
+                                iterations_per_submit = y;
+                            }
+                            if x.noise_threshold.is_some() {
+                                noise_threshold = x.noise_threshold;
+                            }
+                            if let Some(y) = x.denoise {
+                                denoise = y;
+                            }
+                            if let Some(y) = x.cpu {
+                                cpu = y;
+                            }
+                            if let Some(y) = x.multi_gpu {
+                                multi_gpu = y;
+                            }
+                            if x.steps_schedule.is_some() {
+                                steps_schedule = x.steps_schedule;
+                            }
+                            if x.bounces_schedule.is_some() {
+                                bounces_schedule = x.bounces_schedule;
+                            }
+                        }
+                        if let Some(x) = config.tone_mapping {
+                            if let Some(y) = x.gamma {
+                                gamma = y;
+                            }
+                            if let Some(y) = x.tonemap {
+                                tonemap = parse_tonemap(&y)?;
+                            }
+                            if let Some(y) = x.output_colorspace {
+                                output_colorspace = Some(parse_color_space(&y, "output")?);
+                            }
+                            if let Some(y) = x.gamma_only {
+                                gamma_only = y;
+                            }
+                            if let Some(y) = x.saturation {
+                                saturation = y;
+                            }
+                            if let Some(y) = x.tones {
+                                tones = [y[0], y[1], y[2]];
+                            }
+                        }
+                    },
+                    Err(s) => {
+                        return Err(format!("Failed to parse config file {:?}\n  - {}", config_file, s));
+                    }
+                };
+            },
+            Err(e) => {
+                return Err(format!("Failed to read config file {:?}: {}", config_file, e));
+            }
+        }
+    }
+
+    for i in 0..args.len() {
+        if args[i] == "--volume" {
+            volume = args[i+1].to_string();
+            stdin_volume = volume == "-";
+        }
+
+        if args[i] == "--volume-dimensions" {
+            volume_dimensions = Some([
+                args[i+1].parse::<u32>().unwrap(),
+                args[i+2].parse::<u32>().unwrap(),
+                args[i+3].parse::<u32>().unwrap()
+            ]);
+        }
+        else if args[i] == "--volume-format" {
+            volume_format = parse_volume_format(&args[i+1])?;
+        }
+        else if args[i] == "--volume-dtype" {
+            volume_dtype = parse_volume_data_type(&args[i+1])?;
+        }
+        else if args[i] == "--normalize" {
+            normalize = true;
+        }
+        else if args[i] == "--window" {
+            let parse_density = |value: &str| {
+                return value.parse::<u8>().map_err(|_| format!("Invalid density {:?} for --window, expected an integer from 0 to 255", value));
+            };
+            window = Some([parse_density(&args[i+1])?, parse_density(&args[i+2])?]);
+        }
+        else if args[i] == "--flip" {
+            flip = parse_flip(args[i+1].split(','))?;
+        }
+        else if args[i] == "--swap-axes" {
+            axes = parse_swap_axes(args[i+1].split(','))?;
+        }
+        else if args[i] == "--shader-dir" {
+            shader_dir = Some(args[i+1].to_string());
+        }
+        else if args[i] == "--labels" {
+            labels = Some(args[i+1].to_string());
+        }
+        else if args[i] == "--show-labels" {
+            show_labels = Some(parse_labels(&args[i+1])?);
+        }
+        else if args[i] == "--voxel-spacing" {
+            voxel_spacing = Some([
+                args[i+1].parse::<f32>().unwrap(),
+                args[i+2].parse::<f32>().unwrap(),
+                args[i+3].parse::<f32>().unwrap()
+            ]);
+        }
+        else if args[i] == "--tf" {
+            transfer_function = Some(args[i+1].to_string());
+        }
+        else if args[i] == "--tf2" {
+            blend_transfer_function = Some(args[i+1].to_string());
+        }
+        else if args[i] == "--tf-blend" {
+            tf_blend = Some(args[i+1].parse::<f32>().unwrap());
+        }
+        else if args[i] == "--envmap" {
+            envmap = Some(args[i+1].to_string());
+        }
+        else if args[i] == "--camera-position" || args[i] == "--look-from" {
+            camera_position = [
+                args[i+1].parse::<f32>().unwrap(),
+                args[i+2].parse::<f32>().unwrap(),
+                args[i+3].parse::<f32>().unwrap()
+            ];
+        }
+        else if args[i] == "--look-at" || args[i] == "--target" {
+            look_at = [
+                args[i+1].parse::<f32>().unwrap(),
+                args[i+2].parse::<f32>().unwrap(),
+                args[i+3].parse::<f32>().unwrap()
+            ];
+        }
+        else if args[i] == "--up" {
+            up = Some([
+                args[i+1].parse::<f32>().unwrap(),
+                args[i+2].parse::<f32>().unwrap(),
+                args[i+3].parse::<f32>().unwrap()
+            ]);
+        }
+        else if args[i] == "--out-resolution" {
+            output_resolution[0] = args[i+1].parse::<u32>().unwrap();
+            output_resolution[1] = args[i+2].parse::<u32>().unwrap();
+        }
+        else if args[i] == "--preview-resolution" {
+            preview_resolution = Some(parse_number("--preview-resolution", &args[i+1])?);
+        }
+        else if args[i] == "--output-bit-depth" {
+            output_bit_depth = args[i+1].parse::<u32>().unwrap();
+        }
+        else if args[i] == "--output" {
+            output = args[i+1].to_string();
+        }
+        else if args[i] == "--output-dir" {
+            output_dir = Some(args[i+1].to_string());
+        }
+        else if args[i] == "--output-name" {
+            output_name = Some(args[i+1].to_string());
+        }
+        else if args[i] == "--depth" {
+            depth = Some(args[i+1].to_string());
+        }
+        else if args[i] == "--stats" {
+            stats = Some(args[i+1].to_string());
+        }
+        else if args[i] == "--show-axes" {
+            show_axes = true;
+        }
+        else if args[i] == "--flip-y" {
+            flip_y = true;
+        }
+        else if args[i] == "--aov" {
+            (albedo, normal) = parse_aovs(args[i+1].split(','))?;
+        }
+        else if args[i] == "--steps" {
+            steps = Some(args[i+1].parse::<u32>().unwrap());
+        }
+        else if args[i] == "--phase" {
+            phase = parse_phase_function(&args[i+1])?;
+        }
+        else if args[i] == "--phase-params" {
+            phase_params = Some(parse_floats(&args[i+1])?);
+        }
+        else if args[i] == "--anisotropy" {
+            anisotropy = args[i+1].parse::<f32>().unwrap();
+        }
+        else if args[i] == "--clip-x" || args[i] == "--clip-y" || args[i] == "--clip-z" {
+            let axis = match args[i].as_str() {
+                "--clip-x" => 0,
+                "--clip-y" => 1,
+                _ => 2
+            };
+            clip[axis] = [
+                args[i+1].parse::<f32>().unwrap(),
+                args[i+2].parse::<f32>().unwrap()
+            ];
+        }
+        else if args[i] == "--roi" {
+            let values: Vec<f32> = args[i+1..i+7].iter().map(|v| v.parse::<f32>().unwrap()).collect();
+            roi = Some([[values[0], values[1], values[2]], [values[3], values[4], values[5]]]);
+        }
+        else if args[i] == "--volume-origin" {
+            volume_origin = [
+                args[i+1].parse::<f32>().unwrap(),
+                args[i+2].parse::<f32>().unwrap(),
+                args[i+3].parse::<f32>().unwrap()
+            ];
+        }
+        else if args[i] == "--clip-plane" {
+            clip_plane = Some([
+                args[i+1].parse::<f32>().unwrap(),
+                args[i+2].parse::<f32>().unwrap(),
+                args[i+3].parse::<f32>().unwrap(),
+                args[i+4].parse::<f32>().unwrap()
+            ]);
+        }
+        else if args[i] == "--invert-clip-plane" {
+            invert_clip_plane = true;
+        }
+        else if args[i] == "--gradient-shading" {
+            gradient_shading = true;
+        }
+        else if args[i] == "--light-dir" {
+            light_dir = [
+                args[i+1].parse::<f32>().unwrap(),
+                args[i+2].parse::<f32>().unwrap(),
+                args[i+3].parse::<f32>().unwrap()
+            ];
+        }
+        else if args[i] == "--light-position" {
+            light_position = Some([
+                args[i+1].parse::<f32>().unwrap(),
+                args[i+2].parse::<f32>().unwrap(),
+                args[i+3].parse::<f32>().unwrap()
+            ]);
+        }
+        else if args[i] == "--light-intensity" {
+            light_intensity = args[i+1].parse::<f32>().unwrap();
+        }
+        else if args[i] == "--nee" {
+            nee = true;
+        }
+        else if args[i] == "--extinction" {
+            extinction = args[i+1].parse::<f32>().unwrap();
+        }
+        else if args[i] == "--bounces" {
+            bounces = args[i+1].parse::<u32>().unwrap();
+        }
+        else if args[i] == "--rr-start" {
+            rr_start = Some(parse_number("--rr-start", &args[i+1])?);
+        }
+        else if args[i] == "--max-sample-radiance" {
+            max_sample_radiance = Some(args[i+1].parse::<f32>().unwrap());
+        }
+        else if args[i] == "--reset-radiance" {
+            reset_radiance = [
+                args[i+1].parse::<f32>().unwrap(),
+                args[i+2].parse::<f32>().unwrap(),
+                args[i+3].parse::<f32>().unwrap()
+            ];
+        }
+        else if args[i] == "--linear" {
+            linear = true;
+        }
+        else if args[i] == "--tricubic" {
+            tricubic = true;
+        }
+        else if args[i] == "--linear-tf" {
+            linear_tf = true;
+        }
+        else if args[i] == "--no-linear-tf" {
+            linear_tf = false;
+        }
+        else if args[i] == "--address-mode" {
+            address_mode = parse_address_mode(&args[i+1])?;
+        }
+        else if args[i] == "--iterations" {
+            iterations = args[i+1].parse::<u32>().unwrap();
+        }
+        else if args[i] == "--steps-schedule" {
+            steps_schedule = Some(args[i+1].split(',').map(|v| parse_number("--steps-schedule", v.trim())).collect::<Result<_, _>>()?);
+        }
+        else if args[i] == "--bounces-schedule" {
+            bounces_schedule = Some(args[i+1].split(',').map(|v| parse_number("--bounces-schedule", v.trim())).collect::<Result<_, _>>()?);
+        }
+        else if args[i] == "--tf-colorspace" {
+            tf_colorspace = parse_color_space(&args[i+1], "transfer function")?;
+        }
+        else if args[i] == "--tf-linear" {
+            tf_colorspace = ColorSpace::Linear;
+        }
+        else if args[i] == "--tile" {
+            tile = Some(parse_number("--tile", &args[i+1])?);
+        }
+        else if args[i] == "--max-memory" {
+            max_memory = Some(parse_number("--max-memory", &args[i+1])?);
+        }
+        else if args[i] == "--max-iterations-per-submit" {
+            iterations_per_submit = parse_number("--max-iterations-per-submit", &args[i+1])?;
+        }
+        else if args[i] == "--noise-threshold" {
+            noise_threshold = Some(parse_number("--noise-threshold", &args[i+1])?);
+        }
+        else if args[i] == "--denoise" {
+            denoise = true;
+        }
+        else if args[i] == "--cpu" {
+            cpu = true;
+        }
+        else if args[i] == "--mvp-matrix" {
+            mvp_matrix = Some([
+                args[i+1].parse::<f32>().unwrap(),
+                args[i+2].parse::<f32>().unwrap(),
+                args[i+3].parse::<f32>().unwrap(),
+                args[i+4].parse::<f32>().unwrap(),
+                args[i+5].parse::<f32>().unwrap(),
+                args[i+6].parse::<f32>().unwrap(),
+                args[i+7].parse::<f32>().unwrap(),
+                args[i+8].parse::<f32>().unwrap(),
+                args[i+9].parse::<f32>().unwrap(),
+                args[i+10].parse::<f32>().unwrap(),
+                args[i+11].parse::<f32>().unwrap(),
+                args[i+12].parse::<f32>().unwrap(),
+                args[i+13].parse::<f32>().unwrap(),
+                args[i+14].parse::<f32>().unwrap(),
+                args[i+15].parse::<f32>().unwrap(),
+                args[i+16].parse::<f32>().unwrap()
+            ]);
+        }
+        else if args[i] == "--focal-length" {
+            focal_length = args[i+1].parse::<f32>().unwrap();
+        }
+        else if args[i] == "--pixel-aspect" {
+            pixel_aspect = args[i+1].parse::<f32>().unwrap();
+        }
+        else if args[i] == "--levels" {
+            tones[0] = args[i+1].parse::<f32>().unwrap();
+            tones[1] = args[i+2].parse::<f32>().unwrap();
+            tones[2] = args[i+3].parse::<f32>().unwrap();
+        }
+        else if args[i] == "--saturation" {
+            saturation = args[i+1].parse::<f32>().unwrap();
+        }
+        else if args[i] == "--gamma" {
+            gamma = args[i+1].parse::<f32>().unwrap();
+        }
+        else if args[i] == "--gamma-only" {
+            gamma_only = true;
+        }
+        else if args[i] == "--tonemap" {
+            tonemap = parse_tonemap(&args[i+1])?;
+        }
+        else if args[i] == "--output-colorspace" {
+            output_colorspace = Some(parse_color_space(&args[i+1], "output")?);
+        }
+        else if args[i] == "--dump-matrix" {
+            dump_matrix = true;
+        }
+        else if args[i] == "--dump-gbuffer" {
+            dump_gbuffer = true;
+        }
+        else if args[i] == "--dry-run" {
+            dry_run = true;
+        }
+        else if args[i] == "--preview" {
+            preview = true;
+        }
+        else if args[i] == "--benchmark" {
+            benchmark = true;
+        }
+        else if args[i] == "--gpu-timing" {
+            gpu_timing = true;
+        }
+        else if args[i] == "--multi-gpu" {
+            multi_gpu = true;
+        }
+        else if args[i] == "--checkpoint" {
+            checkpoint = Some(args[i+1].to_string());
+        }
+        else if args[i] == "--checkpoint-every" {
+            checkpoint_every = Some(parse_number("--checkpoint-every", &args[i+1])?);
+        }
+        else if args[i] == "--resume" {
+            resume = Some(args[i+1].to_string());
+        }
+        else if args[i] == "--histogram" {
+            histogram = true;
+        }
+        else if args[i] == "--info" {
+            info = true;
+        }
+        else if args[i] == "--list-backends" {
+            list_backends = true;
+        }
+        else if args[i] == "--backend" {
+            backends = parse_backend(&args[i+1])?;
+        }
+        else if args[i] == "--accumulate" {
+            accumulate = Some(args[i+1..].iter().take_while(|a| !a.starts_with("--")).cloned().collect::<Vec<String>>());
+        }
+        else if args[i] == "--accumulate-weights" {
+            accumulate_weights = Some(args[i+1].split(',').map(|v| v.trim().parse::<f32>().unwrap()).collect::<Vec<f32>>());
+        }
+        else if args[i] == "--sweep" {
+            let mut parameters: Vec<(SweepParameter, Vec<f32>)> = Vec::new();
+            for spec in args[i+1..].iter().take_while(|a| !a.starts_with("--")) {
+                let (parameter, values) = sweep::parse_parameter(spec)?;
+                if parameters.iter().any(|(p, _)| *p == parameter) {
+                    return Err(format!("Sweep parameter {} is given more than once!", parameter.name()));
+                }
+                parameters.push((parameter, values));
+            }
+            sweep = Some(parameters);
+        }
+        else if args[i] == "--generate" {
+            generate = Some(parse_volume_shape(&args[i+1])?);
+        }
+        else if args[i] == "--gen-dims" {
+            gen_dims = [
+                args[i+1].parse::<u32>().unwrap(),
+                args[i+2].parse::<u32>().unwrap(),
+                args[i+3].parse::<u32>().unwrap()
+            ];
+        }
+        else if args[i] == "--gen-out" {
+            gen_out = args[i+1].to_string();
+        }
+        else if args[i] == "--seed" {
+            seed = args[i+1].parse::<u64>().unwrap();
+        }
+        else if args[i] == "--version" {
+            return Ok(ParsedArguments::Exit(format!("VPT Lazy Ripoff {}", env!("CARGO_PKG_VERSION"))));
+        }
+        else if args[i] == "--help" {
+            let authors = format!("{} (original)", env!("CARGO_PKG_AUTHORS").replace(':', ", "));
+            let text = format!(
+                "** {} (version {}) **\nAuthors: {}\n\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
+                "VPT Lazy Ripoff",
+                env!("CARGO_PKG_VERSION"),
+                authors,
+                "--volume : Path to file with raw volumetric data, or - to read from standard input",
+                "--volume-dimensions : Three integers representing width, height and depth of texture (optional)",
+                "--tf : Path to the file with transfer function texture (optional)",
+                "--tf2 : Path to the file with a second transfer function of the same length, blended with the first one (optional)",
+                "--tf-blend : Weight of the second transfer function, from 0 for the first one to 1 for the second one (optional)",
+                "--camera-position / --look-from : Three floats representing x,y,z coordinates of camera (optional)",
+                "--look-at / --target : Three floats representing the point in the scene the camera is aimed at (optional)",
+                "--up : Three floats representing the direction that points up in the image (optional)",
+                "--mvp-matrix : 16 floats representing the transposed inverse of the MVP matrix (optional)",
+                "--out-resolution : An integer representing resolution of output image (optional)",
+                "--output : Path to output image file, PNG for a .png extension and PPM otherwise (optional)",
+                "--output-dir : Directory of the output image, created when it does not exist (optional)",
+                "--output-name : Template of the output file name instead of --output, e.g. frame_{index}_{steps}.png (optional)",
+                "--output-bit-depth : Bits per channel of the output image, 8 or 16 (optional)",
+                "--steps : Number of rendering steps (optional)",
+                "--anisotropy : Anisotropy (optional)",
+                "--extinction : Extinction (optional)",
+                "--bounces : Number of bounces per photon (optional)",
+                "--iterations : Number of iterations (optional)",
+                "--focal-length : A float representing distance of projection plane from camera origin (optional)",
+                "--pixel-aspect : Width of a pixel of the target display divided by its height, for anamorphic output (optional)",
+                "--tones : Three floats representing low, mid and high tones (optional)",
+                "--saturation : Saturation on post-processing (optional)",
+                "--gamma : Gamma value on post-processing (optional)",
+                "--quiet : Suppress informational output (optional)",
+                "-v / -vv : Print debug or additionally trace messages for diagnosing problems (optional)",
+                "--dump-matrix : Print the inverse MVP matrix used for rendering in --mvp-matrix format (optional)",
+                "--dump-gbuffer : Write the photon positions, directions and transmittances after the first iteration as PFM (optional)",
+                "--dry-run : Validate inputs and print the resolved scene without rendering (optional)",
+                "--preview : Show the render in a window and orbit the camera with the arrow keys, needs the gui feature (optional)",
+                "--voxel-spacing : Three floats representing physical voxel size along x, y and z (optional)",
+                "--benchmark : Print a timing breakdown of the rendering phases (optional)",
+                "--gpu-timing : Print the GPU time of the reset passes and the iterations, measured with timestamp queries (optional)",
+                "--preview-resolution : Render a square image of the given size and upscale it to the output resolution, for a quick first look (optional)",
+                "--tile : Render the image in square tiles of the given size, for resolutions beyond the device limits (optional)",
+                "--max-memory : Refuse renders whose estimated GPU memory exceeds the given number of megabytes (optional)",
+                "--max-iterations-per-submit : Number of iterations submitted to the GPU at once, lower it if the driver resets the device (optional)",
+                "--noise-threshold : Stop before --iterations once the estimated relative variance of the pixels drops below the float (optional)",
+                "--steps-schedule : Comma separated steps per iteration, ramped over the iterations (optional)",
+                "--bounces-schedule : Comma separated bounces per photon, ramped over the iterations (optional)",
+                "--tricubic : Sample the volume with a smooth cubic B-spline instead of --linear, at eight times the cost (optional)",
+                "--linear-tf / --no-linear-tf : Enable or disable linear interpolation of the transfer function (optional)",
+                "--tf-colorspace : Color space of the transfer function colors, srgb or linear (optional)",
+                "--tf-linear : Use the transfer function bytes as linear values, the same as --tf-colorspace linear (optional)",
+                "--depth : Path to a PFM file for the mean distance to the first interaction along each primary ray (optional)",
+                "--stats : Path to a JSON or CSV file for the min, max, mean and median radiance of every channel (optional)",
+                "--show-axes : Draw the bounding box of the volume over the image, with the X, Y and Z axes in red, green and blue (optional)",
+                "--flip-y : Write the rows of the output image and the PFM images next to it from the bottom one to the top one (optional)",
+                "--aov : Comma separated auxiliary images for denoisers, albedo and/or normal, written next to the output as PFM (optional)",
+                "--denoise : Apply an edge-aware spatial filter to the result before tone mapping (optional)",
+                "--volume-format : Voxel layout of the volume, r8 for density or rgba8 for color and density (optional)",
+                "--volume-dtype : Type of the values of a raw r8 volume, u8, i16le, i16be, u16le, u16be, f32le or f32be (optional)",
+                "--normalize : Rescale the densities of the volume to the full range from 0 to 255 (optional)",
+                "--window : Two integers representing the range of densities that is stretched over the transfer function (optional)",
+                "--swap-axes : Comma separated pairs of volume axes to swap in the given order, e.g. xz (optional)",
+                "--flip : Comma separated volume axes to mirror after swapping, e.g. x,z (optional)",
+                "--labels : Path to file with raw 8-bit labels of the voxels, with the same dimensions as the volume (optional)",
+                "--show-labels : Comma separated labels to render, voxels with other labels are treated as empty (optional)",
+                "--clip-x / --clip-y / --clip-z : Two floats representing the normalized range of the volume to render along the axis (optional)",
+                "--clip-plane : Four floats representing the normal and offset of a plane, the volume behind it is not rendered (optional)",
+                "--invert-clip-plane : Keep the volume behind the clip plane instead of in front of it (optional)",
+                "--roi : Six floats representing the normalized minimum and maximum corner of a box the camera frames instead of the whole volume (optional)",
+                "--volume-origin : Three floats representing the normalized point of the volume placed at the origin of the scene (optional)",
+                "--tonemap : Tone mapping operator for radiance above 1.0, linear, reinhard or aces (optional)",
+                "--output-colorspace : Encode the output image as srgb or linear values instead of applying --gamma (optional)",
+                "--gamma-only : Only clamp the radiance and apply the gamma correction, cannot be combined with tones, saturation and tone mapping operator (optional)",
+                "--cpu : Render a deterministic reference image on the CPU instead of the GPU, for testing (optional)",
+                "--multi-gpu : Split the rows of the image among all GPUs and render them at the same time (optional)",
+                "--checkpoint : Path to a file the accumulated state is written to at the end of the render (optional)",
+                "--checkpoint-every : Also write the checkpoint every given number of iterations (optional)",
+                "--resume : Path to a checkpoint whose state the iterations are added to (optional)",
+                "--generate : Write a procedural volume, sphere, cube, gradient or noise, to the --gen-out file and exit (optional)",
+                "--gen-dims : Three integers representing width, height and depth of the generated volume (optional)",
+                "--gen-out : Path to the generated volume file (optional)",
+                "--seed : Seed of the random values in the generated noise volume (optional)",
+                "--histogram : Print the number of voxels with each density value as CSV and exit (optional)",
+                "--gradient-shading : Shade the scattered light with a diffuse term of the density gradient (optional)",
+                "--light-dir : Three floats representing the direction towards the light of the gradient shading (optional)",
+                "--light-position : Three floats representing the position of a point light in the scene (optional)",
+                "--light-intensity : Intensity of the point light (optional)",
+                "--envmap : Path to an equirectangular Radiance HDR file lighting the volume instead of the white environment (optional)",
+                "--info : Print the GPU adapter and its limits and exit (optional)",
+                "--list-backends : Print the adapters of every graphics API and exit (optional)",
+                "--backend : Graphics API to choose the GPU adapter from, vulkan, metal, dx12, dx11 or gl (optional)",
+                "--version : Print the version and exit (optional)",
+                "--phase : Phase function of the scattering, hg, double-hg or rayleigh (optional)",
+                "--phase-params : Comma separated anisotropies of the two lobes and the weight of the first one for the double-hg phase function (optional)",
+                "--nee : Sample the environment map by its brightness at every scattering event (next event estimation) (optional)",
+                "--rr-start : Number of bounces after which the paths are terminated by russian roulette (optional)",
+                "--max-sample-radiance : Largest radiance of a single path, suppresses fireflies but biases the result (optional)",
+                "--reset-radiance : Three floats representing the radiance of the pixels before their first path finishes, for debugging (optional)",
+                "--address-mode : Sampling of the volume beyond its faces, clamp, repeat or mirror (optional)",
+                "--shader-dir : Directory with WGSL files that replace the embedded shaders of the same name (optional)",
+                "--accumulate : Paths to PFM images of the same scene that are averaged into the --output PFM file without rendering (optional)",
+                "--accumulate-weights : Comma separated weights of the accumulated images, e.g. their iteration counts (optional)",
+                "--sweep : Parameters as NAME=V1,V2,... whose combinations are rendered into a labeled contact sheet (optional)"
+            );
+            return Ok(ParsedArguments::Exit(text));
+        }
+    }
+
+    if let Some(inputs) = &accumulate {
+        if inputs.is_empty() {
+            return Err("No images to accumulate provided!".to_string());
+        }
+        if let Some(weights) = &accumulate_weights {
+            if weights.len() != inputs.len() {
+                return Err(format!("{} accumulation weights given for {} images!", weights.len(), inputs.len()));
+            }
+            if weights.iter().any(|w| !(w.is_finite() && *w >= 0.0)) || weights.iter().sum::<f32>() <= 0.0 {
+                return Err("Accumulation weights must not be negative and may not all be zero!".to_string());
+            }
+        }
+    } else if accumulate_weights.is_some() {
+        warn!("Accumulation weights are only used with --accumulate.");
+    }
+
+    if let Some(parameters) = &sweep {
+        if parameters.is_empty() {
+            return Err("--sweep needs at least one parameter, e.g. extinction=50,100,200!".to_string());
+        }
+        if depth.is_some() || stats.is_some() || albedo || normal || dump_gbuffer {
+            return Err("--sweep only writes the contact sheet, which cannot be combined with --depth, --stats, --aov or --dump-gbuffer".to_string());
+        }
+        if preview {
+            return Err("--sweep renders the contact sheet without a window, which cannot be combined with --preview".to_string());
+        }
+        for (parameter, schedule) in [(SweepParameter::Steps, &steps_schedule), (SweepParameter::Bounces, &bounces_schedule)] {
+            if schedule.is_some() && parameters.iter().any(|(p, _)| *p == parameter) {
+                return Err(format!("Sweeping {} cannot be combined with a {} schedule", parameter.name(), parameter.name()));
+            }
+        }
+    }
+
+    if generate.is_some() {
+        if gen_dims.contains(&0) {
+            return Err("Generated volume dimensions must be positive!".to_string());
+        }
+    } else if volume.is_empty() && !info && !list_backends && accumulate.is_none() {
+        return Err("No volume provided!".to_string());
+    }
+
+    if output_bit_depth != 8 && output_bit_depth != 16 {
+        return Err(format!("Output bit depth must be 8 or 16, not {}!", output_bit_depth));
+    }
+
+    if voxel_spacing.is_some_and(|s| s.iter().any(|s| !(s.is_finite() && *s > 0.0))) {
+        return Err("Voxel spacing must be positive!".to_string());
+    }
+
+    for (name, schedule) in [("Steps", &steps_schedule), ("Bounces", &bounces_schedule)] {
+        if let Some(s) = schedule {
+            if s.is_empty() {
+                return Err(format!("{} schedule is empty!", name));
+            }
+            if s.len() > iterations as usize {
+                warn!("{} schedule has more entries than there are iterations. Some entries will not be used.", name);
+            }
+        }
+    }
+
+    if volume_dtype != VolumeDataType::U8 && volume_format != VolumeFormat::R8 {
+        return Err("Volume data types other than u8 can only be used with volume format r8!".to_string());
+    }
+
+    if show_labels.is_some() && labels.is_none() {
+        return Err("--show-labels requires a label volume (--labels)!".to_string());
+    }
+
+    if window.is_some_and(|[low, high]| low >= high) {
+        return Err("The density window must have the low end below the high end!".to_string());
+    }
+
+    if let Some([min, max]) = roi {
+        if (0..3).any(|i| !(0.0 <= min[i] && min[i] < max[i] && max[i] <= 1.0)) {
+            return Err("Region of interest must lie within 0 and 1, with the minimum below the maximum on every axis!".to_string());
+        }
+        if (0..3).any(|i| clip[i][0].max(min[i]) >= clip[i][1].min(max[i])) {
+            return Err("The clip ranges leave nothing of the region of interest!".to_string());
+        }
+        if mvp_matrix.is_some() {
+            return Err("--roi frames the camera, which cannot be combined with --mvp-matrix".to_string());
+        }
+    }
+
+    if !(pixel_aspect.is_finite() && pixel_aspect > 0.0) {
+        return Err("Pixel aspect must be a positive number!".to_string());
+    }
+
+    if volume_origin.iter().any(|v| !v.is_finite()) {
+        return Err("Volume origin must be finite!".to_string());
+    }
+    if volume_origin != [0.5; 3] && mvp_matrix.is_some() {
+        return Err("--volume-origin places the volume for the camera, which cannot be combined with --mvp-matrix".to_string());
+    }
+
+    if clip.iter().any(|c| !(c[0].is_finite() && c[1].is_finite() && c[0] < c[1])) {
+        return Err("Clip bounds must be finite, with the minimum below the maximum!".to_string());
+    }
+
+    if let Some(p) = clip_plane {
+        if p.iter().any(|v| !v.is_finite()) || p[0..3].iter().all(|v| *v == 0.0) {
+            return Err("Clip plane must be finite and have a non-zero normal!".to_string());
+        }
+    }
+
+    let view = [0, 1, 2].map(|i| look_at[i] - camera_position[i]);
+    if view.iter().chain(camera_position.iter()).chain(look_at.iter()).any(|v| !v.is_finite()) {
+        return Err("Camera position and target must be finite!".to_string());
+    }
+    // Closer than this, the viewing direction is lost to rounding and the
+    // camera rotation degenerates into a black frame
+    let view_length = (view[0] * view[0] + view[1] * view[1] + view[2] * view[2]).sqrt();
+    let scale = camera_position.iter().chain(look_at.iter()).fold(1.0f32, |m, v| m.max(v.abs()));
+    if view_length <= 1e-5 * scale {
+        return Err(format!(
+            "Camera position {:?} coincides with the look-at target {:?}, move the camera away or pass a different --look-at!",
+            camera_position, look_at
+        ));
+    }
+    if let Some(u) = up {
+        let cross = [u[1] * view[2] - u[2] * view[1], u[2] * view[0] - u[0] * view[2], u[0] * view[1] - u[1] * view[0]];
+        let cross_length = (cross[0] * cross[0] + cross[1] * cross[1] + cross[2] * cross[2]).sqrt();
+        let lengths = (u[0] * u[0] + u[1] * u[1] + u[2] * u[2]).sqrt() * (view[0] * view[0] + view[1] * view[1] + view[2] * view[2]).sqrt();
+        if u.iter().any(|v| !v.is_finite()) || cross_length <= 1e-6 * lengths {
+            return Err("Up direction must be finite, non-zero and not parallel to the viewing direction!".to_string());
+        }
+    }
+
+    if light_dir.iter().any(|v| !v.is_finite()) || light_dir.iter().all(|v| *v == 0.0) {
+        return Err("Light direction must be finite and non-zero!".to_string());
+    }
+
+    if light_position.is_some_and(|p| p.iter().any(|v| !v.is_finite())) {
+        return Err("Light position must be finite!".to_string());
+    }
+
+    if !(light_intensity.is_finite() && light_intensity >= 0.0) {
+        return Err("Light intensity must be a non-negative number!".to_string());
+    }
+
+    let phase_params = match (phase, phase_params) {
+        (PhaseFunction::DoubleHenyeyGreenstein, Some(p)) => {
+            if p.len() != 3 || !(p[0].abs() < 1.0 && p[1].abs() < 1.0 && (0.0..=1.0).contains(&p[2])) {
+                return Err("The double-hg phase function needs three parameters G1,G2,W with -1 < G < 1 and 0 <= W <= 1!".to_string());
+            }
+            [p[0], p[1], p[2]]
+        },
+        (PhaseFunction::DoubleHenyeyGreenstein, None) => {
+            return Err("The double-hg phase function needs its parameters (--phase-params G1,G2,W)!".to_string());
+        },
+        (_, Some(_)) => {
+            warn!("Phase function parameters are only used by the double-hg phase function.");
+            [0.0; 3]
+        },
+        (_, None) => [0.0; 3]
+    };
+
+    if shader_dir.as_ref().is_some_and(|d| !Path::new(d).is_dir()) {
+        return Err(format!("Shader directory {:?} does not exist!", shader_dir.unwrap()));
+    }
+
+    if let Some(name) = &output_name {
+        output::validate_template(name)?;
+    }
+
+    if let Some(r) = preview_resolution {
+        if r == 0 {
+            return Err("Preview resolution must be positive!".to_string());
+        }
+        if sweep.is_some() {
+            return Err("--preview-resolution cannot be combined with --sweep, whose cells already have the --out-resolution".to_string());
+        }
+        if r >= output_resolution[0] && r >= output_resolution[1] {
+            warn!("Preview resolution {} is not below the output resolution {}x{}, so it does not save any time.", r, output_resolution[0], output_resolution[1]);
+        }
+    }
+
+    if tile == Some(0) {
+        return Err("Tile size must be positive!".to_string());
+    }
+    if max_memory == Some(0) {
+        return Err("Memory budget must be positive!".to_string());
+    }
+
+    // Every iteration of a submission needs its own aligned random seed slot
+    if !(1..=65536).contains(&iterations_per_submit) {
+        return Err("Iterations per submit must be between 1 and 65536!".to_string());
+    }
+
+    if max_sample_radiance.is_some_and(|m| !(m.is_finite() && m > 0.0)) {
+        return Err("Max sample radiance must be a positive number!".to_string());
+    }
+
+    if reset_radiance.iter().any(|v| !(v.is_finite() && *v >= 0.0)) {
+        return Err("Reset radiance must be three non-negative numbers!".to_string());
+    }
+
+    if noise_threshold.is_some_and(|t| !(t.is_finite() && t > 0.0)) {
+        return Err("Noise threshold must be a positive number!".to_string());
+    }
+
+    if let Some(m) = mvp_matrix {
+        if m.iter().any(|v| !v.is_finite()) {
+            return Err("MVP matrix contains non-finite values!".to_string());
+        }
+        if crate::pipeline::is_degenerate_matrix(&Matrix4f::from_values(m.to_vec())) {
+            warn!("MVP matrix is singular or nearly singular. The render will likely be black or contain NaN values.");
+        }
+    }
+
+    if blend_transfer_function.is_none() && tf_blend.is_some() {
+        return Err("--tf-blend needs a second transfer function to blend with (--tf2)!".to_string());
+    }
+    if tf_blend.is_some_and(|b| !(0.0..=1.0).contains(&b)) {
+        return Err("Transfer function blend must be between 0 and 1!".to_string());
+    }
+    let tf_blend = tf_blend.unwrap_or(0.5);
+
+    // The other stages must keep their identity values
+    if gamma_only && (tones != [0.0, 0.5, 1.0] || saturation != 1.0 || tonemap != ToneMapOperator::Linear) {
+        return Err("--gamma-only skips the tone curve, which cannot be combined with --levels, --saturation or --tonemap".to_string());
+    }
+
+    if output_colorspace.is_some() && gamma != 2.2 {
+        warn!("--output-colorspace replaces the gamma correction, so --gamma is ignored.");
+    }
+
+    if stdin_volume {
+        if config_volume {
+            return Err("Volume is set both to standard input (--volume -) and in the config file!".to_string());
+        }
+        if volume_dimensions.is_none() {
+            return Err("Reading volume from standard input (--volume -) requires --volume-dimensions!".to_string());
+        }
+    }
+
+    if preview && !cfg!(feature = "gui") {
+        return Err("--preview needs the preview window, which was not compiled in (build with --features gui)".to_string());
+    }
+    if gpu_timing && cpu {
+        return Err("--gpu-timing measures the GPU renderer, which --cpu does not use".to_string());
+    }
+    if dump_gbuffer && cpu {
+        return Err("--dump-gbuffer writes the state of the GPU renderer, which --cpu does not use".to_string());
+    }
+    if checkpoint_every.is_some() && checkpoint.is_none() {
+        return Err("--checkpoint-every needs a file to write the checkpoints to (--checkpoint)!".to_string());
+    }
+    if checkpoint_every == Some(0) {
+        return Err("Checkpoint interval must be positive!".to_string());
+    }
+    if checkpoint.is_some() || resume.is_some() {
+        if tile.is_some() {
+            return Err("Checkpoints hold the whole image in one piece, which cannot be combined with --tile".to_string());
+        }
+        if cpu || multi_gpu || preview || sweep.is_some() {
+            return Err("Checkpoints hold the state of a single GPU render, which cannot be combined with --cpu, --multi-gpu, --preview or --sweep".to_string());
+        }
+    }
+    if multi_gpu {
+        if cpu {
+            return Err("--multi-gpu splits the image among the GPUs, which --cpu does not use".to_string());
+        }
+        if preview || sweep.is_some() {
+            return Err("--multi-gpu only splits single renders, which cannot be combined with --preview or --sweep".to_string());
+        }
+    }
+
+    if preview && mvp_matrix.is_some() {
+        return Err("--preview moves the camera, which cannot be combined with --mvp-matrix".to_string());
+    }
+
+    return Ok(ParsedArguments::Run(Box::new(Arguments {
+        volume,
+        volume_dimensions,
+        volume_format,
+        volume_dtype,
+        normalize,
+        window,
+        axes,
+        flip,
+        labels,
+        show_labels,
+        voxel_spacing,
+        transfer_function,
+        blend_transfer_function,
+        tf_blend,
+        tf_colorspace,
+        camera_position,
+        look_at,
+        up,
+        mvp_matrix,
+        shader_dir,
+        output_resolution,
+        preview_resolution,
+        output_bit_depth,
+        output,
+        output_dir,
+        checkpoint,
+        checkpoint_every,
+        resume,
+        output_name,
+        depth,
+        stats,
+        albedo,
+        normal,
+        steps,
+        steps_schedule,
+        anisotropy,
+        phase,
+        phase_params,
+        clip,
+        clip_plane,
+        invert_clip_plane,
+        roi,
+        volume_origin,
+        gradient_shading,
+        light_dir,
+        light_position,
+        light_intensity,
+        nee,
+        envmap,
+        extinction,
+        bounces,
+        bounces_schedule,
+        rr_start,
+        max_sample_radiance,
+        reset_radiance,
+        linear,
+        tricubic,
+        linear_tf,
+        address_mode,
+        iterations,
+        tile,
+        max_memory,
+        iterations_per_submit,
+        noise_threshold,
+        denoise,
+        cpu,
+        focal_length,
+        pixel_aspect,
+        tones,
+        saturation,
+        gamma,
+        output_colorspace,
+        tonemap,
+        dump_matrix,
+        show_axes,
+        flip_y,
+        dump_gbuffer,
+        dry_run,
+        preview,
+        benchmark,
+        gpu_timing,
+        multi_gpu,
+        histogram,
+        info,
+        list_backends,
+        backends,
+        generate,
+        gen_dims,
+        gen_out,
+        seed,
+        accumulate,
+        accumulate_weights,
+        sweep
+    })));
+}
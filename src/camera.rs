@@ -51,21 +51,32 @@ impl Camera {
         self.rotation = q;
     }
 
-    pub fn set_fov_x(&mut self, fov: f32) {
-        self.fov_x = fov;
-    }
-
-    pub fn set_fov_y(&mut self, fov: f32) {
-        self.fov_y = fov;
+    /// Turns the camera towards the focus with its y axis as close to the
+    /// up direction as possible. The camera looks down its -z axis, so the
+    /// columns of its rotation are the right, up and backward directions.
+    pub fn look_at_up(&mut self, focus: Vector3f, up: Vector3f) {
+        let mut forward = focus - self.position;
+        forward.normalize();
+        let mut right = Vector3f::cross(&forward, &up);
+        right.normalize();
+        let camera_up = Vector3f::cross(&right, &forward);
+
+        let rotation = Matrix4f::from_values(vec![
+            right.x, camera_up.x, -forward.x, 0.0,
+            right.y, camera_up.y, -forward.y, 0.0,
+            right.z, camera_up.z, -forward.z, 0.0,
+            0.0, 0.0, 0.0, 1.0
+        ]);
+        self.rotation = Quaternion::from_rotation_matrix(&rotation);
     }
 
+    /// Sets the vertical field of view from the focal length and derives
+    /// the horizontal one from the aspect ratio, so wider images show more
+    /// of the scene instead of stretching it
     pub fn set_fov(&mut self, focal_length: f32, aspect_ratio: f32) {
-        let w = 1.0 * aspect_ratio;
-        let h = 1.0;
-        let fov_x = (w / (2.0 * focal_length)).tan() * 2.0;
-        let fov_y = (h / (2.0 * focal_length)).tan() * 2.0;
+        let fov_y = (1.0 / (2.0 * focal_length)).tan() * 2.0;
 
-        self.fov_x = fov_x;
+        self.fov_x = fov_y * aspect_ratio;
         self.fov_y = fov_y;
     }
 
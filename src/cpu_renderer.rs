@@ -0,0 +1,354 @@
+use crate::{output, pipeline::{self, output_max_value, volume_scale, AddressMode, ColorSpace, PhaseFunction, RenderData, RenderOutput, ToneMapOperator, VolumeFormat}, math::{Matrix4f, Vector3f}};
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        return value / 12.92;
+    }
+    return ((value + 0.055) / 1.055).powf(2.4);
+}
+
+/// Applies the inverse MVP matrix to a point in normalized device coordinates.
+/// The camera matrix is uploaded as is, so its rows become the columns of
+/// the WGSL matrix and the shaders apply its transpose.
+fn unproject(inverse_mvp: &Matrix4f, x: f32, y: f32, z: f32) -> [f32; 3] {
+    let point = inverse_mvp.transform_point(Vector3f::new(x, y, z));
+    return [point.x, point.y, point.z];
+}
+
+fn intersect_cube(origin: [f32; 3], direction: [f32; 3]) -> [f32; 2] {
+    let mut t_near = f32::NEG_INFINITY;
+    let mut t_far = f32::INFINITY;
+    for axis in 0..3 {
+        let t_min = (0.0 - origin[axis]) / direction[axis];
+        let t_max = (1.0 - origin[axis]) / direction[axis];
+        t_near = t_near.max(t_min.min(t_max));
+        t_far = t_far.min(t_min.max(t_max));
+    }
+    return [t_near, t_far];
+}
+
+/// Voxel lookups with the same conventions as the GPU textures: texel
+/// centers at half-integer coordinates and the address mode at the edges
+struct Volume<'a> {
+    data: &'a RenderData,
+    channels: usize
+}
+
+impl<'a> Volume<'a> {
+    fn voxel(&self, voxel: [i64; 3], channel: usize) -> f32 {
+        let dims = self.data.volume_dims;
+        let [x, y, z] = [0, 1, 2].map(|i| {
+            let size = dims[i] as i64;
+            let index = match self.data.address_mode {
+                AddressMode::Clamp => voxel[i].clamp(0, size - 1),
+                AddressMode::Repeat => voxel[i].rem_euclid(size),
+                AddressMode::Mirror => {
+                    let index = voxel[i].rem_euclid(2 * size);
+                    if index < size { index } else { 2 * size - 1 - index }
+                }
+            };
+            index as usize
+        });
+        let index = x + y * dims[0] as usize + z * dims[0] as usize * dims[1] as usize;
+        return self.data.volume[index * self.channels + channel] as f32 / 255.0;
+    }
+
+    fn sample(&self, position: [f32; 3], channel: usize) -> f32 {
+        let dims = self.data.volume_dims;
+        if !self.data.linear {
+            let voxel = [0, 1, 2].map(|i| (position[i] * dims[i] as f32).floor() as i64);
+            return self.voxel(voxel, channel);
+        }
+        let coordinates = [0, 1, 2].map(|i| position[i] * dims[i] as f32 - 0.5);
+        let base = coordinates.map(|c| c.floor());
+        let fraction = [0, 1, 2].map(|i| coordinates[i] - base[i]);
+        let mut value = 0.0;
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let mut weight = 1.0;
+            for i in 0..3 {
+                weight *= if offset[i] == 1 { fraction[i] } else { 1.0 - fraction[i] };
+            }
+            let voxel = [0, 1, 2].map(|i| base[i] as i64 + offset[i] as i64);
+            value += weight * self.voxel(voxel, channel);
+        }
+        return value;
+    }
+
+    fn density(&self, position: [f32; 3]) -> f32 {
+        return self.sample(position, self.channels - 1);
+    }
+
+    fn transfer_function(&self, index: i64) -> [f32; 4] {
+        let index = index.clamp(0, self.data.transfer_function_len as i64 - 1) as usize * 4;
+        let decode = |tf: &[u8]| {
+            let entry = &tf[index..index + 4];
+            let mut color = [0, 1, 2, 3].map(|i| entry[i] as f32 / 255.0);
+            if self.data.tf_colorspace == ColorSpace::Srgb {
+                for c in color.iter_mut().take(3) {
+                    *c = srgb_to_linear(*c);
+                }
+            }
+            return color;
+        };
+        let color = decode(&self.data.transfer_function);
+        return match &self.data.blend_transfer_function {
+            Some(blend) => {
+                let other = decode(blend);
+                [0, 1, 2, 3].map(|i| color[i] + (other[i] - color[i]) * self.data.tf_blend)
+            },
+            None => color
+        };
+    }
+
+    fn color(&self, position: [f32; 3]) -> [f32; 4] {
+        if self.data.volume_format == VolumeFormat::Rgba8 {
+            return [0, 1, 2, 3].map(|i| self.sample(position, i));
+        }
+        let location = self.density(position) * self.data.transfer_function_len as f32;
+        if !self.data.linear_tf {
+            return self.transfer_function(location.floor() as i64);
+        }
+        let base = (location - 0.5).floor();
+        let fraction = location - 0.5 - base;
+        let low = self.transfer_function(base as i64);
+        let high = self.transfer_function(base as i64 + 1);
+        return [0, 1, 2, 3].map(|i| low[i] + (high[i] - low[i]) * fraction);
+    }
+
+    fn normal(&self, position: [f32; 3]) -> [f32; 3] {
+        let h = self.data.volume_dims.map(|d| 1.0 / d as f32);
+        let mut gradient = [0.0; 3];
+        for i in 0..3 {
+            let mut forward = position;
+            let mut backward = position;
+            forward[i] += h[i];
+            backward[i] -= h[i];
+            gradient[i] = self.density(forward) - self.density(backward);
+        }
+        let length = (gradient[0] * gradient[0] + gradient[1] * gradient[1] + gradient[2] * gradient[2]).sqrt();
+        if length < 1e-6 {
+            return [0.0; 3];
+        }
+        return gradient.map(|g| -g / length);
+    }
+
+    /// Opacity of the visible volume at the position, for a segment of the given length
+    fn opacity(&self, position: [f32; 3], length: f32) -> f32 {
+        if !self.is_visible(position) {
+            return 0.0;
+        }
+        return 1.0 - (-self.color(position)[3] * self.data.extinction * length).exp();
+    }
+
+    /// Transmittance from the position to the point light, marched with pipeline::steps samples
+    fn light_transmittance(&self, position: [f32; 3], light: [f32; 3]) -> f32 {
+        let to_light = [0, 1, 2].map(|i| light[i] - position[i]);
+        let distance = (to_light[0] * to_light[0] + to_light[1] * to_light[1] + to_light[2] * to_light[2]).sqrt();
+        let direction = to_light.map(|d| d / distance);
+        let length = intersect_cube(position, direction)[1].clamp(0.0, distance);
+        let steps = pipeline::steps(self.data);
+        let step = length / steps.max(1) as f32;
+        let mut transmittance = 1.0;
+        for i in 0..steps {
+            let t = (i as f32 + 0.5) * step;
+            transmittance *= 1.0 - self.opacity([0, 1, 2].map(|j| position[j] + t * direction[j]), step);
+        }
+        return transmittance;
+    }
+
+    #[allow(clippy::needless_range_loop)]
+    fn is_visible(&self, position: [f32; 3]) -> bool {
+        let data = self.data;
+        for i in 0..3 {
+            if position[i] < data.clip_min[i] || position[i] > data.clip_max[i] {
+                return false;
+            }
+        }
+        if let Some(plane) = data.clip_plane {
+            let side = position[0] * plane[0] + position[1] * plane[1] + position[2] * plane[2] - plane[3];
+            if (side < 0.0) != data.invert_clip_plane {
+                return false;
+            }
+        }
+        if let (Some(labels), Some(show_labels)) = (&data.labels, &data.show_labels) {
+            let dims = data.volume_dims;
+            let [x, y, z] = [0, 1, 2].map(|i| ((position[i] * dims[i] as f32) as usize).min(dims[i] as usize - 1));
+            let label = labels[x + y * dims[0] as usize + z * dims[0] as usize * dims[1] as usize];
+            return show_labels.contains(&label);
+        }
+        return true;
+    }
+}
+
+fn henyey_greenstein(g: f32, angle_cosine: f32) -> f32 {
+    let g2 = g * g;
+    return (1.0 - g2) / (4.0 * std::f32::consts::PI * (1.0 + g2 - 2.0 * g * angle_cosine).powf(1.5));
+}
+
+fn phase_function(data: &RenderData, angle_cosine: f32) -> f32 {
+    let [g1, g2, weight] = data.phase_parameters;
+    return match data.phase_function {
+        PhaseFunction::HenyeyGreenstein => henyey_greenstein(data.anisotropy, angle_cosine),
+        PhaseFunction::DoubleHenyeyGreenstein => weight * henyey_greenstein(g1, angle_cosine) + (1.0 - weight) * henyey_greenstein(g2, angle_cosine),
+        PhaseFunction::Rayleigh => 3.0 / (16.0 * std::f32::consts::PI) * (1.0 + angle_cosine * angle_cosine)
+    };
+}
+
+/// Same tone mapping as tm_artistic_fragment.wgsl
+fn tone_map(data: &RenderData, color: [f32; 3]) -> [u16; 3] {
+    let [low, mid, high] = data.tones;
+    let mut color = color.map(|c| ((c - low) / (high - low)).max(0.0));
+    color = match data.tonemap {
+        ToneMapOperator::Linear => color.map(|c| c.min(1.0)),
+        ToneMapOperator::Reinhard => color.map(|c| c / (1.0 + c)),
+        ToneMapOperator::Aces => color.map(|c| ((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0.0, 1.0))
+    };
+    let luma = 0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2];
+    color = color.map(|c| luma + (c - luma) * data.saturation);
+    let midpoint = (mid - low) / (high - low);
+    let exponent = -midpoint.ln() / 2.0f32.ln();
+    let max_value = output_max_value(data) as f32;
+    let color = match data.output_colorspace {
+        Some(ColorSpace::Srgb) => color.map(|c| output::linear_to_srgb(c.powf(exponent))),
+        Some(ColorSpace::Linear) => color.map(|c| c.powf(exponent)),
+        None => color.map(|c| c.powf(exponent / data.gamma))
+    };
+    return color.map(|c| (c * max_value).round() as u16);
+}
+
+/// Deterministic reference renderer for testing without a GPU. Instead of
+/// the Monte Carlo simulation, it marches every primary ray through the pixel
+/// center with pipeline::steps samples and accumulates single scattering of the
+/// white environment, ignoring shadows. The result resembles the GPU render,
+/// but is not meant to match it or to be used for production images.
+pub fn render(data: &RenderData, camera_matrix: &Matrix4f, output: &mut RenderOutput) {
+    let [width, height] = data.output_resolution;
+    let light = data.light_direction;
+    let light_length = (light[0] * light[0] + light[1] * light[1] + light[2] * light[2]).sqrt();
+    let light = light.map(|l| l / light_length);
+    let scale = volume_scale(data);
+    let steps = pipeline::steps(data);
+    // In texture coordinates, like the volume samples
+    let point_light = data.light_position.map(|p| [0, 1, 2].map(|i| p[i] / scale[i] + data.volume_origin[i]));
+    // The in-scattered environment light ignores its direction
+    let environment = data.envmap.as_ref().map_or([1.0; 3], |e| e.mean());
+    let volume = Volume {
+        data,
+        channels: data.volume_format.bytes_per_voxel() as usize
+    };
+
+    let inverse_mvp = camera_matrix.transpose();
+
+    output.image = vec![0; (width * height * 3) as usize];
+    let pixels = (width * height) as usize;
+    let mut depth = vec![f32::INFINITY; pixels];
+    let mut radiances = vec![0.0; pixels * 3];
+    let mut albedo = vec![0.0; pixels * 3];
+    let mut normal = vec![0.0; pixels * 3];
+
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = (y * width + x) as usize;
+            let position = [
+                (x as f32 + 0.5) / width as f32 * 2.0 - 1.0,
+                (y as f32 + 0.5) / height as f32 * 2.0 - 1.0
+            ];
+            let fr = unproject(&inverse_mvp, position[0], position[1], -1.0);
+            let to = unproject(&inverse_mvp, position[0], position[1], 1.0);
+            let mut direction = [to[0] - fr[0], to[1] - fr[1], to[2] - fr[2]];
+            let length = (direction[0] * direction[0] + direction[1] * direction[1] + direction[2] * direction[2]).sqrt();
+            direction = direction.map(|d| d / length);
+            let [t_near, t_far] = intersect_cube(fr, direction).map(|t| t.max(0.0));
+
+            let mut radiance = [0.0; 3];
+            let mut transmittance = 1.0;
+            let mut mean_depth = 0.0;
+            let mut mean_albedo = [0.0; 3];
+            let mut mean_normal = [0.0; 3];
+            if t_far > t_near {
+                let step = (t_far - t_near) / steps.max(1) as f32;
+                for i in 0..steps {
+                    let t = t_near + (i as f32 + 0.5) * step;
+                    let sample = [0, 1, 2].map(|j| fr[j] + t * direction[j]);
+                    if !volume.is_visible(sample) {
+                        continue;
+                    }
+                    let color = volume.color(sample);
+                    let opacity = volume.opacity(sample, step);
+                    if opacity <= 0.0 {
+                        continue;
+                    }
+                    // Weighted by the probability that the first interaction happens here
+                    let weight = transmittance * opacity;
+                    let sample_normal = volume.normal(sample);
+                    let mut diffuse = 1.0;
+                    if data.gradient_shading && sample_normal != [0.0; 3] {
+                        diffuse = (sample_normal[0] * light[0] + sample_normal[1] * light[1] + sample_normal[2] * light[2]).max(0.0);
+                    }
+                    // Environment and the point light, shaded like scattered light on the GPU
+                    let mut incident = environment;
+                    if let Some(light_position) = point_light {
+                        let to_light = [0, 1, 2].map(|j| light_position[j] - sample[j]);
+                        let scene = [0, 1, 2].map(|j| to_light[j] * scale[j]);
+                        let distance_squared = scene[0] * scene[0] + scene[1] * scene[1] + scene[2] * scene[2];
+                        let length = (to_light[0] * to_light[0] + to_light[1] * to_light[1] + to_light[2] * to_light[2]).sqrt();
+                        let angle_cosine = (0..3).map(|j| direction[j] * to_light[j] / length).sum::<f32>();
+                        let direct = phase_function(data, angle_cosine) * data.light_intensity / distance_squared *
+                            volume.light_transmittance(sample, light_position);
+                        incident = incident.map(|i| i + direct);
+                    }
+                    for j in 0..3 {
+                        radiance[j] += weight * diffuse * incident[j] * color[j];
+                        mean_albedo[j] += weight * color[j];
+                        mean_normal[j] += weight * sample_normal[j];
+                    }
+                    mean_depth += weight * t;
+                    transmittance *= 1.0 - opacity;
+                }
+            }
+            let background = match &data.envmap {
+                Some(envmap) => {
+                    let world = [0, 1, 2].map(|j| direction[j] * scale[j]);
+                    let length = (world[0] * world[0] + world[1] * world[1] + world[2] * world[2]).sqrt();
+                    envmap.sample(world.map(|w| w / length))
+                },
+                None => [1.0; 3]
+            };
+            radiance = [0, 1, 2].map(|j| radiance[j] + transmittance * background[j]);
+
+            let index = pixel * 3;
+            output.image[index..index + 3].copy_from_slice(&tone_map(data, radiance));
+            radiances[index..index + 3].copy_from_slice(&radiance);
+            let hit_probability = 1.0 - transmittance;
+            if hit_probability > 0.0 {
+                // The distances were along the direction in texture coordinates
+                depth[pixel] = mean_depth / hit_probability * pipeline::scene_length(data, direction);
+                for j in 0..3 {
+                    albedo[index + j] = mean_albedo[j] / hit_probability;
+                    normal[index + j] = mean_normal[j] / hit_probability;
+                }
+            }
+        }
+    }
+
+    if data.depth {
+        output.depth = Some(depth);
+    }
+    if data.radiance {
+        output.radiance = Some(radiances);
+    }
+    if data.albedo {
+        output.albedo = Some(albedo);
+    }
+    if data.normal {
+        // Same conversion from texture space as in the GPU renderer
+        let normal_matrix = Matrix4f::from_scale(1.0 / scale[0], 1.0 / scale[1], 1.0 / scale[2]);
+        for n in normal.chunks_exact_mut(3) {
+            let mut world = normal_matrix.transform_vector(Vector3f::new(n[0], n[1], n[2]));
+            world.normalize();
+            n.copy_from_slice(&[world.x, world.y, world.z]);
+        }
+        output.normal = Some(normal);
+    }
+}
@@ -0,0 +1,183 @@
+/// Equirectangular environment map with linear RGB radiance,
+/// stored from the top row to the bottom one
+#[derive(Clone)]
+pub struct EnvironmentMap {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA values, the alpha is always 1
+    pub pixels: Vec<f32>
+}
+
+fn rgbe_to_rgba(rgbe: &[u8]) -> [f32; 4] {
+    if rgbe[3] == 0 {
+        return [0.0, 0.0, 0.0, 1.0];
+    }
+    let scale = 2.0f32.powi(rgbe[3] as i32 - 136);
+    return [rgbe[0] as f32 * scale, rgbe[1] as f32 * scale, rgbe[2] as f32 * scale, 1.0];
+}
+
+/// Decodes one scanline of the new run-length encoding, where the four
+/// components are stored one after another
+fn read_rle_scanline(data: &[u8], offset: &mut usize, width: usize) -> Result<Vec<u8>, String> {
+    let mut scanline = vec![0; width * 4];
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *data.get(*offset).ok_or("unexpected end of run-length encoded data")? as usize;
+            *offset += 1;
+            if count > 128 {
+                let count = count - 128;
+                let value = *data.get(*offset).ok_or("unexpected end of run-length encoded data")?;
+                *offset += 1;
+                if x + count > width {
+                    return Err("run-length encoded run exceeds the scanline".to_string());
+                }
+                for i in 0..count {
+                    scanline[(x + i) * 4 + channel] = value;
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width || *offset + count > data.len() {
+                    return Err("invalid run-length encoded data".to_string());
+                }
+                for i in 0..count {
+                    scanline[(x + i) * 4 + channel] = data[*offset + i];
+                }
+                *offset += count;
+                x += count;
+            }
+        }
+    }
+    return Ok(scanline);
+}
+
+/// Parses a Radiance RGBE (.hdr) file with the standard -Y H +X W
+/// orientation, either flat or with the new run-length encoding
+pub fn read_hdr(contents: &[u8]) -> Result<EnvironmentMap, String> {
+    if !(contents.starts_with(b"#?RADIANCE") || contents.starts_with(b"#?RGBE")) {
+        return Err("not a Radiance HDR file".to_string());
+    }
+
+    let mut offset = 0;
+    let mut next_line = || -> Result<String, String> {
+        let end = match contents[offset..].iter().position(|b| *b == b'\n') {
+            Some(e) => offset + e,
+            None => return Err("header is not terminated".to_string())
+        };
+        let line = String::from_utf8_lossy(&contents[offset..end]).trim().to_string();
+        offset = end + 1;
+        return Ok(line);
+    };
+
+    loop {
+        let line = next_line()?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(format!("unsupported pixel format {:?}", format));
+            }
+        }
+    }
+    let resolution = next_line()?;
+    let parts: Vec<&str> = resolution.split_whitespace().collect();
+    let (height, width) = match parts[..] {
+        ["-Y", h, "+X", w] => match (h.parse::<u32>(), w.parse::<u32>()) {
+            (Ok(h), Ok(w)) if h > 0 && w > 0 => (h, w),
+            _ => return Err(format!("invalid resolution {:?}", resolution))
+        },
+        _ => return Err(format!("unsupported resolution or orientation {:?} (expected -Y H +X W)", resolution))
+    };
+
+    let data = &contents[offset..];
+    let mut offset = 0;
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for _ in 0..height {
+        let rle = (8..0x8000).contains(&width) && data.len() >= offset + 4 &&
+            data[offset] == 2 && data[offset + 1] == 2 && data[offset + 2] & 0x80 == 0;
+        let scanline = if rle {
+            if ((data[offset + 2] as u32) << 8 | data[offset + 3] as u32) != width {
+                return Err("scanline width does not match the resolution".to_string());
+            }
+            offset += 4;
+            read_rle_scanline(data, &mut offset, width as usize)?
+        } else {
+            let length = width as usize * 4;
+            if data.len() < offset + length {
+                return Err("unexpected end of pixel data".to_string());
+            }
+            offset += length;
+            data[offset - length..offset].to_vec()
+        };
+        for rgbe in scanline.chunks_exact(4) {
+            pixels.extend_from_slice(&rgbe_to_rgba(rgbe));
+        }
+    }
+
+    return Ok(EnvironmentMap { width, height, pixels });
+}
+
+impl EnvironmentMap {
+    /// Radiance from the given normalized direction in the scene, with
+    /// the same nearest-texel lookup as the MCM shader. The y axis points
+    /// to the top row of the map.
+    pub fn sample(&self, direction: [f32; 3]) -> [f32; 3] {
+        let u = direction[0].atan2(-direction[2]) / (2.0 * std::f32::consts::PI) + 0.5;
+        let v = direction[1].clamp(-1.0, 1.0).acos() / std::f32::consts::PI;
+        let x = ((u * self.width as f32) as u32).min(self.width - 1);
+        let y = ((v * self.height as f32) as u32).min(self.height - 1);
+        let index = (y * self.width + x) as usize * 4;
+        return [self.pixels[index], self.pixels[index + 1], self.pixels[index + 2]];
+    }
+
+    /// Mean radiance over all directions, weighting the rows by their solid angle
+    #[allow(clippy::needless_range_loop)]
+    pub fn mean(&self) -> [f32; 3] {
+        let mut sum = [0.0; 3];
+        let mut weights = 0.0;
+        for y in 0..self.height {
+            let weight = ((y as f32 + 0.5) / self.height as f32 * std::f32::consts::PI).sin();
+            for x in 0..self.width {
+                let index = (y * self.width + x) as usize * 4;
+                for c in 0..3 {
+                    sum[c] += weight * self.pixels[index + c];
+                }
+                weights += weight;
+            }
+        }
+        return sum.map(|s| s / weights);
+    }
+
+    /// Cumulative distributions for sampling texels by their luminance,
+    /// normalized to end at 1: the conditional distribution of the columns
+    /// within every row, followed by the marginal distribution of the rows,
+    /// which are weighted by their solid angle. Black rows and maps are
+    /// sampled uniformly.
+    #[allow(clippy::needless_range_loop)]
+    pub fn sampling_distribution(&self) -> (Vec<f32>, Vec<f32>) {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut conditional = vec![0.0; width * height];
+        let mut marginal = vec![0.0; height];
+        let mut total = 0.0;
+        for y in 0..height {
+            let row = &mut conditional[y * width..(y + 1) * width];
+            let mut sum = 0.0;
+            for x in 0..width {
+                let index = (y * width + x) * 4;
+                let pixel = &self.pixels[index..index + 3];
+                sum += 0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2];
+                row[x] = sum;
+            }
+            for x in 0..width {
+                row[x] = if sum > 0.0 { row[x] / sum } else { (x + 1) as f32 / width as f32 };
+            }
+            total += sum * ((y as f32 + 0.5) / height as f32 * std::f32::consts::PI).sin();
+            marginal[y] = total;
+        }
+        for y in 0..height {
+            marginal[y] = if total > 0.0 { marginal[y] / total } else { (y + 1) as f32 / height as f32 };
+        }
+        return (conditional, marginal);
+    }
+}
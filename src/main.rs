@@ -1,320 +1,303 @@
+#![allow(clippy::needless_return)]
+
+mod arguments;
 mod camera;
+mod cpu_renderer;
+mod envmap;
 mod pipeline;
 mod math;
 mod mcm_renderer;
+mod output;
+mod overlay;
+#[cfg(feature = "gui")]
+mod preview;
+mod sweep;
+mod volume;
 
-use std::{fs, io::Error, time::Instant, env};
-
-use toml;
-use serde::Deserialize;
-
-struct Arguments {
-    volume: String,
-    volume_dimensions: Option<[u32; 3]>,
-    transfer_function: Option<String>,
-    camera_position: [f32; 3],
-    mvp_matrix: Option<[f32; 16]>,
-    output_resolution: [u32; 2],
-    output: String,
-    steps: u32,
-    anisotropy: f32,
-    extinction: f32,
-    bounces: u32,
-    linear: bool,
-    iterations: u32,
-    focal_length: f32,
-    tones: [f32; 3],
-    saturation: f32,
-    gamma: f32
-}
+use std::{fs, io::{Error, Write}, path::Path, process::{self, ExitCode}, sync::atomic::Ordering, time::Instant, env};
 
-#[derive(Deserialize)]
-struct ConfigFileFormat {
-    output: Option<String>,
-    out_resolution: Option<Vec<u32>>,
-    data: Option<ConfigFileData>,
-    rendering: Option<ConfigFileRendering>,
-    tone_mapping: Option<ConfigFileToneMapping>
-}
+use log::{debug, error, info, warn, Level, LevelFilter};
 
-#[derive(Deserialize)]
-struct ConfigFileData {
-    volume: Option<String>,
-    volume_dimensions: Option<Vec<u32>>,
-    transfer_function: Option<String>
+fn read_u8_file(filename: &str) -> Result<Vec<u8>, Error> {
+    let contents = fs::read(filename)?;
+    return Ok(contents);
 }
 
-#[derive(Deserialize)]
-struct ConfigFileRendering {
-    camera_position: Option<Vec<f32>>,
-    mvp_matrix: Option<Vec<f32>>,
-    steps: Option<u32>,
-    anisotropy: Option<f32>,
-    extinction: Option<f32>,
-    bounces: Option<u32>,
-    linear: Option<bool>,
-    iterations: Option<u32>,
-    focal_length: Option<f32>
+/// Reads the RGBA entries of a transfer function, without the bytes of an
+/// incomplete last entry
+fn read_transfer_function(tf_file: &str) -> Result<Vec<u8>, String> {
+    let mut tf = match read_u8_file(tf_file) {
+        Ok(tf) => tf,
+        Err(e) => return Err(format!("Could not open transfer function {:?}: {}", tf_file, e))
+    };
+    if tf.len() < 4 {
+        return Err(format!("Transfer function {:?} has {} bytes, but needs at least one RGBA entry of 4 bytes", tf_file, tf.len()));
+    }
+    if tf.len() % 4 != 0 {
+        warn!("Transfer function {:?} has {} bytes, which is not a multiple of 4. The last {} bytes are ignored.", tf_file, tf.len(), tf.len() % 4);
+        tf.truncate(tf.len() / 4 * 4);
+    }
+    return Ok(tf);
 }
 
-#[derive(Deserialize)]
-struct ConfigFileToneMapping {
-    tones: Option<Vec<f32>>,
-    saturation: Option<f32>,
-    gamma: Option<f32>
+/// Parameters of the render that output names can refer to and that
+/// --stats records, all of them numbers
+fn render_parameters(data: &pipeline::RenderData) -> [(&'static str, String); 8] {
+    return [
+        ("width", data.output_resolution[0].to_string()),
+        ("height", data.output_resolution[1].to_string()),
+        ("iterations", data.iterations.to_string()),
+        ("steps", pipeline::steps(data).to_string()),
+        ("bounces", data.max_bounces.to_string()),
+        ("extinction", data.extinction.to_string()),
+        ("anisotropy", data.anisotropy.to_string()),
+        ("gamma", data.gamma.to_string())
+    ];
 }
 
-fn read_u8_file(filename: &str) -> Result<Vec<u8>, Error> {
-    let contents = fs::read(filename)?;
-    return Ok(contents);
+/// Returns the path of the output image: the --output-name template filled
+/// with the parameters of the render, or else --output, inside of the
+/// output directory. Templates with {index} take the lowest index whose
+/// file does not exist yet, so that repeated runs do not overwrite images.
+fn output_path(output: &str, directory: Option<&str>, template: Option<&str>, data: &pipeline::RenderData) -> String {
+    let directory = Path::new(directory.unwrap_or(""));
+    let template = match template {
+        Some(t) => t,
+        None => return directory.join(output).to_string_lossy().to_string()
+    };
+    let values = render_parameters(data);
+    let path = |index: u32| {
+        let name = output::fill_template(template, &values).replace("{index}", &index.to_string());
+        return directory.join(name);
+    };
+    if !template.contains("{index}") {
+        return path(0).to_string_lossy().to_string();
+    }
+    let index = (0..).find(|i| !path(*i).exists()).unwrap();
+    return path(index).to_string_lossy().to_string();
 }
 
-fn write_output(filename: &str, width: u32, height: u32, content: Vec<u8>) -> Result<(), Error> {
-    let mut output = format!("P3\n{} {}\n{}\n", width, height, 255);
-    for i in (0..content.len()).step_by(3) {
-        let r = content[i];
-        let g = content[i+1];
-        let b = content[i+2];
-        output.push_str(&format!("{} {} {}\n", r, g, b));
+fn print_scene(data: &pipeline::RenderData, output_file: &str) {
+    println!("Volume dimensions: {:?}", data.volume_dims);
+    println!("Volume format: {:?}", data.volume_format);
+    println!("Label volume: {}", data.labels.is_some());
+    if let Some(show_labels) = &data.show_labels {
+        println!("Shown labels: {:?}", show_labels);
+    }
+    if let Some(spacing) = data.voxel_spacing {
+        println!("Voxel spacing: {:?}", spacing);
+    }
+    println!("Volume origin: {:?}", data.volume_origin);
+    println!("Transfer function entries: {}", data.transfer_function_len);
+    if data.blend_transfer_function.is_some() {
+        println!("Transfer function blend: {}", data.tf_blend);
+    }
+    println!("Transfer function color space: {:?}", data.tf_colorspace);
+    println!("Output: {} ({}x{})", output_file, data.output_resolution[0], data.output_resolution[1]);
+    println!("Output bit depth: {}", data.output_bit_depth);
+    println!("Depth output: {}", data.depth);
+    println!("Albedo output: {}", data.albedo);
+    println!("Normal output: {}", data.normal);
+    match data.steps {
+        Some(steps) => println!("Steps: {}", steps),
+        None => println!("Steps: {} (derived from the extinction)", pipeline::steps(data))
+    }
+    println!("Step length: {}", pipeline::step_length(data));
+    if let Some(schedule) = &data.steps_schedule {
+        println!("Steps schedule: {:?}", schedule);
+    }
+    println!("Iterations: {}", data.iterations);
+    println!("Iterations per submit: {}", data.iterations_per_submit);
+    if let Some(threshold) = data.noise_threshold {
+        println!("Noise threshold: {}", threshold);
+    }
+    if let Some(tile) = data.tile {
+        println!("Tile size: {}", tile);
+    }
+    if let Some(checkpoint) = &data.checkpoint {
+        println!("Checkpoint: {}", checkpoint);
+    }
+    if let Some(every) = data.checkpoint_every {
+        println!("Checkpoint every: {} iterations", every);
+    }
+    if !data.cpu {
+        println!("Estimated GPU memory: {} MB", pipeline::megabytes(mcm_renderer::estimated_memory(data)));
+    }
+    if let Some(budget) = data.max_memory {
+        println!("GPU memory budget: {} MB", budget);
+    }
+    println!("Denoise: {}", data.denoise);
+    println!("CPU reference renderer: {}", data.cpu);
+    println!("Bounces: {}", data.max_bounces);
+    if let Some(schedule) = &data.bounces_schedule {
+        println!("Bounces schedule: {:?}", schedule);
+    }
+    if let Some(start) = data.russian_roulette_start {
+        println!("Russian roulette after bounce: {}", start);
     }
+    if let Some(max) = data.max_sample_radiance {
+        println!("Max sample radiance: {}", max);
+    }
+    if data.reset_radiance != [0.0; 3] {
+        println!("Reset radiance: {:?}", data.reset_radiance);
+    }
+    println!("Extinction: {}", data.extinction);
+    println!("Anisotropy: {}", data.anisotropy);
+    println!("Phase function: {:?}", data.phase_function);
+    if data.phase_function == pipeline::PhaseFunction::DoubleHenyeyGreenstein {
+        println!("Phase function parameters: {:?}", data.phase_parameters);
+    }
+    if let Some([min, max]) = data.roi {
+        println!("Region of interest: {:?} - {:?}", min, max);
+    }
+    println!("Clip bounds: {:?} - {:?}", data.clip_min, data.clip_max);
+    if let Some(plane) = data.clip_plane {
+        println!("Clip plane: {:?}{}", plane, if data.invert_clip_plane { " (inverted)" } else { "" });
+    }
+    println!("Gradient shading: {}", data.gradient_shading);
+    if data.gradient_shading {
+        println!("Light direction: {:?}", data.light_direction);
+    }
+    if let Some(position) = data.light_position {
+        println!("Point light: {:?} (intensity {})", position, data.light_intensity);
+    }
+    println!("Next event estimation: {}", data.next_event_estimation);
+    if let Some(envmap) = &data.envmap {
+        println!("Environment map: {}x{}", envmap.width, envmap.height);
+    }
+    println!("Linear filtering: {}", data.linear);
+    println!("Tricubic filtering: {}", data.tricubic);
+    println!("Linear transfer function filtering: {}", data.linear_tf);
+    println!("Address mode: {:?}", data.address_mode);
+    println!("Camera position: {:?}", data.camera_position);
+    println!("Camera target: {:?}", data.look_at);
+    if let Some(up) = data.up {
+        println!("Camera up direction: {:?}", up);
+    }
+    println!("Focal length: {}", data.focal_length);
+    println!("Pixel aspect: {}", data.pixel_aspect);
+    println!("Tones: {:?}", data.tones);
+    println!("Saturation: {}", data.saturation);
+    match data.output_colorspace {
+        Some(colorspace) => println!("Output color space: {:?}", colorspace),
+        None => println!("Gamma: {}", data.gamma)
+    }
+    println!("Tone mapping operator: {:?}", data.tonemap);
+    println!("Inverse MVP matrix:\n{}", pipeline::camera_matrix(data));
+}
 
-    return fs::write(filename, output);
+/// Logs to standard error, with the messages of the renderer prefixed like
+/// the errors and warnings of the arguments. Messages of the dependencies,
+/// such as wgpu, are only shown with more verbose logging, as they also
+/// report harmless failures, e.g. of backends that are not available.
+/// RUST_LOG overrides the levels.
+fn init_logger(level: LevelFilter) {
+    let dependency_level = match level {
+        LevelFilter::Trace => LevelFilter::Info,
+        LevelFilter::Debug => LevelFilter::Warn,
+        _ => LevelFilter::Off
+    };
+    env_logger::Builder::new()
+        .filter_level(dependency_level)
+        .filter_module(module_path!(), level)
+        .parse_default_env()
+        .format(|buf, record| {
+            if !record.target().starts_with(module_path!()) {
+                return writeln!(buf, "{} [{}]: {}", record.level(), record.target(), record.args());
+            }
+            return match record.level() {
+                Level::Error => writeln!(buf, "Error: {}", record.args()),
+                Level::Warn => writeln!(buf, "WARNING: {}", record.args()),
+                Level::Info => writeln!(buf, "{}", record.args()),
+                level => writeln!(buf, "{} [{}]: {}", level, record.target(), record.args())
+            };
+        })
+        .init();
 }
 
-fn parse_arguments() -> Result<Arguments, String> {
+fn main() -> ExitCode {
     let args: Vec<String> = env::args().collect();
-    let mut volume = String::new();
-    let mut volume_dimensions = None;
-    let mut transfer_function = None;
-    let mut camera_position = [-1.0, -1.0, 1.0];
-    let mut mvp_matrix = None;
-    let mut output_resolution = [512, 512];
-    let mut output = "output.ppm".to_string();
-    let mut steps = 100;
-    let mut anisotropy = 0.0;
-    let mut extinction = 100.0;
-    let mut bounces = 8;
-    let mut linear = false;
-    let mut iterations = 1;
-    let mut focal_length = 2.0;
-    let mut tones = [0.0, 0.5, 1.0];
-    let mut saturation = 1.0;
-    let mut gamma = 2.2;
-
-    for i in 0..args.len() {
-        if args[i] == "--config" {
-            match fs::read_to_string(&args[i+1]) {
-                Ok(s) => {
-                    match toml::from_str::<ConfigFileFormat>(&s) {
-                        Ok(config) => {
-                            if let Some(x) = config.output {
-                                output = x;
-                            }
-                            if let Some(x) = config.out_resolution {
-                                output_resolution = [x[0], x[1]];
-                            }
-                            if let Some(x) = config.data {
-                                if let Some(y) = x.volume {
-                                    volume = y;
-                                }
-                                if let Some(y) = x.volume_dimensions {
-                                    volume_dimensions = Some([y[0], y[1], y[2]])
-                                }
-                                transfer_function = x.transfer_function;
-                            }
-                            if let Some(x) = config.rendering {
-                                if let Some(y) = x.anisotropy {
-                                    anisotropy = y;
-                                }
-                                if let Some(y) = x.bounces {
-                                    bounces = y;
-                                }
-                                if let Some(y) = x.camera_position {
-                                    camera_position = [y[0], y[1], y[2]];
-                                }
-                                if let Some(y) = x.extinction {
-                                    extinction = y;
-                                }
-                                if let Some(y) = x.focal_length {
-                                    focal_length = y;
-                                }
-                                if let Some(y) = x.iterations {
-                                    iterations = y;
-                                }
-                                if let Some(y) = x.linear {
-                                    linear = y;
-                                }
-                                if let Some(y) = x.mvp_matrix {
-                                    mvp_matrix = Some([y[0],y[1],y[2],y[3],y[4],y[5],y[6],y[7],y[8],y[9],y[10],y[11],y[12],y[13],y[14],y[15]]);
-                                }
-                                if let Some(y) = x.steps {
-                                    steps = y;
-                                }
-                            }
-                            if let Some(x) = config.tone_mapping {
-                                if let Some(y) = x.gamma {
-                                    gamma = y;
-                                }
-                                if let Some(y) = x.saturation {
-                                    saturation = y;
-                                }
-                                if let Some(y) = x.tones {
-                                    tones = [y[0], y[1], y[2]];
-                                }
-                            }
-                        },
-                        Err(s) => {
-                            println!("Failed to parse config file\n  - Error: {}", s.to_string());
-                        }
-                    };
-                },
-                _ => {
-                    eprintln!("Failed to read config file");
-                }
-            }
-        }
-        if args[i] == "--volume" {
-            volume = args[i+1].to_string();
+    init_logger(arguments::log_level(&args));
+    let args = match arguments::parse_arguments(&args) {
+        Ok(arguments::ParsedArguments::Run(a)) => {
+            *a
+        },
+        Ok(arguments::ParsedArguments::Exit(text)) => {
+            println!("{}", text);
+            return ExitCode::SUCCESS;
+        },
+        Err(e) => {
+            error!("{}", e);
+            return ExitCode::FAILURE;
         }
+    };
 
-        if args[i] == "--volume-dimensions" {
-            volume_dimensions = Some([
-                args[i+1].parse::<u32>().unwrap(),
-                args[i+2].parse::<u32>().unwrap(),
-                args[i+3].parse::<u32>().unwrap()
-            ]);
-        }
-        else if args[i] == "--tf" {
-            transfer_function = Some(args[i+1].to_string());
-        }
-        else if args[i] == "--camera-position" {
-            camera_position = [
-                args[i+1].parse::<f32>().unwrap(),
-                args[i+2].parse::<f32>().unwrap(),
-                args[i+3].parse::<f32>().unwrap()
-            ];
-        }
-        else if args[i] == "--out-resolution" {
-            output_resolution[0] = args[i+1].parse::<u32>().unwrap();
-            output_resolution[1] = args[i+2].parse::<u32>().unwrap();
-        }
-        else if args[i] == "--output" {
-            output = args[i+1].to_string();
-        }
-        else if args[i] == "--steps" {
-            steps = args[i+1].parse::<u32>().unwrap();
+    if let Some(shape) = args.generate {
+        // A dry run only shows what would be generated, without writing it
+        if args.dry_run {
+            println!("Generated volume: {:?} ({}x{}x{})", shape, args.gen_dims[0], args.gen_dims[1], args.gen_dims[2]);
+            println!("Volume output: {}", args.gen_out);
+            return ExitCode::SUCCESS;
         }
-        else if args[i] == "--anisotropy" {
-            anisotropy = args[i+1].parse::<f32>().unwrap();
+        let volume = volume::generate_volume(shape, args.gen_dims, args.seed);
+        if let Err(e) = fs::write(&args.gen_out, volume) {
+            error!("Could not write volume to file {:?}: {}", args.gen_out, e);
+            return ExitCode::FAILURE;
         }
-        else if args[i] == "--extinction" {
-            extinction = args[i+1].parse::<f32>().unwrap();
-        }
-        else if args[i] == "--bounces" {
-            bounces = args[i+1].parse::<u32>().unwrap();
-        }
-        else if args[i] == "--linear" {
-            linear = true;
-        }
-        else if args[i] == "--iterations" {
-            iterations = args[i+1].parse::<u32>().unwrap();
-        }
-        else if args[i] == "--mvp-matrix" {
-            mvp_matrix = Some([
-                args[i+1].parse::<f32>().unwrap(),
-                args[i+2].parse::<f32>().unwrap(),
-                args[i+3].parse::<f32>().unwrap(),
-                args[i+4].parse::<f32>().unwrap(),
-                args[i+5].parse::<f32>().unwrap(),
-                args[i+6].parse::<f32>().unwrap(),
-                args[i+7].parse::<f32>().unwrap(),
-                args[i+8].parse::<f32>().unwrap(),
-                args[i+9].parse::<f32>().unwrap(),
-                args[i+10].parse::<f32>().unwrap(),
-                args[i+11].parse::<f32>().unwrap(),
-                args[i+12].parse::<f32>().unwrap(),
-                args[i+13].parse::<f32>().unwrap(),
-                args[i+14].parse::<f32>().unwrap(),
-                args[i+15].parse::<f32>().unwrap(),
-                args[i+16].parse::<f32>().unwrap()
-            ]);
-        }
-        else if args[i] == "--focal-length" {
-            focal_length = args[i+1].parse::<f32>().unwrap();
-        }
-        else if args[i] == "--levels" {
-            tones[0] = args[i+1].parse::<f32>().unwrap();
-            tones[1] = args[i+2].parse::<f32>().unwrap();
-            tones[2] = args[i+3].parse::<f32>().unwrap();
-        }
-        else if args[i] == "--saturation" {
-            saturation = args[i+1].parse::<f32>().unwrap();
-        }
-        else if args[i] == "--gamma" {
-            gamma = args[i+1].parse::<f32>().unwrap();
+        info!("Volume written!");
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(inputs) = &args.accumulate {
+        let mut images: Vec<output::PfmImage> = Vec::with_capacity(inputs.len());
+        for input in inputs {
+            let image = match read_u8_file(input) {
+                Ok(contents) => match output::read_pfm(&contents) {
+                    Ok(i) => i,
+                    Err(e) => {
+                        error!("Could not read PFM image {:?}: {}", input, e);
+                        return ExitCode::FAILURE;
+                    }
+                },
+                Err(e) => {
+                    error!("Could not open PFM image {:?}: {}", input, e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            if let Some(first) = images.first() {
+                if (image.width, image.height, image.channels) != (first.width, first.height, first.channels) {
+                    error!(
+                        "PFM image {:?} is {}x{} with {} channels, but {:?} is {}x{} with {} channels",
+                        input, image.width, image.height, image.channels, inputs[0], first.width, first.height, first.channels
+                    );
+                    return ExitCode::FAILURE;
+                }
+            }
+            images.push(image);
         }
-        else if args[i] == "--help" {
-            let text = format!(
-                "** {} (version {}) **\nAuthors: {}\n\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
-                "VPT Lazy Ripoff",
-                "0.1.0",
-                "Gorazd Gorup, Žiga Lesar (original)",
-                "--volume : Path to file with raw volumetric data",
-                "--volume-dimensions : Three integers representing width, height and depth of texture (optional)",
-                "--tf : Path to the file with transfer function texture (optional)",
-                "--camera-position : Three floats representing x,y,z coordinates of camera (optional)",
-                "--mvp-matrix : 16 floats representing inversed transformation matrix (optional)",
-                "--out-resolution : An integer representing resolution of output image (optional)",
-                "--output : Path to output image file (optional)",
-                "--steps : Number of rendering steps (optional)",
-                "--anisotropy : Anisotropy (optional)",
-                "--extinction : Extinction (optional)",
-                "--bounces : Number of bounces per photon (optional)",
-                "--iterations : Number of iterations (optional)",
-                "--focal-length : A float representing distance of projection plane from camera origin (optional)",
-                "--tones : Three floats representing low, mid and high tones (optional)",
-                "--saturation : Saturation on post-processing (optional)",
-                "--gamma : Gamma value on post-processing (optional)"
-            );
-            return Err(text);
+
+        let weights = args.accumulate_weights.clone().unwrap_or_else(|| vec![1.0; images.len()]);
+        let average = output::weighted_average(&images, &weights);
+        if let Err(e) = output::write_pfm(&args.output, images[0].width, images[0].height, images[0].channels, &average) {
+            error!("Could not write image to file {:?}: {}", args.output, e);
+            return ExitCode::FAILURE;
         }
+        info!("Accumulated {} images!", images.len());
+        return ExitCode::SUCCESS;
     }
 
-    if volume.is_empty() {
-        return Err("Error: No volume provided!".to_string());
+    if args.list_backends {
+        pipeline::print_backends();
+        return ExitCode::SUCCESS;
     }
 
-    return Ok(Arguments {
-        volume,
-        volume_dimensions,
-        transfer_function,
-        camera_position,
-        mvp_matrix,
-        output_resolution,
-        output,
-        steps,
-        anisotropy,
-        extinction,
-        bounces,
-        linear,
-        iterations,
-        focal_length,
-        tones,
-        saturation,
-        gamma
-    });
-}
-
-fn main() {
-    let args = match parse_arguments() {
-        Ok(a) => {
-            a
-        },
-        Err(e) => {
-            println!("{}", e);
-            return;
+    if args.info {
+        if let Err(e) = pollster::block_on(pipeline::print_info(args.backends)) {
+            error!("{}", e);
+            return ExitCode::FAILURE;
         }
-    };
+        return ExitCode::SUCCESS;
+    }
 
     let output_file = args.output;
     let volume_file = args.volume;
@@ -333,38 +316,153 @@ fn main() {
     let saturation = args.saturation;
     let gamma = args.gamma;
 
-    println!("Starting...");
+    info!("Starting...");
     let timer = Instant::now();
 
-    let volume = match read_u8_file(&volume_file) {
+    let volume = match volume::read_volume(&volume_file) {
         Ok(v) => v,
         Err(e) => {
-            eprintln!("Error: Coult not open volume {:?}: {}", volume_file, e);
-            return;
+            error!("Coult not open volume {:?}: {}", volume_file, e);
+            return ExitCode::FAILURE;
         }
     };
-    let volume_dims =  match args.volume_dimensions {
-        Some(c) => {
+    let (volume, header_dims) = if volume::is_nrrd(&volume) {
+        match volume::read_nrrd(&volume) {
+            Ok((v, d)) => (v, Some(d)),
+            Err(e) => {
+                error!("Could not read NRRD volume {:?}: {}", volume_file, e);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        (volume, None)
+    };
+    if header_dims.is_some() && args.volume_format != pipeline::VolumeFormat::R8 {
+        error!("NRRD volume {:?} can only be read with volume format r8", volume_file);
+        return ExitCode::FAILURE;
+    }
+    if header_dims.is_some() && args.volume_dtype != volume::VolumeDataType::U8 {
+        error!("NRRD volume {:?} already declares its data type, it can not be read with volume data type {:?}", volume_file, args.volume_dtype);
+        return ExitCode::FAILURE;
+    }
+    let element_size = if header_dims.is_some() { 1 } else { args.volume_dtype.element_size() };
+    let bytes_per_voxel = args.volume_format.bytes_per_voxel() as usize * element_size;
+    let volume_dims =  match (header_dims, args.volume_dimensions) {
+        (Some(h), Some(c)) if h != c => {
+            error!("Volume dimensions {:?} do not match NRRD header sizes {:?}", c, h);
+            return ExitCode::FAILURE;
+        },
+        (Some(h), _) => {
+            h
+        },
+        (None, Some(c)) => {
             [c[0], c[1], c[2]]
         },
-        None => {
-            let vol_size = volume.len() as f32;
+        (None, None) => {
+            let vol_size = (volume.len() / bytes_per_voxel) as f32;
             let candidate = vol_size.cbrt().floor();
             let x = candidate as u32;
             let y = candidate as u32;
             let z = (vol_size / (candidate * candidate)) as u32;
-            println!("WARNING: No dimensions provided. Using [{},{},{}] as calculated dimensions.", x, y, z);
+            warn!("No dimensions provided. Using [{},{},{}] as calculated dimensions.", x, y, z);
             [x, y, z]
         },
     };
+    // Textures can not be empty, but a single slice is a valid volume
+    if volume_dims.contains(&0) {
+        error!("Volume dimensions {:?} of {:?} must all be positive, a single slice has a depth of 1", volume_dims, volume_file);
+        return ExitCode::FAILURE;
+    }
+
+    let volume_bytes = volume_dims[0] as usize * volume_dims[1] as usize * volume_dims[2] as usize * bytes_per_voxel;
+    if volume.len() < volume_bytes {
+        error!("Volume {:?} has {} bytes, but dimensions {:?} require {}", volume_file, volume.len(), volume_dims, volume_bytes);
+        return ExitCode::FAILURE;
+    } else if volume.len() > volume_bytes {
+        // Extra bytes in a multi-channel volume most likely mean the channels are misread
+        if args.volume_format != pipeline::VolumeFormat::R8 {
+            error!("Volume {:?} has {} bytes, but dimensions {:?} with format {:?} require exactly {}", volume_file, volume.len(), volume_dims, args.volume_format, volume_bytes);
+            return ExitCode::FAILURE;
+        }
+        // and in a volume with wider values, that the data type is wrong
+        if element_size > 1 {
+            error!("Volume {:?} has {} bytes, but dimensions {:?} with data type {:?} require exactly {}", volume_file, volume.len(), volume_dims, args.volume_dtype, volume_bytes);
+            return ExitCode::FAILURE;
+        }
+        warn!("Volume {:?} has {} bytes, but dimensions {:?} only use {}", volume_file, volume.len(), volume_dims, volume_bytes);
+    }
+
+    // Wider values are rescaled to the 8-bit density the renderer samples
+    let (mut volume, volume_bytes, bytes_per_voxel) = if element_size > 1 {
+        (volume::convert_to_u8(&volume[..volume_bytes], args.volume_dtype), volume_bytes / element_size, 1)
+    } else {
+        (volume, volume_bytes, bytes_per_voxel)
+    };
+
+    if args.normalize {
+        let (min, max) = volume::normalize(&mut volume[..volume_bytes], bytes_per_voxel);
+        if min == max {
+            warn!("All voxels of volume {:?} have density {}, so it can not be normalized", volume_file, min);
+        } else {
+            info!("Normalized densities from {} to {} to the full range", min, max);
+        }
+    }
+
+    if args.histogram {
+        let mut csv = "value,count\n".to_string();
+        for (value, count) in volume::histogram(&volume[..volume_bytes], bytes_per_voxel).iter().enumerate() {
+            csv.push_str(&format!("{},{}\n", value, count));
+        }
+        print!("{}", csv);
+        return ExitCode::SUCCESS;
+    }
+
+    let labels = match &args.labels {
+        Some(labels_file) => {
+            let labels = match volume::read_volume(labels_file) {
+                Ok(l) => l,
+                Err(e) => {
+                    error!("Could not open label volume {:?}: {}", labels_file, e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let voxels = volume_dims[0] as usize * volume_dims[1] as usize * volume_dims[2] as usize;
+            if volume::is_nrrd(&labels) || labels.len() != voxels {
+                error!("Label volume {:?} must contain exactly {} raw bytes to match volume dimensions {:?}", labels_file, voxels, volume_dims);
+                return ExitCode::FAILURE;
+            }
+            Some(labels)
+        },
+        None => None
+    };
+
+    // Everything else refers to the axes of the reoriented volume
+    let reoriented = args.axes != [0, 1, 2] || args.flip.contains(&true);
+    let (volume, labels, volume_dims, voxel_spacing) = if reoriented {
+        let (volume, dims) = volume::reorient(&volume, volume_dims, bytes_per_voxel, args.axes, args.flip);
+        let labels = labels.map(|l| volume::reorient(&l, volume_dims, 1, args.axes, args.flip).0);
+        debug!("Reoriented the volume to dimensions {:?}", dims);
+        (volume, labels, dims, args.voxel_spacing.map(|s| args.axes.map(|a| s[a])))
+    } else {
+        (volume, labels, volume_dims, args.voxel_spacing)
+    };
+
+    // The preview moves the window, so it keeps the densities from before it.
+    // The histogram shows them as well, to choose the window from.
+    #[cfg(feature = "gui")]
+    let unwindowed = if args.preview { Some(volume.clone()) } else { None };
+    let mut volume = volume;
+    if let Some(window) = args.window {
+        volume::apply_window(&mut volume, bytes_per_voxel, window);
+    }
 
     let transfer_function = match transfer_function_file {
         Some(tf_file) => {
-            match read_u8_file(&tf_file) {
+            match read_transfer_function(&tf_file) {
                 Ok(tf) => tf,
                 Err(e) => {
-                    eprintln!("Error: Could not open transfer function {:?}: {}", tf_file, e);
-                    return;
+                    error!("{}", e);
+                    return ExitCode::FAILURE;
                 }
             }
         },
@@ -375,43 +473,348 @@ fn main() {
 
     let tf_len = transfer_function.len() / 4;
 
-    let image_size = out_res[0] * out_res[1] * 3;
-    let mut image: Vec<u8> = Vec::with_capacity(image_size as usize);
+    // The blend is per entry, so both transfer functions need the same entries
+    let blend_transfer_function = match &args.blend_transfer_function {
+        Some(tf_file) => {
+            let tf = match read_transfer_function(tf_file) {
+                Ok(tf) => tf,
+                Err(e) => {
+                    error!("{}", e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            if tf.len() / 4 != tf_len {
+                error!("Transfer function {:?} has {} entries, but the first transfer function has {}, and blended transfer functions must be the same length", tf_file, tf.len() / 4, tf_len);
+                return ExitCode::FAILURE;
+            }
+            Some(tf)
+        },
+        None => None
+    };
+
+    let envmap = match &args.envmap {
+        Some(envmap_file) => {
+            let contents = match read_u8_file(envmap_file) {
+                Ok(c) => c,
+                Err(e) => {
+                    error!("Could not open environment map {:?}: {}", envmap_file, e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            match envmap::read_hdr(&contents) {
+                Ok(e) => Some(e),
+                Err(e) => {
+                    error!("Could not read environment map {:?}: {}", envmap_file, e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        },
+        None => None
+    };
+
+    let mut output = pipeline::RenderOutput::default();
+
+    let render_data = pipeline::RenderData {
+        output_resolution: out_res,
+        output_bit_depth: args.output_bit_depth,
+        volume,
+        volume_dims,
+        volume_format: args.volume_format,
+        labels,
+        show_labels: args.show_labels,
+        voxel_spacing,
+        transfer_function,
+        transfer_function_len: tf_len as u32,
+        blend_transfer_function,
+        tf_blend: args.tf_blend,
+        tf_colorspace: args.tf_colorspace,
+        extinction,
+        anisotropy,
+        phase_function: args.phase,
+        phase_parameters: args.phase_params,
+        max_bounces: bounces,
+        steps,
+        camera_position,
+        look_at: args.look_at,
+        up: args.up,
+        linear: linear_filter,
+        tricubic: args.tricubic,
+        linear_tf: args.linear_tf,
+        address_mode: args.address_mode,
+        iterations,
+        tile: args.tile,
+        max_memory: args.max_memory,
+        iterations_per_submit: args.iterations_per_submit,
+        noise_threshold: args.noise_threshold,
+        denoise: args.denoise,
+        cpu: args.cpu,
+        steps_schedule: args.steps_schedule,
+        bounces_schedule: args.bounces_schedule,
+        russian_roulette_start: args.rr_start,
+        max_sample_radiance: args.max_sample_radiance,
+        reset_radiance: args.reset_radiance,
+        // Nothing outside of the region of interest is rendered
+        clip_min: [0, 1, 2].map(|i| args.roi.map_or(args.clip[i][0], |r| args.clip[i][0].max(r[0][i]))),
+        clip_max: [0, 1, 2].map(|i| args.roi.map_or(args.clip[i][1], |r| args.clip[i][1].min(r[1][i]))),
+        clip_plane: args.clip_plane,
+        invert_clip_plane: args.invert_clip_plane,
+        roi: args.roi,
+        volume_origin: args.volume_origin,
+        gradient_shading: args.gradient_shading,
+        light_direction: args.light_dir,
+        light_position: args.light_position,
+        light_intensity: args.light_intensity,
+        next_event_estimation: args.nee,
+        envmap,
+        mvp_matrix,
+        shader_dir: args.shader_dir,
+        focal_length,
+        pixel_aspect: args.pixel_aspect,
+        tones,
+        saturation,
+        gamma,
+        output_colorspace: args.output_colorspace,
+        tonemap: args.tonemap,
+        depth: args.depth.is_some(),
+        radiance: args.stats.is_some(),
+        albedo: args.albedo,
+        dump_gbuffer: args.dump_gbuffer,
+        normal: args.normal,
+        benchmark: args.benchmark,
+        gpu_timing: args.gpu_timing,
+        multi_gpu: args.multi_gpu,
+        checkpoint: args.checkpoint.clone(),
+        checkpoint_every: args.checkpoint_every,
+        resume: None,
+        backends: args.backends
+    };
+    let load_time = timer.elapsed();
+    debug!("Step length: {} ({} steps per iteration)", pipeline::step_length(&render_data), pipeline::steps(&render_data));
+    let output_file = output_path(&output_file, args.output_dir.as_deref(), args.output_name.as_deref(), &render_data);
+
+    if args.dump_matrix {
+        let values: Vec<String> = pipeline::camera_matrix(&render_data).to_values().iter().map(|v| v.to_string()).collect();
+        println!("{}", values.join(" "));
+    }
+
+    if args.dry_run {
+        print_scene(&render_data, &output_file);
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(directory) = Path::new(&output_file).parent() {
+        if let Err(e) = fs::create_dir_all(directory) {
+            error!("Could not create output directory {:?}: {}", directory, e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    #[cfg(feature = "gui")]
+    if let Some(unwindowed) = unwindowed {
+        preview::run(render_data, unwindowed, args.window.unwrap_or([0, 255]), output_file, args.flip_y);
+    }
+
+    let max_value = pipeline::output_max_value(&render_data);
+
+    if let Some(parameters) = &args.sweep {
+        let cells = sweep::combinations(parameters);
+        info!("Rendering {} combinations of the sweep...", cells.len());
+        let camera_matrix = pipeline::camera_matrix(&render_data);
+        let mut images = match pollster::block_on(pipeline::render_sweep(render_data, &cells)) {
+            Ok(i) => i,
+            Err(e) => {
+                error!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        };
+        if args.show_axes {
+            for image in images.iter_mut() {
+                overlay::draw_axes(image, out_res, &camera_matrix, max_value);
+            }
+        }
+        // Every cell is flipped on its own, so the labels stay below them
+        if args.flip_y {
+            for image in images.iter_mut() {
+                output::flip_rows(image, out_res[0], 3);
+            }
+        }
+        let labels: Vec<String> = cells.iter().map(|c| sweep::label(c)).collect();
+        let (sheet, size) = sweep::contact_sheet(&images, &labels, parameters[0].1.len(), out_res, max_value);
+        if let Err(e) = output::write_output(&output_file, size[0], size[1], max_value, sheet) {
+            error!("Could not write contact sheet to file {:?}: {}", output_file, e);
+            return ExitCode::FAILURE;
+        }
+        info!("Contact sheet of {}x{} cells written!", parameters[0].1.len(), cells.len() / parameters[0].1.len());
+        return ExitCode::SUCCESS;
+    }
+
+    // The camera keeps the aspect ratio of the output, so the square
+    // preview is stretched back to it
+    let mut render_data = render_data;
+    let render_res = match args.preview_resolution {
+        Some(r) => {
+            render_data.pixel_aspect *= out_res[0] as f32 / out_res[1] as f32;
+            render_data.output_resolution = [r, r];
+            [r, r]
+        },
+        None => out_res
+    };
+
+    // The checkpoint must come from a render of the same size
+    if let Some(resume_file) = &args.resume {
+        let checkpoint = match read_u8_file(resume_file) {
+            Ok(contents) => output::read_checkpoint(&contents),
+            Err(e) => Err(e.to_string())
+        };
+        let checkpoint = match checkpoint {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Could not read checkpoint {:?}: {}", resume_file, e);
+                return ExitCode::FAILURE;
+            }
+        };
+        if checkpoint.resolution != render_res {
+            error!("Checkpoint {:?} was rendered at {}x{}, but this render is {}x{}", resume_file,
+                checkpoint.resolution[0], checkpoint.resolution[1], render_res[0], render_res[1]);
+            return ExitCode::FAILURE;
+        }
+        info!("Resuming from {} iterations of checkpoint {:?}", checkpoint.iterations, resume_file);
+        render_data.resume = Some(checkpoint);
+    }
 
-    pollster::block_on(
+    // The first Ctrl-C stops the iterations and writes what was rendered
+    // so far, the second one exits right away
+    if !render_data.cpu {
+        let handler = ctrlc::set_handler(|| {
+            if pipeline::CANCELLED.swap(true, Ordering::SeqCst) {
+                process::exit(130);
+            }
+            warn!("Cancelling after the current batch of iterations, press Ctrl-C again to exit right away");
+        });
+        if let Err(e) = handler {
+            warn!("Could not install the Ctrl-C handler, so cancelling loses the render: {}", e);
+        }
+    }
+
+    let parameters = render_parameters(&render_data);
+    let camera_matrix = pipeline::camera_matrix(&render_data);
+    let timings = match pollster::block_on(
         pipeline::render(
-            pipeline::RenderData {
-                output_resolution: out_res,
-                volume,
-                volume_dims,
-                transfer_function,
-                transfer_function_len: tf_len as u32,
-                extinction,
-                anisotropy,
-                max_bounces: bounces,
-                steps,
-                camera_position,
-                linear: linear_filter,
-                iterations,
-                mvp_matrix,
-                focal_length,
-                tones,
-                saturation,
-                gamma
-            },
-            &mut image
+            render_data,
+            &mut output
         )
-    );
+    ) {
+        Ok(t) => t,
+        Err(e) => {
+            error!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
 
-    match write_output(&output_file, out_res[0], out_res[1], image) {
+    // The statistics are of the rendered pixels, before any upscaling
+    if let (Some(stats_file), Some(radiance)) = (&args.stats, &output.radiance) {
+        let statistics = output::channel_statistics(radiance, 3);
+        if let Err(e) = output::write_statistics(stats_file, &statistics, &parameters) {
+            error!("Could not write statistics to file {:?}: {}", stats_file, e);
+            return ExitCode::FAILURE;
+        }
+        for (channel, s) in ["Red", "Green", "Blue"].iter().zip(&statistics) {
+            debug!("{} radiance: min {}, max {}, mean {}, median {}", channel, s.min, s.max, s.mean, s.median);
+        }
+    }
+
+    if render_res != out_res {
+        let image: Vec<f32> = output.image.iter().map(|v| *v as f32).collect();
+        output.image = output::upscale(&image, render_res, out_res, 3).iter().map(|v| v.round() as u16).collect();
+        for (aov, channels) in [(&mut output.depth, 1), (&mut output.albedo, 3), (&mut output.normal, 3)] {
+            if let Some(values) = aov {
+                *values = output::upscale(values, render_res, out_res, channels);
+            }
+        }
+        if let Some(gbuffer) = &mut output.gbuffer {
+            for values in gbuffer.iter_mut() {
+                *values = output::upscale(values, render_res, out_res, 3);
+            }
+        }
+        info!("Upscaled the {}x{} preview to {}x{}", render_res[0], render_res[1], out_res[0], out_res[1]);
+    }
+
+    // Drawn at the output resolution, so the lines stay one pixel wide
+    if args.show_axes {
+        overlay::draw_axes(&mut output.image, out_res, &camera_matrix, max_value);
+    }
+
+    // The images next to the output are flipped too, so their pixels stay
+    // in the same places as those of the output
+    if args.flip_y {
+        output::flip_rows(&mut output.image, out_res[0], 3);
+        for (aov, channels) in [(&mut output.depth, 1), (&mut output.albedo, 3), (&mut output.normal, 3)] {
+            if let Some(values) = aov {
+                output::flip_rows(values, out_res[0], channels);
+            }
+        }
+        if let Some(gbuffer) = &mut output.gbuffer {
+            for values in gbuffer.iter_mut() {
+                output::flip_rows(values, out_res[0], 3);
+            }
+        }
+    }
+
+    let write_timer = Instant::now();
+
+    match output::write_output(&output_file, out_res[0], out_res[1], max_value, output.image) {
         Ok(()) => {
-            println!("Image written!")
+            info!("Image written!");
         },
         Err(e) => {
-            eprintln!("Error: Could not write image to file {:?}: {}", output_file, e);
-            return;
+            error!("Could not write image to file {:?}: {}", output_file, e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if let (Some(depth_file), Some(depth)) = (&args.depth, &output.depth) {
+        if let Err(e) = output::write_pfm(depth_file, out_res[0], out_res[1], 1, depth) {
+            error!("Could not write depth to file {:?}: {}", depth_file, e);
+            return ExitCode::FAILURE;
         }
     }
 
-    println!("Time: {}", timer.elapsed().as_secs_f32());
+    for (name, aov) in [("albedo", &output.albedo), ("normal", &output.normal)] {
+        if let Some(aov) = aov {
+            let aov_file = Path::new(&output_file).with_extension(format!("{}.pfm", name));
+            if let Err(e) = output::write_pfm(&aov_file.to_string_lossy(), out_res[0], out_res[1], 3, aov) {
+                error!("Could not write {} to file {:?}: {}", name, aov_file, e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if let Some(gbuffer) = &output.gbuffer {
+        for (name, values) in ["position", "direction", "transmittance"].iter().zip(gbuffer) {
+            let gbuffer_file = Path::new(&output_file).with_extension(format!("{}.pfm", name));
+            if let Err(e) = output::write_pfm(&gbuffer_file.to_string_lossy(), out_res[0], out_res[1], 3, values) {
+                error!("Could not write {} to file {:?}: {}", name, gbuffer_file, e);
+                return ExitCode::FAILURE;
+            }
+            info!("Wrote the photon {} after the first iteration to {:?}", name, gbuffer_file);
+        }
+    }
+
+    if args.benchmark {
+        println!("Loading: {}", load_time.as_secs_f32());
+        println!("Device acquisition: {}", timings.device.as_secs_f32());
+        println!("Texture upload: {}", timings.upload.as_secs_f32());
+        println!("Pipeline setup: {}", timings.setup.as_secs_f32());
+        println!("Rendering: {}", timings.render.as_secs_f32());
+        println!("Readback: {}", timings.readback.as_secs_f32());
+        println!("Writing: {}", write_timer.elapsed().as_secs_f32());
+    }
+
+    if let (Some(reset), Some(iterations)) = (timings.gpu_reset, timings.gpu_iterations) {
+        println!("GPU reset passes: {} us", reset.as_micros());
+        println!("GPU iterations: {} us", iterations.as_micros());
+    }
+
+    info!("Time: {}", timer.elapsed().as_secs_f32());
+    return ExitCode::SUCCESS;
 }
@@ -1,4 +1,4 @@
-use std::{ops::{Sub, Neg}, fmt::Display};
+use std::{ops::{Mul, Sub, Neg}, fmt::Display};
 
 #[derive(Debug)]
 pub struct Matrix4f {
@@ -12,6 +12,7 @@ pub struct Vector3f {
     pub z: f32
 }
 
+#[derive(Debug)]
 pub struct Quaternion {
     pub x: f32,
     pub y: f32,
@@ -29,6 +30,13 @@ impl Vector3f {
         };
     }
 
+    /// Whether every component differs by at most eps, for comparing
+    /// results of floating point math in the tests
+    #[allow(dead_code)]
+    pub fn approx_eq(&self, other: &Vector3f, eps: f32) -> bool {
+        return (self.x - other.x).abs() <= eps && (self.y - other.y).abs() <= eps && (self.z - other.z).abs() <= eps;
+    }
+
     /*pub fn clone(&self) -> Self {
         Vector3f {
             x: self.x,
@@ -37,21 +45,33 @@ impl Vector3f {
         }
     }*/
 
+    pub fn length(&self) -> f32 {
+        return self.length_squared().sqrt();
+    }
+
+    /// Cheaper than the length for comparisons, as it avoids the square root
+    pub fn length_squared(&self) -> f32 {
+        return self.x*self.x + self.y*self.y + self.z*self.z;
+    }
+
+    /// Length of the vector, not a distance between two points
+    #[deprecated(note = "renamed to length")]
+    #[allow(dead_code)]
     pub fn distance(&self) -> f32 {
-        return (self.x*self.x + self.y*self.y + self.z*self.z).sqrt();
+        return self.length();
     }
 
     pub fn normalize(&mut self) {
-        let dist = self.distance();
-        if dist == 0.0 {
+        if self.length_squared() == 0.0 {
             self.x = 0.0;
             self.y = 0.0;
             self.z = 0.0;
             return;
         }
-        self.x = self.x / dist;
-        self.y = self.y / dist;
-        self.z = self.z / dist;
+        let length = self.length();
+        self.x /= length;
+        self.y /= length;
+        self.z /= length;
     }
 
     pub fn cross(vec1: &Self, vec2: &Self) -> Self {
@@ -90,6 +110,14 @@ impl Neg for Vector3f {
     }
 }
 
+impl Mul<&Matrix4f> for &Matrix4f {
+    type Output = Matrix4f;
+
+    fn mul(self, rhs: &Matrix4f) -> Self::Output {
+        return Matrix4f::multiply(self, rhs);
+    }
+}
+
 impl Quaternion {
     pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
         return Self {
@@ -102,9 +130,28 @@ impl Quaternion {
 
     pub fn normalize(&mut self) {
         let len = (self.x*self.x + self.y*self.y + self.z*self.z + self.w*self.w).sqrt();
-        self.x = self.x / len;
-        self.y = self.y / len;
-        self.z = self.z / len;
+        self.x /= len;
+        self.y /= len;
+        self.z /= len;
+    }
+
+    /// Inverse of to_rotation_matrix for matrices without scaling
+    pub fn from_rotation_matrix(matrix: &Matrix4f) -> Self {
+        let m = matrix.m;
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        // Divides by the largest component to stay accurate for all angles
+        if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            return Self::new((m[1][2] - m[2][1]) / s, (m[2][0] - m[0][2]) / s, (m[0][1] - m[1][0]) / s, 0.25 * s);
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.0;
+            return Self::new(0.25 * s, (m[0][1] + m[1][0]) / s, (m[0][2] + m[2][0]) / s, (m[1][2] - m[2][1]) / s);
+        } else if m[1][1] > m[2][2] {
+            let s = (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.0;
+            return Self::new((m[0][1] + m[1][0]) / s, 0.25 * s, (m[1][2] + m[2][1]) / s, (m[2][0] - m[0][2]) / s);
+        }
+        let s = (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.0;
+        return Self::new((m[0][2] + m[2][0]) / s, (m[1][2] + m[2][1]) / s, 0.25 * s, (m[0][1] - m[1][0]) / s);
     }
 
     pub fn to_rotation_matrix(&self) -> Matrix4f {
@@ -151,7 +198,21 @@ impl Matrix4f {
         return Matrix4f { m };
     }
 
-    pub fn mutiply(matrix1: &Matrix4f, matrix2: &Matrix4f) -> Self {
+    /// Whether every element differs by at most eps, for comparing
+    /// results of floating point math in the tests
+    #[allow(dead_code)]
+    pub fn approx_eq(&self, other: &Matrix4f, eps: f32) -> bool {
+        return self.m.iter().flatten().zip(other.m.iter().flatten()).all(|(a, b)| (a - b).abs() <= eps);
+    }
+
+    /// Returns the matrix values row by row, in the same order
+    /// as expected by from_values
+    pub fn to_values(&self) -> Vec<f32> {
+        return self.m.iter().flatten().cloned().collect();
+    }
+
+    #[allow(clippy::needless_range_loop)]
+    pub fn multiply(matrix1: &Matrix4f, matrix2: &Matrix4f) -> Self {
         let mut res = Matrix4f::new();
         let m1 = matrix1.m;
         let m2 = matrix2.m;
@@ -169,6 +230,22 @@ impl Matrix4f {
         return res;
     }
 
+    /// Applies the matrix to a point with w = 1, including the translation
+    /// and the perspective divide
+    pub fn transform_point(&self, point: Vector3f) -> Vector3f {
+        let m = self.m;
+        let p = [point.x, point.y, point.z, 1.0];
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| m[i][0]*p[0] + m[i][1]*p[1] + m[i][2]*p[2] + m[i][3]*p[3]);
+        return Vector3f::new(x / w, y / w, z / w);
+    }
+
+    /// Applies the matrix to a direction with w = 0, so it is not translated
+    pub fn transform_vector(&self, vector: Vector3f) -> Vector3f {
+        let m = self.m;
+        let [x, y, z] = [0, 1, 2].map(|i| m[i][0]*vector.x + m[i][1]*vector.y + m[i][2]*vector.z);
+        return Vector3f::new(x, y, z);
+    }
+
     pub fn transpose(&self) -> Self {
         let mut res = Matrix4f::new();
         for i in 0..4 {
@@ -187,7 +264,7 @@ impl Matrix4f {
                     + m[1][2] * m[2][3] * m[3][1]
                     + m[1][3] * m[2][1] * m[3][2]
                     - m[1][3] * m[2][2] * m[3][1]
-                    - m[1][2] * m[1][2] * m[3][3]
+                    - m[1][2] * m[2][1] * m[3][3]
                     - m[1][1] * m[2][3] * m[3][2]) -
                   m[1][0] *
                      (m[0][1] * m[2][2] * m[3][3]
@@ -263,7 +340,7 @@ impl Matrix4f {
         return res;
     }
 
-    /*pub fn from_translation(x: f32, y: f32, z: f32) -> Matrix4f {
+    pub fn from_translation(x: f32, y: f32, z: f32) -> Matrix4f {
         let mut res = Matrix4f::new();
 
         res.m[0][3] = x;
@@ -273,7 +350,7 @@ impl Matrix4f {
         return res;
     }
 
-    pub fn from_rotation_x(angle: f32) -> Matrix4f {
+    /*pub fn from_rotation_x(angle: f32) -> Matrix4f {
         let mut res = Matrix4f::new();
 
         let s = f32::sin(angle);
@@ -313,7 +390,7 @@ impl Matrix4f {
         res.m[1][1] = c;
 
         return res;
-    }
+    }*/
 
     pub fn from_scale(x: f32, y: f32, z: f32) -> Matrix4f {
         let mut res = Matrix4f::new();
@@ -321,7 +398,7 @@ impl Matrix4f {
         res.m[1][1] = y;
         res.m[2][2] = z;
         return res;
-    }*/
+    }
 }
 
 impl Display for Matrix4f {
@@ -1,8 +1,9 @@
-use std::num::NonZeroU32;
+use std::{fs, io::ErrorKind, num::{NonZeroU32, NonZeroU64}, path::Path, sync::atomic::Ordering, time::{Duration, Instant}};
 
-use wgpu::{util::DeviceExt, include_wgsl};
+use log::{debug, info, trace, warn};
+use wgpu::util::DeviceExt;
 
-use crate::{pipeline::RenderData, math::Matrix4f};
+use crate::{output::{self, Checkpoint, CHECKPOINT_TEXTURES}, pipeline::{self, output_max_value, volume_scale, AddressMode, ColorSpace, PhaseFunction, RenderData, RenderError, RenderOutput, Timings, ToneMapOperator, VolumeFormat}, math::Matrix4f};
 
 struct TextureViewSampler {
     pub texture: wgpu::Texture,
@@ -14,39 +15,30 @@ struct RenderPassTextures {
     pub position: [TextureViewSampler; 2],
     pub direction: [TextureViewSampler; 2],
     pub transmittance_sampes: [TextureViewSampler; 2],
-    pub radiance_bounces: [TextureViewSampler; 2]
+    pub radiance_bounces: [TextureViewSampler; 2],
+    pub depth_hits: [TextureViewSampler; 2],
+    pub albedo: [TextureViewSampler; 2],
+    pub normal: [TextureViewSampler; 2]
 }
 
-fn create_texture_view_sampler_pair(device: &wgpu::Device, w: u32, h: u32) -> [TextureViewSampler; 2] {
-    let texture1 = device.create_texture(
-        &wgpu::TextureDescriptor {
-            label: Some("Texture"),
-            size: wgpu::Extent3d {
-                width: w,
-                height: h,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba32Float,
-            usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT
-        }
-    );
-    let view1 = texture1.create_view(&wgpu::TextureViewDescriptor::default());
-    let sampler1 = device.create_sampler(
-        &wgpu::SamplerDescriptor {
-            label: Some("Sampler"),
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        }
-    );
+impl RenderPassTextures {
+    /// Textures of the photon state on one side of the pairs, in the order
+    /// of the checkpoint files
+    fn state(&self, index: usize) -> [&TextureViewSampler; CHECKPOINT_TEXTURES] {
+        return [
+            &self.position[index],
+            &self.direction[index],
+            &self.transmittance_sampes[index],
+            &self.radiance_bounces[index],
+            &self.depth_hits[index],
+            &self.albedo[index],
+            &self.normal[index]
+        ];
+    }
+}
 
-    let texture2 = device.create_texture(
+fn create_texture_view_sampler(device: &wgpu::Device, w: u32, h: u32) -> TextureViewSampler {
+    let texture = device.create_texture(
         &wgpu::TextureDescriptor {
             label: Some("Texture"),
             size: wgpu::Extent3d {
@@ -58,11 +50,13 @@ fn create_texture_view_sampler_pair(device: &wgpu::Device, w: u32, h: u32) -> [T
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba32Float,
-            usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT
+            usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT
         }
     );
-    let view2 = texture2.create_view(&wgpu::TextureViewDescriptor::default());
-    let sampler2 = device.create_sampler(
+    // The view is created in the same place as its texture, so that
+    // the two halves of a pair can never alias the same texture
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = device.create_sampler(
         &wgpu::SamplerDescriptor {
             label: Some("Sampler"),
             address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -74,31 +68,37 @@ fn create_texture_view_sampler_pair(device: &wgpu::Device, w: u32, h: u32) -> [T
         }
     );
 
-    let tvs1 = TextureViewSampler {
-        texture: texture1,
-        view: view1,
-        sampler: sampler1
-    };
-    let tvs2 = TextureViewSampler {
-        texture: texture2,
-        view: view2,
-        sampler: sampler2
+    return TextureViewSampler {
+        texture,
+        view,
+        sampler
     };
+}
 
-    return [tvs1, tvs2];
+/// Creates both halves of a ping-pong pair, each with a texture and view of its own
+fn create_texture_view_sampler_pair(device: &wgpu::Device, w: u32, h: u32) -> [TextureViewSampler; 2] {
+    return [
+        create_texture_view_sampler(device, w, h),
+        create_texture_view_sampler(device, w, h)
+    ];
 }
 
-fn create_texture_sampler(device: &wgpu::Device, label: &str, linear: bool) -> wgpu::Sampler {
+fn create_texture_sampler(device: &wgpu::Device, label: &str, linear: bool, address_mode: AddressMode) -> wgpu::Sampler {
     let mut filter_mode = wgpu::FilterMode::Nearest;
     if linear {
         filter_mode = wgpu::FilterMode::Linear;
     }
+    let address_mode = match address_mode {
+        AddressMode::Clamp => wgpu::AddressMode::ClampToEdge,
+        AddressMode::Repeat => wgpu::AddressMode::Repeat,
+        AddressMode::Mirror => wgpu::AddressMode::MirrorRepeat
+    };
     return device.create_sampler(
         &wgpu::SamplerDescriptor {
             label: Some(label),
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
             mag_filter: filter_mode,
             min_filter: filter_mode,
             mipmap_filter: wgpu::FilterMode::Nearest,
@@ -107,6 +107,49 @@ fn create_texture_sampler(device: &wgpu::Device, label: &str, linear: bool) -> w
     );
 }
 
+/// Source of the shader file with the given name. With a shader directory,
+/// the file is read from it at runtime, so shaders can be edited without
+/// rebuilding the crate. The source compiled into the binary is used when
+/// the directory does not contain the file.
+fn shader_source(shader_dir: Option<&str>, name: &str, embedded: &str) -> String {
+    if let Some(dir) = shader_dir {
+        let path = Path::new(dir).join(name);
+        match fs::read_to_string(&path) {
+            Ok(source) => return source,
+            Err(e) if e.kind() == ErrorKind::NotFound => {},
+            Err(e) => warn!("Could not read shader {:?}, using the embedded one: {}", path, e)
+        }
+    }
+    return embedded.to_string();
+}
+
+/// Compiles the shader inside an error scope, so that shaders with errors are
+/// reported instead of panicking in the default error handler of wgpu
+async fn compile_shader(device: &wgpu::Device, name: &str, source: String) -> Result<wgpu::ShaderModule, RenderError> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let module = device.create_shader_module(
+        wgpu::ShaderModuleDescriptor {
+            label: Some(name),
+            source: wgpu::ShaderSource::Wgsl(source.into())
+        }
+    );
+    pop_validation_error(device, name).await?;
+    return Ok(module);
+}
+
+async fn create_shader_module(device: &wgpu::Device, shader_dir: Option<&str>, name: &str, embedded: &str) -> Result<wgpu::ShaderModule, RenderError> {
+    return compile_shader(device, name, shader_source(shader_dir, name, embedded)).await;
+}
+
+/// Ends an error scope around the creation of shaders or pipelines. Pipelines
+/// fail when the shaders do not match the bind groups or render targets.
+async fn pop_validation_error(device: &wgpu::Device, name: &str) -> Result<(), RenderError> {
+    return match device.pop_error_scope().await {
+        Some(e) => Err(RenderError::ShaderCompile(format!("{}: {}", name, e.to_string().trim_end()))),
+        None => Ok(())
+    };
+}
+
 fn create_matrix_uniform_buffer(device: &wgpu::Device, matrix: &Matrix4f, label: &str) -> wgpu::Buffer {
     return device.create_buffer_init(
         &wgpu::util::BufferInitDescriptor {
@@ -137,6 +180,16 @@ fn create_vector2_f32_uniform_buffer(device: &wgpu::Device, vector: &[f32; 2], l
     );
 }
 
+fn create_vector4_f32_uniform_buffer(device: &wgpu::Device, vector: &[f32; 4], label: &str) -> wgpu::Buffer {
+    return device.create_buffer_init(
+        &wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents: bytemuck::cast_slice(vector),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        }
+    );
+}
+
 fn create_f32_uniform_buffer(device: &wgpu::Device, num: f32, label: &str) -> wgpu::Buffer {
     return device.create_buffer_init(
         &wgpu::util::BufferInitDescriptor {
@@ -157,70 +210,348 @@ fn create_u32_uniform_buffer(device: &wgpu::Device, num: u32, label: &str) -> wg
     );
 }
 
+/// Creates a layout of uniform buffers that are visible to the fragment
+/// shaders, bound one after another like in create_uniforms_bind_group
+fn create_uniforms_bind_group_layout(device: &wgpu::Device, label: &str, count: u32) -> wgpu::BindGroupLayout {
+    let entries: Vec<wgpu::BindGroupLayoutEntry> = (0..count).map(|i| {
+        wgpu::BindGroupLayoutEntry {
+            binding: i,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None
+            },
+            count: None
+        }
+    }).collect();
+    return device.create_bind_group_layout(
+        &wgpu::BindGroupLayoutDescriptor {
+            label: Some(label),
+            entries: &entries
+        }
+    );
+}
+
+fn create_uniforms_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, buffers: &[&wgpu::Buffer]) -> wgpu::BindGroup {
+    let entries: Vec<wgpu::BindGroupEntry> = buffers.iter().enumerate().map(|(i, buffer)| {
+        wgpu::BindGroupEntry {
+            binding: i as u32,
+            resource: buffer.as_entire_binding()
+        }
+    }).collect();
+    return device.create_bind_group(
+        &wgpu::BindGroupDescriptor {
+            label: Some("UniformsGroup"),
+            layout,
+            entries: &entries
+        }
+    );
+}
 
-fn reset(device: &wgpu::Device, render_pass_textures: &RenderPassTextures, global_uniforms_layout: &wgpu::BindGroupLayout,
-    global_uniforms_group: &wgpu::BindGroup, encoder: &mut wgpu::CommandEncoder) {
-    /* -------------- Global Uniforms --------------- */
+/// Returns the scheduled value for the given iteration. The schedule
+/// entries are spread over equally long consecutive runs of iterations,
+/// so that the values ramp from the first entry to the last one.
+fn schedule_value(schedule: &Option<Vec<u32>>, default: u32, iteration: u32, iterations: u32) -> u32 {
+    return match schedule {
+        Some(values) => values[(iteration as u64 * values.len() as u64 / iterations as u64) as usize],
+        None => default
+    };
+}
 
-    let random_seed = rand::random::<f32>();
-    let random_seed_buffer = create_f32_uniform_buffer(&device, random_seed, "RandSeedBuffer");
+/// Returns a bitmask with a bit set for every label that is rendered
+fn label_mask(show_labels: &Option<Vec<u8>>) -> [u32; 8] {
+    let mut mask = [0; 8];
+    match show_labels {
+        Some(labels) => {
+            for label in labels {
+                mask[(label / 32) as usize] |= 1 << (label % 32);
+            }
+        },
+        None => mask = [u32::MAX; 8]
+    }
+    return mask;
+}
 
-    /* -------------- Local Bind Groups --------------- */
+/// Pipeline of the pass that starts new paths, which is compiled once and
+/// then used for every tile
+struct ResetPass {
+    pipeline: wgpu::RenderPipeline,
+    local_uniforms_layout: wgpu::BindGroupLayout,
+    reset_radiance: [f32; 3],
+    reset_radiance_buffer: wgpu::Buffer
+}
 
-    let local_uniforms_bind_group_layout = device.create_bind_group_layout(
+impl ResetPass {
+    async fn new(device: &wgpu::Device, uniforms_layout: &wgpu::BindGroupLayout, reset_radiance: [f32; 3],
+        shader_dir: Option<&str>) -> Result<ResetPass, RenderError> {
+        let [r, g, b] = reset_radiance;
+        let reset_radiance_buffer = create_vector4_f32_uniform_buffer(device, &[r, g, b, 0.0], "ResetRadianceBuffer");
+        let local_uniforms_layout = create_uniforms_bind_group_layout(device, "LocalUniformsGroupLayout", 2);
+
+        /* -------------- Pipeline --------------- */
+
+        let vertex_shader = create_shader_module(device, shader_dir, "mcm_reset_vertex.wgsl", include_str!("shaders/mcm_reset_vertex.wgsl")).await?;
+        let fragment_shader = create_shader_module(device, shader_dir, "mcm_reset_fragment.wgsl", include_str!("shaders/mcm_reset_fragment.wgsl")).await?;
+
+        let render_pipeline_layout = device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: Some("ResetRenderPipelineLayout"),
+                bind_group_layouts: &[
+                    &local_uniforms_layout,
+                    uniforms_layout
+                ],
+                push_constant_ranges: &[]
+            }
+        );
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let render_pipeline = device.create_render_pipeline(
+            &wgpu::RenderPipelineDescriptor {
+                label: Some("ResetRenderPipeline"),
+                layout: Some(&render_pipeline_layout),
+                multiview: None,
+                depth_stencil: None,
+                vertex: wgpu::VertexState {
+                    module: &vertex_shader,
+                    entry_point: "main",
+                    buffers: &[]
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &fragment_shader,
+                    entry_point: "main",
+                    targets: &[
+                        Some(wgpu::ColorTargetState {
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::ALL
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::ALL
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::ALL
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::ALL
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::ALL
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::ALL
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::ALL
+                        })
+                    ],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleStrip,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: Some(wgpu::Face::Back),
+                    unclipped_depth: false,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    conservative: false
+                },
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false
+                },
+            }
+        );
+        pop_validation_error(device, "ResetRenderPipeline").await?;
+
+        return Ok(ResetPass {
+            pipeline: render_pipeline,
+            local_uniforms_layout,
+            reset_radiance,
+            reset_radiance_buffer
+        });
+    }
+
+    /// Starts new paths in all pixels. The radiance of the pixels is set to
+    /// the reset radiance, which stays until their first path finishes.
+    fn encode(&self, device: &wgpu::Device, render_pass_textures: &RenderPassTextures, uniforms_group: &wgpu::BindGroup,
+        encoder: &mut wgpu::CommandEncoder) {
+        /* -------------- Local Uniforms --------------- */
+
+        let random_seed = rand::random::<u32>();
+        let random_seed_buffer = create_u32_uniform_buffer(device, random_seed, "RandSeedBuffer");
+        let local_uniforms_bind_group = create_uniforms_bind_group(device, &self.local_uniforms_layout, &[&random_seed_buffer, &self.reset_radiance_buffer]);
+        let [r, g, b] = self.reset_radiance;
+
+        /* -------------- Rendering --------------- */
+
+        {
+            let render_pass_descriptor = wgpu::RenderPassDescriptor {
+                label: Some("RenderPass"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &render_pass_textures.position[0].view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(
+                                wgpu::Color::TRANSPARENT
+                            ),
+                            store: true
+                        }
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &render_pass_textures.direction[0].view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(
+                                wgpu::Color::TRANSPARENT
+                            ),
+                            store: true
+                        }
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &render_pass_textures.transmittance_sampes[0].view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(
+                                wgpu::Color::TRANSPARENT
+                            ),
+                            store: true
+                        }
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &render_pass_textures.radiance_bounces[0].view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(
+                                wgpu::Color { r: r as f64, g: g as f64, b: b as f64, a: 0.0 }
+                            ),
+                            store: true
+                        }
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &render_pass_textures.depth_hits[0].view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(
+                                wgpu::Color::TRANSPARENT
+                            ),
+                            store: true
+                        }
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &render_pass_textures.albedo[0].view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(
+                                wgpu::Color::TRANSPARENT
+                            ),
+                            store: true
+                        }
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &render_pass_textures.normal[0].view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(
+                                wgpu::Color::TRANSPARENT
+                            ),
+                            store: true
+                        }
+                    }),
+                ],
+                depth_stencil_attachment: None,
+            };
+
+            let mut render_pass = encoder.begin_render_pass(&render_pass_descriptor);
+
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &local_uniforms_bind_group, &[]);
+            render_pass.set_bind_group(1, uniforms_group, &[]);
+            render_pass.draw(0..4, 0..1);
+        }
+    }
+}
+
+/// Number of a-trous levels of the denoiser, with the step
+/// between the filter taps doubling on every level
+const DENOISE_PASSES: u32 = 4;
+
+/// Filters the accumulated radiance with an edge-aware a-trous filter.
+/// The position and direction textures that are not holding the last
+/// state are used as scratch space; with an even number of passes the
+/// result ends up in the direction one.
+async fn denoise(device: &wgpu::Device, render_pass_textures: &RenderPassTextures, result_index: usize,
+    shader_dir: Option<&str>, encoder: &mut wgpu::CommandEncoder) -> Result<(), RenderError> {
+    let scratch = [
+        &render_pass_textures.position[(result_index + 1) % 2],
+        &render_pass_textures.direction[(result_index + 1) % 2]
+    ];
+    let texture_entry = |binding: u32| {
+        return wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float {
+                    filterable: false
+                },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false
+            },
+            count: None
+        };
+    };
+
+    let bind_group_layout = device.create_bind_group_layout(
         &wgpu::BindGroupLayoutDescriptor {
-            label: Some("LocalUniformsGroupLayout"),
+            label: Some("DenoiseBindGroupLayout"),
             entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                texture_entry(2),
                 wgpu::BindGroupLayoutEntry {
-                    binding: 0,
+                    binding: 3,
                     visibility: wgpu::ShaderStages::FRAGMENT,
-                    count: None,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
                         min_binding_size: None
-                    }
-                }
-            ]
-        }
-    );
-
-    let local_uniforms_bind_group = device.create_bind_group(
-        &wgpu::BindGroupDescriptor {
-            label: Some("LocalUniformsBindGroup"),
-            layout: &local_uniforms_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: random_seed_buffer.as_entire_binding()
+                    },
+                    count: None
                 }
             ]
         }
     );
 
-    /* -------------- Pipeline --------------- */
-
-    let vertex_shader = device.create_shader_module(
-        include_wgsl!("shaders/mcm_reset_vertex.wgsl")
-    );
-    let fragment_shader = device.create_shader_module(
-        include_wgsl!("shaders/mcm_reset_fragment.wgsl")
-    );
+    let vertex_shader = create_shader_module(device, shader_dir, "denoise_vertex.wgsl", include_str!("shaders/denoise_vertex.wgsl")).await?;
+    let fragment_shader = create_shader_module(device, shader_dir, "denoise_fragment.wgsl", include_str!("shaders/denoise_fragment.wgsl")).await?;
 
     let render_pipeline_layout = device.create_pipeline_layout(
         &wgpu::PipelineLayoutDescriptor {
-            label: Some("ResetRenderPipelineLayout"),
+            label: Some("DenoiseRenderPipelineLayout"),
             bind_group_layouts: &[
-                global_uniforms_layout,
-                &local_uniforms_bind_group_layout
+                &bind_group_layout
             ],
             push_constant_ranges: &[]
         }
     );
 
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
     let render_pipeline = device.create_render_pipeline(
         &wgpu::RenderPipelineDescriptor {
-            label: Some("ResetRenderPipeline"),
+            label: Some("DenoiseRenderPipeline"),
             layout: Some(&render_pipeline_layout),
             multiview: None,
             depth_stencil: None,
@@ -233,21 +564,6 @@ fn reset(device: &wgpu::Device, render_pass_textures: &RenderPassTextures, globa
                 module: &fragment_shader,
                 entry_point: "main",
                 targets: &[
-                    Some(wgpu::ColorTargetState {
-                        format: wgpu::TextureFormat::Rgba32Float,
-                        blend: None,
-                        write_mask: wgpu::ColorWrites::ALL
-                    }),
-                    Some(wgpu::ColorTargetState {
-                        format: wgpu::TextureFormat::Rgba32Float,
-                        blend: None,
-                        write_mask: wgpu::ColorWrites::ALL
-                    }),
-                    Some(wgpu::ColorTargetState {
-                        format: wgpu::TextureFormat::Rgba32Float,
-                        blend: None,
-                        write_mask: wgpu::ColorWrites::ALL
-                    }),
                     Some(wgpu::ColorTargetState {
                         format: wgpu::TextureFormat::Rgba32Float,
                         blend: None,
@@ -271,273 +587,691 @@ fn reset(device: &wgpu::Device, render_pass_textures: &RenderPassTextures, globa
             },
         }
     );
-
-    /* -------------- Rendering --------------- */
-
-    {
-        let render_pass_descriptor = wgpu::RenderPassDescriptor {
-            label: Some("RenderPass"),
-            color_attachments: &[
-                Some(wgpu::RenderPassColorAttachment {
-                    view: &render_pass_textures.position[0].view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(
-                            wgpu::Color::TRANSPARENT
-                        ),
-                        store: true
-                    }
-                }),
-                Some(wgpu::RenderPassColorAttachment {
-                    view: &render_pass_textures.direction[0].view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(
-                            wgpu::Color::TRANSPARENT
-                        ),
-                        store: true
-                    }
-                }),
-                Some(wgpu::RenderPassColorAttachment {
-                    view: &render_pass_textures.transmittance_sampes[0].view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(
-                            wgpu::Color::TRANSPARENT
-                        ),
-                        store: true
-                    }
-                }),
-                Some(wgpu::RenderPassColorAttachment {
-                    view: &render_pass_textures.radiance_bounces[0].view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(
-                            wgpu::Color::TRANSPARENT
-                        ),
-                        store: true
+    pop_validation_error(device, "DenoiseRenderPipeline").await?;
+
+    let mut input = &render_pass_textures.radiance_bounces[result_index];
+    for i in 0..DENOISE_PASSES {
+        // Every pass needs its own buffer, as writes would only
+        // take effect once the whole encoder is submitted
+        let step_buffer = create_u32_uniform_buffer(device, 1 << i, "DenoiseStepBuffer");
+        let bind_group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: Some("DenoiseBindGroup"),
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&input.view)
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&render_pass_textures.depth_hits[result_index].view)
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&render_pass_textures.normal[result_index].view)
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: step_buffer.as_entire_binding()
                     }
-                }),
-            ],
-            depth_stencil_attachment: None,
-        };
+                ]
+            }
+        );
 
-        let mut render_pass = encoder.begin_render_pass(&render_pass_descriptor);
+        let output = scratch[(i % 2) as usize];
+        {
+            let mut render_pass = encoder.begin_render_pass(
+                &wgpu::RenderPassDescriptor {
+                    label: Some("DenoiseRenderPass"),
+                    color_attachments: &[
+                        Some(wgpu::RenderPassColorAttachment {
+                            view: &output.view,
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(
+                                    wgpu::Color::TRANSPARENT
+                                ),
+                                store: true
+                            }
+                        })
+                    ],
+                    depth_stencil_attachment: None
+                }
+            );
 
-        render_pass.set_pipeline(&render_pipeline);
-        render_pass.set_bind_group(0, &global_uniforms_group, &[]);
-        render_pass.set_bind_group(1, &local_uniforms_bind_group, &[]);
-        render_pass.draw(0..4, 0..1);
+            render_pass.set_pipeline(&render_pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..4, 0..1);
+        }
+        input = output;
     }
+    return Ok(());
 }
 
-pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderData, camera_matrix: &Matrix4f, output: &mut Vec<u8>) {
-    /* -------------- Global Textures --------------- */
+/// Returns the size of the render textures, with the width padded
+/// so that rows can be copied into a buffer
+pub fn padded_resolution(resolution: [u32; 2]) -> [u32; 2] {
     let bytes_alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
-    let res_x = (data.output_resolution[0] as f32 / bytes_alignment as f32).ceil() as u32 * bytes_alignment;
-    let res_y = data.output_resolution[1];
-
-    let position_texture_pair = create_texture_view_sampler_pair(&device, res_x, res_y);
-    let direction_texture_pair = create_texture_view_sampler_pair(&device, res_x, res_y);
-    let transmittance_samples_texture_pair = create_texture_view_sampler_pair(&device, res_x, res_y);
-    let radiance_bounces_texture_pair = create_texture_view_sampler_pair(&device, res_x, res_y);
-
-    let render_pass_textures = RenderPassTextures {
-        position: position_texture_pair,
-        direction: direction_texture_pair,
-        transmittance_sampes: transmittance_samples_texture_pair,
-        radiance_bounces: radiance_bounces_texture_pair,
+    let res_x = (resolution[0] as f32 / bytes_alignment as f32).ceil() as u32 * bytes_alignment;
+    return [res_x, resolution[1]];
+}
+
+/// Returns the size in bytes of the buffer the final image is read back into
+pub fn result_buffer_size(resolution: [u32; 2]) -> u64 {
+    let padded = padded_resolution(resolution);
+    let f32_size = std::mem::size_of::<f32>() as u64;
+    return f32_size * 4 * padded[0] as u64 * padded[1] as u64;
+}
+
+/// Returns an estimate of the bytes of GPU memory a render allocates: the
+/// fourteen Rgba32Float render textures and the readback buffers of a tile,
+/// and the textures of the scene. Drivers add alignment and bookkeeping of
+/// their own, so the real use is somewhat higher.
+pub fn estimated_memory(data: &RenderData) -> u64 {
+    let tile = tile_resolution(data);
+    let render_textures = 14 * result_buffer_size(tile);
+    let readbacks = [true, data.depth, data.radiance, data.albedo, data.normal, data.noise_threshold.is_some()]
+        .iter()
+        .filter(|enabled| **enabled)
+        .count() as u64 + if data.dump_gbuffer { 3 } else { 0 };
+    let voxels: u64 = data.volume_dims.iter().map(|d| *d as u64).product();
+    let volume = voxels * data.volume_format.bytes_per_voxel() as u64;
+    let labels = if data.labels.is_some() { voxels } else { 0 };
+    let tf_count = if data.blend_transfer_function.is_some() { 2 } else { 1 };
+    let transfer_functions = tf_count * data.transfer_function_len as u64 * 4;
+    // Four floats per texel and one per row of the marginal distribution
+    let envmap = data.envmap.as_ref().map_or(0, |e| (e.width as u64 * 16 + 4) * e.height as u64);
+    return render_textures + readbacks * result_buffer_size(tile) + volume + labels + transfer_functions + envmap;
+}
+
+/// Returns the size of a single rendered tile, which is the whole image
+/// when tiling is disabled
+pub fn tile_resolution(data: &RenderData) -> [u32; 2] {
+    let [width, height] = data.output_resolution;
+    return match data.tile {
+        Some(tile) => [tile.min(width), tile.min(height)],
+        None => [width, height]
     };
+}
 
-    /* -------------- Global Uniforms --------------- */
+/// Narrows the inverse MVP matrix down to the part of the view frustum
+/// covered by a tile, so that the normalized device coordinates of the
+/// tile map onto the matching region of the whole image
+pub fn tile_camera_matrix(camera_matrix: &Matrix4f, resolution: [u32; 2], origin: [u32; 2], size: [u32; 2]) -> Matrix4f {
+    let mut tile_to_image = Matrix4f::new();
+    for i in 0..2 {
+        let image_size = resolution[i] as f32;
+        tile_to_image.m[i][i] = size[i] as f32 / image_size;
+        tile_to_image.m[i][3] = (2.0 * origin[i] as f32 + size[i] as f32) / image_size - 1.0;
+    }
+    // The camera matrix is stored transposed, so the remap is applied from the left
+    return &tile_to_image.transpose() * camera_matrix;
+}
 
-    let inverse_resolution_x = 1.0 / res_x as f32;
-    let inverse_resolution_y = 1.0 / res_y as f32;
+/// Layout of tightly packed data for queue.write_texture, which unlike
+/// copies between buffers and textures does not require the rows to be
+/// aligned to COPY_BYTES_PER_ROW_ALIGNMENT. Data that is ever copied
+/// through a buffer must use padded rows instead (see padded_resolution).
+fn packed_data_layout(bytes_per_row: u32, rows_per_image: u32) -> wgpu::ImageDataLayout {
+    return wgpu::ImageDataLayout {
+        offset: 0,
+        bytes_per_row: NonZeroU32::new(bytes_per_row),
+        rows_per_image: NonZeroU32::new(rows_per_image)
+    };
+}
 
-    let mvp_inverse_buffer = create_matrix_uniform_buffer(&device, &camera_matrix, "MVPInverseBuffer");
-    let resolution_buffer = create_vector2_u32_uniform_buffer(&device, &[res_x, res_y], "ResolutionBuffer");
-    let inverse_resolution_buffer = create_vector2_f32_uniform_buffer(&device, &[inverse_resolution_x, inverse_resolution_y], "InvResBuffer");
+/// Removes the padding at the end of the rows of a buffer read back from
+/// a render target, which is padded_width texels wide, leaving the four
+/// channels of the texels of a size[0] by size[1] tile without gaps
+fn crop_padding(values: &[f32], padded_width: u32, size: [u32; 2]) -> Vec<f32> {
+    let mut cropped = Vec::with_capacity((size[0] * size[1] * 4) as usize);
+    for row in values.chunks_exact(padded_width as usize * 4).take(size[1] as usize) {
+        cropped.extend_from_slice(&row[..size[0] as usize * 4]);
+    }
+    // Missing rows of a short buffer stay black instead of shrinking the image
+    cropped.resize((size[0] * size[1] * 4) as usize, 0.0);
+    return cropped;
+}
 
-    /* -------------- Global Bind Groups --------------- */
+fn copy_texture_to_buffer(encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture, buffer: &wgpu::Buffer, resolution: [u32; 2]) {
+    let f32_size = std::mem::size_of::<f32>() as u32;
+    // Buffer copies fail validation with unaligned rows
+    assert_eq!(
+        f32_size * 4 * resolution[0] % wgpu::COPY_BYTES_PER_ROW_ALIGNMENT, 0,
+        "rows of the copied texture must be padded, see padded_resolution"
+    );
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTextureBase {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All
+        },
+        wgpu::ImageCopyBuffer {
+            buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(f32_size * 4 * resolution[0]),
+                rows_per_image: NonZeroU32::new(resolution[1])
+            },
+        },
+        wgpu::Extent3d {
+            width: resolution[0],
+            height: resolution[1],
+            depth_or_array_layers: 1,
+        }
+    );
+}
 
-    let global_uniforms_bind_group_layout = device.create_bind_group_layout(
-        &wgpu::BindGroupLayoutDescriptor {
-            label: Some("GlobalUniformsBindGroupLayout"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    count: None,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None
-                    }
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    count: None,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None
-                    }
+/// Waits until the GPU is done writing the buffer and returns a copy of its
+/// contents. Mapping fails, or never finishes, when the device was lost.
+async fn read_buffer<T: bytemuck::Pod>(device: &wgpu::Device, buffer: &wgpu::Buffer) -> Result<Vec<T>, RenderError> {
+    let values = {
+        let buffer_slice = buffer.slice(..);
+        let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            // The receiver is only gone when the render was already abandoned
+            let _ = tx.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        match rx.receive().await {
+            Some(Ok(())) => {},
+            Some(Err(_)) | None => return Err(RenderError::DeviceLost)
+        }
+        let buffer_data = buffer_slice.get_mapped_range();
+        bytemuck::cast_slice::<u8, T>(&buffer_data).to_vec()
+    };
+    buffer.unmap();
+    return Ok(values);
+}
+
+/// Ping-pong textures holding the state of the photons, kept between
+/// renders so that the same image can be refined with more iterations
+pub struct Accumulation {
+    textures: RenderPassTextures,
+    /// Iterations accumulated in the textures since the last reset
+    iterations: u32
+}
+
+impl Accumulation {
+    /// Creates the textures for rendering a single tile of the given data
+    pub fn new(device: &wgpu::Device, data: &RenderData) -> Accumulation {
+        let [res_x, res_y] = padded_resolution(tile_resolution(data));
+
+        let position_texture_pair = create_texture_view_sampler_pair(device, res_x, res_y);
+        let direction_texture_pair = create_texture_view_sampler_pair(device, res_x, res_y);
+        let transmittance_samples_texture_pair = create_texture_view_sampler_pair(device, res_x, res_y);
+        let radiance_bounces_texture_pair = create_texture_view_sampler_pair(device, res_x, res_y);
+        let depth_hits_texture_pair = create_texture_view_sampler_pair(device, res_x, res_y);
+        let albedo_texture_pair = create_texture_view_sampler_pair(device, res_x, res_y);
+        let normal_texture_pair = create_texture_view_sampler_pair(device, res_x, res_y);
+
+        let textures = RenderPassTextures {
+            position: position_texture_pair,
+            direction: direction_texture_pair,
+            transmittance_sampes: transmittance_samples_texture_pair,
+            radiance_bounces: radiance_bounces_texture_pair,
+            depth_hits: depth_hits_texture_pair,
+            albedo: albedo_texture_pair,
+            normal: normal_texture_pair,
+        };
+
+        return Accumulation {
+            textures,
+            iterations: 0
+        };
+    }
+
+    /// Creates the textures and uploads the state of the checkpoint into
+    /// those that the next iteration reads. The checkpoint must have the
+    /// resolution of the data, which is not tiled.
+    pub fn resume(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderData, checkpoint: &Checkpoint) -> Accumulation {
+        let mut accumulation = Accumulation::new(device, data);
+        let [width, height] = checkpoint.resolution;
+        let index = (checkpoint.iterations % 2) as usize;
+        for (texture, values) in accumulation.textures.state(index).iter().zip(&checkpoint.textures) {
+            queue.write_texture(
+                wgpu::ImageCopyTextureBase {
+                    texture: &texture.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All
                 },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    count: None,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None
-                    }
+                bytemuck::cast_slice(values),
+                packed_data_layout(width * 16, height),
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1
                 }
-            ]
+            );
         }
-    );
+        accumulation.iterations = checkpoint.iterations;
+        return accumulation;
+    }
+}
 
-    let global_uniforms_bind_group = device.create_bind_group(
-        &wgpu::BindGroupDescriptor {
-            label: Some("GlobalUniformsBindGroup"),
-            layout: &global_uniforms_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: mvp_inverse_buffer.as_entire_binding()
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: resolution_buffer.as_entire_binding()
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: inverse_resolution_buffer.as_entire_binding()
-                },
-            ]
+/// Reads back the photon state after the given number of iterations and
+/// writes it to the checkpoint file. The state is in the textures of the
+/// pairs that the next iteration would read.
+async fn save_checkpoint(device: &wgpu::Device, queue: &wgpu::Queue, textures: &RenderPassTextures, iterations: u32,
+    size: [u32; 2], path: &str) -> Result<(), RenderError> {
+    let [res_x, res_y] = padded_resolution(size);
+    let buffer = device.create_buffer(
+        &wgpu::BufferDescriptor {
+            label: Some("CheckpointBuffer"),
+            size: result_buffer_size(size),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
         }
     );
+    // One texture at a time, so that the state needs a single extra buffer
+    let mut values = Vec::with_capacity(CHECKPOINT_TEXTURES);
+    for texture in textures.state((iterations % 2) as usize) {
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: Some("CheckpointCommandEncoder"),
+            }
+        );
+        copy_texture_to_buffer(&mut encoder, &texture.texture, &buffer, [res_x, res_y]);
+        queue.submit([encoder.finish()]);
+        values.push(crop_padding(&read_buffer(device, &buffer).await?, res_x, size));
+    }
+    let checkpoint = Checkpoint {
+        resolution: size,
+        iterations,
+        textures: values.try_into().unwrap()
+    };
+    if let Err(e) = output::write_checkpoint(path, &checkpoint) {
+        return Err(RenderError::CheckpointWrite(format!("{:?}: {}", path, e)));
+    }
+    info!("Checkpoint of {} iterations written to {:?}", iterations, path);
+    return Ok(());
+}
 
-    /* -------------- Rendering --------------- */
+/// Renders data.iterations more iterations into the accumulation and reads
+/// back the result. A new accumulation starts from scratch, while passing
+/// the one from a previous render adds the new samples to the previous ones,
+/// which must have been rendered with the same data. Tiled images can not be
+/// continued, as the accumulation only holds a single tile, so every tile
+/// starts from scratch.
+pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderData, camera_matrix: &Matrix4f,
+    accumulation: &mut Accumulation, output: &mut RenderOutput, timings: &mut Timings) -> Result<(), RenderError> {
+    /* -------------- Global Textures --------------- */
+    let tile_size = tile_resolution(data);
+    let [res_x, res_y] = padded_resolution(tile_size);
+    let render_pass_textures = &accumulation.textures;
+    let shader_dir = data.shader_dir.as_deref();
+    debug!("Tile size: {:?}, padded to {:?} for readback", tile_size, [res_x, res_y]);
+
+    let reset = accumulation.iterations == 0 || tile_size != data.output_resolution;
+    let first_iteration = if reset { 0 } else { accumulation.iterations };
+
+    /* -------------- Global Uniforms --------------- */
 
-    let mut encoder = device.create_command_encoder(
-        &wgpu::CommandEncoderDescriptor {
-            label: Some("MCMRendererCommandEncoder"),
+    // These are rewritten for every tile. The resolution is the real size of
+    // the tile, while the textures may be wider because of row padding.
+    let mvp_inverse_buffer = create_matrix_uniform_buffer(device, camera_matrix, "MVPInverseBuffer");
+    let resolution_buffer = create_vector2_u32_uniform_buffer(device, &tile_size, "ResolutionBuffer");
+    let inverse_resolution_buffer = create_vector2_f32_uniform_buffer(device, &[1.0, 1.0], "InvResBuffer");
+
+    /* -------------- Global Bind Groups --------------- */
+
+    // The main pass and the reset pass share this layout, although the
+    // reset pass only reads the camera and the resolution
+    let uniforms_bind_group_layout = create_uniforms_bind_group_layout(device, "UniformsGroupLayout", 9);
+
+    let extinction_buffer = create_f32_uniform_buffer(device, data.extinction, "ExtinctionBuffer");
+    let anisotropy_buffer = create_f32_uniform_buffer(device, data.anisotropy, "AnisotropyBuffer");
+    // Laid out as a WGSL struct of two vec3 and a vec4, each aligned to 16 bytes.
+    // Without a clip plane, every position is on its kept side.
+    let clip_plane = match data.clip_plane {
+        Some(p) if data.invert_clip_plane => [-p[0], -p[1], -p[2], -p[3]],
+        Some(p) => p,
+        None => [0.0, 0.0, 0.0, -1.0]
+    };
+    let clipping = [
+        data.clip_min[0], data.clip_min[1], data.clip_min[2], 0.0,
+        data.clip_max[0], data.clip_max[1], data.clip_max[2], 0.0,
+        clip_plane[0], clip_plane[1], clip_plane[2], clip_plane[3]
+    ];
+    let clipping_buffer = device.create_buffer_init(
+        &wgpu::util::BufferInitDescriptor {
+            label: Some("ClippingBuffer"),
+            contents: bytemuck::cast_slice(&clipping),
+            usage: wgpu::BufferUsages::UNIFORM
         }
     );
 
-    reset(device, &render_pass_textures, &global_uniforms_bind_group_layout, &global_uniforms_bind_group, &mut encoder);
+    // Laid out as a WGSL struct of four vec3, each followed by a 4 byte value,
+    // and two last 4 byte values padded to 16 bytes. The point light is converted to texture coordinates.
+    let light_direction = data.light_direction;
+    let length = (light_direction[0] * light_direction[0] + light_direction[1] * light_direction[1] + light_direction[2] * light_direction[2]).sqrt();
+    let scale = volume_scale(data);
+    let (light_position, light_intensity) = match data.light_position {
+        Some(p) => ([0, 1, 2].map(|i| p[i] / scale[i] + data.volume_origin[i]), data.light_intensity),
+        None => ([0.0; 3], 0.0)
+    };
+    let mut shading: [u32; 20] = [0; 20];
+    for i in 0..3 {
+        shading[i] = (light_direction[i] / length).to_bits();
+        shading[4 + i] = light_position[i].to_bits();
+        shading[8 + i] = scale[i].to_bits();
+        shading[12 + i] = data.phase_parameters[i].to_bits();
+    }
+    shading[3] = data.gradient_shading as u32;
+    shading[7] = light_intensity.to_bits();
+    shading[11] = match data.phase_function {
+        PhaseFunction::HenyeyGreenstein => 0,
+        PhaseFunction::DoubleHenyeyGreenstein => 1,
+        PhaseFunction::Rayleigh => 2
+    };
+    shading[15] = data.next_event_estimation as u32;
+    shading[16] = data.russian_roulette_start.unwrap_or(u32::MAX);
+    shading[17] = data.max_sample_radiance.unwrap_or(f32::MAX).to_bits();
+    shading[18] = data.tricubic as u32;
+    let shading_buffer = device.create_buffer_init(
+        &wgpu::util::BufferInitDescriptor {
+            label: Some("ShadingBuffer"),
+            contents: bytemuck::cast_slice(&shading),
+            usage: wgpu::BufferUsages::UNIFORM
+        }
+    );
 
-    let extinction_buffer = create_f32_uniform_buffer(&device, data.extinction, "ExtinctionBuffer");
-    let anisotropy_buffer = create_f32_uniform_buffer(&device, data.anisotropy, "AnisotropyBuffer");
-    let max_bounces_buffer = create_u32_uniform_buffer(&device, data.max_bounces, "MaxBouncesBuffer");
-    let steps_buffer = create_u32_uniform_buffer(&device, data.steps, "StepsBuffer");
+    let upload_timer = Instant::now();
 
-    let tf_texture = device.create_texture(
-        &wgpu::TextureDescriptor {
-            label: Some("TFTexture"),
-            size: wgpu::Extent3d {
+    // sRGB colors are decoded to linear light by the sampler, the tone
+    // mapper then applies the only output encoding (its gamma)
+    let tf_format = match data.tf_colorspace {
+        ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+        ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm
+    };
+
+    let create_tf_texture = |label: &str, tf: &[u8]| {
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: data.transfer_function_len,
+                    height: 1,
+                    depth_or_array_layers: 1
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: tf_format,
+                usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+            }
+        );
+        queue.write_texture(
+            wgpu::ImageCopyTextureBase {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All
+            },
+            tf,
+            packed_data_layout(data.transfer_function_len * 4, 1),
+            wgpu::Extent3d {
                 width: data.transfer_function_len,
                 height: 1,
                 depth_or_array_layers: 1
+            }
+        );
+        return texture;
+    };
+    let tf_texture = create_tf_texture("TFTexture", &data.transfer_function);
+    // Without a second transfer function, the blend has no weight and the
+    // first one is bound in its place
+    let blend_tf_texture = create_tf_texture("BlendTFTexture", data.blend_transfer_function.as_ref().unwrap_or(&data.transfer_function));
+    let tf_blend = if data.blend_transfer_function.is_some() { data.tf_blend } else { 0.0 };
+
+    let volume_format = match data.volume_format {
+        VolumeFormat::R8 => wgpu::TextureFormat::R8Unorm,
+        VolumeFormat::Rgba8 => wgpu::TextureFormat::Rgba8Unorm
+    };
+    let volume_texture = device.create_texture(
+        &wgpu::TextureDescriptor {
+            label: Some("VolumeTexture"),
+            size: wgpu::Extent3d {
+                width: data.volume_dims[0],
+                height: data.volume_dims[1],
+                depth_or_array_layers: data.volume_dims[2]
             },
             mip_level_count: 1,
             sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            dimension: wgpu::TextureDimension::D3,
+            format: volume_format,
             usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
         }
     );
 
-    let volume_texture = device.create_texture(
+    // The volume is only ever uploaded directly, so its rows stay tightly
+    // packed for any width rather than padded to 256 bytes like the render targets
+    queue.write_texture(
+        wgpu::ImageCopyTextureBase {
+            texture: &volume_texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All
+        },
+        &data.volume,
+        packed_data_layout(data.volume_dims[0] * data.volume_format.bytes_per_voxel(), data.volume_dims[1]),
+        wgpu::Extent3d {
+            width: data.volume_dims[0],
+            height: data.volume_dims[1],
+            depth_or_array_layers: data.volume_dims[2]
+        }
+    );
+
+    // Without a label volume, a single voxel with label 0 makes every voxel visible
+    let (labels, label_dims) = match &data.labels {
+        Some(labels) => (labels.as_slice(), data.volume_dims),
+        None => (&[0u8][..], [1, 1, 1])
+    };
+    let label_texture = device.create_texture(
         &wgpu::TextureDescriptor {
-            label: Some("VolumeTexture"),
+            label: Some("LabelTexture"),
             size: wgpu::Extent3d {
-                width: data.volume_dims[0],
-                height: data.volume_dims[1],
-                depth_or_array_layers: data.volume_dims[2]
+                width: label_dims[0],
+                height: label_dims[1],
+                depth_or_array_layers: label_dims[2]
             },
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D3,
-            format: wgpu::TextureFormat::R8Unorm,
+            format: wgpu::TextureFormat::R8Uint,
             usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
         }
     );
 
     queue.write_texture(
         wgpu::ImageCopyTextureBase {
-            texture: &tf_texture,
+            texture: &label_texture,
             mip_level: 0,
             origin: wgpu::Origin3d::ZERO,
             aspect: wgpu::TextureAspect::All
         },
-        &data.transfer_function,
-        wgpu::ImageDataLayout {
-            offset: 0,
-            bytes_per_row: std::num::NonZeroU32::new(data.transfer_function_len * 4),
-            rows_per_image: std::num::NonZeroU32::new(1)
+        labels,
+        packed_data_layout(label_dims[0], label_dims[1]),
+        wgpu::Extent3d {
+            width: label_dims[0],
+            height: label_dims[1],
+            depth_or_array_layers: label_dims[2]
+        }
+    );
+
+    // Without an environment map, a single white texel is the constant environment.
+    // The unused alpha channel holds the distribution of the columns for
+    // next event estimation, the distribution of the rows has its own texture.
+    let (envmap, envmap_marginal, envmap_dims) = match &data.envmap {
+        Some(envmap) => {
+            let (conditional, marginal) = envmap.sampling_distribution();
+            let mut pixels = envmap.pixels.clone();
+            for (pixel, cdf) in pixels.chunks_exact_mut(4).zip(conditional) {
+                pixel[3] = cdf;
+            }
+            (pixels, marginal, [envmap.width, envmap.height])
         },
+        None => (vec![1.0; 4], vec![1.0], [1, 1])
+    };
+    let envmap_texture = device.create_texture(
+        &wgpu::TextureDescriptor {
+            label: Some("EnvironmentMapTexture"),
+            size: wgpu::Extent3d {
+                width: envmap_dims[0],
+                height: envmap_dims[1],
+                depth_or_array_layers: 1
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+        }
+    );
+
+    queue.write_texture(
+        wgpu::ImageCopyTextureBase {
+            texture: &envmap_texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All
+        },
+        bytemuck::cast_slice(&envmap),
+        packed_data_layout(envmap_dims[0] * 16, envmap_dims[1]),
         wgpu::Extent3d {
-            width: data.transfer_function_len,
-            height: 1,
+            width: envmap_dims[0],
+            height: envmap_dims[1],
             depth_or_array_layers: 1
         }
     );
 
+    let envmap_marginal_texture = device.create_texture(
+        &wgpu::TextureDescriptor {
+            label: Some("EnvironmentMapMarginalTexture"),
+            size: wgpu::Extent3d {
+                width: envmap_dims[1],
+                height: 1,
+                depth_or_array_layers: 1
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+        }
+    );
+
     queue.write_texture(
         wgpu::ImageCopyTextureBase {
-            texture: &volume_texture,
+            texture: &envmap_marginal_texture,
             mip_level: 0,
             origin: wgpu::Origin3d::ZERO,
             aspect: wgpu::TextureAspect::All
         },
-        &data.volume,
-        wgpu::ImageDataLayout {
-            offset: 0,
-            bytes_per_row: std::num::NonZeroU32::new(data.volume_dims[0]),
-            rows_per_image: std::num::NonZeroU32::new(data.volume_dims[1])
-        },
+        bytemuck::cast_slice(&envmap_marginal),
+        packed_data_layout(envmap_dims[1] * 4, 1),
         wgpu::Extent3d {
-            width: data.volume_dims[0],
-            height: data.volume_dims[1],
-            depth_or_array_layers: data.volume_dims[2]
+            width: envmap_dims[1],
+            height: 1,
+            depth_or_array_layers: 1
         }
     );
 
+    if data.benchmark {
+        queue.submit([]);
+        device.poll(wgpu::Maintain::Wait);
+    }
+    timings.upload = upload_timer.elapsed();
+    let setup_timer = Instant::now();
+
     let volume_view = volume_texture.create_view(&wgpu::TextureViewDescriptor::default());
-    let volume_sampler = create_texture_sampler(&device, "VolumeSampler", data.linear);
+    // The tricubic lookups are built from linear ones
+    let volume_sampler = create_texture_sampler(device, "VolumeSampler", data.linear || data.tricubic, data.address_mode);
 
     let tf_view = tf_texture.create_view(&wgpu::TextureViewDescriptor::default());
-    let tf_sampler = create_texture_sampler(&device, "TFSampler", false);
+    let blend_tf_view = blend_tf_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let tf_blend_buffer = device.create_buffer_init(
+        &wgpu::util::BufferInitDescriptor {
+            label: Some("TFBlendBuffer"),
+            contents: bytemuck::cast_slice(&[tf_blend]),
+            usage: wgpu::BufferUsages::UNIFORM
+        }
+    );
+    let tf_sampler = create_texture_sampler(device, "TFSampler", data.linear_tf, AddressMode::Clamp);
+
+    // Integer textures can not be filtered, so labels are loaded without a sampler
+    let label_view = label_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let label_mask_buffer = device.create_buffer_init(
+        &wgpu::util::BufferInitDescriptor {
+            label: Some("LabelMaskBuffer"),
+            contents: bytemuck::cast_slice(&label_mask(&data.show_labels)),
+            usage: wgpu::BufferUsages::UNIFORM
+        }
+    );
+    // 32-bit float textures are not filterable on all devices, so the
+    // environment map is also loaded without a sampler
+    let envmap_view = envmap_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let envmap_marginal_view = envmap_marginal_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-    let uniforms_bind_group_layout = device.create_bind_group_layout(
+    let textures_bind_group_layout = device.create_bind_group_layout(
         &wgpu::BindGroupLayoutDescriptor {
-            label: Some("UniformsGroupLayout"),
+            label: Some("TexturesGroupLayout"),
             entries: &[
                 wgpu::BindGroupLayoutEntry {
-                    binding: 0,
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float {
+                            filterable: true
+                        },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(
+                        wgpu::SamplerBindingType::Filtering
+                    ),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float {
+                            filterable: true
+                        },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
                     visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None
-                    },
+                    ty: wgpu::BindingType::Sampler(
+                        wgpu::SamplerBindingType::Filtering
+                    ),
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    binding: 1,
+                    binding: 4,
                     visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false
                     },
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    binding: 2,
+                    binding: 5,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
@@ -547,37 +1281,43 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    binding: 3,
+                    binding: 6,
                     visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float {
+                            filterable: false
+                        },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false
                     },
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    binding: 4,
+                    binding: 7,
                     visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float {
+                            filterable: false
+                        },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false
                     },
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    binding: 5,
+                    binding: 8,
                     visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float {
+                            filterable: true
+                        },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false
                     },
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    binding: 6,
+                    binding: 9,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
@@ -585,12 +1325,12 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                         min_binding_size: None
                     },
                     count: None,
-                }
+                },
             ]
         }
     );
 
-    let textures_bind_group_layout = device.create_bind_group_layout(
+    let old_data_bind_group_layout = device.create_bind_group_layout(
         &wgpu::BindGroupLayoutDescriptor {
             label: Some("TexturesGroupLayout"),
             entries: &[
@@ -599,9 +1339,9 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Texture {
                         sample_type: wgpu::TextureSampleType::Float {
-                            filterable: true
+                            filterable: false
                         },
-                        view_dimension: wgpu::TextureViewDimension::D3,
+                        view_dimension: wgpu::TextureViewDimension::D2,
                         multisampled: false
                     },
                     count: None,
@@ -610,7 +1350,7 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                     binding: 1,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Sampler(
-                        wgpu::SamplerBindingType::Filtering
+                        wgpu::SamplerBindingType::NonFiltering
                     ),
                     count: None,
                 },
@@ -619,7 +1359,7 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Texture {
                         sample_type: wgpu::TextureSampleType::Float {
-                            filterable: true
+                            filterable: false
                         },
                         view_dimension: wgpu::TextureViewDimension::D2,
                         multisampled: false
@@ -630,20 +1370,12 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                     binding: 3,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Sampler(
-                        wgpu::SamplerBindingType::Filtering
+                        wgpu::SamplerBindingType::NonFiltering
                     ),
                     count: None,
                 },
-            ]
-        }
-    );
-
-    let old_data_bind_group_layout = device.create_bind_group_layout(
-        &wgpu::BindGroupLayoutDescriptor {
-            label: Some("TexturesGroupLayout"),
-            entries: &[
                 wgpu::BindGroupLayoutEntry {
-                    binding: 0,
+                    binding: 4,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Texture {
                         sample_type: wgpu::TextureSampleType::Float {
@@ -655,7 +1387,7 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    binding: 1,
+                    binding: 5,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Sampler(
                         wgpu::SamplerBindingType::NonFiltering
@@ -663,7 +1395,7 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    binding: 2,
+                    binding: 6,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Texture {
                         sample_type: wgpu::TextureSampleType::Float {
@@ -675,7 +1407,7 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    binding: 3,
+                    binding: 7,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Sampler(
                         wgpu::SamplerBindingType::NonFiltering
@@ -683,7 +1415,7 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    binding: 4,
+                    binding: 8,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Texture {
                         sample_type: wgpu::TextureSampleType::Float {
@@ -695,7 +1427,7 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    binding: 5,
+                    binding: 9,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Sampler(
                         wgpu::SamplerBindingType::NonFiltering
@@ -703,7 +1435,7 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    binding: 6,
+                    binding: 10,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Texture {
                         sample_type: wgpu::TextureSampleType::Float {
@@ -715,7 +1447,27 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    binding: 7,
+                    binding: 11,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(
+                        wgpu::SamplerBindingType::NonFiltering
+                    ),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 12,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float {
+                            filterable: false
+                        },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 13,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Sampler(
                         wgpu::SamplerBindingType::NonFiltering
@@ -735,8 +1487,8 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None
+                        has_dynamic_offset: true,
+                        min_binding_size: NonZeroU64::new(4)
                     },
                     count: None,
                 }
@@ -744,74 +1496,95 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
         }
     );
 
-    let uniforms_bind_group = device.create_bind_group(
+    // The reset pass has a bind group of its own, as the scheduled ones
+    // below are empty without iterations
+    let reset_uniforms_bind_group = create_uniforms_bind_group(
+        device, &uniforms_bind_group_layout,
+        &[&mvp_inverse_buffer, &resolution_buffer, &inverse_resolution_buffer, &extinction_buffer,
+          &anisotropy_buffer, &create_u32_uniform_buffer(device, data.max_bounces, "MaxBouncesBuffer"),
+          &create_u32_uniform_buffer(device, pipeline::steps(data), "StepsBuffer"), &clipping_buffer, &shading_buffer]
+    );
+
+    // Every run of iterations with the same steps and bounces gets its own
+    // uniforms bind group, as buffer writes only take effect between submits
+    let mut uniforms_bind_groups = Vec::new();
+    let mut iteration_uniforms = Vec::new();
+    let mut scheduled_uniforms = None;
+    for i in 0..data.iterations {
+        let steps = schedule_value(&data.steps_schedule, pipeline::steps(data), i, data.iterations);
+        let max_bounces = schedule_value(&data.bounces_schedule, data.max_bounces, i, data.iterations);
+        if scheduled_uniforms != Some((steps, max_bounces)) {
+            scheduled_uniforms = Some((steps, max_bounces));
+            debug!("Iterations from {}: {} steps, {} bounces", i, steps, max_bounces);
+            let max_bounces_buffer = create_u32_uniform_buffer(device, max_bounces, "MaxBouncesBuffer");
+            let steps_buffer = create_u32_uniform_buffer(device, steps, "StepsBuffer");
+            uniforms_bind_groups.push(create_uniforms_bind_group(
+                device, &uniforms_bind_group_layout,
+                &[&mvp_inverse_buffer, &resolution_buffer, &inverse_resolution_buffer, &extinction_buffer,
+                  &anisotropy_buffer, &max_bounces_buffer, &steps_buffer, &clipping_buffer, &shading_buffer]
+            ));
+        }
+        iteration_uniforms.push(uniforms_bind_groups.len() - 1);
+    }
+
+    let textures_bind_group = device.create_bind_group(
         &wgpu::BindGroupDescriptor {
-            label: Some("UniformsGroup"),
-            layout: &uniforms_bind_group_layout,
+            label: Some("TexturesGroup"),
+            layout: &textures_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: mvp_inverse_buffer.as_entire_binding()
+                    resource: wgpu::BindingResource::TextureView(&volume_view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: resolution_buffer.as_entire_binding()
+                    resource: wgpu::BindingResource::Sampler(&volume_sampler)
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: inverse_resolution_buffer.as_entire_binding()
+                    resource: wgpu::BindingResource::TextureView(&tf_view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 3,
-                    resource: extinction_buffer.as_entire_binding()
+                    resource: wgpu::BindingResource::Sampler(&tf_sampler)
                 },
                 wgpu::BindGroupEntry {
                     binding: 4,
-                    resource: anisotropy_buffer.as_entire_binding()
+                    resource: wgpu::BindingResource::TextureView(&label_view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 5,
-                    resource: max_bounces_buffer.as_entire_binding()
+                    resource: label_mask_buffer.as_entire_binding()
                 },
                 wgpu::BindGroupEntry {
                     binding: 6,
-                    resource: steps_buffer.as_entire_binding()
-                },
-            ]
-        }
-    );
-
-    let textures_bind_group = device.create_bind_group(
-        &wgpu::BindGroupDescriptor {
-            label: Some("TexturesGroup"),
-            layout: &textures_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&volume_view),
+                    resource: wgpu::BindingResource::TextureView(&envmap_view),
                 },
                 wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&volume_sampler)
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&envmap_marginal_view),
                 },
                 wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&tf_view),
+                    binding: 8,
+                    resource: wgpu::BindingResource::TextureView(&blend_tf_view),
                 },
                 wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&tf_sampler)
+                    binding: 9,
+                    resource: tf_blend_buffer.as_entire_binding()
                 }
             ]
         }
     );
 
-    let vertex_shader = device.create_shader_module(
-        include_wgsl!("shaders/mcm_main_vertex.wgsl")
-    );
-    let fragment_shader = device.create_shader_module(
-        include_wgsl!("shaders/mcm_main_fragment.wgsl")
-    );
+    let vertex_shader = create_shader_module(device, shader_dir, "mcm_main_vertex.wgsl", include_str!("shaders/mcm_main_vertex.wgsl")).await?;
+    let volume_shader_source = match data.volume_format {
+        VolumeFormat::R8 => shader_source(shader_dir, "mcm_volume_r8.wgsl", include_str!("shaders/mcm_volume_r8.wgsl")),
+        VolumeFormat::Rgba8 => shader_source(shader_dir, "mcm_volume_rgba8.wgsl", include_str!("shaders/mcm_volume_rgba8.wgsl"))
+    };
+    let fragment_shader_source = shader_source(shader_dir, "mcm_main_fragment.wgsl", include_str!("shaders/mcm_main_fragment.wgsl"));
+    let fragment_shader = compile_shader(
+        device, "mcm_main_fragment.wgsl", fragment_shader_source.replacen("// #volume_sampling", &volume_shader_source, 1)
+    ).await?;
 
     let render_pipeline_layout = device.create_pipeline_layout(
         &wgpu::PipelineLayoutDescriptor {
@@ -826,6 +1599,7 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
         }
     );
 
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
     let render_pipeline = device.create_render_pipeline(
         &wgpu::RenderPipelineDescriptor {
             label: Some("RenderPipeline"),
@@ -856,6 +1630,21 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                         blend: None,
                         write_mask: wgpu::ColorWrites::ALL
                     }),
+                    Some(wgpu::ColorTargetState {
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL
+                    }),
+                    Some(wgpu::ColorTargetState {
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL
+                    }),
+                    Some(wgpu::ColorTargetState {
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL
+                    }),
                     Some(wgpu::ColorTargetState {
                         format: wgpu::TextureFormat::Rgba32Float,
                         blend: None,
@@ -879,6 +1668,7 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
             },
         }
     );
+    pop_validation_error(device, "RenderPipeline").await?;
 
     let old_data_bind_group1 = device.create_bind_group(
         &wgpu::BindGroupDescriptor {
@@ -916,6 +1706,30 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                 wgpu::BindGroupEntry {
                     binding: 7,
                     resource: wgpu::BindingResource::Sampler(&render_pass_textures.radiance_bounces[0].sampler)
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::TextureView(&render_pass_textures.depth_hits[0].view)
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::Sampler(&render_pass_textures.depth_hits[0].sampler)
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: wgpu::BindingResource::TextureView(&render_pass_textures.albedo[0].view)
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: wgpu::BindingResource::Sampler(&render_pass_textures.albedo[0].sampler)
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: wgpu::BindingResource::TextureView(&render_pass_textures.normal[0].view)
+                },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: wgpu::BindingResource::Sampler(&render_pass_textures.normal[0].sampler)
                 }
             ]
         }
@@ -957,11 +1771,39 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                 wgpu::BindGroupEntry {
                     binding: 7,
                     resource: wgpu::BindingResource::Sampler(&render_pass_textures.radiance_bounces[1].sampler)
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::TextureView(&render_pass_textures.depth_hits[1].view)
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::Sampler(&render_pass_textures.depth_hits[1].sampler)
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: wgpu::BindingResource::TextureView(&render_pass_textures.albedo[1].view)
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: wgpu::BindingResource::Sampler(&render_pass_textures.albedo[1].sampler)
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: wgpu::BindingResource::TextureView(&render_pass_textures.normal[1].view)
+                },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: wgpu::BindingResource::Sampler(&render_pass_textures.normal[1].sampler)
                 }
             ]
         }
     );
 
+    // The targets of an iteration can be cleared, as the fragment shader
+    // writes every texel: it reads the photon state and the running mean of
+    // the radiance from the other texture of each pair and adds the new
+    // sample to that mean, so the radiance is averaged over all iterations
     let render_pass_descriptor1 = wgpu::RenderPassDescriptor {
         label: Some("RenderPassDesc"),
         color_attachments: &[
@@ -1005,15 +1847,75 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                     store: true
                 }
             }),
-        ],
-        depth_stencil_attachment: None,
-    };
-
-    let render_pass_descriptor2 = wgpu::RenderPassDescriptor {
-        label: Some("RenderPassDesc"),
-        color_attachments: &[
             Some(wgpu::RenderPassColorAttachment {
-                view: &render_pass_textures.position[0].view,
+                view: &render_pass_textures.depth_hits[1].view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(
+                        wgpu::Color::TRANSPARENT
+                    ),
+                    store: true
+                }
+            }),
+            Some(wgpu::RenderPassColorAttachment {
+                view: &render_pass_textures.albedo[1].view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(
+                        wgpu::Color::TRANSPARENT
+                    ),
+                    store: true
+                }
+            }),
+            Some(wgpu::RenderPassColorAttachment {
+                view: &render_pass_textures.normal[1].view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(
+                        wgpu::Color::TRANSPARENT
+                    ),
+                    store: true
+                }
+            }),
+        ],
+        depth_stencil_attachment: None,
+    };
+
+    let render_pass_descriptor2 = wgpu::RenderPassDescriptor {
+        label: Some("RenderPassDesc"),
+        color_attachments: &[
+            Some(wgpu::RenderPassColorAttachment {
+                view: &render_pass_textures.position[0].view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(
+                        wgpu::Color::TRANSPARENT
+                    ),
+                    store: true
+                }
+            }),
+            Some(wgpu::RenderPassColorAttachment {
+                view: &render_pass_textures.direction[0].view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(
+                        wgpu::Color::TRANSPARENT
+                    ),
+                    store: true
+                }
+            }),
+            Some(wgpu::RenderPassColorAttachment {
+                view: &render_pass_textures.transmittance_sampes[0].view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(
+                        wgpu::Color::TRANSPARENT
+                    ),
+                    store: true
+                }
+            }),
+            Some(wgpu::RenderPassColorAttachment {
+                view: &render_pass_textures.radiance_bounces[0].view,
                 resolve_target: None,
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Clear(
@@ -1023,7 +1925,7 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                 }
             }),
             Some(wgpu::RenderPassColorAttachment {
-                view: &render_pass_textures.direction[0].view,
+                view: &render_pass_textures.depth_hits[0].view,
                 resolve_target: None,
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Clear(
@@ -1033,7 +1935,7 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                 }
             }),
             Some(wgpu::RenderPassColorAttachment {
-                view: &render_pass_textures.transmittance_sampes[0].view,
+                view: &render_pass_textures.albedo[0].view,
                 resolve_target: None,
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Clear(
@@ -1043,7 +1945,7 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                 }
             }),
             Some(wgpu::RenderPassColorAttachment {
-                view: &render_pass_textures.radiance_bounces[0].view,
+                view: &render_pass_textures.normal[0].view,
                 resolve_target: None,
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Clear(
@@ -1056,54 +1958,63 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
         depth_stencil_attachment: None,
     };
 
-    for i in 0..data.iterations {
-        let random_seed = rand::random::<f32>();
-        let random_seed_buffer = create_f32_uniform_buffer(&device, random_seed, "RandSeedBuffer");
-        let random_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                label: Some("RandomBindGroup"),
-                layout: &random_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: random_seed_buffer.as_entire_binding()
-                    }
-                ]
-            }
-        );
-
-        {
-            let mut render_pass = if i % 2 == 0 {
-                encoder.begin_render_pass(&render_pass_descriptor1)
-            } else {
-                encoder.begin_render_pass(&render_pass_descriptor2)
-            };
-            
-            render_pass.set_pipeline(&render_pipeline);
-            render_pass.set_bind_group(0, &random_bind_group, &[]);
-            render_pass.set_bind_group(1, &uniforms_bind_group, &[]);
-            render_pass.set_bind_group(2, &textures_bind_group, &[]);
-            if i % 2 == 0 {
-                render_pass.set_bind_group(3, &old_data_bind_group1, &[]);
-            } else {
-                render_pass.set_bind_group(3, &old_data_bind_group2, &[]);
-            }
-            render_pass.draw(0..4, 0..1);
-        }
-    }
-
-    let f32_size = std::mem::size_of::<f32>() as u32;
-    let result_buffer_size = (f32_size * 4 * res_x * res_y) as u64;
     let result_buffer = device.create_buffer(
         &wgpu::BufferDescriptor {
             label: Some("ResultBuffer"),
-            size: result_buffer_size,
+            size: result_buffer_size(tile_size),
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
             mapped_at_creation: false,
         }
     );
+    debug!("Result buffer size: {} bytes", result_buffer_size(tile_size));
 
-    let result_index = (data.iterations % 2) as usize;
+    let create_aov_buffer = |enabled: bool, label: &str| {
+        if !enabled {
+            return None;
+        }
+        return Some(device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some(label),
+                size: result_buffer_size(tile_size),
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }
+        ));
+    };
+    let depth_buffer = create_aov_buffer(data.depth, "DepthBuffer");
+    let radiance_buffer = create_aov_buffer(data.radiance, "RadianceBuffer");
+    let albedo_buffer = create_aov_buffer(data.albedo, "AlbedoBuffer");
+    let normal_buffer = create_aov_buffer(data.normal, "NormalBuffer");
+    let variance_buffer = create_aov_buffer(data.noise_threshold.is_some(), "VarianceBuffer");
+    let gbuffer_buffers = ["PositionBuffer", "DirectionBuffer", "TransmittanceBuffer"].map(|label| create_aov_buffer(data.dump_gbuffer, label));
+
+    // Timestamps at the start and end of the iterations, followed by those
+    // of the reset pass, resolved and read back after every tile
+    let timestamps = if !data.gpu_timing {
+        None
+    } else if device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+        let query_set = device.create_query_set(
+            &wgpu::QuerySetDescriptor {
+                label: Some("TimestampQuerySet"),
+                ty: wgpu::QueryType::Timestamp,
+                count: 4
+            }
+        );
+        let timestamp_buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("TimestampBuffer"),
+                size: 4 * std::mem::size_of::<u64>() as u64,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false
+            }
+        );
+        timings.gpu_reset = Some(Duration::ZERO);
+        timings.gpu_iterations = Some(Duration::ZERO);
+        Some((query_set, timestamp_buffer))
+    } else {
+        warn!("The adapter does not support timestamp queries, so --gpu-timing has no effect");
+        None
+    };
 
     /* -------------- Tone Mapping --------------- */
 
@@ -1113,14 +2024,25 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
     let saturation = data.saturation;
     let gamma = data.gamma;
 
-    let low_tone_buffer = create_f32_uniform_buffer(&device, low_tone, "LowLevelBuffer");
-    let mid_tone_buffer = create_f32_uniform_buffer(&device, mid_tone, "MidLevelBuffer");
-    let high_tone_buffer = create_f32_uniform_buffer(&device, high_tone, "HighLevelBuffer");
-    let saturation_buffer = create_f32_uniform_buffer(&device, saturation, "SaturationBuffer");
-    let gamma_buffer = create_f32_uniform_buffer(&device, gamma, "GammaBuffer");
+    let low_tone_buffer = create_f32_uniform_buffer(device, low_tone, "LowLevelBuffer");
+    let mid_tone_buffer = create_f32_uniform_buffer(device, mid_tone, "MidLevelBuffer");
+    let high_tone_buffer = create_f32_uniform_buffer(device, high_tone, "HighLevelBuffer");
+    let saturation_buffer = create_f32_uniform_buffer(device, saturation, "SaturationBuffer");
+    let gamma_buffer = create_f32_uniform_buffer(device, gamma, "GammaBuffer");
+    let operator = match data.tonemap {
+        ToneMapOperator::Linear => 0,
+        ToneMapOperator::Reinhard => 1,
+        ToneMapOperator::Aces => 2
+    };
+    let operator_buffer = create_u32_uniform_buffer(device, operator, "OperatorBuffer");
+    // Without an output color space, --gamma encodes the image
+    let output_colorspace = match data.output_colorspace {
+        None => 0,
+        Some(ColorSpace::Srgb) => 1,
+        Some(ColorSpace::Linear) => 2
+    };
+    let output_colorspace_buffer = create_u32_uniform_buffer(device, output_colorspace, "OutputColorSpaceBuffer");
 
-    let input_texture = &render_pass_textures.radiance_bounces[result_index];
-    let result_texture = &render_pass_textures.radiance_bounces[(result_index + 1) % 2];
 
     let bind_group_layout = device.create_bind_group_layout(
         &wgpu::BindGroupLayoutDescriptor {
@@ -1195,56 +2117,91 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
                         min_binding_size: None
                     },
                     count: None,
-                }
-            ]
-        }
-    );
-
-    let bind_group = device.create_bind_group(
-        &wgpu::BindGroupDescriptor {
-            label: Some("ToneMapperBindGroup"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&input_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&input_texture.sampler)
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: low_tone_buffer.as_entire_binding()
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: mid_tone_buffer.as_entire_binding()
-                },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: high_tone_buffer.as_entire_binding()
-                },
-                wgpu::BindGroupEntry {
-                    binding: 5,
-                    resource: saturation_buffer.as_entire_binding()
                 },
-                wgpu::BindGroupEntry {
-                    binding: 6,
-                    resource: gamma_buffer.as_entire_binding()
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None,
+                }
             ]
         }
     );
 
-    let tm_vertex_shader = device.create_shader_module(
-        include_wgsl!("shaders/tm_artistic_vertex.wgsl")
-    );
-    let tm_fragment_shader = device.create_shader_module(
-        include_wgsl!("shaders/tm_artistic_fragment.wgsl")
-    );
+    // The iterations end in either texture of the pairs, as rendering can
+    // stop early, so there is a bind group for the result in each of them.
+    // Denoising happens inside the tile loop, but always ends in the same
+    // texture as the result.
+    let bind_groups = [0, 1].map(|result_index| {
+        let input_texture = if data.denoise {
+            &render_pass_textures.direction[(result_index + 1) % 2]
+        } else {
+            &render_pass_textures.radiance_bounces[result_index]
+        };
+        return device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: Some("ToneMapperBindGroup"),
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&input_texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&input_texture.sampler)
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: low_tone_buffer.as_entire_binding()
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: mid_tone_buffer.as_entire_binding()
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: high_tone_buffer.as_entire_binding()
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: saturation_buffer.as_entire_binding()
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 6,
+                        resource: gamma_buffer.as_entire_binding()
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 7,
+                        resource: operator_buffer.as_entire_binding()
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 8,
+                        resource: output_colorspace_buffer.as_entire_binding()
+                    },
+                ]
+            }
+        );
+    });
 
-    let render_pipeline_layout = device.create_pipeline_layout(
+    let tm_vertex_shader = create_shader_module(device, shader_dir, "tm_artistic_vertex.wgsl", include_str!("shaders/tm_artistic_vertex.wgsl")).await?;
+    let tm_fragment_shader = create_shader_module(device, shader_dir, "tm_artistic_fragment.wgsl", include_str!("shaders/tm_artistic_fragment.wgsl")).await?;
+
+    let tm_render_pipeline_layout = device.create_pipeline_layout(
         &wgpu::PipelineLayoutDescriptor {
             label: Some("TMRenderPipelineLayout"),
             bind_group_layouts: &[
@@ -1254,10 +2211,11 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
         }
     );
 
-    let render_pipeline = device.create_render_pipeline(
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let tm_render_pipeline = device.create_render_pipeline(
         &wgpu::RenderPipelineDescriptor {
             label: Some("TMRenderPipeline"),
-            layout: Some(&render_pipeline_layout),
+            layout: Some(&tm_render_pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &tm_vertex_shader,
                 entry_point: "main",
@@ -1292,85 +2250,392 @@ pub async fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderDat
             multiview: None
         }
     );
+    pop_validation_error(device, "TMRenderPipeline").await?;
 
-    {
-        let mut render_pass = encoder.begin_render_pass(
-            &wgpu::RenderPassDescriptor {
-                label: Some("TMRenderPass"),
-                color_attachments: &[
-                    Some(wgpu::RenderPassColorAttachment {
-                        view: &result_texture.view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(
-                                wgpu::Color::TRANSPARENT
-                            ),
-                            store: true
-                        }
+    timings.setup = setup_timer.elapsed();
+
+    /* -------------- Rendering --------------- */
+
+    let [width, height] = data.output_resolution;
+    output.image = vec![0; (width * height * 3) as usize];
+    if data.depth {
+        output.depth = Some(vec![0.0; (width * height) as usize]);
+    }
+    if data.radiance {
+        output.radiance = Some(vec![0.0; (width * height * 3) as usize]);
+    }
+    if data.albedo {
+        output.albedo = Some(vec![0.0; (width * height * 3) as usize]);
+    }
+    if data.normal {
+        output.normal = Some(vec![0.0; (width * height * 3) as usize]);
+    }
+    if data.dump_gbuffer {
+        output.gbuffer = Some([0, 1, 2].map(|_| vec![0.0; (width * height * 3) as usize]));
+    }
+
+    // The random seeds of a batch live in one buffer, one aligned slot per
+    // iteration, so that a single bind group can select them with dynamic
+    // offsets. Rewriting a single seed between passes would not work,
+    // because buffer writes only take effect at the next submit.
+    let seed_stride = device.limits().min_uniform_buffer_offset_alignment as usize;
+    let seed_slots = data.iterations.clamp(1, data.iterations_per_submit) as usize;
+    let mut random_seeds = vec![0u32; seed_slots * seed_stride / 4];
+    let random_seed_buffer = device.create_buffer(
+        &wgpu::BufferDescriptor {
+            label: Some("RandSeedBuffer"),
+            size: (seed_slots * seed_stride) as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        }
+    );
+    let random_bind_group = device.create_bind_group(
+        &wgpu::BindGroupDescriptor {
+            label: Some("RandomBindGroup"),
+            layout: &random_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &random_seed_buffer,
+                        offset: 0,
+                        size: NonZeroU64::new(4)
                     })
-                ],
-                depth_stencil_attachment: None
+                }
+            ]
+        }
+    );
+
+    let reset_pass = if reset {
+        Some(ResetPass::new(device, &uniforms_bind_group_layout, data.reset_radiance, shader_dir).await?)
+    } else {
+        None
+    };
+
+    let mut tiles = Vec::new();
+    for tile_y in (0..height).step_by(tile_size[1] as usize) {
+        for tile_x in (0..width).step_by(tile_size[0] as usize) {
+            let size = [tile_size[0].min(width - tile_x), tile_size[1].min(height - tile_y)];
+            tiles.push(([tile_x, tile_y], size));
+        }
+    }
+
+    // Iterations of the tile that needed the most of them, and the highest
+    // noise estimate of the tiles, for the noise threshold
+    let mut most_iterations = 0;
+    let mut highest_noise: Option<f32> = None;
+    let tile_count = tiles.len();
+    let mut cancelled = None;
+
+    debug!("Rendering {} tiles", tiles.len());
+    for (tile, (origin, size)) in tiles.into_iter().enumerate() {
+        trace!("Rendering the {:?} tile at {:?}", size, origin);
+        let tile_matrix = tile_camera_matrix(camera_matrix, data.output_resolution, origin, size);
+        queue.write_buffer(&mvp_inverse_buffer, 0, bytemuck::cast_slice(&tile_matrix.m));
+        queue.write_buffer(&resolution_buffer, 0, bytemuck::cast_slice(&size));
+        queue.write_buffer(&inverse_resolution_buffer, 0, bytemuck::cast_slice(&[1.0 / size[0] as f32, 1.0 / size[1] as f32]));
+
+        let render_timer = Instant::now();
+
+        if let Some(reset_pass) = &reset_pass {
+            let mut encoder = device.create_command_encoder(
+                &wgpu::CommandEncoderDescriptor {
+                    label: Some("ResetCommandEncoder"),
+                }
+            );
+            if let Some((query_set, _)) = &timestamps {
+                encoder.write_timestamp(query_set, 2);
+            }
+            reset_pass.encode(device, render_pass_textures, &reset_uniforms_bind_group, &mut encoder);
+            if let Some((query_set, _)) = &timestamps {
+                encoder.write_timestamp(query_set, 3);
+            }
+            queue.submit([encoder.finish()]);
+        }
+
+        // Submitting in batches lets the GPU start working early and keeps
+        // the submissions short. At most two batches are in flight, so that
+        // the batches do not pile up in the queue of the driver.
+        let mut previous_submission = None;
+        let mut iterations = data.iterations;
+        let mut noise = None;
+        for batch_start in (0..data.iterations).step_by(data.iterations_per_submit as usize) {
+            if pipeline::CANCELLED.load(Ordering::SeqCst) {
+                iterations = batch_start;
+                cancelled = Some((tile, batch_start));
+                break;
+            }
+            let batch_end = (batch_start + data.iterations_per_submit).min(data.iterations);
+            for slot in 0..(batch_end - batch_start) as usize {
+                random_seeds[slot * seed_stride / 4] = rand::random::<u32>();
+            }
+            queue.write_buffer(&random_seed_buffer, 0, bytemuck::cast_slice(&random_seeds));
+
+            let mut encoder = device.create_command_encoder(
+                &wgpu::CommandEncoderDescriptor {
+                    label: Some("MCMRendererCommandEncoder"),
+                }
+            );
+            if let (Some((query_set, _)), 0) = (&timestamps, batch_start) {
+                encoder.write_timestamp(query_set, 0);
+            }
+
+            for i in batch_start..batch_end {
+                // The parity continues from the previous render
+                let parity = (first_iteration + i) % 2;
+                let mut render_pass = if parity == 0 {
+                    encoder.begin_render_pass(&render_pass_descriptor1)
+                } else {
+                    encoder.begin_render_pass(&render_pass_descriptor2)
+                };
+
+                let seed_offset = ((i - batch_start) as usize * seed_stride) as u32;
+                render_pass.set_pipeline(&render_pipeline);
+                render_pass.set_bind_group(0, &random_bind_group, &[seed_offset]);
+                render_pass.set_bind_group(1, &uniforms_bind_groups[iteration_uniforms[i as usize]], &[]);
+                render_pass.set_bind_group(2, &textures_bind_group, &[]);
+                if parity == 0 {
+                    render_pass.set_bind_group(3, &old_data_bind_group1, &[]);
+                } else {
+                    render_pass.set_bind_group(3, &old_data_bind_group2, &[]);
+                }
+                render_pass.draw(0..4, 0..1);
+                drop(render_pass);
+
+                if i == 0 && data.dump_gbuffer {
+                    let written = 1 - parity as usize;
+                    let textures = [
+                        &render_pass_textures.position[written],
+                        &render_pass_textures.direction[written],
+                        &render_pass_textures.transmittance_sampes[written]
+                    ];
+                    for (texture, buffer) in textures.iter().zip(&gbuffer_buffers) {
+                        if let Some(buffer) = buffer {
+                            copy_texture_to_buffer(&mut encoder, &texture.texture, buffer, [res_x, res_y]);
+                        }
+                    }
+                }
+            }
+
+            // The relative variance of every pixel is in the fourth channel of the direction
+            let latest_index = ((first_iteration + batch_end) % 2) as usize;
+            if let Some(variance_buffer) = &variance_buffer {
+                copy_texture_to_buffer(&mut encoder, &render_pass_textures.direction[latest_index].texture, variance_buffer, [res_x, res_y]);
+            }
+
+            // The end is written by every batch, the last one is read back
+            if let Some((query_set, _)) = &timestamps {
+                encoder.write_timestamp(query_set, 1);
+            }
+
+            let submission = queue.submit([encoder.finish()]);
+            match previous_submission.replace(submission) {
+                Some(previous) => device.poll(wgpu::Maintain::WaitForSubmissionIndex(previous)),
+                None => device.poll(wgpu::Maintain::Poll)
+            };
+
+            if let (Some(path), Some(every), true) = (&data.checkpoint, data.checkpoint_every, batch_end < data.iterations) {
+                if batch_end / every > batch_start / every {
+                    save_checkpoint(device, queue, render_pass_textures, first_iteration + batch_end, size, path).await?;
+                }
+            }
+
+            if let (Some(threshold), Some(variance_buffer)) = (data.noise_threshold, &variance_buffer) {
+                let variances = crop_padding(&read_buffer(device, variance_buffer).await?, res_x, size);
+                let sum: f64 = variances.chunks_exact(4).map(|v| v[3] as f64).sum();
+                let mean = (sum / (size[0] * size[1]) as f64) as f32;
+                trace!("Estimated noise after {} iterations: {}", batch_end, mean);
+                noise = Some(mean);
+                if mean < threshold {
+                    iterations = batch_end;
+                    break;
+                }
+            }
+        }
+        most_iterations = most_iterations.max(iterations);
+        if let Some(noise) = noise {
+            highest_noise = Some(highest_noise.map_or(noise, |n| n.max(noise)));
+        }
+
+        if data.benchmark {
+            device.poll(wgpu::Maintain::Wait);
+        }
+        timings.render += render_timer.elapsed();
+        accumulation.iterations = first_iteration + iterations;
+        let result_index = ((first_iteration + iterations) % 2) as usize;
+        let result_texture = &render_pass_textures.radiance_bounces[(result_index + 1) % 2];
+        let readback_timer = Instant::now();
+
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: Some("ToneMapperCommandEncoder"),
             }
         );
 
-        render_pass.set_pipeline(&render_pipeline);
-        render_pass.set_bind_group(0, &bind_group, &[]);
-        render_pass.draw(0..4, 0..1);
-    }
+        if data.denoise {
+            denoise(device, render_pass_textures, result_index, shader_dir, &mut encoder).await?;
+        }
+
+        {
+            let mut render_pass = encoder.begin_render_pass(
+                &wgpu::RenderPassDescriptor {
+                    label: Some("TMRenderPass"),
+                    color_attachments: &[
+                        Some(wgpu::RenderPassColorAttachment {
+                            view: &result_texture.view,
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(
+                                    wgpu::Color::TRANSPARENT
+                                ),
+                                store: true
+                            }
+                        })
+                    ],
+                    depth_stencil_attachment: None
+                }
+            );
 
-    /* -------------- Texture Copy --------------- */
+            render_pass.set_pipeline(&tm_render_pipeline);
+            render_pass.set_bind_group(0, &bind_groups[result_index], &[]);
+            render_pass.draw(0..4, 0..1);
+        }
 
-    encoder.copy_texture_to_buffer(
-        wgpu::ImageCopyTextureBase {
-            texture: &result_texture.texture,
-            mip_level: 0,
-            origin: wgpu::Origin3d::ZERO,
-            aspect: wgpu::TextureAspect::All
-        },
-        wgpu::ImageCopyBuffer {
-            buffer: &result_buffer,
-            layout: wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: NonZeroU32::new(f32_size * 4 * res_x),
-                rows_per_image: NonZeroU32::new(res_y)
-            },
-        },
-        wgpu::Extent3d {
-            width: res_x,
-            height: res_y,
-            depth_or_array_layers: 1,
+        /* -------------- Texture Copy --------------- */
+
+        copy_texture_to_buffer(&mut encoder, &result_texture.texture, &result_buffer, [res_x, res_y]);
+        if let Some(depth_buffer) = &depth_buffer {
+            copy_texture_to_buffer(&mut encoder, &render_pass_textures.depth_hits[result_index].texture, depth_buffer, [res_x, res_y]);
+        }
+        if let Some(radiance_buffer) = &radiance_buffer {
+            // The input of the tone mapper, after denoising
+            let radiance_texture = if data.denoise {
+                &render_pass_textures.direction[(result_index + 1) % 2]
+            } else {
+                &render_pass_textures.radiance_bounces[result_index]
+            };
+            copy_texture_to_buffer(&mut encoder, &radiance_texture.texture, radiance_buffer, [res_x, res_y]);
+        }
+        if let Some(albedo_buffer) = &albedo_buffer {
+            copy_texture_to_buffer(&mut encoder, &render_pass_textures.albedo[result_index].texture, albedo_buffer, [res_x, res_y]);
+        }
+        if let Some(normal_buffer) = &normal_buffer {
+            copy_texture_to_buffer(&mut encoder, &render_pass_textures.normal[result_index].texture, normal_buffer, [res_x, res_y]);
+        }
+        if let Some((query_set, timestamp_buffer)) = &timestamps {
+            encoder.resolve_query_set(query_set, 0..if reset { 4 } else { 2 }, timestamp_buffer, 0);
         }
-    );
 
-    queue.submit([encoder.finish()]);
+        queue.submit([encoder.finish()]);
 
-    {
-        let buffer_slice = result_buffer.slice(..);
-        let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
-        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
-            tx.send(result).unwrap();
-        });
-        device.poll(wgpu::Maintain::Wait);
-        rx.receive().await.unwrap().unwrap();
-        let buffer_data = buffer_slice.get_mapped_range();
+        // Index of the first pixel of each texel of the cropped tile in the image
+        let image_index = |texel: usize| -> usize {
+            let x = texel as u32 % size[0];
+            let y = texel as u32 / size[0];
+            return ((origin[1] + y) * width + origin[0] + x) as usize;
+        };
+
+        if let Some((_, timestamp_buffer)) = &timestamps {
+            let ticks = read_buffer::<u64>(device, timestamp_buffer).await?;
+            let duration = |start: u64, end: u64| {
+                return Duration::from_nanos((end.saturating_sub(start) as f64 * queue.get_timestamp_period() as f64) as u64);
+            };
+            timings.gpu_iterations = timings.gpu_iterations.map(|d| d + duration(ticks[0], ticks[1]));
+            if reset {
+                timings.gpu_reset = timings.gpu_reset.map(|d| d + duration(ticks[2], ticks[3]));
+            }
+        }
+
+        let max_value = output_max_value(data) as f32;
+        let colors = crop_padding(&read_buffer(device, &result_buffer).await?, res_x, size);
+        for (i, color) in colors.chunks_exact(4).enumerate() {
+            let output_index = image_index(i) * 3;
+            for (value, c) in output.image[output_index..output_index+3].iter_mut().zip(color) {
+                *value = (c * max_value).round() as u16;
+            }
+        }
+
+        if let (Some(depth_buffer), Some(depth)) = (&depth_buffer, &mut output.depth) {
+            let depth_hits = crop_padding(&read_buffer(device, depth_buffer).await?, res_x, size);
+            for (i, dh) in depth_hits.chunks_exact(4).enumerate() {
+                // Rays that never interacted with the volume are infinitely far away
+                depth[image_index(i)] = if dh[1] > 0.0 { dh[0] } else { f32::INFINITY };
+            }
+        }
 
-        unsafe {
-            let (_, colors, _) = buffer_data.align_to::<f32>();
-            let colors_width = res_x;
-            let real_width = data.output_resolution[0];
-            let real_hight = data.output_resolution[1];
-
-            for y in 0..real_hight {
-                for x in 0..real_width {
-                    let index = ((y * colors_width + x) * 4) as usize;
-                    let r = (colors[index] * 255.0) as u8;
-                    let g = (colors[index+1] * 255.0) as u8;
-                    let b = (colors[index+2] * 255.0) as u8;
-                    output.push(r);
-                    output.push(g);
-                    output.push(b);
+        // Every three channel image, without the fourth channel of the texture
+        let mut images = Vec::new();
+        for (buffer, image) in [(&radiance_buffer, &mut output.radiance), (&albedo_buffer, &mut output.albedo), (&normal_buffer, &mut output.normal)] {
+            if let (Some(buffer), Some(image)) = (buffer, image) {
+                images.push((buffer, image));
+            }
+        }
+        if let Some(gbuffer) = &mut output.gbuffer {
+            for (buffer, image) in gbuffer_buffers.iter().zip(gbuffer.iter_mut()) {
+                if let Some(buffer) = buffer {
+                    images.push((buffer, image));
                 }
             }
         }
+        for (buffer, image) in images {
+            let values = crop_padding(&read_buffer(device, buffer).await?, res_x, size);
+            for (i, value) in values.chunks_exact(4).enumerate() {
+                let output_index = image_index(i) * 3;
+                image[output_index..output_index+3].copy_from_slice(&value[..3]);
+            }
+        }
+
+        timings.readback += readback_timer.elapsed();
+
+        // The tiles that were not reached yet stay black
+        if cancelled.is_some() {
+            break;
+        }
+    }
+
+    // Checkpoints are only written for untiled renders
+    if let Some(path) = &data.checkpoint {
+        save_checkpoint(device, queue, render_pass_textures, accumulation.iterations, tile_size, path).await?;
+    }
+
+    if let Some((tile, completed)) = cancelled {
+        if tile_count > 1 {
+            warn!("Cancelled after {} of {} iterations of tile {} of {}", completed, data.iterations, tile + 1, tile_count);
+        } else {
+            warn!("Cancelled after {} of {} iterations", completed, data.iterations);
+        }
+    }
+
+    if let (Some(threshold), Some(noise)) = (data.noise_threshold, highest_noise) {
+        if noise < threshold {
+            info!("Reached the noise threshold after {} iterations, with an estimated noise of {:.3e}", most_iterations, noise);
+        } else {
+            warn!("Stopped after {} iterations without reaching the noise threshold, with an estimated noise of {:.3e}", most_iterations, noise);
+        }
+    }
+
+    // The distances of the depth were along the rays in texture space
+    if let Some(depth) = &mut output.depth {
+        let [width, height] = data.output_resolution;
+        for y in 0..height {
+            for x in 0..width {
+                let direction = pipeline::primary_direction(camera_matrix, data.output_resolution, [x, y]);
+                depth[(y * width + x) as usize] *= pipeline::scene_length(data, direction);
+            }
+        }
+    }
+
+    // The normals were computed in texture space, which is stretched
+    // by the voxel spacing in the scene
+    if let Some(normal) = &mut output.normal {
+        let scale = volume_scale(data);
+        for n in normal.chunks_exact_mut(3) {
+            let world = [n[0] / scale[0], n[1] / scale[1], n[2] / scale[2]];
+            let length = (world[0] * world[0] + world[1] * world[1] + world[2] * world[2]).sqrt();
+            if length > 0.0 {
+                n.copy_from_slice(&world.map(|v| v / length));
+            }
+        }
     }
-}
\ No newline at end of file
+    return Ok(());
+}
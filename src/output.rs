@@ -0,0 +1,356 @@
+use std::{fs::File, io::{BufWriter, Error, ErrorKind, Write}, path::Path};
+
+use image::{ImageBuffer, Rgb};
+
+/// Fails before anything is written when the content does not cover the
+/// whole image, so that no partial files are left behind
+fn check_length(kind: &str, width: u32, height: u32, channels: usize, length: usize) -> Result<(), Error> {
+    let expected = width as usize * height as usize * channels;
+    if length != expected {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} has {} values, but a {}x{} image with {} channels needs {}", kind, length, width, height, channels, expected)
+        ));
+    }
+    return Ok(());
+}
+
+/// Piecewise sRGB transfer function, from a linear value between 0 and 1 to
+/// the encoded one
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        return value * 12.92;
+    }
+    return 1.055 * value.powf(1.0 / 2.4) - 0.055;
+}
+
+/// Writes RGB values between 0 and max_value as a PNG image when the
+/// filename ends with .png, with 16 bits per channel for a max_value above
+/// 255, or as a plain PPM (P3) image otherwise
+pub fn write_output(filename: &str, width: u32, height: u32, max_value: u16, content: Vec<u16>) -> Result<(), Error> {
+    check_length("image", width, height, 3, content.len())?;
+    let png = Path::new(filename).extension().is_some_and(|e| e.eq_ignore_ascii_case("png"));
+    if png {
+        let result = if max_value > u8::MAX as u16 {
+            ImageBuffer::<Rgb<u16>, _>::from_raw(width, height, content).unwrap().save(filename)
+        } else {
+            let content = content.iter().map(|v| *v as u8).collect();
+            ImageBuffer::<Rgb<u8>, Vec<u8>>::from_raw(width, height, content).unwrap().save(filename)
+        };
+        return result.map_err(|e| match e {
+            image::ImageError::IoError(e) => e,
+            e => Error::other(e)
+        });
+    }
+
+    let mut writer = BufWriter::new(File::create(filename)?);
+    write_ppm_to(&mut writer, width, height, max_value, &content)?;
+    return writer.flush();
+}
+
+/// Writes RGB values between 0 and max_value as a plain PPM (P3) image,
+/// one pixel at a time, so that the text is never held in memory as a whole
+pub fn write_ppm_to(writer: &mut impl Write, width: u32, height: u32, max_value: u16, content: &[u16]) -> Result<(), Error> {
+    check_length("image", width, height, 3, content.len())?;
+    write!(writer, "P3\n{} {}\n{}\n", width, height, max_value)?;
+    for pixel in content.chunks_exact(3) {
+        writeln!(writer, "{} {} {}", pixel[0], pixel[1], pixel[2])?;
+    }
+    return Ok(());
+}
+
+/// Writes a PFM image with one (grayscale) or three (color) channels
+pub fn write_pfm(filename: &str, width: u32, height: u32, channels: usize, content: &[f32]) -> Result<(), Error> {
+    check_length("PFM image", width, height, channels, content.len())?;
+    let mut writer = BufWriter::new(File::create(filename)?);
+    write_pfm_to(&mut writer, width, height, channels, content)?;
+    return writer.flush();
+}
+
+/// Streams a PFM image into the writer. PFM stores rows from bottom to
+/// top, a negative scale marks little-endian values.
+pub fn write_pfm_to(writer: &mut impl Write, width: u32, height: u32, channels: usize, content: &[f32]) -> Result<(), Error> {
+    check_length("PFM image", width, height, channels, content.len())?;
+    let kind = if channels == 3 { "PF" } else { "Pf" };
+    write!(writer, "{}\n{} {}\n-1.0\n", kind, width, height)?;
+    for row in content.chunks(width as usize * channels).rev() {
+        for value in row {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    return Ok(());
+}
+
+/// PFM image with its rows stored from the top to the bottom one, the
+/// same order write_pfm expects
+pub struct PfmImage {
+    pub width: u32,
+    pub height: u32,
+    pub channels: usize,
+    pub pixels: Vec<f32>
+}
+
+/// Parses a grayscale (Pf) or color (PF) PFM file of either endianness
+pub fn read_pfm(contents: &[u8]) -> Result<PfmImage, String> {
+    // The header consists of three whitespace separated tokens after the
+    // kind, followed by a single whitespace character before the data
+    let mut tokens = Vec::new();
+    let mut offset = 0;
+    while tokens.len() < 4 {
+        while contents.get(offset).is_some_and(|b| b.is_ascii_whitespace()) {
+            offset += 1;
+        }
+        let start = offset;
+        while contents.get(offset).is_some_and(|b| !b.is_ascii_whitespace()) {
+            offset += 1;
+        }
+        if start == offset {
+            return Err("header is not terminated".to_string());
+        }
+        tokens.push(String::from_utf8_lossy(&contents[start..offset]).to_string());
+    }
+    offset += 1;
+
+    let channels = match tokens[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err("not a PFM file".to_string())
+    };
+    let (width, height, scale) = match (tokens[1].parse::<u32>(), tokens[2].parse::<u32>(), tokens[3].parse::<f32>()) {
+        (Ok(w), Ok(h), Ok(s)) if w > 0 && h > 0 && s != 0.0 && s.is_finite() => (w, h, s),
+        _ => return Err(format!("invalid header {:?}", tokens[1..].join(" ")))
+    };
+
+    let length = width as usize * height as usize * channels;
+    let data = contents.get(offset..).unwrap_or_default();
+    if data.len() < length * 4 {
+        return Err(format!("has {} bytes of pixel data, but a {}x{} image needs {}", data.len(), width, height, length * 4));
+    }
+    let values: Vec<f32> = data[..length * 4].chunks_exact(4).map(|b| {
+        let bytes = [b[0], b[1], b[2], b[3]];
+        return if scale < 0.0 { f32::from_le_bytes(bytes) } else { f32::from_be_bytes(bytes) };
+    }).collect();
+
+    let mut pixels = Vec::with_capacity(length);
+    for row in values.chunks(width as usize * channels).rev() {
+        pixels.extend_from_slice(row);
+    }
+    return Ok(PfmImage { width, height, channels, pixels });
+}
+
+/// Per-pixel weighted average of images with the same size, for merging
+/// renders of the same scene with different seeds. The weights must not
+/// all be zero.
+pub fn weighted_average(images: &[PfmImage], weights: &[f32]) -> Vec<f32> {
+    let total: f32 = weights.iter().sum();
+    let mut average = vec![0.0; images[0].pixels.len()];
+    for (image, weight) in images.iter().zip(weights) {
+        for (a, value) in average.iter_mut().zip(&image.pixels) {
+            *a += value * weight / total;
+        }
+    }
+    return average;
+}
+
+/// Reverses the order of the rows of an image with the given number of
+/// channels per pixel, so that the bottom row comes first
+pub fn flip_rows<T>(content: &mut [T], width: u32, channels: usize) {
+    let row = width as usize * channels;
+    let rows = content.len() / row;
+    for y in 0..rows / 2 {
+        let (top, bottom) = content.split_at_mut((rows - 1 - y) * row);
+        top[y * row..(y + 1) * row].swap_with_slice(&mut bottom[..row]);
+    }
+}
+
+/// Bilinearly resamples an image with the given number of channels per
+/// pixel to another size, with the pixel centers of both images aligned
+/// and the edge pixels extended beyond the border
+pub fn upscale(content: &[f32], from: [u32; 2], to: [u32; 2], channels: usize) -> Vec<f32> {
+    let from_width = from[0] as usize;
+    let source = |axis: usize, i: usize| -> (usize, usize, f32) {
+        let position = ((i as f32 + 0.5) * from[axis] as f32 / to[axis] as f32 - 0.5).max(0.0);
+        let low = (position as usize).min(from[axis] as usize - 1);
+        let high = (low + 1).min(from[axis] as usize - 1);
+        return (low, high, position - low as f32);
+    };
+
+    let mut upscaled = Vec::with_capacity(to[0] as usize * to[1] as usize * channels);
+    for y in 0..to[1] as usize {
+        let (y0, y1, fy) = source(1, y);
+        for x in 0..to[0] as usize {
+            let (x0, x1, fx) = source(0, x);
+            for c in 0..channels {
+                let value = |x: usize, y: usize| content[(y * from_width + x) * channels + c];
+                let top = value(x0, y0) + (value(x1, y0) - value(x0, y0)) * fx;
+                let bottom = value(x0, y1) + (value(x1, y1) - value(x0, y1)) * fx;
+                upscaled.push(top + (bottom - top) * fy);
+            }
+        }
+    }
+    return upscaled;
+}
+
+/// Keys that output name templates can refer to in braces, e.g. "frame_{index}_{steps}.png"
+pub const TEMPLATE_KEYS: [&str; 9] = ["index", "width", "height", "iterations", "steps", "bounces", "extinction", "anisotropy", "gamma"];
+
+/// Checks that the template only refers to known keys and that its braces are balanced
+pub fn validate_template(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        if &rest[start..start + 1] == "}" {
+            return Err(format!("Output name template {:?} has a closing brace without an opening one!", template));
+        }
+        let end = match rest[start..].find('}') {
+            Some(e) => start + e,
+            None => return Err(format!("Output name template {:?} has an opening brace without a closing one!", template))
+        };
+        let key = &rest[start + 1..end];
+        if !TEMPLATE_KEYS.contains(&key) {
+            return Err(format!("Unknown key {:?} in output name template {:?} (expected one of {})", key, template, TEMPLATE_KEYS.join(", ")));
+        }
+        rest = &rest[end + 1..];
+    }
+    return Ok(());
+}
+
+/// Replaces the keys in braces with their values. The template must have
+/// passed validate_template.
+pub fn fill_template(template: &str, values: &[(&str, String)]) -> String {
+    let mut filled = template.to_string();
+    for (key, value) in values {
+        filled = filled.replace(&format!("{{{}}}", key), value);
+    }
+    return filled;
+}
+
+/// Radiance statistics of one channel of an image
+#[derive(Debug, PartialEq)]
+pub struct ChannelStatistics {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub median: f32
+}
+
+/// Returns the statistics of every channel of interleaved values, where
+/// the median of an even number of pixels is the mean of the middle two
+pub fn channel_statistics(content: &[f32], channels: usize) -> Vec<ChannelStatistics> {
+    return (0..channels).map(|c| {
+        let mut values: Vec<f32> = content.iter().skip(c).step_by(channels).cloned().collect();
+        values.sort_by(|a, b| a.total_cmp(b));
+        let count = values.len();
+        let median = if count.is_multiple_of(2) {
+            (values[count / 2 - 1] + values[count / 2]) / 2.0
+        } else {
+            values[count / 2]
+        };
+        let sum: f64 = values.iter().map(|v| *v as f64).sum();
+        return ChannelStatistics {
+            min: values[0],
+            max: values[count - 1],
+            mean: (sum / count as f64) as f32,
+            median
+        };
+    }).collect();
+}
+
+/// Formats a number for JSON, which has no infinities or NaN
+fn json_number(value: f32) -> String {
+    if value.is_finite() {
+        return value.to_string();
+    }
+    return "null".to_string();
+}
+
+/// Writes the statistics of the RGB channels and the parameters of the
+/// render, as CSV with one row per channel and a column per parameter when
+/// the filename ends with .csv, or as JSON otherwise. The parameter values
+/// must be numbers.
+pub fn write_statistics(filename: &str, statistics: &[ChannelStatistics], parameters: &[(&str, String)]) -> Result<(), Error> {
+    let names = ["r", "g", "b"];
+    let csv = Path::new(filename).extension().is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+    let mut writer = BufWriter::new(File::create(filename)?);
+    if csv {
+        let keys: Vec<&str> = parameters.iter().map(|(key, _)| *key).collect();
+        writeln!(writer, "channel,min,max,mean,median,{}", keys.join(","))?;
+        let values: Vec<&str> = parameters.iter().map(|(_, value)| value.as_str()).collect();
+        for (name, s) in names.iter().zip(statistics) {
+            writeln!(writer, "{},{},{},{},{},{}", name, s.min, s.max, s.mean, s.median, values.join(","))?;
+        }
+        return writer.flush();
+    }
+
+    writeln!(writer, "{{")?;
+    writeln!(writer, "  \"parameters\": {{")?;
+    for (i, (key, value)) in parameters.iter().enumerate() {
+        let separator = if i + 1 < parameters.len() { "," } else { "" };
+        writeln!(writer, "    \"{}\": {}{}", key, value, separator)?;
+    }
+    writeln!(writer, "  }},")?;
+    writeln!(writer, "  \"radiance\": {{")?;
+    for (i, (name, s)) in names.iter().zip(statistics).enumerate() {
+        let separator = if i + 1 < statistics.len() { "," } else { "" };
+        writeln!(writer, "    \"{}\": {{ \"min\": {}, \"max\": {}, \"mean\": {}, \"median\": {} }}{}",
+            name, json_number(s.min), json_number(s.max), json_number(s.mean), json_number(s.median), separator)?;
+    }
+    writeln!(writer, "  }}")?;
+    writeln!(writer, "}}")?;
+    return writer.flush();
+}
+
+/// Marks checkpoint files and the version of their layout
+const CHECKPOINT_MAGIC: &[u8; 8] = b"VPTCKPT1";
+
+/// Textures of the photon state in a checkpoint, in the order they are stored
+pub const CHECKPOINT_TEXTURES: usize = 7;
+
+/// Accumulated state of a render that --resume continues: the iterations
+/// so far and the RGBA values of every texture of the photon state, from
+/// the top row without any row padding
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub resolution: [u32; 2],
+    pub iterations: u32,
+    pub textures: [Vec<f32>; CHECKPOINT_TEXTURES]
+}
+
+/// Writes the checkpoint as the magic bytes, the width, the height and the
+/// iterations as little-endian u32, and the little-endian f32 values of the
+/// textures one after another. The file is written next to the target and
+/// then renamed, so that an interruption never leaves a partial checkpoint.
+pub fn write_checkpoint(filename: &str, checkpoint: &Checkpoint) -> Result<(), Error> {
+    let temporary = format!("{}.partial", filename);
+    {
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        for value in [checkpoint.resolution[0], checkpoint.resolution[1], checkpoint.iterations] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for texture in &checkpoint.textures {
+            check_length("checkpoint texture", checkpoint.resolution[0], checkpoint.resolution[1], 4, texture.len())?;
+            for value in texture {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        writer.flush()?;
+    }
+    return std::fs::rename(temporary, filename);
+}
+
+/// Parses a checkpoint written by write_checkpoint
+pub fn read_checkpoint(contents: &[u8]) -> Result<Checkpoint, String> {
+    if contents.len() < 20 || &contents[..8] != CHECKPOINT_MAGIC {
+        return Err("not a checkpoint file".to_string());
+    }
+    let header: Vec<u32> = contents[8..20].chunks_exact(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+    let (width, height, iterations) = (header[0], header[1], header[2]);
+    let length = width as usize * height as usize * 4;
+    let data = &contents[20..];
+    if data.len() != length * 4 * CHECKPOINT_TEXTURES {
+        return Err(format!("has {} bytes of state, but a {}x{} checkpoint needs {}", data.len(), width, height, length * 4 * CHECKPOINT_TEXTURES));
+    }
+    let values: Vec<f32> = data.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+    let textures = [0, 1, 2, 3, 4, 5, 6].map(|i| values[i * length..(i + 1) * length].to_vec());
+    return Ok(Checkpoint { resolution: [width, height], iterations, textures });
+}
@@ -0,0 +1,92 @@
+use crate::math::Matrix4f;
+
+/// Edges of the [0, 1] cube of the volume, with the three through the
+/// origin first in the order of their axes
+const EDGES: [([f32; 3], [f32; 3]); 12] = [
+    ([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]),
+    ([0.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([0.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+    ([1.0, 0.0, 0.0], [1.0, 1.0, 0.0]),
+    ([1.0, 0.0, 0.0], [1.0, 0.0, 1.0]),
+    ([0.0, 1.0, 0.0], [1.0, 1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 1.0, 1.0]),
+    ([0.0, 0.0, 1.0], [1.0, 0.0, 1.0]),
+    ([0.0, 0.0, 1.0], [0.0, 1.0, 1.0]),
+    ([1.0, 1.0, 0.0], [1.0, 1.0, 1.0]),
+    ([1.0, 0.0, 1.0], [1.0, 1.0, 1.0]),
+    ([0.0, 1.0, 1.0], [1.0, 1.0, 1.0])
+];
+
+/// Clip coordinates of a point of the volume, before the perspective divide
+fn to_clip(mvp: &Matrix4f, point: [f32; 3]) -> [f32; 4] {
+    let p = [point[0], point[1], point[2], 1.0];
+    return [0, 1, 2, 3].map(|i| (0..4).map(|j| mvp.m[i][j] * p[j]).sum());
+}
+
+/// Cuts the segment to the part in front of the camera and inside the
+/// image, in clip coordinates, so that lines through the camera plane do
+/// not wrap around. Returns None when nothing of it is visible.
+fn clip_segment(a: [f32; 4], b: [f32; 4]) -> Option<([f32; 4], [f32; 4])> {
+    let planes: [fn([f32; 4]) -> f32; 5] = [
+        |p: [f32; 4]| p[3] + p[0],
+        |p: [f32; 4]| p[3] - p[0],
+        |p: [f32; 4]| p[3] + p[1],
+        |p: [f32; 4]| p[3] - p[1],
+        |p: [f32; 4]| p[3] - 1e-5
+    ];
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    for plane in planes {
+        let (da, db) = (plane(a), plane(b));
+        if da < 0.0 && db < 0.0 {
+            return None;
+        }
+        if da < 0.0 {
+            t0 = t0.max(da / (da - db));
+        } else if db < 0.0 {
+            t1 = t1.min(da / (da - db));
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    let lerp = |t: f32| [0, 1, 2, 3].map(|i| a[i] + t * (b[i] - a[i]));
+    return Some((lerp(t0), lerp(t1)));
+}
+
+/// Draws the wireframe of the bounding box of the volume over the RGB
+/// image, with the edges along the X, Y and Z axes from the origin of the
+/// volume in red, green and blue and the others in white. The camera matrix
+/// is the one of the renderers, the transposed inverse of the MVP matrix,
+/// and the rows of the image go from -1 to 1 in normalized device
+/// coordinates like the rays of the renderers.
+pub fn draw_axes(image: &mut [u16], resolution: [u32; 2], camera_matrix: &Matrix4f, max_value: u16) {
+    let mvp = camera_matrix.transpose().inverse();
+    let [width, height] = resolution.map(|r| r as f32);
+    let to_pixel = |p: [f32; 4]| [(p[0] / p[3] + 1.0) * 0.5 * width - 0.5, (p[1] / p[3] + 1.0) * 0.5 * height - 0.5];
+
+    // The colored axes are drawn last, so they stay on top where edges cross
+    for (index, (from, to)) in EDGES.iter().enumerate().rev() {
+        let color = match index {
+            0 => [max_value, 0, 0],
+            1 => [0, max_value, 0],
+            2 => [0, 0, max_value],
+            _ => [max_value; 3]
+        };
+        let (a, b) = match clip_segment(to_clip(&mvp, *from), to_clip(&mvp, *to)) {
+            Some(s) => s,
+            None => continue
+        };
+        let (a, b) = (to_pixel(a), to_pixel(b));
+        let steps = (b[0] - a[0]).abs().max((b[1] - a[1]).abs()).ceil().max(1.0) as u32;
+        for step in 0..=steps {
+            let t = step as f32 / steps as f32;
+            let x = (a[0] + t * (b[0] - a[0])).round();
+            let y = (a[1] + t * (b[1] - a[1])).round();
+            if x < 0.0 || y < 0.0 || x >= width || y >= height {
+                continue;
+            }
+            let index = (y as usize * resolution[0] as usize + x as usize) * 3;
+            image[index..index + 3].copy_from_slice(&color);
+        }
+    }
+}
@@ -1,29 +1,389 @@
-use crate::{camera::Camera, math::{Vector3f, Matrix4f}, mcm_renderer};
+use std::{fmt::Display, sync::atomic::AtomicBool, time::{Duration, Instant}};
 
+use log::{debug, info};
+
+use crate::{camera::Camera, envmap::EnvironmentMap, math::{Vector3f, Matrix4f}, output::Checkpoint, cpu_renderer, mcm_renderer, sweep::SweepParameter};
+
+#[derive(Clone)]
 pub struct RenderData {
     pub output_resolution: [u32; 2],
+    /// Bits per channel the image is quantized to, 8 or 16
+    pub output_bit_depth: u32,
     pub volume: Vec<u8>,
     pub volume_dims: [u32; 3],
+    pub volume_format: VolumeFormat,
+    /// Label of every voxel, with the same dimensions as the volume
+    pub labels: Option<Vec<u8>>,
+    /// Labels to render, all others are treated as empty. All labels
+    /// are rendered when not set.
+    pub show_labels: Option<Vec<u8>>,
+    /// Size of a voxel along every axis. The volume is stretched to the
+    /// unit cube when not set.
+    pub voxel_spacing: Option<[f32; 3]>,
     pub transfer_function: Vec<u8>,
     pub transfer_function_len: u32,
+    /// Second transfer function with as many entries, which is mixed into
+    /// the first one with the weight tf_blend
+    pub blend_transfer_function: Option<Vec<u8>>,
+    pub tf_blend: f32,
+    pub tf_colorspace: ColorSpace,
     pub extinction: f32,
     pub anisotropy: f32,
+    pub phase_function: PhaseFunction,
+    /// Anisotropies of the two lobes of the two-term Henyey-Greenstein
+    /// phase function, followed by the weight of the first one
+    pub phase_parameters: [f32; 3],
     pub max_bounces: u32,
-    pub steps: u32,
+    /// Steps per iteration, derived from the extinction when not given (see steps)
+    pub steps: Option<u32>,
+    pub steps_schedule: Option<Vec<u32>>,
+    pub bounces_schedule: Option<Vec<u32>>,
+    /// Bounces after which the paths are terminated by russian roulette
+    pub russian_roulette_start: Option<u32>,
+    /// Largest radiance of a single path, brighter paths are scaled down to it
+    pub max_sample_radiance: Option<f32>,
+    /// Radiance of the pixels before their first path finishes
+    pub reset_radiance: [f32; 3],
     pub camera_position: [f32; 3],
+    /// Point the camera is aimed at
+    pub look_at: [f32; 3],
+    /// Turns the camera towards the target along the shortest arc when not set
+    pub up: Option<[f32; 3]>,
     pub linear: bool,
+    /// Sample the volume with a cubic B-spline, made of eight linear lookups
+    pub tricubic: bool,
+    pub linear_tf: bool,
+    pub address_mode: AddressMode,
     pub iterations: u32,
+    pub tile: Option<u32>,
+    /// Budget of GPU memory in megabytes, checked against the estimate
+    /// of mcm_renderer::estimated_memory before acquiring the device
+    pub max_memory: Option<u64>,
+    /// Iterations encoded into one command buffer and submitted at once.
+    /// Short submissions keep drivers from resetting the device when a
+    /// single submission runs for too long.
+    pub iterations_per_submit: u32,
+    /// Mean relative variance of the pixels below which rendering stops
+    /// early, checked after every submission
+    pub noise_threshold: Option<f32>,
+    pub denoise: bool,
+    /// Use the reference renderer on the CPU instead of the GPU
+    pub cpu: bool,
+    /// Normalized bounds of the volume that is rendered, everything
+    /// outside of them is treated as empty
+    pub clip_min: [f32; 3],
+    pub clip_max: [f32; 3],
+    /// Normal and offset of a plane in normalized volume coordinates,
+    /// the volume on its negative side is treated as empty
+    pub clip_plane: Option<[f32; 4]>,
+    pub invert_clip_plane: bool,
+    /// Normalized minimum and maximum corner of the box that the camera
+    /// frames in place of the whole volume
+    pub roi: Option<[[f32; 3]; 2]>,
+    /// Point of the volume in normalized coordinates that is placed at the
+    /// origin of the scene
+    pub volume_origin: [f32; 3],
+    /// Modulate the scattered light by a diffuse term of the density gradient
+    pub gradient_shading: bool,
+    /// Direction towards the light of the gradient shading, not normalized
+    pub light_direction: [f32; 3],
+    /// Point light in the scene, sampled at every scattering event
+    pub light_position: Option<[f32; 3]>,
+    pub light_intensity: f32,
+    /// Sample the environment by its brightness at every scattering event,
+    /// instead of adding it when the photons escape
+    pub next_event_estimation: bool,
+    /// Radiance of the escaped photons, a constant white environment when not set
+    pub envmap: Option<EnvironmentMap>,
     pub mvp_matrix: Option<[f32; 16]>,
+    /// Directory with WGSL files that replace the embedded shaders of the same name
+    pub shader_dir: Option<String>,
     pub focal_length: f32,
+    /// Width of a pixel of the target display divided by its height
+    pub pixel_aspect: f32,
     pub tones: [f32; 3],
     pub saturation: f32,
-    pub gamma: f32
+    pub gamma: f32,
+    /// Encoding of the output image instead of the gamma
+    pub output_colorspace: Option<ColorSpace>,
+    pub tonemap: ToneMapOperator,
+    pub depth: bool,
+    /// Read back the radiance before tone mapping, for --stats
+    pub radiance: bool,
+    pub albedo: bool,
+    pub normal: bool,
+    /// Read back the state of the photons after the first iteration
+    pub dump_gbuffer: bool,
+    pub benchmark: bool,
+    /// Measure the rendering phases on the GPU with timestamp queries
+    pub gpu_timing: bool,
+    /// Render a band of rows on every GPU adapter at the same time
+    pub multi_gpu: bool,
+    /// File the accumulated state is written to at the end of the render,
+    /// and every checkpoint_every iterations
+    pub checkpoint: Option<String>,
+    pub checkpoint_every: Option<u32>,
+    /// State of an earlier render that the iterations are added to
+    pub resume: Option<Checkpoint>,
+    /// Graphics APIs the GPU adapter is chosen from
+    pub backends: wgpu::Backends
+}
+
+/// Images produced by the renderer
+#[derive(Default)]
+pub struct RenderOutput {
+    /// RGB values quantized to RenderData::output_bit_depth bits
+    pub image: Vec<u16>,
+    /// Mean distance along the primary rays to their first interaction
+    /// with the volume, only produced when RenderData::depth is set
+    pub depth: Option<Vec<f32>>,
+    /// Linear RGB radiance before tone mapping, only produced when
+    /// RenderData::radiance is set
+    pub radiance: Option<Vec<f32>>,
+    /// Mean voxel color at the first interaction, RGB
+    pub albedo: Option<Vec<f32>>,
+    /// Mean normalized density gradient at the first interaction, XYZ
+    pub normal: Option<Vec<f32>>,
+    /// Position, direction and transmittance of the photons after the
+    /// first iteration, each XYZ or RGB, only produced when
+    /// RenderData::dump_gbuffer is set
+    pub gbuffer: Option<[Vec<f32>; 3]>
+}
+
+impl RenderOutput {
+    /// Adds the rows of an image of the same width below those of this one
+    pub fn append(&mut self, below: RenderOutput) {
+        self.image.extend(below.image);
+        for (values, below) in [(&mut self.depth, below.depth), (&mut self.radiance, below.radiance),
+            (&mut self.albedo, below.albedo), (&mut self.normal, below.normal)] {
+            if let Some(below) = below {
+                values.get_or_insert_with(Vec::new).extend(below);
+            }
+        }
+        if let Some(below) = below.gbuffer {
+            let gbuffer = self.gbuffer.get_or_insert_with(Default::default);
+            for (values, below) in gbuffer.iter_mut().zip(below) {
+                values.extend(below);
+            }
+        }
+    }
+}
+
+/// Color space the transfer function colors are authored in, or that the
+/// output image is encoded in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear
+}
+
+/// Distribution of the directions of scattered light
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PhaseFunction {
+    /// One lobe with the anisotropy as its parameter
+    HenyeyGreenstein,
+    /// Weighted sum of two lobes, for media with both forward and backward scattering
+    DoubleHenyeyGreenstein,
+    /// Scattering by particles much smaller than the wavelength
+    Rayleigh
+}
+
+/// Curve that maps the HDR radiance into the displayable range
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapOperator {
+    /// Clamps values above 1.0
+    Linear,
+    Reinhard,
+    Aces
+}
+
+/// Lookups of the volume beyond its faces, by linear filtering and the
+/// gradients at the edges
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressMode {
+    /// Repeats the voxels at the faces
+    Clamp,
+    /// Tiles the volume, for periodic data
+    Repeat,
+    /// Tiles the volume, mirrored at every face
+    Mirror
+}
+
+/// Layout of the voxels in the volume file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VolumeFormat {
+    /// Scalar density, colored by the transfer function
+    R8,
+    /// Color in RGB and density in alpha, the transfer function is not used
+    Rgba8
+}
+
+impl VolumeFormat {
+    pub fn bytes_per_voxel(&self) -> u32 {
+        return match self {
+            VolumeFormat::R8 => 1,
+            VolumeFormat::Rgba8 => 4
+        };
+    }
+}
+
+/// Wall-clock durations of the individual rendering phases. GPU phases are
+/// only waited on when benchmarking, otherwise they only measure encoding.
+#[derive(Default)]
+pub struct Timings {
+    pub device: Duration,
+    pub upload: Duration,
+    pub setup: Duration,
+    pub render: Duration,
+    pub readback: Duration,
+    /// Durations of the reset passes and of the iterations measured on
+    /// the GPU with timestamp queries, only with RenderData::gpu_timing
+    /// on adapters that support them
+    pub gpu_reset: Option<Duration>,
+    pub gpu_iterations: Option<Duration>
+}
+
+#[derive(Debug)]
+pub enum RenderError {
+    NoAdapter,
+    NoDevice(wgpu::RequestDeviceError),
+    ResolutionTooLarge { requested: [u32; 2], max: u32 },
+    VolumeTooLarge { requested: [u32; 3], max: u32 },
+    EnvironmentMapTooLarge { requested: [u32; 2], max: u32 },
+    BufferTooLarge { resolution: [u32; 2], requested: u64, max: u64 },
+    MemoryBudgetExceeded { resolution: [u32; 2], estimated: u64, budget: u64 },
+    /// The checkpoint file and the reason it could not be written
+    CheckpointWrite(String),
+    /// A shader does not compile or does not match its pipeline
+    ShaderCompile(String),
+    /// The device stopped working while rendering, e.g. because the
+    /// driver reset it after a timeout
+    DeviceLost
+}
+
+impl Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderError::NoAdapter => write!(f, "No suitable GPU adapter found"),
+            RenderError::NoDevice(e) => write!(f, "Could not acquire GPU device: {}", e),
+            RenderError::ResolutionTooLarge { requested, max } => write!(f,
+                "Render target {}x{} exceeds the maximum texture size of the device (at most {}x{} is supported, including row padding). Use --tile to render the image in smaller pieces",
+                requested[0], requested[1], max, max
+            ),
+            RenderError::VolumeTooLarge { requested, max } => write!(f,
+                "Volume dimensions {:?} exceed the maximum 3D texture size of the device ({} per axis)",
+                requested, max
+            ),
+            RenderError::EnvironmentMapTooLarge { requested, max } => write!(f,
+                "Environment map {}x{} exceeds the maximum texture size of the device ({} per axis)",
+                requested[0], requested[1], max
+            ),
+            RenderError::BufferTooLarge { resolution, requested, max } => {
+                let side = ((*max / 16) as f64).sqrt() as u64;
+                write!(f,
+                    "Render target {}x{} needs a {} byte readback buffer, but the device supports at most {} bytes (about {}x{} pixels). Use --tile to render the image in smaller pieces",
+                    resolution[0], resolution[1], requested, max, side, side
+                )
+            },
+            RenderError::MemoryBudgetExceeded { resolution, estimated, budget } => write!(f,
+                "Render target {}x{} needs an estimated {} MB of GPU memory, which exceeds the budget of {} MB set by --max-memory. Use a lower --out-resolution, or render the image in smaller pieces with --tile",
+                resolution[0], resolution[1], megabytes(*estimated), budget
+            ),
+            RenderError::CheckpointWrite(e) => write!(f, "Could not write checkpoint {}", e),
+            RenderError::ShaderCompile(e) => write!(f, "Shader validation failed in {}", e),
+            RenderError::DeviceLost => write!(f,
+                "The GPU device was lost while rendering, most likely because the driver reset it after a submission ran for too long. Lower --max-iterations-per-submit, --steps or --iterations, or render the image in smaller pieces with --tile"
+            )
+        }
+    }
 }
 
-pub async fn render(data: RenderData, output: &mut Vec<u8>) {
+/// Checks whether the matrix is singular or so close to singular that
+/// it can not produce a meaningful projection. The determinant is compared
+/// against the product of row lengths, so the check does not depend on the
+/// overall scale of the matrix.
+pub fn is_degenerate_matrix(matrix: &Matrix4f) -> bool {
+    let mut bound = 1.0;
+    for row in matrix.m.iter() {
+        bound *= row.iter().map(|v| v * v).sum::<f32>().sqrt();
+    }
+    if bound == 0.0 {
+        return true;
+    }
+    return (matrix.det() / bound).abs() < 1e-6;
+}
+
+/// Largest value of a channel of the quantized image
+pub fn output_max_value(data: &RenderData) -> u16 {
+    return if data.output_bit_depth == 16 { u16::MAX } else { u8::MAX as u16 };
+}
+
+/// Returns the size of the volume in the scene. With a voxel spacing, it
+/// is the number of voxels times their spacing along every axis, and the
+/// longest side of the volume spans the unit cube. Without one, the volume
+/// fills the unit cube.
+pub fn volume_scale(data: &RenderData) -> [f32; 3] {
+    let spacing = match data.voxel_spacing {
+        Some(s) => s,
+        None => return [1.0; 3]
+    };
+    let extent = [0, 1, 2].map(|i| data.volume_dims[i] as f32 * spacing[i]);
+    let max_extent = extent.iter().cloned().fold(f32::MIN, f32::max);
+    return extent.map(|e| e / max_extent);
+}
+
+/// Length in the scene of a unit direction in texture coordinates, which
+/// the voxel spacing stretches differently along every axis
+pub fn scene_length(data: &RenderData, direction: [f32; 3]) -> f32 {
+    let scale = volume_scale(data);
+    return (0..3).map(|i| (direction[i] * scale[i]).powi(2)).sum::<f32>().sqrt();
+}
+
+/// Direction of the primary ray through the center of a pixel, in texture
+/// coordinates, from the camera matrix of the renderers
+pub fn primary_direction(camera_matrix: &Matrix4f, resolution: [u32; 2], pixel: [u32; 2]) -> [f32; 3] {
+    let inverse_mvp = camera_matrix.transpose();
+    let [x, y] = [0, 1].map(|i| (pixel[i] as f32 + 0.5) / resolution[i] as f32 * 2.0 - 1.0);
+    let from = inverse_mvp.transform_point(Vector3f::new(x, y, -1.0));
+    let to = inverse_mvp.transform_point(Vector3f::new(x, y, 1.0));
+    let mut direction = to - from;
+    direction.normalize();
+    return [direction.x, direction.y, direction.z];
+}
+
+/// Set by Ctrl-C to stop the iterations of the render in progress, after
+/// which the image of the iterations completed so far is read back
+pub static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Derived step counts never exceed this, so that very dense volumes do not
+/// make single iterations take so long that the driver resets the device
+pub const MAX_DERIVED_STEPS: u32 = 4096;
+
+/// Returns the length of a step through the volume, which spans the unit
+/// cube: the mean free path 1 / extinction of the Beer-Lambert law, so that
+/// a step through the densest regions has an optical depth of one, but at
+/// most the size of a voxel, so that no voxels are skipped
+pub fn step_length(data: &RenderData) -> f32 {
+    let voxel = 1.0 / data.volume_dims.iter().cloned().max().unwrap_or(1).max(1) as f32;
+    return (1.0 / data.extinction).min(voxel);
+}
+
+/// Returns the steps per iteration, either as given by the user or enough
+/// steps of step_length to cross the diagonal of the volume
+pub fn steps(data: &RenderData) -> u32 {
+    if let Some(steps) = data.steps {
+        return steps;
+    }
+    let diagonal = 3.0f32.sqrt();
+    return ((diagonal / step_length(data)).ceil() as u32).clamp(1, MAX_DERIVED_STEPS);
+}
+
+/// Returns the inverse MVP matrix used for rendering, either as given
+/// by the user or computed from the camera parameters
+pub fn camera_matrix(data: &RenderData) -> Matrix4f {
     //let vol_dims = data.volume_dims;
     //let tf_len = data.transfer_function_len;
-    let volume_scale = vec![1.0, 1.0, 1.0];
+    let volume_scale = volume_scale(data);
 
     let mut camera = Camera::new();
     camera.set_position(
@@ -33,51 +393,348 @@ pub async fn render(data: RenderData, output: &mut Vec<u8>) {
             data.camera_position[2]
         )
     );
-    camera.look_at(Vector3f::new(0.0, 0.0, 0.0));
-    camera.set_fov_x(0.512);
-    camera.set_fov_y(0.512);
-    let aspect_ratio = data.output_resolution[0] as f32 / data.output_resolution[1] as f32;
+    let focus = Vector3f::new(data.look_at[0], data.look_at[1], data.look_at[2]);
+    match data.up {
+        Some(up) => camera.look_at_up(focus, Vector3f::new(up[0], up[1], up[2])),
+        None => camera.look_at(focus)
+    }
+    // Wider pixels show more of the scene each, so the image covers more horizontally
+    let aspect_ratio = data.output_resolution[0] as f32 * data.pixel_aspect / data.output_resolution[1] as f32;
     camera.set_fov(data.focal_length, aspect_ratio);
     camera.update_matrices();
 
-    let pvm_inverse = if data.mvp_matrix.is_none() {
-        let model_matrix = Matrix4f::from_values(vec![
-            volume_scale[0], 0.0, 0.0, -0.5,
-            0.0, volume_scale[1], 0.0, -0.5,
-            0.0, 0.0, volume_scale[2], -0.5,
-            0.0, 0.0, 0.0, 1.0
-        ]);
-    
-        let vm_matrix = Matrix4f::mutiply(
-            camera.get_view_matrix(), &model_matrix
+    if let Some(mvp_matrix) = data.mvp_matrix {
+        Matrix4f::from_values(
+            mvp_matrix.to_vec()
+        )
+    } else {
+        let origin = [0, 1, 2].map(|i| -data.volume_origin[i] * volume_scale[i]);
+        let mut model_matrix = &Matrix4f::from_translation(origin[0], origin[1], origin[2]) * &Matrix4f::from_scale(
+            volume_scale[0], volume_scale[1], volume_scale[2]
         );
+        // The region of interest is scaled uniformly and moved into the
+        // place of the whole volume, so the camera frames it the same way
+        if let Some([min, max]) = data.roi {
+            let size = (0..3).map(|i| max[i] - min[i]).fold(f32::MIN, f32::max);
+            let mut roi_matrix = Matrix4f::from_scale(1.0 / size, 1.0 / size, 1.0 / size);
+            for i in 0..3 {
+                roi_matrix.m[i][3] = 0.5 - 0.5 * (min[i] + max[i]) / size;
+            }
+            model_matrix = &model_matrix * &roi_matrix;
+        }
     
-        let pvm_matrix = Matrix4f::mutiply(
-            camera.get_projection_matrix(), &vm_matrix
-        );
+        let vm_matrix = camera.get_view_matrix() * &model_matrix;
+        let pvm_matrix = camera.get_projection_matrix() * &vm_matrix;
     
         pvm_matrix.inverse().transpose()
-    } else {
-        Matrix4f::from_values(
-            data.mvp_matrix.unwrap().to_vec()
-        )
-    };
+    }
+}
 
-    // -------------- Initialization -------------- //
+/// Checks that all textures and buffers needed for rendering fit into the device limits
+/// With tiling, the limits apply to a single tile rather than the whole image.
+pub fn validate_limits(data: &RenderData, limits: &wgpu::Limits) -> Result<(), RenderError> {
+    let target = mcm_renderer::tile_resolution(data);
+    let padded = mcm_renderer::padded_resolution(target);
+    if padded[0] > limits.max_texture_dimension_2d || padded[1] > limits.max_texture_dimension_2d {
+        return Err(RenderError::ResolutionTooLarge {
+            requested: target,
+            max: limits.max_texture_dimension_2d
+        });
+    }
+    if data.volume_dims.iter().any(|d| *d > limits.max_texture_dimension_3d) {
+        return Err(RenderError::VolumeTooLarge {
+            requested: data.volume_dims,
+            max: limits.max_texture_dimension_3d
+        });
+    }
+    if let Some(envmap) = &data.envmap {
+        if envmap.width > limits.max_texture_dimension_2d || envmap.height > limits.max_texture_dimension_2d {
+            return Err(RenderError::EnvironmentMapTooLarge {
+                requested: [envmap.width, envmap.height],
+                max: limits.max_texture_dimension_2d
+            });
+        }
+    }
+    let buffer_size = mcm_renderer::result_buffer_size(target);
+    if buffer_size > limits.max_buffer_size {
+        return Err(RenderError::BufferTooLarge {
+            resolution: target,
+            requested: buffer_size,
+            max: limits.max_buffer_size
+        });
+    }
+    return Ok(());
+}
+
+/// Rounds a size in bytes up to whole megabytes
+pub fn megabytes(bytes: u64) -> u64 {
+    return bytes.div_ceil(1024 * 1024);
+}
+
+/// Checks the estimated GPU memory of the render against the budget of
+/// --max-memory. wgpu does not report how much memory the adapter has, so
+/// without a budget the allocations are only limited by validate_limits.
+pub fn validate_memory(data: &RenderData) -> Result<(), RenderError> {
+    let estimated = mcm_renderer::estimated_memory(data);
+    info!("Estimated GPU memory: {} MB", megabytes(estimated));
+    if let Some(budget) = data.max_memory {
+        if estimated > budget * 1024 * 1024 {
+            return Err(RenderError::MemoryBudgetExceeded {
+                resolution: mcm_renderer::tile_resolution(data),
+                estimated,
+                budget
+            });
+        }
+    }
+    return Ok(());
+}
 
-    let instance = wgpu::Instance::new(wgpu::Backends::all());
+/// Names of the graphics APIs that --backend accepts
+pub const BACKENDS: [(&str, wgpu::Backends); 5] = [
+    ("vulkan", wgpu::Backends::VULKAN),
+    ("metal", wgpu::Backends::METAL),
+    ("dx12", wgpu::Backends::DX12),
+    ("dx11", wgpu::Backends::DX11),
+    ("gl", wgpu::Backends::GL)
+];
+
+async fn acquire_device(backends: wgpu::Backends, optional_features: wgpu::Features) -> Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue), RenderError> {
+    let instance = wgpu::Instance::new(backends);
+    return request_device(&instance, None, optional_features).await;
+}
+
+/// Requests a device from the instance, which must be able to present to
+/// the surface when one is given. The optional features are only enabled
+/// when the adapter supports them.
+pub async fn request_device(instance: &wgpu::Instance, surface: Option<&wgpu::Surface>, optional_features: wgpu::Features)
+    -> Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue), RenderError> {
     let adapter = instance.request_adapter(
         &wgpu::RequestAdapterOptionsBase {
             power_preference: wgpu::PowerPreference::default(),
-            compatible_surface: None,
+            compatible_surface: surface,
             force_fallback_adapter: false,
         }
-    ).await.unwrap();
-    let (device, queue) = adapter.request_device(
-        &Default::default(), None
-    ).await.unwrap();
+    ).await.ok_or(RenderError::NoAdapter)?;
+    let (device, queue) = open_device(&adapter, optional_features).await?;
+    return Ok((adapter, device, queue));
+}
 
+/// Opens a device of the adapter with its own limits, and with the optional
+/// features it supports
+async fn open_device(adapter: &wgpu::Adapter, optional_features: wgpu::Features) -> Result<(wgpu::Device, wgpu::Queue), RenderError> {
+    let info = adapter.get_info();
+    debug!("Adapter: {} ({:?}, {:?})", info.name, info.backend, info.device_type);
+    return adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: None,
+            features: adapter.features() & optional_features,
+            limits: adapter.limits()
+        }, None
+    ).await.map_err(RenderError::NoDevice);
+}
+
+/// Returns the adapters --multi-gpu renders with: those of the first
+/// graphics API that has any, as a GPU is listed once for every API that
+/// drives it, and only the hardware ones when there are any, as software
+/// rasterizers would hold the others back
+fn multi_gpu_adapters(backends: wgpu::Backends) -> Vec<wgpu::Adapter> {
+    for (_, backend) in BACKENDS {
+        if !backends.contains(backend) {
+            continue;
+        }
+        let instance = wgpu::Instance::new(backend);
+        let (software, hardware): (Vec<wgpu::Adapter>, Vec<wgpu::Adapter>) = instance.enumerate_adapters(backend)
+            .partition(|a| a.get_info().device_type == wgpu::DeviceType::Cpu);
+        if !hardware.is_empty() {
+            return hardware;
+        }
+        if !software.is_empty() {
+            return software;
+        }
+    }
+    return Vec::new();
+}
+
+/// Splits the rows of an image into consecutive bands of nearly the same
+/// height, as the first row and the number of rows of every band
+pub fn split_rows(height: u32, count: u32) -> Vec<(u32, u32)> {
+    let count = count.clamp(1, height);
+    let mut first_row = 0;
+    return (0..count).map(|i| {
+        let rows = height / count + if i < height % count { 1 } else { 0 };
+        let band = (first_row, rows);
+        first_row += rows;
+        return band;
+    }).collect();
+}
+
+/// Renders the band of rows starting at first_row with its own device of the
+/// adapter. The camera matrix is that of the whole image.
+async fn render_band(adapter: &wgpu::Adapter, data: &RenderData, camera_matrix: &Matrix4f, first_row: u32, rows: u32)
+    -> Result<(RenderOutput, Timings), RenderError> {
+    let mut timings = Timings::default();
+    let device_timer = Instant::now();
+    let optional_features = if data.gpu_timing { wgpu::Features::TIMESTAMP_QUERY } else { wgpu::Features::empty() };
+    let (device, queue) = open_device(adapter, optional_features).await?;
+    timings.device = device_timer.elapsed();
+
+    let mut band = data.clone();
+    band.output_resolution = [data.output_resolution[0], rows];
+    validate_limits(&band, &device.limits())?;
+    let band_matrix = mcm_renderer::tile_camera_matrix(camera_matrix, data.output_resolution, [0, first_row], band.output_resolution);
+
+    let mut output = RenderOutput::default();
+    let mut accumulation = mcm_renderer::Accumulation::new(&device, &band);
+    mcm_renderer::render(&device, &queue, &band, &band_matrix, &mut accumulation, &mut output, &mut timings).await?;
+    return Ok((output, timings));
+}
+
+/// Renders a band of rows on every adapter, each on its own thread, and
+/// stitches the bands together from the top one. The bands run at the same
+/// time, so the wall-clock timings are those of the slowest band, while the
+/// GPU timings add up.
+fn render_multi_gpu(data: &RenderData, adapters: &[wgpu::Adapter], camera_matrix: &Matrix4f, output: &mut RenderOutput) -> Result<Timings, RenderError> {
+    let bands = split_rows(data.output_resolution[1], adapters.len() as u32);
+    let render = |adapter: &wgpu::Adapter, (first_row, rows): (u32, u32)| {
+        info!("Rendering rows {} to {} on {}", first_row, first_row + rows - 1, adapter.get_info().name);
+        return pollster::block_on(render_band(adapter, data, camera_matrix, first_row, rows));
+    };
+    // The GL backend fails when its contexts are made current on several
+    // threads at once, so its bands take turns on the calling thread
+    let parallel = adapters.iter().all(|a| a.get_info().backend != wgpu::Backend::Gl);
+    let results: Vec<Result<(RenderOutput, Timings), RenderError>> = if parallel {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = adapters.iter().zip(&bands).map(|(adapter, band)| {
+                return scope.spawn(move || render(adapter, *band));
+            }).collect();
+            return handles.into_iter().map(|h| h.join().unwrap()).collect();
+        })
+    } else {
+        adapters.iter().zip(&bands).map(|(adapter, band)| render(adapter, *band)).collect()
+    };
+
+    let add = |total: Option<Duration>, band: Option<Duration>| total.map_or(band, |t| Some(t + band.unwrap_or_default()));
+    let mut timings = Timings::default();
+    for result in results {
+        let (band, band_timings) = result?;
+        output.append(band);
+        timings.device = timings.device.max(band_timings.device);
+        timings.upload = timings.upload.max(band_timings.upload);
+        timings.setup = timings.setup.max(band_timings.setup);
+        timings.render = timings.render.max(band_timings.render);
+        timings.readback = timings.readback.max(band_timings.readback);
+        timings.gpu_reset = add(timings.gpu_reset, band_timings.gpu_reset);
+        timings.gpu_iterations = add(timings.gpu_iterations, band_timings.gpu_iterations);
+    }
+    return Ok(timings);
+}
+
+/// Prints the adapter that rendering would use and the device limits
+/// that decide the largest supported images and volumes
+pub async fn print_info(backends: wgpu::Backends) -> Result<(), RenderError> {
+    let (adapter, device, _) = acquire_device(backends, wgpu::Features::empty()).await?;
+    let info = adapter.get_info();
+    let limits = device.limits();
+    println!("Adapter: {}", info.name);
+    println!("Backend: {:?}", info.backend);
+    println!("Device type: {:?}", info.device_type);
+    if !info.driver.is_empty() {
+        println!("Driver: {} {}", info.driver, info.driver_info);
+    }
+    println!("Vendor and device ID: {:#06x} {:#06x}", info.vendor, info.device);
+    println!("Max texture dimension 2D: {}", limits.max_texture_dimension_2d);
+    println!("Max texture dimension 3D: {}", limits.max_texture_dimension_3d);
+    println!("Max buffer size: {}", limits.max_buffer_size);
+    println!("Max uniform buffers per shader stage: {}", limits.max_uniform_buffers_per_shader_stage);
+    println!("Max bind groups: {}", limits.max_bind_groups);
+    return Ok(());
+}
+
+/// Prints every adapter of every graphics API as `backend: adapter (device
+/// type)`, or `backend: none` for the APIs without adapters, which are
+/// either not compiled into this build or have no working driver
+pub fn print_backends() {
+    for (name, backends) in BACKENDS {
+        let instance = wgpu::Instance::new(backends);
+        let adapters: Vec<wgpu::AdapterInfo> = instance.enumerate_adapters(backends).map(|a| a.get_info()).collect();
+        if adapters.is_empty() {
+            println!("{}: none", name);
+        }
+        for info in adapters {
+            println!("{}: {} ({:?})", name, info.name, info.device_type);
+        }
+    }
+}
+
+pub async fn render(data: RenderData, output: &mut RenderOutput) -> Result<Timings, RenderError> {
+    let mut timings = Timings::default();
+    let pvm_inverse = camera_matrix(&data);
+    debug!("Inverse MVP matrix:\n{}", pvm_inverse);
+
+    if data.cpu {
+        let render_timer = Instant::now();
+        cpu_renderer::render(&data, &pvm_inverse, output);
+        timings.render = render_timer.elapsed();
+        return Ok(timings);
+    }
+
+    // -------------- Initialization -------------- //
+
+    validate_memory(&data)?;
+    if data.multi_gpu {
+        let adapters = multi_gpu_adapters(data.backends);
+        if adapters.len() > 1 {
+            info!("Splitting the image among {} adapters", adapters.len());
+            return render_multi_gpu(&data, &adapters, &pvm_inverse, output);
+        }
+        info!("--multi-gpu found a single adapter, so the image is rendered on one GPU");
+    }
+    let device_timer = Instant::now();
+    let optional_features = if data.gpu_timing { wgpu::Features::TIMESTAMP_QUERY } else { wgpu::Features::empty() };
+    let (_, device, queue) = acquire_device(data.backends, optional_features).await?;
+    timings.device = device_timer.elapsed();
+
+    validate_limits(&data, &device.limits())?;
 
     //mcm_renderer::render(&device, &queue, &data, &pvm_inverse, output).await;
-    mcm_renderer::render(&device, &queue, &data, &pvm_inverse, output).await;
-}
\ No newline at end of file
+    let mut accumulation = match &data.resume {
+        Some(checkpoint) => mcm_renderer::Accumulation::resume(&device, &queue, &data, checkpoint),
+        None => mcm_renderer::Accumulation::new(&device, &data)
+    };
+    mcm_renderer::render(&device, &queue, &data, &pvm_inverse, &mut accumulation, output, &mut timings).await?;
+    return Ok(timings);
+}
+
+/// Renders the image of every cell of a parameter sweep with the same
+/// device, in the order of the cells
+pub async fn render_sweep(mut data: RenderData, cells: &[Vec<(SweepParameter, f32)>]) -> Result<Vec<Vec<u16>>, RenderError> {
+    let pvm_inverse = camera_matrix(&data);
+    let device = if data.cpu {
+        None
+    } else {
+        // The swept parameters do not change the size of the textures
+        validate_memory(&data)?;
+        let optional_features = if data.gpu_timing { wgpu::Features::TIMESTAMP_QUERY } else { wgpu::Features::empty() };
+        let (_, device, queue) = acquire_device(data.backends, optional_features).await?;
+        validate_limits(&data, &device.limits())?;
+        Some((device, queue))
+    };
+
+    let mut images = Vec::with_capacity(cells.len());
+    for (i, cell) in cells.iter().enumerate() {
+        for (parameter, value) in cell {
+            parameter.apply(&mut data, *value);
+        }
+        let mut output = RenderOutput::default();
+        match &device {
+            Some((device, queue)) => {
+                let mut accumulation = mcm_renderer::Accumulation::new(device, &data);
+                mcm_renderer::render(device, queue, &data, &pvm_inverse, &mut accumulation, &mut output, &mut Timings::default()).await?;
+            },
+            None => cpu_renderer::render(&data, &pvm_inverse, &mut output)
+        }
+        info!("Rendered sweep cell {} of {}", i + 1, cells.len());
+        images.push(output.image);
+    }
+    return Ok(images);
+}
@@ -0,0 +1,424 @@
+use std::time::Instant;
+
+use log::{error, info};
+use winit::{
+    dpi::PhysicalSize,
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    window::{Window, WindowBuilder}
+};
+
+use crate::{math::Vector3f, cpu_renderer, mcm_renderer, volume, pipeline::{self, output_max_value, RenderData, RenderError, RenderOutput, Timings}};
+
+/// Angle the camera orbits by with every press of an arrow key, in radians
+const ORBIT_STEP: f32 = 10.0 * std::f32::consts::PI / 180.0;
+/// Factor the distance to the target changes by when zooming
+const ZOOM_STEP: f32 = 0.9;
+/// Densities the density window moves or grows by with every key press
+const WINDOW_STEP: i32 = 8;
+/// Smallest angle between the viewing direction and the up direction, so
+/// that the camera never turns over the poles of the orbit
+const MIN_POLE_ANGLE: f32 = 5.0 * std::f32::consts::PI / 180.0;
+
+fn scaled(v: Vector3f, s: f32) -> Vector3f {
+    return Vector3f::new(v.x * s, v.y * s, v.z * s);
+}
+
+fn added(a: Vector3f, b: Vector3f) -> Vector3f {
+    return Vector3f::new(a.x + b.x, a.y + b.y, a.z + b.z);
+}
+
+/// Rotates the vector around the normalized axis by the angle (Rodrigues' formula)
+fn rotated(v: Vector3f, axis: Vector3f, angle: f32) -> Vector3f {
+    let parallel = scaled(axis, Vector3f::dot(&axis, &v) * (1.0 - angle.cos()));
+    return added(added(scaled(v, angle.cos()), scaled(Vector3f::cross(&axis, &v), angle.sin())), parallel);
+}
+
+/// Moves the camera around the target, where azimuth turns around the up
+/// direction and elevation towards it. Moves that would bring the camera
+/// too close to the up direction are ignored.
+fn orbit(data: &mut RenderData, up: Vector3f, azimuth: f32, elevation: f32, zoom: f32) {
+    let target = Vector3f::new(data.look_at[0], data.look_at[1], data.look_at[2]);
+    let position = Vector3f::new(data.camera_position[0], data.camera_position[1], data.camera_position[2]);
+    let mut offset = rotated(position - target, up, azimuth);
+    let mut right = Vector3f::cross(&up, &offset);
+    if right.length_squared() > 0.0 {
+        right.normalize();
+        offset = rotated(offset, right, -elevation);
+    }
+    offset = scaled(offset, zoom);
+
+    let cos_angle = Vector3f::dot(&offset, &up) / offset.length();
+    if cos_angle.is_nan() || cos_angle.abs() >= MIN_POLE_ANGLE.cos() {
+        return;
+    }
+    let position = added(target, offset);
+    data.camera_position = [position.x, position.y, position.z];
+    data.up = Some([up.x, up.y, up.z]);
+}
+
+/// Moves the density window by shift and each of its ends outwards by widen,
+/// keeping it inside the range of densities and at least one density wide
+fn adjusted_window([low, high]: [u8; 2], shift: i32, widen: i32) -> [u8; 2] {
+    let (low, high) = (low as i32, high as i32);
+    let shift = shift.clamp(-low, 255 - high);
+    let widen = widen.max(-((high - low - 1) / 2));
+    return [(low + shift - widen).clamp(0, 255) as u8, (high + shift + widen).clamp(0, 255) as u8];
+}
+
+struct Display {
+    surface: wgpu::Surface,
+    config: wgpu::SurfaceConfiguration,
+    image_texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline
+}
+
+impl Display {
+    fn new(adapter: &wgpu::Adapter, device: &wgpu::Device, surface: wgpu::Surface, size: PhysicalSize<u32>, resolution: [u32; 2]) -> Display {
+        let format = surface.get_supported_formats(adapter)[0];
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto
+        };
+        surface.configure(device, &config);
+
+        // The image is already sRGB encoded. Surfaces with an sRGB format
+        // encode what is written to them, so the texture is decoded when
+        // loaded to show the values unchanged.
+        let image_format = if format.describe().srgb {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        };
+        let image_texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some("PreviewTexture"),
+                size: wgpu::Extent3d {
+                    width: resolution[0],
+                    height: resolution[1],
+                    depth_or_array_layers: 1
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: image_format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST
+            }
+        );
+        let image_view = image_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let bind_group_layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: Some("PreviewBindGroupLayout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false
+                        },
+                        count: None
+                    }
+                ]
+            }
+        );
+        let bind_group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: Some("PreviewBindGroup"),
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&image_view)
+                    }
+                ]
+            }
+        );
+
+        let vertex_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("PreviewVertexShader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/tm_artistic_vertex.wgsl").into())
+        });
+        let fragment_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("PreviewFragmentShader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/preview_fragment.wgsl").into())
+        });
+        let pipeline_layout = device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: Some("PreviewPipelineLayout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[]
+            }
+        );
+        let pipeline = device.create_render_pipeline(
+            &wgpu::RenderPipelineDescriptor {
+                label: Some("PreviewRenderPipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &vertex_shader,
+                    entry_point: "main",
+                    buffers: &[]
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &fragment_shader,
+                    entry_point: "main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL
+                    })]
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleStrip,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None
+            }
+        );
+
+        return Display { surface, config, image_texture, bind_group, pipeline };
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        if size.width > 0 && size.height > 0 {
+            self.config.width = size.width;
+            self.config.height = size.height;
+            self.surface.configure(device, &self.config);
+        }
+    }
+
+    /// Uploads the quantized RGB image, whose row 0 is shown at the top
+    fn upload(&self, queue: &wgpu::Queue, data: &RenderData, output: &RenderOutput) {
+        let scale = 255.0 / output_max_value(data) as f32;
+        let rgba: Vec<u8> = output.image.chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2]].map(|c| (c as f32 * scale).round() as u8).into_iter().chain([255]))
+            .collect();
+        let [width, height] = data.output_resolution;
+        queue.write_texture(
+            self.image_texture.as_image_copy(),
+            &rgba,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(width * 4),
+                rows_per_image: std::num::NonZeroU32::new(height)
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1
+            }
+        );
+    }
+
+    fn present(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let frame = match self.surface.get_current_texture() {
+            Ok(f) => f,
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                // The surface is recreated and presented on the next redraw
+                self.surface.configure(device, &self.config);
+                return;
+            },
+            Err(e) => {
+                error!("Could not present the preview: {}", e);
+                return;
+            }
+        };
+        let view = frame.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: Some("PreviewCommandEncoder")
+            }
+        );
+        {
+            let mut render_pass = encoder.begin_render_pass(
+                &wgpu::RenderPassDescriptor {
+                    label: Some("PreviewRenderPass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            store: true
+                        }
+                    })],
+                    depth_stencil_attachment: None
+                }
+            );
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.draw(0..4, 0..1);
+        }
+        queue.submit([encoder.finish()]);
+        frame.present();
+    }
+}
+
+fn render(device: &wgpu::Device, queue: &wgpu::Queue, data: &RenderData, output: &mut RenderOutput) -> Result<(), RenderError> {
+    let timer = Instant::now();
+    let camera_matrix = pipeline::camera_matrix(data);
+    if data.cpu {
+        cpu_renderer::render(data, &camera_matrix, output);
+        info!("Rendered from {:?} in {} s", data.camera_position, timer.elapsed().as_secs_f32());
+        return Ok(());
+    }
+    let mut accumulation = mcm_renderer::Accumulation::new(device, data);
+    let mut timings = Timings::default();
+    pollster::block_on(mcm_renderer::render(device, queue, data, &camera_matrix, &mut accumulation, output, &mut timings))?;
+    info!("Rendered from {:?} in {} s", data.camera_position, timer.elapsed().as_secs_f32());
+    return Ok(());
+}
+
+fn update_title(window: &Window, data: &RenderData, [low, high]: [u8; 2]) {
+    let p = data.camera_position;
+    window.set_title(&format!(
+        "Preview from [{:.2}, {:.2}, {:.2}], window {} {}: arrows orbit, +/- zoom, [/] move and ,/. resize the window, S saves, Esc quits",
+        p[0], p[1], p[2], low, high
+    ));
+}
+
+/// Shows the render in a window instead of writing it, and renders it again
+/// whenever the camera or the density window changes. The arrow keys orbit
+/// around the target, plus and minus zoom, the brackets move the density
+/// window and comma and period narrow and widen it, S writes the current
+/// image to the output file and Escape closes the window. The window is
+/// applied to the given source densities, which are those of the volume
+/// before any window. Never returns, the process exits with the window.
+pub fn run(mut data: RenderData, source: Vec<u8>, mut density_window: [u8; 2], output_file: String, flip_y: bool) -> ! {
+    // Creating the event loop panics without a display server to connect to
+    #[cfg(all(unix, not(target_os = "macos")))]
+    if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        error!("Could not open the preview window: neither DISPLAY nor WAYLAND_DISPLAY is set");
+        std::process::exit(1);
+    }
+    let event_loop = EventLoop::new();
+    let window = match WindowBuilder::new()
+        .with_title("Preview")
+        .with_inner_size(PhysicalSize::new(data.output_resolution[0], data.output_resolution[1]))
+        .build(&event_loop) {
+        Ok(w) => w,
+        Err(e) => {
+            error!("Could not open the preview window: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let instance = wgpu::Instance::new(data.backends);
+    if let Err(e) = pipeline::validate_memory(&data) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    let surface = unsafe { instance.create_surface(&window) };
+    let (adapter, device, queue) = match pollster::block_on(pipeline::request_device(&instance, Some(&surface), wgpu::Features::empty())) {
+        Ok(d) => d,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = pipeline::validate_limits(&data, &device.limits()) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    let mut display = Display::new(&adapter, &device, surface, window.inner_size(), data.output_resolution);
+
+    // Orbits keep the y axis up, unless another up direction was given
+    let up = data.up.map_or(Vector3f::new(0.0, 1.0, 0.0), |u| {
+        let mut up = Vector3f::new(u[0], u[1], u[2]);
+        up.normalize();
+        return up;
+    });
+    let mut output = RenderOutput::default();
+    let mut stale = true;
+    let bytes_per_voxel = data.volume_format.bytes_per_voxel() as usize;
+    update_title(&window, &data, density_window);
+
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Wait;
+        match event {
+            Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
+                *control_flow = ControlFlow::Exit;
+            },
+            Event::WindowEvent { event: WindowEvent::Resized(size), .. } => {
+                display.resize(&device, size);
+                window.request_redraw();
+            },
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput {
+                    input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+                    ..
+                },
+                ..
+            } => {
+                let window_change = match key {
+                    VirtualKeyCode::LBracket => Some((-WINDOW_STEP, 0)),
+                    VirtualKeyCode::RBracket => Some((WINDOW_STEP, 0)),
+                    VirtualKeyCode::Comma => Some((0, -WINDOW_STEP)),
+                    VirtualKeyCode::Period => Some((0, WINDOW_STEP)),
+                    _ => None
+                };
+                if let Some((shift, widen)) = window_change {
+                    density_window = adjusted_window(density_window, shift, widen);
+                    data.volume.copy_from_slice(&source);
+                    volume::apply_window(&mut data.volume, bytes_per_voxel, density_window);
+                    update_title(&window, &data, density_window);
+                    stale = true;
+                    window.request_redraw();
+                    return;
+                }
+
+                let (azimuth, elevation, zoom) = match key {
+                    VirtualKeyCode::Left => (-ORBIT_STEP, 0.0, 1.0),
+                    VirtualKeyCode::Right => (ORBIT_STEP, 0.0, 1.0),
+                    VirtualKeyCode::Up => (0.0, ORBIT_STEP, 1.0),
+                    VirtualKeyCode::Down => (0.0, -ORBIT_STEP, 1.0),
+                    VirtualKeyCode::Plus | VirtualKeyCode::Equals | VirtualKeyCode::NumpadAdd => (0.0, 0.0, ZOOM_STEP),
+                    VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => (0.0, 0.0, 1.0 / ZOOM_STEP),
+                    VirtualKeyCode::S => {
+                        let mut image = output.image.clone();
+                        if flip_y {
+                            crate::output::flip_rows(&mut image, data.output_resolution[0], 3);
+                        }
+                        let result = crate::output::write_output(&output_file, data.output_resolution[0], data.output_resolution[1],
+                            output_max_value(&data), image);
+                        match result {
+                            Ok(()) => info!("Image written!"),
+                            Err(e) => error!("Could not write image to file {:?}: {}", output_file, e)
+                        }
+                        return;
+                    },
+                    VirtualKeyCode::Escape | VirtualKeyCode::Q => {
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    },
+                    _ => return
+                };
+                orbit(&mut data, up, azimuth, elevation, zoom);
+                update_title(&window, &data, density_window);
+                stale = true;
+                window.request_redraw();
+            },
+            Event::RedrawRequested(_) => {
+                if stale {
+                    if let Err(e) = render(&device, &queue, &data, &mut output) {
+                        error!("{}", e);
+                        *control_flow = ControlFlow::ExitWithCode(1);
+                        return;
+                    }
+                    display.upload(&queue, &data, &output);
+                    stale = false;
+                }
+                display.present(&device, &queue);
+            },
+            _ => {}
+        }
+    });
+}
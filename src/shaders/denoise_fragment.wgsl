@@ -0,0 +1,76 @@
+// One level of an edge-aware a-trous wavelet filter. The depth and normal
+// of the first interaction act as edge-stopping guides, so that the
+// filter does not blur across silhouettes and surface creases.
+
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var dh_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var normal_texture: texture_2d<f32>;
+@group(0) @binding(3)
+var<uniform> step_size: u32;
+
+let sigma_color = 0.5;
+let sigma_depth = 0.05;
+let normal_exponent = 32.0;
+let hits_exponent = 4.0;
+
+// Weights of the B3 spline kernel
+fn kernel_weight(offset: i32) -> f32 {
+    if offset == 0 {
+        return 3.0 / 8.0;
+    }
+    if abs(offset) == 1 {
+        return 1.0 / 4.0;
+    }
+    return 1.0 / 16.0;
+}
+
+@fragment
+fn main(@builtin(position) in_position: vec4<f32>) -> @location(0) vec4<f32> {
+    let dims = vec2<i32>(textureDimensions(input_texture));
+    let center = vec2<i32>(in_position.xy);
+    let center_color = textureLoad(input_texture, center, 0);
+    let center_dh = textureLoad(dh_texture, center, 0);
+    let center_normal = textureLoad(normal_texture, center, 0).xyz;
+    let center_hit = center_dh.y > 0.0;
+
+    var color_sum = vec3<f32>(0.0);
+    var weight_sum = 0.0;
+
+    for (var dy = -2; dy <= 2; dy++) {
+        for (var dx = -2; dx <= 2; dx++) {
+            let offset = vec2<i32>(dx, dy) * i32(step_size);
+            let position = clamp(center + offset, vec2<i32>(0), dims - 1);
+
+            let color = textureLoad(input_texture, position, 0);
+            let dh = textureLoad(dh_texture, position, 0);
+            let normal = textureLoad(normal_texture, position, 0).xyz;
+
+            // Never mix pixels that hit the volume with ones that did not
+            if (dh.y > 0.0) != center_hit {
+                continue;
+            }
+
+            let color_difference = color.rgb - center_color.rgb;
+            var weight = kernel_weight(dx) * kernel_weight(dy);
+            weight *= exp(-dot(color_difference, color_difference) / (sigma_color * sigma_color));
+            if center_hit {
+                // Pixels on silhouettes only partially hit the volume
+                // and should not bleed into the fully covered ones
+                weight *= pow(min(dh.y, center_dh.y) / max(dh.y, center_dh.y), hits_exponent);
+                weight *= exp(-abs(dh.x - center_dh.x) / (sigma_depth * f32(step_size)));
+                weight *= pow(max(dot(normal, center_normal), 0.0), normal_exponent);
+            }
+
+            color_sum += weight * color.rgb;
+            weight_sum += weight;
+        }
+    }
+
+    if weight_sum <= 0.0 {
+        return center_color;
+    }
+    return vec4<f32>(color_sum / weight_sum, center_color.a);
+}
@@ -3,19 +3,62 @@ struct Photon {
     direction: vec3<f32>,
     transmittance: vec3<f32>,
     radiance: vec3<f32>,
+    // Light of the point light, and of the environment with next event
+    // estimation, gathered along the current path
+    direct_light: vec3<f32>,
     bounces: u32,
-    samples: u32
+    samples: u32,
+    // Mean of the squared luminance of the finished paths, for the noise estimate
+    mean_square: f32,
+    // Distance travelled along the primary ray, from the near plane
+    distance: f32
+}
+
+struct Clipping {
+    min: vec3<f32>,
+    max: vec3<f32>,
+    // Normal and offset, positions on the negative side are clipped
+    plane: vec4<f32>
+}
+
+struct Shading {
+    // Normalized, points towards the light
+    light_direction: vec3<f32>,
+    gradient_shading: u32,
+    // Point light in texture coordinates, turned off with zero intensity
+    light_position: vec3<f32>,
+    light_intensity: f32,
+    // Size of the volume in the scene, for distances to the light
+    volume_scale: vec3<f32>,
+    // 0 for Henyey-Greenstein with the anisotropy, 1 for the two-term
+    // Henyey-Greenstein with the parameters g1, g2 and the weight of g1,
+    // 2 for Rayleigh
+    phase_function: u32,
+    phase_parameters: vec3<f32>,
+    // Sample the environment at every scattering event instead of
+    // adding it when the photon escapes
+    next_event_estimation: u32,
+    // Bounces after which the paths are terminated by russian roulette,
+    // the largest u32 when turned off
+    russian_roulette_start: u32,
+    // Largest radiance of a single path, the largest f32 when turned off
+    max_sample_radiance: f32,
+    // 1 for the tricubic volume lookups, 0 for the sampler filtering alone
+    tricubic: u32
 }
 
 struct FragmentOutput {
     @location(0) position: vec4<f32>,
     @location(1) direction: vec4<f32>,
     @location(2) ts: vec4<f32>,
-    @location(3) rb: vec4<f32>
+    @location(3) rb: vec4<f32>,
+    @location(4) dh: vec4<f32>,
+    @location(5) albedo: vec4<f32>,
+    @location(6) normal: vec4<f32>
 }
 
 @group(0) @binding(0)
-var<uniform> random_seed: f32;
+var<uniform> random_seed: u32;
 
 @group(1) @binding(0)
 var<uniform> mvp_inverse: mat4x4<f32>;
@@ -31,6 +74,10 @@ var<uniform> anisotropy: f32;
 var<uniform> max_bounces: u32;
 @group(1) @binding(6)
 var<uniform> steps: u32;
+@group(1) @binding(7)
+var<uniform> clipping: Clipping;
+@group(1) @binding(8)
+var<uniform> shading: Shading;
 
 @group(2) @binding(0)
 var volume_texture: texture_3d<f32>;
@@ -40,6 +87,19 @@ var volume_sampler: sampler;
 var transfer_function_texture: texture_2d<f32>;
 @group(2) @binding(3)
 var transfer_function_sampler: sampler;
+@group(2) @binding(4)
+var label_texture: texture_3d<u32>;
+@group(2) @binding(5)
+var<uniform> label_mask: array<vec4<u32>, 2>;
+@group(2) @binding(6)
+var envmap_texture: texture_2d<f32>;
+@group(2) @binding(7)
+var envmap_marginal_texture: texture_2d<f32>;
+// Second transfer function, sampled like the first one and mixed into it
+@group(2) @binding(8)
+var blend_transfer_function_texture: texture_2d<f32>;
+@group(2) @binding(9)
+var<uniform> transfer_function_blend: f32;
 
 @group(3) @binding(0)
 var position_texture: texture_2d<f32>;
@@ -57,6 +117,18 @@ var ts_sampler: sampler;
 var rb_texture: texture_2d<f32>;
 @group(3) @binding(7)
 var rb_sampler: sampler;
+@group(3) @binding(8)
+var dh_texture: texture_2d<f32>;
+@group(3) @binding(9)
+var dh_sampler: sampler;
+@group(3) @binding(10)
+var albedo_texture: texture_2d<f32>;
+@group(3) @binding(11)
+var albedo_sampler: sampler;
+@group(3) @binding(12)
+var normal_texture: texture_2d<f32>;
+@group(3) @binding(13)
+var normal_sampler: sampler;
 
 fn hash(x: ptr<function, u32>) -> u32 {
     *x = *x * 747796405u + 2891336453u;
@@ -64,8 +136,13 @@ fn hash(x: ptr<function, u32>) -> u32 {
     return (*x >> 22u) ^ *x;
 }
 
-fn squash_linear(x: vec3<u32>) -> u32 {
-    var state = 19u*x.x + 47u*x.y + 101u*x.z + 131u;
+// Independent random state for every pixel and seed. The values are
+// hashed one after another, because mixing them linearly before a single
+// hash maps some pixels to the same state.
+fn pixel_state(pixel: vec2<u32>, seed: u32) -> u32 {
+    var state = seed;
+    state = hash(&state) ^ pixel.x;
+    state = hash(&state) ^ pixel.y;
     return hash(&state);
 }
 
@@ -84,11 +161,93 @@ fn random_exponential(state: ptr<function, u32>, rate: f32) -> f32 {
     return -log(random_uniform(state)) / rate;
 }
 
-fn sample_volume_color(position: vec3<f32>) -> vec4<f32> {
-    let volume_sample = textureSample(volume_texture, volume_sampler, position).r;
-    let location = vec2<f32>(volume_sample, 0.5);
-    let transfer_sample = textureSample(transfer_function_texture, transfer_function_sampler, location);
-    return transfer_sample;
+// Cubic B-spline interpolation of the volume from eight linear lookups,
+// each placed between two voxels so that the linear filtering weighs
+// them with the spline (Sigg and Hadwiger, GPU Gems 2, chapter 20)
+fn sample_volume_tricubic(position: vec3<f32>) -> vec4<f32> {
+    let size = vec3<f32>(textureDimensions(volume_texture));
+    let coordinate = position * size - 0.5;
+    let index = floor(coordinate);
+    let f = coordinate - index;
+    let f2 = f * f;
+    let f3 = f2 * f;
+    let w0 = (1.0 - 3.0 * f + 3.0 * f2 - f3) / 6.0;
+    let w1 = (4.0 - 6.0 * f2 + 3.0 * f3) / 6.0;
+    let w2 = (1.0 + 3.0 * f + 3.0 * f2 - 3.0 * f3) / 6.0;
+    let w3 = f3 / 6.0;
+    let g0 = w0 + w1;
+    let g1 = w2 + w3;
+    let h0 = (index - 0.5 + w1 / g0) / size;
+    let h1 = (index + 1.5 + w3 / g1) / size;
+
+    let s000 = textureSampleLevel(volume_texture, volume_sampler, h0, 0.0);
+    let s100 = textureSampleLevel(volume_texture, volume_sampler, vec3<f32>(h1.x, h0.y, h0.z), 0.0);
+    let s010 = textureSampleLevel(volume_texture, volume_sampler, vec3<f32>(h0.x, h1.y, h0.z), 0.0);
+    let s110 = textureSampleLevel(volume_texture, volume_sampler, vec3<f32>(h1.x, h1.y, h0.z), 0.0);
+    let s001 = textureSampleLevel(volume_texture, volume_sampler, vec3<f32>(h0.x, h0.y, h1.z), 0.0);
+    let s101 = textureSampleLevel(volume_texture, volume_sampler, vec3<f32>(h1.x, h0.y, h1.z), 0.0);
+    let s011 = textureSampleLevel(volume_texture, volume_sampler, vec3<f32>(h0.x, h1.y, h1.z), 0.0);
+    let s111 = textureSampleLevel(volume_texture, volume_sampler, h1, 0.0);
+
+    let near = g0.y * (g0.x * s000 + g1.x * s100) + g1.y * (g0.x * s010 + g1.x * s110);
+    let far = g0.y * (g0.x * s001 + g1.x * s101) + g1.y * (g0.x * s011 + g1.x * s111);
+    return g0.z * near + g1.z * far;
+}
+
+// Filtered volume lookup for the volume format variants
+fn sample_volume(position: vec3<f32>) -> vec4<f32> {
+    if shading.tricubic != 0u {
+        return sample_volume_tricubic(position);
+    }
+    // Explicit level of detail, implicit derivatives are undefined in the non-uniform photon loop
+    return textureSampleLevel(volume_texture, volume_sampler, position, 0.0);
+}
+
+// Replaced with the volume format variant (mcm_volume_*.wgsl), which
+// provides sample_volume_density and sample_volume_color
+// #volume_sampling
+
+// Voxels with labels outside of the mask are treated as empty
+fn is_label_visible(position: vec3<f32>) -> bool {
+    let dimensions = vec3<i32>(textureDimensions(label_texture));
+    let voxel = clamp(vec3<i32>(position * vec3<f32>(dimensions)), vec3<i32>(0), dimensions - 1);
+    let label = textureLoad(label_texture, voxel, 0).r;
+    return (label_mask[label / 128u][(label / 32u) % 4u] & (1u << (label % 32u))) != 0u;
+}
+
+// Volume color and density, with clipped and hidden voxels treated as empty
+fn sample_visible_color(position: vec3<f32>) -> vec4<f32> {
+    let clipped = any(position < clipping.min) || any(position > clipping.max) ||
+        dot(position, clipping.plane.xyz) - clipping.plane.w < 0.0;
+    if clipped || !is_label_visible(position) {
+        return vec4<f32>(0.0);
+    }
+    return sample_volume_color(position);
+}
+
+// Radiance of the equirectangular environment map in the given direction
+// (in texture coordinates), with the y axis of the scene pointing up
+fn sample_environment(direction: vec3<f32>) -> vec3<f32> {
+    let d = normalize(direction * shading.volume_scale);
+    let uv = vec2<f32>(atan2(d.x, -d.z) / 6.28318530718 + 0.5, acos(clamp(d.y, -1.0, 1.0)) / 3.14159265359);
+    let dimensions = vec2<i32>(textureDimensions(envmap_texture));
+    let texel = clamp(vec2<i32>(uv * vec2<f32>(dimensions)), vec2<i32>(0), dimensions - 1);
+    return textureLoad(envmap_texture, texel, 0).rgb;
+}
+
+// Points away from increasing density, or is zero in homogeneous regions
+fn sample_volume_normal(position: vec3<f32>) -> vec3<f32> {
+    let h = 1.0 / vec3<f32>(textureDimensions(volume_texture));
+    let gradient = vec3<f32>(
+        sample_volume_density(position + vec3<f32>(h.x, 0.0, 0.0)) - sample_volume_density(position - vec3<f32>(h.x, 0.0, 0.0)),
+        sample_volume_density(position + vec3<f32>(0.0, h.y, 0.0)) - sample_volume_density(position - vec3<f32>(0.0, h.y, 0.0)),
+        sample_volume_density(position + vec3<f32>(0.0, 0.0, h.z)) - sample_volume_density(position - vec3<f32>(0.0, 0.0, h.z))
+    );
+    let length_squared = dot(gradient, gradient);
+    if length_squared < 1e-12 {
+        return vec3<f32>(0.0);
+    }
+    return -gradient * inverseSqrt(length_squared);
 }
 
 fn random_square(state: ptr<function, u32>) -> vec2<f32> {
@@ -149,6 +308,8 @@ fn reset_photon(state: ptr<function, u32>, position: vec2<f32>, photon: ptr<func
     let t_bounds = max(intersect_cube(fr, (*photon).direction), vec2<f32>(0.0));
     (*photon).position = fr + t_bounds.x * (*photon).direction;
     (*photon).transmittance = vec3<f32>(1.0);
+    (*photon).direct_light = vec3<f32>(0.0);
+    (*photon).distance = t_bounds.x;
 }
 
 fn sample_henyey_greenstein_angle_cosine(state: ptr<function, u32>, g: f32) -> f32 {
@@ -168,6 +329,131 @@ fn sample_henyey_greenstein(state: ptr<function, u32>, g: f32, direction: vec3<f
     return normalize(u + lambda * direction);
 }
 
+fn henyey_greenstein(g: f32, angle_cosine: f32) -> f32 {
+    let g2 = g * g;
+    return (1.0 - g2) / (12.56637061436 * pow(1.0 + g2 - 2.0 * g * angle_cosine, 1.5));
+}
+
+fn cube_root(x: f32) -> f32 {
+    return sign(x) * pow(abs(x), 1.0 / 3.0);
+}
+
+// Inverts the cumulative distribution (x^3 + 3x + 4) / 8 of the cosine
+fn sample_rayleigh(state: ptr<function, u32>, direction: vec3<f32>) -> vec3<f32> {
+    let u = random_sphere(state);
+    let z = 4.0 * random_uniform(state) - 2.0;
+    let root = sqrt(z * z + 1.0);
+    let rayleigh_cos = cube_root(z + root) + cube_root(z - root);
+    let lambda = rayleigh_cos - dot(direction, u);
+    return normalize(u + lambda * direction);
+}
+
+fn sample_phase_function(state: ptr<function, u32>, direction: vec3<f32>) -> vec3<f32> {
+    if shading.phase_function == 1u {
+        let p = shading.phase_parameters;
+        let g = select(p.y, p.x, random_uniform(state) < p.z);
+        return sample_henyey_greenstein(state, g, direction);
+    } else if shading.phase_function == 2u {
+        return sample_rayleigh(state, direction);
+    }
+    return sample_henyey_greenstein(state, anisotropy, direction);
+}
+
+fn phase_function(angle_cosine: f32) -> f32 {
+    if shading.phase_function == 1u {
+        let p = shading.phase_parameters;
+        return p.z * henyey_greenstein(p.x, angle_cosine) + (1.0 - p.z) * henyey_greenstein(p.y, angle_cosine);
+    } else if shading.phase_function == 2u {
+        return 0.05968310365 * (1.0 + angle_cosine * angle_cosine);
+    }
+    return henyey_greenstein(anisotropy, angle_cosine);
+}
+
+// Ratio tracking of the transmittance along a segment inside the volume
+fn shadow_transmittance(state: ptr<function, u32>, origin: vec3<f32>, direction: vec3<f32>, length: f32) -> f32 {
+    var transmittance = 1.0;
+    var t = 0.0;
+    for (var i = 0u; i < 1024u; i++) {
+        t += random_exponential(state, extinction);
+        if t >= length || transmittance < 1e-3 {
+            break;
+        }
+        transmittance *= 1.0 - sample_visible_color(origin + t * direction).a;
+    }
+    return transmittance;
+}
+
+// Next event estimation of the point light at a scattering event
+fn sample_point_light(state: ptr<function, u32>, photon: Photon) -> vec3<f32> {
+    let to_light = shading.light_position - photon.position;
+    let distance = length(to_light);
+    let light_direction = to_light / distance;
+    let scene_distance = length(to_light * shading.volume_scale);
+    let t_far = max(intersect_cube(photon.position, light_direction).y, 0.0);
+    let transmittance = shadow_transmittance(state, photon.position, light_direction, min(distance, t_far));
+    let phase = phase_function(dot(photon.direction, light_direction));
+    return photon.transmittance * phase * transmittance * shading.light_intensity / (scene_distance * scene_distance);
+}
+
+// First texel of the cumulative distribution in the given texture row
+// (or the alpha channel of the environment map) that is above u
+fn find_cdf_texel(texture: texture_2d<f32>, row: i32, count: i32, u: f32, alpha: bool) -> i32 {
+    var low = 0;
+    var high = count - 1;
+    while low < high {
+        let middle = (low + high) / 2;
+        let texel = textureLoad(texture, vec2<i32>(middle, row), 0);
+        if select(texel.r, texel.a, alpha) > u {
+            high = middle;
+        } else {
+            low = middle + 1;
+        }
+    }
+    return low;
+}
+
+fn cdf_probability(texture: texture_2d<f32>, row: i32, index: i32, alpha: bool) -> f32 {
+    let texel = textureLoad(texture, vec2<i32>(index, row), 0);
+    var previous = vec4<f32>(0.0);
+    if index > 0 {
+        previous = textureLoad(texture, vec2<i32>(index - 1, row), 0);
+    }
+    return select(texel.r - previous.r, texel.a - previous.a, alpha);
+}
+
+// Next event estimation of the environment at a scattering event. The
+// direction is importance sampled by the luminance of the environment map
+// and weighted by the phase function.
+fn sample_environment_light(state: ptr<function, u32>, photon: Photon) -> vec3<f32> {
+    let dimensions = vec2<i32>(textureDimensions(envmap_texture));
+    let row = find_cdf_texel(envmap_marginal_texture, 0, dimensions.y, random_uniform(state), false);
+    let column = find_cdf_texel(envmap_texture, row, dimensions.x, random_uniform(state), true);
+    let texel_probability = cdf_probability(envmap_marginal_texture, 0, row, false) *
+        cdf_probability(envmap_texture, row, column, true);
+
+    // Uniformly within the texel, inverting the mapping of sample_environment
+    let uv = (vec2<f32>(f32(column), f32(row)) + random_square(state)) / vec2<f32>(dimensions);
+    let theta = uv.y * 3.14159265359;
+    let phi = (uv.x - 0.5) * 6.28318530718;
+    let scene_direction = vec3<f32>(sin(theta) * sin(phi), cos(theta), -sin(theta) * cos(phi));
+    let scene_pdf = texel_probability * f32(dimensions.x * dimensions.y) / (19.7392088022 * sin(theta));
+
+    // Density over the directions in texture coordinates, where the phase
+    // function is defined, from the change of solid angle
+    let scaled = scene_direction / shading.volume_scale;
+    let s = shading.volume_scale;
+    let pdf = scene_pdf * s.x * s.y * s.z * pow(length(scaled), 3.0);
+    if !(pdf > 0.0) || texel_probability <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+
+    let light_direction = normalize(scaled);
+    let t_far = max(intersect_cube(photon.position, light_direction).y, 0.0);
+    let transmittance = shadow_transmittance(state, photon.position, light_direction, t_far);
+    let phase = phase_function(dot(photon.direction, light_direction));
+    return photon.transmittance * phase * transmittance * sample_environment(light_direction) / pdf;
+}
+
 fn max3(v: vec3<f32>) -> f32 {
     return max(max(v.x, v.y), v.z);
 }
@@ -176,6 +462,37 @@ fn mean3(v: vec3<f32>) -> f32 {
     return dot(v, vec3<f32>(1.0 / 3.0));
 }
 
+// Scales down the radiance of paths that are brighter than the limit in
+// any channel, keeping their color
+fn clamp_sample(radiance: vec3<f32>) -> vec3<f32> {
+    let brightest = max3(radiance);
+    if brightest > shading.max_sample_radiance {
+        return radiance * (shading.max_sample_radiance / brightest);
+    }
+    return radiance;
+}
+
+fn luminance(v: vec3<f32>) -> f32 {
+    return dot(v, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+fn squared_luminance(v: vec3<f32>) -> f32 {
+    let l = luminance(v);
+    return l * l;
+}
+
+// Variance of the mean luminance of the pixel, estimated from its finished
+// paths, relative to the squared mean. The small constant keeps dark pixels
+// from dominating the estimate.
+fn relative_variance(photon: Photon) -> f32 {
+    if photon.samples < 2u {
+        return 1.0;
+    }
+    let mean = luminance(photon.radiance);
+    let variance = max(photon.mean_square - mean * mean, 0.0) / f32(photon.samples - 1u);
+    return variance / (mean * mean + 1e-4);
+}
+
 @fragment
 fn main(@builtin(position) in_position: vec4<f32>) -> FragmentOutput {
     let res_x_f32 = f32(resolution.x);
@@ -189,6 +506,9 @@ fn main(@builtin(position) in_position: vec4<f32>) -> FragmentOutput {
         out.direction = vec4<f32>(0.0);
         out.ts = vec4<f32>(0.0);
         out.rb = vec4<f32>(0.0);
+        out.dh = vec4<f32>(0.0);
+        out.albedo = vec4<f32>(0.0);
+        out.normal = vec4<f32>(0.0);
         return out;
     }
 
@@ -199,10 +519,13 @@ fn main(@builtin(position) in_position: vec4<f32>) -> FragmentOutput {
         (in_position.x / res_x_f32) * 2.0 - 1.0,
         (in_position.y / res_y_f32) * 2.0 - 1.0
     );
-    let mapped_position = position * 0.5 + 0.5;
+    // The textures can be wider than the rendered area because of row padding
+    let mapped_position = in_position.xy / vec2<f32>(textureDimensions(position_texture));
 
     var photon: Photon;
-    photon.position = textureSampleLevel(position_texture, position_sampler, mapped_position, 0.0).xyz;
+    let position_sample = textureSampleLevel(position_texture, position_sampler, mapped_position, 0.0);
+    photon.position = position_sample.xyz;
+    photon.mean_square = position_sample.w;
     photon.direction = textureSampleLevel(direction_texture, direction_sampler, mapped_position, 0.0).xyz;
     let ts = textureSampleLevel(ts_texture, ts_sampler, mapped_position, 0.0);
     photon.transmittance = ts.xyz;
@@ -210,18 +533,32 @@ fn main(@builtin(position) in_position: vec4<f32>) -> FragmentOutput {
     let rb = textureSampleLevel(rb_texture, rb_sampler, mapped_position, 0.0);
     photon.radiance = rb.xyz;
     photon.bounces = u32(rb.w + 0.5);
-
-    var state = squash_linear(vec3<u32>(
-        bitcast<u32>(position.x),
-        bitcast<u32>(position.y),
-        bitcast<u32>(random_seed)
-    ));
+    // The direct light is stored in the otherwise unused fourth channels
+    photon.direct_light = vec3<f32>(
+        textureSampleLevel(dh_texture, dh_sampler, mapped_position, 0.0).w,
+        textureSampleLevel(albedo_texture, albedo_sampler, mapped_position, 0.0).w,
+        textureSampleLevel(normal_texture, normal_sampler, mapped_position, 0.0).w
+    );
+    // Mean distance to the first interaction and the number of photons it
+    // was averaged over, followed by the distance of the current photon.
+    // Albedo and normal are averaged over the same first interactions.
+    let dh = textureSampleLevel(dh_texture, dh_sampler, mapped_position, 0.0);
+    var mean_depth = dh.x;
+    var hits = u32(dh.y + 0.5);
+    photon.distance = dh.z;
+    var mean_albedo = textureSampleLevel(albedo_texture, albedo_sampler, mapped_position, 0.0).rgb;
+    var mean_normal = textureSampleLevel(normal_texture, normal_sampler, mapped_position, 0.0).xyz;
+
+    var state = pixel_state(vec2<u32>(in_position.xy), random_seed);
 
     for (var i = 0u; i < steps; i++) {
         let dist = random_exponential(&state, extinction);
         photon.position += dist * photon.direction;
+        if photon.bounces == 0u {
+            photon.distance += dist;
+        }
 
-        let volume_sample = sample_volume_color(photon.position);
+        let volume_sample = sample_visible_color(photon.position);
 
         let p_null = 1.0 - volume_sample.a;
         var p_scattering: f32;
@@ -233,27 +570,66 @@ fn main(@builtin(position) in_position: vec4<f32>) -> FragmentOutput {
         let p_absorption = 1.0 - p_null - p_scattering;
 
         let fortune_wheel = random_uniform(&state);
-        if any(photon.position > v1) || any(photon.position < v0) {
-            let env_sample = vec3<f32>(1.0);
-            let radiance = photon.transmittance * env_sample;
+        let outside = any(photon.position > v1) || any(photon.position < v0);
+        if !outside && photon.bounces == 0u && fortune_wheel < p_absorption + p_scattering {
+            hits++;
+            let weight = 1.0 / f32(hits);
+            mean_depth += (photon.distance - mean_depth) * weight;
+            mean_albedo += (volume_sample.rgb - mean_albedo) * weight;
+            mean_normal += (sample_volume_normal(photon.position) - mean_normal) * weight;
+        }
+
+        if outside {
+            // With next event estimation, only the unscattered photons add the environment
+            var env_sample = sample_environment(photon.direction);
+            if shading.next_event_estimation != 0u && photon.bounces > 0u {
+                env_sample = v0;
+            }
+            let radiance = clamp_sample(photon.transmittance * env_sample + photon.direct_light);
             photon.samples++;
             photon.radiance += (radiance - photon.radiance) / f32(photon.samples);
+            photon.mean_square += (squared_luminance(radiance) - photon.mean_square) / f32(photon.samples);
             reset_photon(&state, position, &photon);
         } else if fortune_wheel < p_absorption {
-            let radiance = vec3<f32>(0.0);
+            let radiance = clamp_sample(photon.direct_light);
             photon.samples++;
             photon.radiance += (radiance - photon.radiance) / f32(photon.samples);
+            photon.mean_square += (squared_luminance(radiance) - photon.mean_square) / f32(photon.samples);
             reset_photon(&state, position, &photon);
         } else if fortune_wheel < p_absorption + p_scattering {
             photon.transmittance *= volume_sample.rgb;
-            photon.direction = sample_henyey_greenstein(&state, anisotropy, photon.direction);
+            // Diffuse term of the light from the gradient, homogeneous regions are left unshaded
+            let normal = sample_volume_normal(photon.position);
+            if shading.gradient_shading != 0u && any(normal != v0) {
+                photon.transmittance *= max(dot(normal, shading.light_direction), 0.0);
+            }
+            if shading.light_intensity > 0.0 {
+                photon.direct_light += sample_point_light(&state, photon);
+            }
+            if shading.next_event_estimation != 0u {
+                photon.direct_light += sample_environment_light(&state, photon);
+            }
+            photon.direction = sample_phase_function(&state, photon.direction);
             photon.bounces++;
+            // Surviving paths are brightened by the inverse probability to stay unbiased
+            if photon.bounces > shading.russian_roulette_start {
+                let survival = min(max3(photon.transmittance), 1.0);
+                if random_uniform(&state) < survival {
+                    photon.transmittance /= survival;
+                } else {
+                    let radiance = clamp_sample(photon.direct_light);
+                    photon.samples++;
+                    photon.radiance += (radiance - photon.radiance) / f32(photon.samples);
+                    photon.mean_square += (squared_luminance(radiance) - photon.mean_square) / f32(photon.samples);
+                    reset_photon(&state, position, &photon);
+                }
+            }
         }
     }
     
     var out: FragmentOutput;
-    out.position = vec4<f32>(photon.position, 0.0);
-    out.direction = vec4<f32>(photon.direction, 0.0);
+    out.position = vec4<f32>(photon.position, photon.mean_square);
+    out.direction = vec4<f32>(photon.direction, relative_variance(photon));
     out.ts = vec4<f32>(
         photon.transmittance,
         f32(photon.samples)
@@ -262,6 +638,9 @@ fn main(@builtin(position) in_position: vec4<f32>) -> FragmentOutput {
         photon.radiance,
         f32(photon.bounces)
     );
+    out.dh = vec4<f32>(mean_depth, f32(hits), photon.distance, photon.direct_light.r);
+    out.albedo = vec4<f32>(mean_albedo, photon.direct_light.g);
+    out.normal = vec4<f32>(mean_normal, photon.direct_light.b);
 
     return out;
 }
\ No newline at end of file
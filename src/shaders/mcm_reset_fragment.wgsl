@@ -16,17 +16,24 @@ struct FragmentOutput {
     @location(0) position: vec4<f32>,
     @location(1) direction: vec4<f32>,
     @location(2) ts: vec4<f32>,
-    @location(3) rb: vec4<f32>
+    @location(3) rb: vec4<f32>,
+    @location(4) dh: vec4<f32>,
+    @location(5) albedo: vec4<f32>,
+    @location(6) normal: vec4<f32>
 }
 
 @group(0) @binding(0)
-var<uniform> mvp_inverse: mat4x4<f32>;
+var<uniform> random_seed: u32;
 @group(0) @binding(1)
+var<uniform> reset_radiance: vec4<f32>;
+
+// The same uniforms as in the main pass, of which only the first are used
+@group(1) @binding(0)
+var<uniform> mvp_inverse: mat4x4<f32>;
+@group(1) @binding(1)
 var<uniform> resolution: vec2<u32>;
-@group(0) @binding(2)
+@group(1) @binding(2)
 var<uniform> inverse_resolution: vec2<f32>;
-@group(1) @binding(0)
-var<uniform> random_seed: f32;
 
 fn hash(x: ptr<function, u32>) -> u32 {
     *x = *x * 747796405u + 2891336453u;
@@ -34,8 +41,13 @@ fn hash(x: ptr<function, u32>) -> u32 {
     return (*x >> 22u) ^ *x;
 }
 
-fn squash_linear(x: vec3<u32>) -> u32 {
-    var state = 19u*x.x + 47u*x.y + 101u*x.z + 131u;
+// Independent random state for every pixel and seed. The values are
+// hashed one after another, because mixing them linearly before a single
+// hash maps some pixels to the same state.
+fn pixel_state(pixel: vec2<u32>, seed: u32) -> u32 {
+    var state = seed;
+    state = hash(&state) ^ pixel.x;
+    state = hash(&state) ^ pixel.y;
     return hash(&state);
 }
 
@@ -107,34 +119,35 @@ fn main(@builtin(position) in_position: vec4<f32>) -> FragmentOutput {
         out.direction = vec4<f32>(0.0);
         out.ts = vec4<f32>(0.0);
         out.rb = vec4<f32>(0.0);
+        out.dh = vec4<f32>(0.0);
+        out.albedo = vec4<f32>(0.0);
+        out.normal = vec4<f32>(0.0);
         return out;
     }
 
     let index = x + y * resolution.x;
 
     let position = vec2<f32>(
-        in_position.x / res_x_f32,
-        in_position.y / res_y_f32
+        (in_position.x / res_x_f32) * 2.0 - 1.0,
+        (in_position.y / res_y_f32) * 2.0 - 1.0
     );
 
     var photon: FragmentOutput;
     var fr: vec3<f32>;
     var to: vec3<f32>;
 
-    let hash_arg = vec3<u32>(
-        bitcast<u32>(in_position.x),
-        bitcast<u32>(in_position.y),
-        bitcast<u32>(random_seed)
-    );
-    var state = squash_linear(hash_arg);
+    var state = pixel_state(vec2<u32>(in_position.xy), random_seed);
 
     unproject_rand(&state, position, mvp_inverse, inverse_resolution, &fr, &to);
 
     photon.direction = vec4<f32>(normalize(to - fr), 0.0);
     let t_bounds = max(intersect_cube(fr, photon.direction.xyz), vec2<f32>(0.0, 0.0));
-    photon.position = vec4<f32>(fr - t_bounds.x * photon.direction.xyz, 0.0);
-    photon.ts = vec4<f32>(0.0, 0.0, 0.0, 0.0);
-    photon.rb = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    photon.position = vec4<f32>(fr + t_bounds.x * photon.direction.xyz, 0.0);
+    photon.ts = vec4<f32>(1.0, 1.0, 1.0, 0.0);
+    photon.rb = vec4<f32>(reset_radiance.xyz, 0.0);
+    photon.dh = vec4<f32>(0.0, 0.0, t_bounds.x, 0.0);
+    photon.albedo = vec4<f32>(0.0);
+    photon.normal = vec4<f32>(0.0);
 
     return photon;
 }
\ No newline at end of file
@@ -0,0 +1,13 @@
+// Volume sampling for scalar volumes, colored by the transfer function.
+// Inserted into mcm_main_fragment.wgsl.
+
+fn sample_volume_density(position: vec3<f32>) -> f32 {
+    return sample_volume(position).r;
+}
+
+fn sample_volume_color(position: vec3<f32>) -> vec4<f32> {
+    let location = vec2<f32>(sample_volume_density(position), 0.5);
+    let transfer_sample = textureSampleLevel(transfer_function_texture, transfer_function_sampler, location, 0.0);
+    let blend_sample = textureSampleLevel(blend_transfer_function_texture, transfer_function_sampler, location, 0.0);
+    return mix(transfer_sample, blend_sample, transfer_function_blend);
+}
@@ -0,0 +1,11 @@
+// Volume sampling for pre-colored volumes, which store the color in RGB
+// and the density in alpha. The transfer function is not used.
+// Inserted into mcm_main_fragment.wgsl.
+
+fn sample_volume_density(position: vec3<f32>) -> f32 {
+    return sample_volume(position).a;
+}
+
+fn sample_volume_color(position: vec3<f32>) -> vec4<f32> {
+    return sample_volume(position);
+}
@@ -0,0 +1,11 @@
+// Shows the tone mapped image in the preview window, one texel per pixel
+
+@group(0) @binding(0)
+var image: texture_2d<f32>;
+
+@fragment
+fn main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(image));
+    let texel = min(vec2<i32>(position.xy), size - vec2<i32>(1, 1));
+    return vec4<f32>(textureLoad(image, texel, 0).rgb, 1.0);
+}
@@ -12,6 +12,30 @@ var<uniform> high: f32;
 var<uniform> saturation: f32;
 @group(0) @binding(6)
 var<uniform> gamma: f32;
+@group(0) @binding(7)
+var<uniform> tonemap_operator: u32;
+@group(0) @binding(8)
+var<uniform> output_colorspace: u32;
+
+// Maps radiance above 1.0 back into the displayable range
+fn tone_map(color: vec3<f32>) -> vec3<f32> {
+    if tonemap_operator == 1u {
+        // Reinhard
+        return color / (1.0 + color);
+    }
+    if tonemap_operator == 2u {
+        // ACES filmic curve, as fitted by Narkowicz
+        return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+    }
+    return clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Piecewise sRGB transfer function, from linear values to encoded ones
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
 
 @fragment
 fn main(@builtin(position) in_position: vec4<f32>) -> @location(0) vec4<f32> {
@@ -23,10 +47,12 @@ fn main(@builtin(position) in_position: vec4<f32>) -> @location(0) vec4<f32> {
 
     var color = textureSample(input_texture, input_sampler, position);
     color = (color - low) / (high - low);
-    let gray = normalize(vec3<f32>(1.0));
+    color = vec4<f32>(tone_map(max(color.rgb, vec3<f32>(0.0))), 1.0);
+    // Rec. 709 luma stays the same for every saturation
+    let luma = dot(color.rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
     color = vec4<f32>(
         mix(
-            dot(color.rgb, gray) * gray,
+            vec3<f32>(luma),
             color.rgb,
             saturation
         ),
@@ -34,7 +60,13 @@ fn main(@builtin(position) in_position: vec4<f32>) -> @location(0) vec4<f32> {
     );
     let midpoint = (mid - low) / (high - low);
     let exponent = -log(midpoint) / log(2.0);
-    color = pow(color, vec4(exponent / gamma));
+    if output_colorspace == 1u {
+        color = vec4<f32>(linear_to_srgb(pow(color.rgb, vec3<f32>(exponent))), 1.0);
+    } else if output_colorspace == 2u {
+        color = pow(color, vec4(exponent));
+    } else {
+        color = pow(color, vec4(exponent / gamma));
+    }
     color = vec4<f32>(color.rgb, 1.0);
     return color;
 }
\ No newline at end of file
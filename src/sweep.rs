@@ -0,0 +1,156 @@
+use crate::pipeline::RenderData;
+
+/// Rendering parameter that --sweep varies
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SweepParameter {
+    Extinction,
+    Anisotropy,
+    Bounces,
+    Steps
+}
+
+impl SweepParameter {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            SweepParameter::Extinction => "extinction",
+            SweepParameter::Anisotropy => "anisotropy",
+            SweepParameter::Bounces => "bounces",
+            SweepParameter::Steps => "steps"
+        };
+    }
+
+    /// Letter that stands for the parameter in the labels of the contact sheet
+    fn letter(&self) -> char {
+        return match self {
+            SweepParameter::Extinction => 'E',
+            SweepParameter::Anisotropy => 'A',
+            SweepParameter::Bounces => 'B',
+            SweepParameter::Steps => 'S'
+        };
+    }
+
+    fn is_integer(&self) -> bool {
+        return matches!(self, SweepParameter::Bounces | SweepParameter::Steps);
+    }
+
+    pub fn apply(&self, data: &mut RenderData, value: f32) {
+        match self {
+            SweepParameter::Extinction => data.extinction = value,
+            SweepParameter::Anisotropy => data.anisotropy = value,
+            SweepParameter::Bounces => data.max_bounces = value as u32,
+            SweepParameter::Steps => data.steps = Some(value as u32)
+        }
+    }
+}
+
+/// Parses one parameter of --sweep, such as "extinction=50,100,200"
+pub fn parse_parameter(spec: &str) -> Result<(SweepParameter, Vec<f32>), String> {
+    let (name, values) = match spec.split_once('=') {
+        Some(s) => s,
+        None => return Err(format!("Sweep parameter {:?} has no values (expected NAME=V1,V2,...)!", spec))
+    };
+    let parameter = match name.trim() {
+        "extinction" => SweepParameter::Extinction,
+        "anisotropy" => SweepParameter::Anisotropy,
+        "bounces" => SweepParameter::Bounces,
+        "steps" => SweepParameter::Steps,
+        _ => return Err(format!("Unknown sweep parameter {:?} (expected extinction, anisotropy, bounces or steps)", name))
+    };
+    let mut parsed = Vec::new();
+    for v in values.split(',') {
+        let value = match v.trim().parse::<f32>() {
+            Ok(f) if f.is_finite() => f,
+            _ => return Err(format!("Sweep value {:?} of {} is not a number!", v, parameter.name()))
+        };
+        let minimum = if parameter == SweepParameter::Steps { 1.0 } else { 0.0 };
+        if parameter.is_integer() && !(value.fract() == 0.0 && value >= minimum) {
+            return Err(format!("Sweep value {:?} of {} must be an integer of at least {}!", v, parameter.name(), minimum));
+        }
+        parsed.push(value);
+    }
+    return Ok((parameter, parsed));
+}
+
+/// Every combination of the swept values, with the first parameter changing
+/// fastest, so that it runs along the columns of the contact sheet
+pub fn combinations(sweep: &[(SweepParameter, Vec<f32>)]) -> Vec<Vec<(SweepParameter, f32)>> {
+    let count = sweep.iter().map(|(_, values)| values.len()).product();
+    return (0..count).map(|i| {
+        let mut rest = i;
+        return sweep.iter().map(|(parameter, values)| {
+            let value = values[rest % values.len()];
+            rest /= values.len();
+            return (*parameter, value);
+        }).collect();
+    }).collect();
+}
+
+/// Short label of a cell of the contact sheet, such as "E=50 A=-0.3"
+pub fn label(cell: &[(SweepParameter, f32)]) -> String {
+    let parts: Vec<String> = cell.iter().map(|(p, v)| format!("{}={}", p.letter(), v)).collect();
+    return parts.join(" ");
+}
+
+/// Rows of the 3x5 pixel glyphs of the labels, from the top one, with the
+/// left column in the highest of the three bits
+fn glyph(c: char) -> [u8; 5] {
+    return match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        _ => [0; 5]
+    };
+}
+
+/// Height of the strip below every cell that holds its label
+pub const LABEL_HEIGHT: u32 = 9;
+
+/// Puts the images of the cells into a grid with the label of every cell
+/// in a black strip below it, drawn in white text that is clipped at the
+/// edge of the cell. The columns hold the values of the first parameter.
+/// Returns the RGB values and the size of the contact sheet.
+pub fn contact_sheet(images: &[Vec<u16>], labels: &[String], columns: usize, cell_size: [u32; 2], max_value: u16) -> (Vec<u16>, [u32; 2]) {
+    let [cell_width, cell_height] = cell_size.map(|s| s as usize);
+    let rows = images.len().div_ceil(columns);
+    let width = cell_width * columns;
+    let row_height = cell_height + LABEL_HEIGHT as usize;
+    let height = row_height * rows;
+    let mut sheet = vec![0; width * height * 3];
+
+    for (i, (image, label)) in images.iter().zip(labels).enumerate() {
+        let (x0, y0) = ((i % columns) * cell_width, (i / columns) * row_height);
+        for y in 0..cell_height {
+            let source = y * cell_width * 3;
+            let target = ((y0 + y) * width + x0) * 3;
+            sheet[target..target + cell_width * 3].copy_from_slice(&image[source..source + cell_width * 3]);
+        }
+
+        let top = y0 + cell_height + 2;
+        for (c, character) in label.chars().enumerate() {
+            for (gy, bits) in glyph(character).iter().enumerate() {
+                for gx in 0..3 {
+                    let x = 1 + c * 4 + gx;
+                    if x < cell_width && bits & (0b100 >> gx) != 0 {
+                        let target = ((top + gy) * width + x0 + x) * 3;
+                        sheet[target..target + 3].fill(max_value);
+                    }
+                }
+            }
+        }
+    }
+    return (sheet, [width as u32, height as u32]);
+}
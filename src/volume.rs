@@ -0,0 +1,332 @@
+use std::{fs, io::{Error, Read}};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+#[cfg(feature = "gzip")]
+fn decompress_gzip(contents: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut decoder = flate2::read::MultiGzDecoder::new(contents);
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed)?;
+    return Ok(decompressed);
+}
+
+#[cfg(not(feature = "gzip"))]
+fn decompress_gzip(_contents: &[u8]) -> Result<Vec<u8>, Error> {
+    return Err(Error::new(
+        std::io::ErrorKind::Unsupported,
+        "volume is gzip-compressed, but gzip support was not compiled in (enable the \"gzip\" feature)"
+    ));
+}
+
+/// Reads the volume from the given file, or from standard input
+/// when the filename is "-". Gzip-compressed data is detected by
+/// its magic bytes and decompressed transparently. Raw voxels can
+/// start with the same bytes, so data that fails to decompress is
+/// read as it is, unless the name of the file ends with ".gz".
+pub fn read_volume(filename: &str) -> Result<Vec<u8>, Error> {
+    let contents = if filename == "-" {
+        let mut contents = Vec::new();
+        std::io::stdin().read_to_end(&mut contents)?;
+        contents
+    } else {
+        fs::read(filename)?
+    };
+
+    // The magic bytes are followed by the deflate method, the only one of gzip
+    if contents.starts_with(&[0x1f, 0x8b, 0x08]) {
+        match decompress_gzip(&contents) {
+            Ok(decompressed) => return Ok(decompressed),
+            Err(e) if filename.ends_with(".gz") => return Err(e),
+            Err(_) => {}
+        }
+    }
+    return Ok(contents);
+}
+
+/// Linearly maps values so that the smallest finite one becomes 0 and
+/// the largest finite one 255. Infinities are clamped to the range and
+/// NaNs become 0.
+fn rescale_to_u8(values: &[f32]) -> Vec<u8> {
+    let finite = values.iter().cloned().filter(|v| v.is_finite());
+    let min = finite.clone().fold(f32::INFINITY, f32::min);
+    let max = finite.fold(f32::NEG_INFINITY, f32::max);
+    let range = max - min;
+    if !range.is_finite() || range <= 0.0 {
+        return vec![0; values.len()];
+    }
+    return values.iter().map(|v| ((v - min) / range * 255.0).clamp(0.0, 255.0).round() as u8).collect();
+}
+
+/// Type and byte order of the values of a raw volume
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VolumeDataType {
+    U8,
+    I16Le,
+    I16Be,
+    U16Le,
+    U16Be,
+    F32Le,
+    F32Be
+}
+
+impl VolumeDataType {
+    pub fn element_size(&self) -> usize {
+        return match self {
+            VolumeDataType::U8 => 1,
+            VolumeDataType::I16Le | VolumeDataType::I16Be | VolumeDataType::U16Le | VolumeDataType::U16Be => 2,
+            VolumeDataType::F32Le | VolumeDataType::F32Be => 4
+        };
+    }
+}
+
+/// Interprets the bytes as values of the given type and rescales them to
+/// the full 8-bit range, like the 16-bit data of NRRD volumes. 8-bit data
+/// is returned unchanged.
+pub fn convert_to_u8(data: &[u8], data_type: VolumeDataType) -> Vec<u8> {
+    let values: Vec<f32> = match data_type {
+        VolumeDataType::U8 => return data.to_vec(),
+        VolumeDataType::I16Le => data.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]]) as f32).collect(),
+        VolumeDataType::I16Be => data.chunks_exact(2).map(|b| i16::from_be_bytes([b[0], b[1]]) as f32).collect(),
+        VolumeDataType::U16Le => data.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]]) as f32).collect(),
+        VolumeDataType::U16Be => data.chunks_exact(2).map(|b| u16::from_be_bytes([b[0], b[1]]) as f32).collect(),
+        VolumeDataType::F32Le => data.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect(),
+        VolumeDataType::F32Be => data.chunks_exact(4).map(|b| f32::from_be_bytes([b[0], b[1], b[2], b[3]])).collect()
+    };
+    return rescale_to_u8(&values);
+}
+
+pub fn is_nrrd(contents: &[u8]) -> bool {
+    return contents.starts_with(b"NRRD000");
+}
+
+/// Parses an attached-header NRRD file and returns the voxel data
+/// as 8-bit values together with the volume dimensions. Supports
+/// raw and gzip encodings of 8-bit and 16-bit data; 16-bit data is
+/// rescaled to the full 8-bit range.
+pub fn read_nrrd(contents: &[u8]) -> Result<(Vec<u8>, [u32; 3]), String> {
+    let mut header_end = None;
+    for i in 0..contents.len() {
+        if contents[i..].starts_with(b"\n\n") {
+            header_end = Some((i, i + 2));
+            break;
+        }
+        if contents[i..].starts_with(b"\r\n\r\n") {
+            header_end = Some((i, i + 4));
+            break;
+        }
+    }
+    let (header_len, data_start) = match header_end {
+        Some(h) => h,
+        None => return Err("NRRD header is not terminated by an empty line".to_string())
+    };
+    let header = match std::str::from_utf8(&contents[..header_len]) {
+        Ok(h) => h,
+        Err(_) => return Err("NRRD header is not valid text".to_string())
+    };
+
+    let mut sizes = None;
+    let mut element_type = None;
+    let mut encoding = "raw".to_string();
+    let mut big_endian = false;
+
+    for line in header.lines().skip(1) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.contains(":=") {
+            continue;
+        }
+        let (key, value) = match line.split_once(": ") {
+            Some(kv) => kv,
+            None => return Err(format!("Malformed NRRD header line {:?}", line))
+        };
+        let value = value.trim();
+        match key {
+            "dimension" if value != "3" => {
+                return Err(format!("Only 3-dimensional NRRD volumes are supported (got dimension {})", value));
+            },
+            "sizes" => {
+                let parsed: Result<Vec<u32>, _> = value.split_whitespace().map(|v| v.parse::<u32>()).collect();
+                match parsed {
+                    Ok(v) if v.len() == 3 => sizes = Some([v[0], v[1], v[2]]),
+                    _ => return Err(format!("Invalid NRRD sizes {:?}", value))
+                }
+            },
+            "type" => element_type = Some(value.to_string()),
+            "encoding" => encoding = value.to_string(),
+            "endian" => big_endian = value == "big",
+            "data file" | "datafile" => {
+                return Err("Detached NRRD headers are not supported".to_string());
+            },
+            _ => {}
+        }
+    }
+
+    let dimensions = match sizes {
+        Some(s) => s,
+        None => return Err("NRRD header is missing the sizes field".to_string())
+    };
+    let element_type = match element_type {
+        Some(t) => t,
+        None => return Err("NRRD header is missing the type field".to_string())
+    };
+
+    let data = match encoding.as_str() {
+        "raw" => contents[data_start..].to_vec(),
+        "gzip" | "gz" => match decompress_gzip(&contents[data_start..]) {
+            Ok(d) => d,
+            Err(e) => return Err(format!("Could not decompress NRRD data: {}", e))
+        },
+        _ => return Err(format!("Unsupported NRRD encoding {:?}", encoding))
+    };
+
+    let voxels = dimensions[0] as usize * dimensions[1] as usize * dimensions[2] as usize;
+
+    let volume = match element_type.as_str() {
+        "uchar" | "unsigned char" | "uint8" | "uint8_t" => {
+            if data.len() < voxels {
+                return Err(format!("NRRD data has {} bytes, expected {}", data.len(), voxels));
+            }
+            data[..voxels].to_vec()
+        },
+        "short" | "short int" | "signed short" | "signed short int" | "int16" | "int16_t" |
+        "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => {
+            if data.len() < voxels * 2 {
+                return Err(format!("NRRD data has {} bytes, expected {}", data.len(), voxels * 2));
+            }
+            let data_type = match (element_type.starts_with('u'), big_endian) {
+                (true, true) => VolumeDataType::U16Be,
+                (true, false) => VolumeDataType::U16Le,
+                (false, true) => VolumeDataType::I16Be,
+                (false, false) => VolumeDataType::I16Le
+            };
+            convert_to_u8(&data[..voxels * 2], data_type)
+        },
+        _ => return Err(format!("Unsupported NRRD type {:?}", element_type))
+    };
+
+    return Ok((volume, dimensions));
+}
+
+/// Counts the voxels with each density value, which is the last
+/// byte of each voxel
+pub fn histogram(volume: &[u8], bytes_per_voxel: usize) -> [u64; 256] {
+    let mut counts = [0; 256];
+    for voxel in volume.chunks_exact(bytes_per_voxel) {
+        counts[voxel[bytes_per_voxel - 1] as usize] += 1;
+    }
+    return counts;
+}
+
+/// Linearly rescales the density of every voxel, its last byte, so that the
+/// smallest density in the volume becomes 0 and the largest one 255, and
+/// returns the smallest and largest density before rescaling. Volumes with
+/// a single density are left unchanged.
+pub fn normalize(volume: &mut [u8], bytes_per_voxel: usize) -> (u8, u8) {
+    let counts = histogram(volume, bytes_per_voxel);
+    let min = counts.iter().position(|c| *c > 0).unwrap_or(0) as u8;
+    let max = counts.iter().rposition(|c| *c > 0).unwrap_or(0) as u8;
+    if min < max {
+        let scale = 255.0 / (max - min) as f32;
+        for voxel in volume.chunks_exact_mut(bytes_per_voxel) {
+            let density = &mut voxel[bytes_per_voxel - 1];
+            *density = ((*density - min) as f32 * scale).round() as u8;
+        }
+    }
+    return (min, max);
+}
+
+/// Linearly remaps the density of every voxel, its last byte, so that the
+/// window from low to high covers the full range from 0 to 255. Densities
+/// below the window become 0 and densities above it 255.
+pub fn apply_window(volume: &mut [u8], bytes_per_voxel: usize, [low, high]: [u8; 2]) {
+    let scale = 255.0 / (high - low) as f32;
+    for voxel in volume.chunks_exact_mut(bytes_per_voxel) {
+        let density = &mut voxel[bytes_per_voxel - 1];
+        *density = (((*density).clamp(low, high) - low) as f32 * scale).round() as u8;
+    }
+}
+
+/// Permutes and mirrors the axes of the volume, where axis i of the result
+/// is axis axes[i] of the given volume, and is then mirrored if flip[i] is
+/// set. Returns the reoriented voxels and their dimensions.
+pub fn reorient(volume: &[u8], dimensions: [u32; 3], bytes_per_voxel: usize, axes: [usize; 3], flip: [bool; 3]) -> (Vec<u8>, [u32; 3]) {
+    let dims = dimensions.map(|d| d as usize);
+    let new_dims = axes.map(|a| dims[a]);
+    let mut reoriented = Vec::with_capacity(dims[0] * dims[1] * dims[2] * bytes_per_voxel);
+    for z in 0..new_dims[2] {
+        for y in 0..new_dims[1] {
+            for x in 0..new_dims[0] {
+                let mut source = [0; 3];
+                for (i, coordinate) in [x, y, z].into_iter().enumerate() {
+                    source[axes[i]] = if flip[i] { new_dims[i] - 1 - coordinate } else { coordinate };
+                }
+                let index = ((source[2] * dims[1] + source[1]) * dims[0] + source[0]) * bytes_per_voxel;
+                reoriented.extend_from_slice(&volume[index..index + bytes_per_voxel]);
+            }
+        }
+    }
+    return (reoriented, new_dims.map(|d| d as u32));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VolumeShape {
+    Sphere,
+    Cube,
+    Gradient,
+    Noise
+}
+
+/// Side of the cells between the random values of the noise volume, in voxels
+const NOISE_CELL_SIZE: u32 = 8;
+
+/// Generates a procedural 8-bit volume for demos and testing. The shapes
+/// are defined in normalized coordinates, so they fill the same part of
+/// the volume regardless of its dimensions. The noise volume interpolates
+/// random values on a coarse lattice, generated from the given seed.
+pub fn generate_volume(shape: VolumeShape, dimensions: [u32; 3], seed: u64) -> Vec<u8> {
+    let lattice = dimensions.map(|d| (d / NOISE_CELL_SIZE + 2) as usize);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let noise: Vec<f32> = match shape {
+        VolumeShape::Noise => (0..lattice[0] * lattice[1] * lattice[2]).map(|_| rng.gen::<f32>()).collect(),
+        _ => Vec::new()
+    };
+
+    let voxels = dimensions[0] as usize * dimensions[1] as usize * dimensions[2] as usize;
+    let mut volume = Vec::with_capacity(voxels);
+    for z in 0..dimensions[2] {
+        for y in 0..dimensions[1] {
+            for x in 0..dimensions[0] {
+                let voxel = [x, y, z];
+                let position = [0, 1, 2].map(|i| (voxel[i] as f32 + 0.5) / dimensions[i] as f32);
+                let density = match shape {
+                    VolumeShape::Sphere => {
+                        let d = position.map(|p| p - 0.5);
+                        if d[0] * d[0] + d[1] * d[1] + d[2] * d[2] <= 0.4 * 0.4 { 1.0 } else { 0.0 }
+                    },
+                    VolumeShape::Cube => {
+                        if position.iter().all(|p| (0.2..=0.8).contains(p)) { 1.0 } else { 0.0 }
+                    },
+                    VolumeShape::Gradient => position[0],
+                    VolumeShape::Noise => {
+                        let cell = voxel.map(|v| v as f32 / NOISE_CELL_SIZE as f32);
+                        let mut value = 0.0;
+                        for corner in 0..8 {
+                            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+                            let mut weight = 1.0;
+                            let mut index = 0;
+                            for i in (0..3).rev() {
+                                let base = cell[i].floor();
+                                let fraction = cell[i] - base;
+                                weight *= if offset[i] == 1 { fraction } else { 1.0 - fraction };
+                                index = index * lattice[i] + base as usize + offset[i];
+                            }
+                            value += weight * noise[index];
+                        }
+                        value
+                    }
+                };
+                volume.push((density * 255.0f32).round() as u8);
+            }
+        }
+    }
+    return volume;
+}
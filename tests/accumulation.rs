@@ -0,0 +1,42 @@
+//! Checks that the radiance is averaged over the iterations rather than
+//! replaced by the last one: the difference to a reference render of a
+//! noisy scattering medium shrinks with more iterations.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+/// Channel values of the 16-bit render, or None when there is no GPU
+fn render(iterations: u32) -> Option<Vec<f64>> {
+    let directory = common::directory("accumulation");
+    let name = format!("{}_iterations", iterations);
+    let volume = common::volume(&directory, &name, &[128; 8 * 8 * 8]);
+    let output = common::output(&directory, &format!("{}.ppm", name));
+
+    let result = common::run_on_gpu(&name, common::command()
+        .args(["--quiet", "--volume-dimensions", "8", "8", "8", "--out-resolution", "16", "16", "--output-bit-depth", "16",
+            "--extinction", "20", "--iterations", &iterations.to_string(), "--volume"])
+        .arg(&volume)
+        .arg("--output")
+        .arg(&output))?;
+    common::assert_success(&result);
+    return Some(common::ppm_values(&output).into_iter().map(f64::from).collect());
+}
+
+fn mean_squared_error(image: &[f64], reference: &[f64]) -> f64 {
+    return image.iter().zip(reference).map(|(a, b)| (a - b) * (a - b)).sum::<f64>() / image.len() as f64;
+}
+
+#[test]
+fn error_decreases_with_iterations() {
+    let reference = match render(1024) {
+        Some(r) => r,
+        None => return
+    };
+    let errors: Vec<f64> = [4, 32, 256].iter().map(|i| mean_squared_error(&render(*i).unwrap(), &reference)).collect();
+    assert!(errors[0] > 0.0, "a render of 4 iterations matches the reference");
+    // The variance of a mean falls with the number of samples, so eight
+    // times the iterations should leave well under half the error
+    assert!(errors[1] < errors[0] * 0.5, "errors {:?}", errors);
+    assert!(errors[2] < errors[1] * 0.5, "errors {:?}", errors);
+}
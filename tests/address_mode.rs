@@ -0,0 +1,31 @@
+//! Checks that unknown --address-mode values are rejected, on the command
+//! line and in config files
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::fs;
+
+fn assert_rejected(args: &[&str]) {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere"])
+        .args(args)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!result.status.success(), "{:?} was accepted", args);
+    assert!(stderr.contains("Unknown address mode \"wrap\""), "unexpected error: {}", stderr);
+}
+
+#[test]
+fn rejects_unknown_mode() {
+    assert_rejected(&["--address-mode", "wrap"]);
+}
+
+#[test]
+fn rejects_unknown_mode_in_config() {
+    let config = common::directory("address_mode").join("wrap.toml");
+    fs::write(&config, "[rendering]\naddress_mode = \"wrap\"\n").unwrap();
+    assert_rejected(&["--config", config.to_str().unwrap()]);
+}
@@ -0,0 +1,35 @@
+//! Checks the output of --list-backends and the validation of --backend
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+const BACKENDS: [&str; 5] = ["vulkan", "metal", "dx12", "dx11", "gl"];
+
+#[test]
+fn lists_every_backend() {
+    let result = common::command()
+        .arg("--list-backends")
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "listing the backends failed: {}", String::from_utf8_lossy(&result.stderr));
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    for line in stdout.lines() {
+        let name = line.split(": ").next().unwrap();
+        assert!(BACKENDS.contains(&name), "unexpected line {:?}", line);
+    }
+    for backend in BACKENDS {
+        assert!(stdout.lines().any(|l| l.starts_with(&format!("{}: ", backend))), "{} is missing from {:?}", backend, stdout);
+    }
+}
+
+#[test]
+fn rejects_unknown_backend() {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere", "--backend", "opengl"])
+        .output()
+        .unwrap();
+    assert!(!result.status.success(), "an unknown backend was accepted");
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("Unknown backend \"opengl\"") && stderr.contains("vulkan, metal, dx12, dx11 or gl"), "unexpected error: {}", stderr);
+}
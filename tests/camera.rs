@@ -0,0 +1,36 @@
+//! Checks that a camera placed at its look-at target is rejected instead of
+//! rendering a black frame.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::process::Output;
+
+fn parse(extra_args: &[&str]) -> Output {
+    return common::command()
+        .args(["--dry-run", "--generate", "sphere"])
+        .args(extra_args)
+        .output()
+        .unwrap();
+}
+
+#[test]
+fn rejects_camera_at_target() {
+    let result = parse(&["--camera-position", "0", "0", "0"]);
+    assert!(!result.status.success(), "a camera at the default target was accepted");
+    assert!(String::from_utf8_lossy(&result.stderr).contains("coincides with the look-at target"));
+}
+
+#[test]
+fn rejects_camera_next_to_target() {
+    let result = parse(&["--camera-position", "0.5", "0.5", "0.5", "--look-at", "0.5", "0.5", "0.500001"]);
+    assert!(!result.status.success(), "a camera a rounding error away from the target was accepted");
+    assert!(String::from_utf8_lossy(&result.stderr).contains("coincides with the look-at target"));
+}
+
+#[test]
+fn accepts_close_camera() {
+    let result = parse(&["--camera-position", "0", "0", "0.01"]);
+    assert!(result.status.success(), "a camera close to the target was rejected: {}", String::from_utf8_lossy(&result.stderr));
+}
@@ -0,0 +1,57 @@
+//! Checks that Ctrl-C stops a long render early and still writes the image
+//! of the iterations that were completed.
+
+#![cfg(unix)]
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::{io::{BufRead, BufReader}, process::{Command, Stdio}};
+
+#[test]
+fn writes_partial_image() {
+    let directory = common::directory("cancel");
+    let volume = common::volume(&directory, "volume", &[255; 8 * 8 * 8]);
+    let output = common::output(&directory, "output.ppm");
+
+    let mut child = common::command()
+        .args(["-v", "--volume-dimensions", "8", "8", "8", "--out-resolution", "16", "16"])
+        .args(["--iterations", "1000000", "--max-iterations-per-submit", "1", "--volume"])
+        .arg(&volume)
+        .arg("--output")
+        .arg(&output)
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // The handler is installed before the tiles are rendered
+    let mut stderr = String::new();
+    let mut lines = BufReader::new(child.stderr.take().unwrap()).lines();
+    for line in lines.by_ref() {
+        let line = line.unwrap();
+        stderr.push_str(&line);
+        stderr.push('\n');
+        if line.contains("Rendering 1 tiles") || common::no_gpu(&line) {
+            break;
+        }
+    }
+    if common::no_gpu(&stderr) {
+        let _ = child.kill();
+        let _ = child.wait();
+        eprintln!("Skipping, no GPU adapter");
+        return;
+    }
+
+    let kill = Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+    assert!(kill.success());
+    for line in lines {
+        stderr.push_str(&line.unwrap());
+        stderr.push('\n');
+    }
+    let status = child.wait().unwrap();
+    assert!(status.success(), "render failed: {}", stderr);
+    assert!(stderr.contains("of 1000000 iterations"), "no cancellation in: {}", stderr);
+    // Reported through the logger like every other diagnostic
+    assert!(stderr.contains("WARNING: Cancelling after the current batch"), "no warning in: {}", stderr);
+    assert!(output.exists());
+}
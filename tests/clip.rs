@@ -0,0 +1,39 @@
+//! Checks that invalid clip ranges and clip planes are rejected
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+fn error(args: &[&str]) -> String {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(!result.status.success(), "{:?} was accepted", args);
+    return String::from_utf8_lossy(&result.stderr).to_string();
+}
+
+#[test]
+fn rejects_inverted_range() {
+    let stderr = error(&["--clip-x", "0.6", "0.4"]);
+    assert!(stderr.contains("Clip bounds must be finite, with the minimum below the maximum"), "unexpected error: {}", stderr);
+}
+
+#[test]
+fn rejects_nan_bound() {
+    let stderr = error(&["--clip-z", "0", "NaN"]);
+    assert!(stderr.contains("Clip bounds must be finite"), "unexpected error: {}", stderr);
+}
+
+#[test]
+fn rejects_plane_without_normal() {
+    let stderr = error(&["--clip-plane", "0", "0", "0", "0.5"]);
+    assert!(stderr.contains("Clip plane must be finite and have a non-zero normal"), "unexpected error: {}", stderr);
+}
+
+#[test]
+fn rejects_infinite_plane() {
+    let stderr = error(&["--clip-plane", "1", "0", "0", "inf"]);
+    assert!(stderr.contains("Clip plane must be finite"), "unexpected error: {}", stderr);
+}
@@ -0,0 +1,117 @@
+//! Helpers of the integration tests, which run the renderer on small
+//! volumes written into the temporary directory of Cargo. The renders that
+//! need a GPU are skipped when no GPU adapter is available.
+
+use std::{fs, path::{Path, PathBuf}, process::{Command, Output}};
+
+/// Directory of the files of a test, created when it does not exist yet
+pub fn directory(name: &str) -> PathBuf {
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::create_dir_all(&directory).unwrap();
+    return directory;
+}
+
+/// Writes the voxels into a raw volume. Every test writes volumes of its
+/// own, so that tests running in parallel never read a half written one.
+pub fn volume(directory: &Path, name: &str, voxels: &[u8]) -> PathBuf {
+    let volume = directory.join(format!("{}.raw", name));
+    fs::write(&volume, voxels).unwrap();
+    return volume;
+}
+
+/// Path of an output file, without the file of an earlier run
+pub fn output(directory: &Path, file_name: &str) -> PathBuf {
+    let output = directory.join(file_name);
+    let _ = fs::remove_file(&output);
+    return output;
+}
+
+/// Writes a cube of the given size with a sphere of full density, made by
+/// --generate
+pub fn sphere(directory: &Path, name: &str, size: u32) -> PathBuf {
+    let volume = directory.join(format!("{}.raw", name));
+    let size = size.to_string();
+    let result = command()
+        .args(["--quiet", "--generate", "sphere", "--gen-dims", &size, &size, &size, "--gen-out"])
+        .arg(&volume)
+        .output()
+        .unwrap();
+    assert_success(&result);
+    return volume;
+}
+
+/// Renders the sphere of --generate in a cube of the given size into the
+/// output, which the extension of its name picks the format of
+pub fn render_sphere(directory: &Path, name: &str, size: u32, output: &Path, args: &[&str]) -> Output {
+    let volume = sphere(directory, name, size);
+    let size = size.to_string();
+    return command()
+        .args(["--quiet", "--volume-dimensions", &size, &size, &size, "--volume"])
+        .arg(&volume)
+        .arg("--output")
+        .arg(output)
+        .args(args)
+        .output()
+        .unwrap();
+}
+
+/// Writes an environment map of 4x2 texels in the same RGBE color, e.g.
+/// [128, 128, 128, 127] for 128 * 2^(127 - 136) = 0.25 in every channel
+pub fn uniform_envmap(directory: &Path, name: &str, rgbe: [u8; 4]) -> PathBuf {
+    let mut hdr = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 4\n".to_vec();
+    for _ in 0..8 {
+        hdr.extend_from_slice(&rgbe);
+    }
+    let envmap = directory.join(format!("{}.hdr", name));
+    fs::write(&envmap, hdr).unwrap();
+    return envmap;
+}
+
+/// Renders an empty volume in front of a uniform environment map, so that
+/// every pixel shows its radiance. Returns the values of the image, or None
+/// when the render needs a GPU and there is no GPU adapter.
+pub fn render_envmap(directory: &Path, name: &str, rgbe: [u8; 4], args: &[&str]) -> Option<Vec<u32>> {
+    let volume = volume(directory, name, &[0; 8]);
+    let envmap = uniform_envmap(directory, name, rgbe);
+    let output = output(directory, &format!("{}.ppm", name));
+    let result = run_on_gpu(name, command()
+        .args(["--quiet", "--out-resolution", "4", "4", "--iterations", "2", "--volume-dimensions", "2", "2", "2", "--volume"])
+        .arg(&volume)
+        .arg("--envmap")
+        .arg(&envmap)
+        .arg("--output")
+        .arg(&output)
+        .args(args))?;
+    assert_success(&result);
+    return Some(ppm_values(&output));
+}
+
+/// Command running the renderer
+pub fn command() -> Command {
+    return Command::new(env!("CARGO_BIN_EXE_vpt-lazy-ripoff"));
+}
+
+/// Whether the renderer stopped for the lack of a GPU adapter
+pub fn no_gpu(stderr: &str) -> bool {
+    return stderr.contains("No suitable GPU adapter");
+}
+
+/// Runs a render on the GPU, or returns None when there is no GPU adapter
+pub fn run_on_gpu(name: &str, command: &mut Command) -> Option<Output> {
+    let result = command.output().unwrap();
+    if no_gpu(&String::from_utf8_lossy(&result.stderr)) {
+        eprintln!("Skipping {}: no GPU adapter", name);
+        return None;
+    }
+    return Some(result);
+}
+
+pub fn assert_success(result: &Output) {
+    assert!(result.status.success(), "rendering failed: {}", String::from_utf8_lossy(&result.stderr));
+}
+
+/// Values of a plain PPM image after its header
+pub fn ppm_values(path: &Path) -> Vec<u32> {
+    let text = fs::read_to_string(path).unwrap();
+    return text.split_whitespace().skip(4).map(|v| v.parse().unwrap()).collect();
+}
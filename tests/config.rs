@@ -0,0 +1,77 @@
+//! Checks how --config files combine with the command line and with each
+//! other, through the matrix that --dump-matrix prints before rendering
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::{fs, process::Output};
+
+fn write(name: &str, contents: &str) -> String {
+    let file = common::directory("config").join(name);
+    fs::write(&file, contents).unwrap();
+    return file.to_str().unwrap().to_string();
+}
+
+fn run(name: &str, args: &[&str]) -> Output {
+    let directory = common::directory("config");
+    let volume = common::volume(&directory, name, &[0; 8 * 8 * 8]);
+    return common::command()
+        .args(["--quiet", "--dump-matrix", "--volume-dimensions", "8", "8", "8", "--out-resolution", "2", "2", "--volume"])
+        .arg(&volume)
+        .arg("--output")
+        .arg(directory.join(format!("{}.ppm", name)))
+        .args(args)
+        .output()
+        .unwrap();
+}
+
+/// Inverse MVP matrix of the scene, which is printed even when there is
+/// no GPU to render it with
+fn matrix(name: &str, args: &[&str]) -> String {
+    let result = run(name, args);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let matrix = stdout.lines().next().unwrap_or_default().to_string();
+    assert_eq!(matrix.split(' ').count(), 16, "no matrix for {}: {}", name, String::from_utf8_lossy(&result.stderr));
+    return matrix;
+}
+
+#[test]
+fn command_line_wins_in_any_order() {
+    let config = write("camera.toml", "[rendering]\ncamera_position = [0.0, 1.0, 2.0]\nfocal_length = 4.0\n");
+    let before = matrix("before", &["--config", &config, "--camera-position", "2", "1", "0"]);
+    let after = matrix("after", &["--camera-position", "2", "1", "0", "--config", &config]);
+    assert_eq!(before, after);
+    // The options that are only in the config file still apply
+    assert_eq!(before, matrix("flags", &["--camera-position", "2", "1", "0", "--focal-length", "4"]));
+    assert_ne!(before, matrix("config", &["--config", &config]));
+}
+
+#[test]
+fn later_files_keep_the_transfer_function() {
+    let missing = common::directory("config").join("missing.tf");
+    let first = write("tf.toml", &format!("[data]\ntransfer_function = {:?}\n", missing.to_str().unwrap()));
+    let second = write("dimensions.toml", "[data]\nvolume_dimensions = [8, 8, 8]\n");
+    // The transfer function of the first file is still read
+    let result = run("tf", &["--config", &first, "--config", &second]);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("Could not open transfer function"), "unexpected output: {}", stderr);
+}
+
+#[test]
+fn rejects_unknown_keys() {
+    let config = write("unknown.toml", "[rendering]\nfocal_lenght = 4.0\n");
+    let result = run("unknown", &["--config", &config]);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!result.status.success(), "a misspelled key was ignored");
+    assert!(stderr.contains("unknown field") && stderr.contains("focal_lenght"), "unexpected error: {}", stderr);
+}
+
+#[test]
+fn rejects_unreadable_file() {
+    let missing = common::directory("config").join("missing.toml");
+    let result = run("unreadable", &["--config", missing.to_str().unwrap()]);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!result.status.success(), "a missing config file was ignored");
+    assert!(stderr.contains("Failed to read config file"), "unexpected error: {}", stderr);
+}
@@ -0,0 +1,56 @@
+//! Checks that --depth holds distances in the scene, which --voxel-spacing
+//! stretches, on both renderers
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::fs;
+
+/// Depth of the center pixel of a dense volume seen head-on along z
+fn center_depth(name: &str, spacing_z: &str, args: &[&str]) -> Option<f32> {
+    let directory = common::directory("depth_units");
+    let volume = common::volume(&directory, name, &[255; 8 * 8 * 8]);
+    let depth = common::output(&directory, &format!("{}.pfm", name));
+
+    let result = common::run_on_gpu(name, common::command()
+        .args(["--quiet", "--volume-dimensions", "8", "8", "8", "--voxel-spacing", "1", "1", spacing_z])
+        .args(["--out-resolution", "3", "3", "--iterations", "64", "--extinction", "1000", "--camera-position", "0", "0", "2", "--volume"])
+        .arg(&volume)
+        .arg("--output")
+        .arg(directory.join(format!("{}.ppm", name)))
+        .arg("--depth")
+        .arg(&depth)
+        .args(args))?;
+    common::assert_success(&result);
+
+    // The header of the file is three lines, followed by 3 x 3 little
+    // endian floats
+    let contents = fs::read(&depth).unwrap();
+    let mut data = 0;
+    for _ in 0..3 {
+        data += contents[data..].iter().position(|b| *b == b'\n').unwrap() + 1;
+    }
+    let center = &contents[data + 4 * 4..data + 5 * 4];
+    return Some(f32::from_le_bytes(center.try_into().unwrap()));
+}
+
+/// Halving the spacing along z moves the front face of the volume, which
+/// spans 0.5 on either side of the origin, a quarter closer to the origin
+fn assert_front_face_moves(name: &str, args: &[&str]) {
+    let cube = center_depth(&format!("{}_cube", name), "1", args);
+    let slab = center_depth(&format!("{}_slab", name), "0.5", args);
+    if let (Some(cube), Some(slab)) = (cube, slab) {
+        assert!((slab - cube - 0.25).abs() < 0.02, "the depth went from {} to {}", cube, slab);
+    }
+}
+
+#[test]
+fn gpu_depth_in_scene_units() {
+    assert_front_face_moves("gpu", &[]);
+}
+
+#[test]
+fn cpu_depth_in_scene_units() {
+    assert_front_face_moves("cpu", &["--cpu"]);
+}
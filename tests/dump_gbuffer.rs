@@ -0,0 +1,50 @@
+//! Checks that --dump-gbuffer writes the state of the photons next to the
+//! output
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::fs;
+
+#[test]
+fn writes_photon_state() {
+    let directory = common::directory("dump_gbuffer");
+    let volume = common::volume(&directory, "volume", &[255; 8 * 8 * 8]);
+    for name in ["position", "direction", "transmittance"] {
+        let _ = fs::remove_file(directory.join(format!("output.{}.pfm", name)));
+    }
+
+    let result = match common::run_on_gpu("the G-buffer", common::command()
+        .args(["--quiet", "--volume-dimensions", "8", "8", "8", "--out-resolution", "8", "8", "--iterations", "2", "--dump-gbuffer", "--volume"])
+        .arg(&volume)
+        .arg("--output")
+        .arg(directory.join("output.ppm"))) {
+        Some(r) => r,
+        None => return
+    };
+    common::assert_success(&result);
+
+    for name in ["position", "direction", "transmittance"] {
+        let contents = fs::read(directory.join(format!("output.{}.pfm", name))).unwrap();
+        assert!(contents.starts_with(b"PF\n8 8\n"), "{} is not an 8x8 color PFM", name);
+        let pixels = &contents[contents.len() - 8 * 8 * 3 * 4..];
+        let values: Vec<f32> = pixels.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        assert!(values.iter().all(|v| v.is_finite()), "{} has non-finite values", name);
+        if name == "direction" {
+            for d in values.chunks_exact(3) {
+                let length = (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt();
+                assert!((length - 1.0).abs() < 1e-3, "direction {:?} is not normalized", d);
+            }
+        }
+    }
+}
+
+#[test]
+fn rejects_cpu() {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere", "--cpu", "--dump-gbuffer"])
+        .output()
+        .unwrap();
+    assert!(!result.status.success(), "--dump-gbuffer was accepted with --cpu");
+}
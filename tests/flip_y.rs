@@ -0,0 +1,47 @@
+//! Checks that --flip-y writes the rows of the output image in reverse,
+//! through the CPU renderer and in both PPM and PNG images
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::path::{Path, PathBuf};
+
+fn render(name: &str, extension: &str, args: &[&str]) -> PathBuf {
+    let directory = common::directory("flip_y");
+    let output = common::output(&directory, &format!("{}.{}", name, extension));
+    // The axes make the image differ between the top and the bottom
+    let result = common::render_sphere(&directory, name, 8, &output, &[&["--cpu", "--show-axes", "--out-resolution", "8", "6"], args].concat());
+    common::assert_success(&result);
+    return output;
+}
+
+/// Rows of RGB values of the plain PPM image
+fn ppm_rows(output: &Path) -> Vec<Vec<u32>> {
+    return common::ppm_values(output).chunks(8 * 3).map(|r| r.to_vec()).collect();
+}
+
+/// Rows of RGB values of the 8-bit PNG image
+fn png_rows(output: &Path) -> Vec<Vec<u8>> {
+    let image = image::open(output).unwrap().to_rgb8();
+    assert_eq!(image.dimensions(), (8, 6));
+    return image.as_raw().chunks(8 * 3).map(|r| r.to_vec()).collect();
+}
+
+#[test]
+fn reverses_the_rows() {
+    let plain = ppm_rows(&render("plain", "ppm", &[]));
+    let mut flipped = ppm_rows(&render("flipped", "ppm", &["--flip-y"]));
+    assert_ne!(plain, flipped, "the image is the same upside down");
+    flipped.reverse();
+    assert_eq!(plain, flipped);
+}
+
+#[test]
+fn flips_png() {
+    let plain = png_rows(&render("plain_png", "png", &[]));
+    let mut flipped = png_rows(&render("flipped_png", "png", &["--flip-y"]));
+    assert_ne!(plain, flipped, "the image is the same upside down");
+    flipped.reverse();
+    assert_eq!(plain, flipped);
+}
@@ -0,0 +1,36 @@
+//! Checks that --generate writes the volume, and that --dry-run only shows
+//! what would be written
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::{fs, path::PathBuf, process::Output};
+
+fn generate(name: &str, args: &[&str]) -> (PathBuf, Output) {
+    let directory = common::directory("generate");
+    let volume = common::output(&directory, &format!("{}.raw", name));
+    let result = common::command()
+        .args(["--quiet", "--generate", "sphere", "--gen-dims", "4", "4", "2", "--gen-out"])
+        .arg(&volume)
+        .args(args)
+        .output()
+        .unwrap();
+    common::assert_success(&result);
+    return (volume, result);
+}
+
+#[test]
+fn writes_the_volume() {
+    let (volume, _) = generate("written", &[]);
+    assert_eq!(fs::read(&volume).unwrap().len(), 4 * 4 * 2);
+}
+
+#[test]
+fn dry_run_writes_nothing() {
+    let (volume, result) = generate("dry_run", &["--dry-run"]);
+    assert!(!volume.exists(), "the dry run wrote {:?}", volume);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(stdout.contains("Generated volume: Sphere (4x4x2)"), "unexpected output: {}", stdout);
+    assert!(stdout.contains(&format!("Volume output: {}", volume.display())), "unexpected output: {}", stdout);
+}
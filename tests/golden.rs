@@ -0,0 +1,108 @@
+//! Renders small synthetic scenes with the CPU reference renderer and
+//! compares them against committed golden images. Run with BLESS=1 to
+//! regenerate the golden images after an intentional change in behavior.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::{fs, path::{Path, PathBuf}};
+
+/// Largest allowed difference of a single color channel
+const TOLERANCE: u8 = 2;
+
+fn read_ppm(path: &Path) -> (u32, u32, Vec<u8>) {
+    let contents = fs::read_to_string(path).unwrap();
+    let mut tokens = contents.split_whitespace();
+    assert_eq!(tokens.next(), Some("P3"), "{:?} is not a plain PPM", path);
+    let mut next = || tokens.next().unwrap().parse::<u32>().unwrap();
+    let (width, height, _max) = (next(), next(), next());
+    let pixels = (0..width * height * 3).map(|_| next() as u8).collect();
+    return (width, height, pixels);
+}
+
+fn render(name: &str, args: &[&str]) -> PathBuf {
+    let directory = common::directory("golden");
+    let output = common::output(&directory, &format!("{}.ppm", name));
+    let result = common::render_sphere(&directory, name, 16, &output, &[&["--cpu"], args].concat());
+    common::assert_success(&result);
+    return output;
+}
+
+fn check_golden(name: &str, args: &[&str]) {
+    let output = render(name, args);
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(format!("{}.ppm", name));
+    if std::env::var("BLESS").map(|v| v == "1").unwrap_or(false) {
+        fs::copy(&output, &golden).unwrap();
+        return;
+    }
+
+    let (width, height, pixels) = read_ppm(&output);
+    let (golden_width, golden_height, golden_pixels) = read_ppm(&golden);
+    assert_eq!((width, height), (golden_width, golden_height), "{} has the wrong size", name);
+    let (index, difference) = pixels.iter().zip(golden_pixels.iter())
+        .map(|(a, b)| a.abs_diff(*b))
+        .enumerate()
+        .max_by_key(|(_, d)| *d)
+        .unwrap();
+    let pixel = index as u32 / 3;
+    assert!(
+        difference <= TOLERANCE,
+        "{} differs from the golden image by {} at pixel ({}, {}), run with BLESS=1 if this is intended",
+        name, difference, pixel % width, pixel / width
+    );
+}
+
+#[test]
+fn sphere_default_camera() {
+    check_golden("sphere_default_camera", &["--out-resolution", "32", "32"]);
+}
+
+#[test]
+fn sphere_front_linear() {
+    check_golden("sphere_front_linear", &[
+        "--out-resolution", "40", "24", "--camera-position", "0", "0", "2", "--linear", "--steps", "64"
+    ]);
+}
+
+/// Width and height of the bounding box of the pixels that differ from
+/// the top left corner, which shows the background
+fn foreground_extent(width: u32, height: u32, pixels: &[u8]) -> (u32, u32) {
+    let background = &pixels[0..3];
+    let (mut min, mut max) = ([width, height], [0, 0]);
+    for y in 0..height {
+        for x in 0..width {
+            let index = ((y * width + x) * 3) as usize;
+            if pixels[index..index + 3].iter().zip(background).any(|(a, b)| a.abs_diff(*b) > 16) {
+                min = [min[0].min(x), min[1].min(y)];
+                max = [max[0].max(x), max[1].max(y)];
+            }
+        }
+    }
+    assert!(min[0] <= max[0], "the image only shows the background");
+    return (max[0] - min[0] + 1, max[1] - min[1] + 1);
+}
+
+#[test]
+fn sphere_wide_aspect_ratio() {
+    let args = ["--out-resolution", "200", "100", "--camera-position", "0", "0", "2"];
+    check_golden("sphere_wide_aspect_ratio", &args);
+    let (width, height, pixels) = read_ppm(&render("sphere_wide_aspect_ratio", &args));
+    let (sphere_width, sphere_height) = foreground_extent(width, height, &pixels);
+    assert!(
+        sphere_width.abs_diff(sphere_height) <= 1,
+        "the sphere is stretched to {}x{} pixels in a {}x{} image", sphere_width, sphere_height, width, height
+    );
+}
+
+#[test]
+fn sphere_pixel_aspect() {
+    // Pixels twice as wide as they are high each cover twice as much of the scene horizontally
+    let output = render("sphere_pixel_aspect", &["--out-resolution", "80", "80", "--camera-position", "0", "0", "2", "--pixel-aspect", "2"]);
+    let (width, height, pixels) = read_ppm(&output);
+    let (sphere_width, sphere_height) = foreground_extent(width, height, &pixels);
+    assert!(
+        (2 * sphere_width).abs_diff(sphere_height) <= 2,
+        "the sphere covers {}x{} pixels instead of half as many horizontally", sphere_width, sphere_height
+    );
+}
@@ -0,0 +1,38 @@
+//! Checks that --gpu-timing either reports the GPU time or warns that the
+//! adapter has no timestamp queries
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+#[test]
+fn reports_gpu_time() {
+    let directory = common::directory("gpu_timing");
+    let volume = common::volume(&directory, "volume", &[64; 8 * 8 * 8]);
+
+    let result = match common::run_on_gpu("reports_gpu_time", common::command()
+        .args(["--gpu-timing", "--volume-dimensions", "8", "8", "8", "--out-resolution", "8", "8", "--iterations", "4", "--volume"])
+        .arg(&volume)
+        .arg("--output")
+        .arg(directory.join("output.ppm"))) {
+        Some(r) => r,
+        None => return
+    };
+    common::assert_success(&result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(
+        (stdout.contains("GPU reset passes:") && stdout.contains("GPU iterations:")) || stderr.contains("does not support timestamp queries"),
+        "neither the GPU time nor the warning was printed: {}{}", stdout, stderr
+    );
+}
+
+#[test]
+fn rejects_cpu_renderer() {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere", "--cpu", "--gpu-timing"])
+        .output()
+        .unwrap();
+    assert!(!result.status.success(), "--gpu-timing was accepted with --cpu");
+    assert!(String::from_utf8_lossy(&result.stderr).contains("--gpu-timing measures the GPU renderer"));
+}
@@ -0,0 +1,39 @@
+//! Checks that raw volumes which start with the magic bytes of gzip are
+//! still read as raw data
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::{fs, process::Output};
+
+fn histogram(file: &std::path::Path) -> Output {
+    return common::command()
+        .args(["--quiet", "--histogram", "--volume-dimensions", "4", "4", "4", "--volume"])
+        .arg(file)
+        .output()
+        .unwrap();
+}
+
+/// The magic bytes and the deflate method, followed by voxels that are not
+/// a valid gzip stream
+fn gzip_like() -> Vec<u8> {
+    let mut voxels = vec![0x1f, 0x8b, 0x08];
+    voxels.resize(64, 255);
+    return voxels;
+}
+
+#[test]
+fn raw_volume_with_magic_bytes() {
+    let file = common::volume(&common::directory("gzip"), "magic", &gzip_like());
+    let result = histogram(&file);
+    assert!(result.status.success(), "the raw volume was not read: {}", String::from_utf8_lossy(&result.stderr));
+}
+
+#[test]
+fn invalid_gz_file() {
+    let file = common::directory("gzip").join("invalid.raw.gz");
+    fs::write(&file, gzip_like()).unwrap();
+    let result = histogram(&file);
+    assert!(!result.status.success(), "an invalid .gz file was read as raw data");
+}
@@ -0,0 +1,27 @@
+//! Checks that invalid --max-iterations-per-submit values are rejected
+//! instead of panicking
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+fn error(value: &str) -> String {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere", "--max-iterations-per-submit", value])
+        .output()
+        .unwrap();
+    assert!(!result.status.success(), "--max-iterations-per-submit {} was accepted", value);
+    return String::from_utf8_lossy(&result.stderr).to_string();
+}
+
+#[test]
+fn rejects_typo() {
+    let stderr = error("6y");
+    assert!(stderr.contains("Invalid value \"6y\" for --max-iterations-per-submit"), "unexpected error: {}", stderr);
+}
+
+#[test]
+fn rejects_zero() {
+    let stderr = error("0");
+    assert!(stderr.contains("between 1 and 65536"), "unexpected error: {}", stderr);
+}
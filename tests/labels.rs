@@ -0,0 +1,27 @@
+//! Checks that invalid --show-labels values are rejected
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+fn error(args: &[&str]) -> String {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(!result.status.success(), "{:?} was accepted", args);
+    return String::from_utf8_lossy(&result.stderr).to_string();
+}
+
+#[test]
+fn rejects_label_out_of_range() {
+    let stderr = error(&["--labels", "labels.raw", "--show-labels", "1,300"]);
+    assert!(stderr.contains("Label \"300\" is not an integer between 0 and 255"), "unexpected error: {}", stderr);
+}
+
+#[test]
+fn rejects_labels_without_label_volume() {
+    let stderr = error(&["--show-labels", "1"]);
+    assert!(stderr.contains("--show-labels requires a label volume"), "unexpected error: {}", stderr);
+}
@@ -0,0 +1,146 @@
+//! Checks the vector and matrix helpers used for the camera. The module is
+//! compiled into the test directly, as the crate has no library target.
+
+#![allow(clippy::needless_return, clippy::needless_range_loop)]
+
+#[allow(dead_code)]
+#[path = "../src/math.rs"]
+mod math;
+
+use math::{Matrix4f, Quaternion, Vector3f};
+
+fn translation(x: f32, y: f32, z: f32) -> Matrix4f {
+    let mut matrix = Matrix4f::new();
+    matrix.m[0][3] = x;
+    matrix.m[1][3] = y;
+    matrix.m[2][3] = z;
+    return matrix;
+}
+
+#[test]
+fn vector_length() {
+    let vector = Vector3f::new(3.0, 4.0, 0.0);
+    assert_eq!(vector.length_squared(), 25.0);
+    assert_eq!(vector.length(), 5.0);
+    assert_eq!(Vector3f::new(2.0, 3.0, 6.0).length(), 7.0);
+    #[allow(deprecated)]
+    let distance = vector.distance();
+    assert_eq!(distance, 5.0);
+}
+
+#[test]
+fn vector_normalize() {
+    let mut vector = Vector3f::new(0.0, 3.0, -4.0);
+    vector.normalize();
+    assert!(vector.approx_eq(&Vector3f::new(0.0, 0.6, -0.8), 1e-6), "{:?}", vector);
+    assert!((vector.length() - 1.0).abs() < 1e-6);
+
+    let mut zero = Vector3f::new(0.0, 0.0, 0.0);
+    zero.normalize();
+    assert_eq!(zero.length_squared(), 0.0);
+}
+
+#[test]
+fn matrix_multiply_identity() {
+    let matrix = Matrix4f::from_values((1..=16).map(|v| v as f32).collect());
+    assert_eq!((&matrix * &Matrix4f::new()).m, matrix.m);
+    assert_eq!((&Matrix4f::new() * &matrix).m, matrix.m);
+}
+
+#[test]
+fn matrix_multiply() {
+    // Scaling after translating moves the translation as well
+    let translation = translation(1.0, 2.0, 3.0);
+    let scale = Matrix4f::from_scale(2.0, 3.0, 4.0);
+    let expected = [
+        [2.0, 0.0, 0.0, 2.0],
+        [0.0, 3.0, 0.0, 6.0],
+        [0.0, 0.0, 4.0, 12.0],
+        [0.0, 0.0, 0.0, 1.0]
+    ];
+    assert_eq!((&scale * &translation).m, expected);
+    assert_eq!(Matrix4f::multiply(&scale, &translation).m, expected);
+}
+
+#[test]
+fn transform_point() {
+    let point = translation(1.0, 2.0, 3.0).transform_point(Vector3f::new(1.0, 1.0, 1.0));
+    assert_eq!([point.x, point.y, point.z], [2.0, 3.0, 4.0]);
+
+    // The homogeneous coordinate is divided out
+    let mut projection = Matrix4f::from_scale(2.0, 2.0, 2.0);
+    projection.m[3][3] = 2.0;
+    let point = projection.transform_point(Vector3f::new(1.0, 2.0, 3.0));
+    assert_eq!([point.x, point.y, point.z], [1.0, 2.0, 3.0]);
+}
+
+#[test]
+fn transform_vector() {
+    let matrix = &Matrix4f::from_scale(2.0, 3.0, 4.0) * &translation(1.0, 2.0, 3.0);
+    let vector = matrix.transform_vector(Vector3f::new(1.0, 1.0, 1.0));
+    assert_eq!([vector.x, vector.y, vector.z], [2.0, 3.0, 4.0]);
+}
+
+#[test]
+fn quaternion_rotation_matrix() {
+    // Rotations by 90 and 180 degrees around every axis and a generic one
+    let s = 0.5f32.sqrt();
+    for [x, y, z, w] in [[s, 0.0, 0.0, s], [0.0, s, 0.0, s], [0.0, 0.0, s, -s], [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.5, -0.5, 0.5, 0.5], [0.0, 0.0, 0.0, 1.0]] {
+        let matrix = Quaternion::new(x, y, z, w).to_rotation_matrix();
+        let roundtrip = Quaternion::from_rotation_matrix(&matrix).to_rotation_matrix();
+        assert!(matrix.approx_eq(&roundtrip, 1e-6), "{:?} became {:?}", matrix, roundtrip);
+    }
+}
+
+#[test]
+fn vector_approx_eq() {
+    let vector = Vector3f::new(1.0, -2.0, 3.0);
+    assert!(vector.approx_eq(&vector, 0.0));
+    assert!(vector.approx_eq(&Vector3f::new(1.05, -2.05, 2.95), 0.1));
+    assert!(!vector.approx_eq(&Vector3f::new(1.0, -2.0, 3.2), 0.1));
+    assert!(!vector.approx_eq(&Vector3f::new(f32::NAN, -2.0, 3.0), 0.1), "NaN compared equal");
+}
+
+#[test]
+fn matrix_approx_eq() {
+    let matrix = Matrix4f::from_values((1..=16).map(|v| v as f32).collect());
+    assert!(matrix.approx_eq(&matrix, 0.0));
+    let mut close = Matrix4f::from_values(matrix.to_values());
+    close.m[3][2] += 1e-4;
+    assert!(matrix.approx_eq(&close, 1e-3));
+    assert!(!matrix.approx_eq(&close, 1e-5));
+    assert!(!matrix.approx_eq(&Matrix4f::new(), 1.0));
+}
+
+/// Matrix with two 2x2 blocks, whose determinants are 1 and -5, and its inverse
+fn block_matrix() -> (Matrix4f, Matrix4f) {
+    let matrix = Matrix4f::from_values(vec![
+        2.0, 0.0, 0.0, 1.0,
+        0.0, 1.0, 2.0, 0.0,
+        0.0, 3.0, 1.0, 0.0,
+        1.0, 0.0, 0.0, 1.0
+    ]);
+    let inverse = Matrix4f::from_values(vec![
+        1.0, 0.0, 0.0, -1.0,
+        0.0, -0.2, 0.4, 0.0,
+        0.0, 0.6, -0.2, 0.0,
+        -1.0, 0.0, 0.0, 2.0
+    ]);
+    return (matrix, inverse);
+}
+
+#[test]
+fn matrix_det() {
+    assert_eq!(Matrix4f::new().det(), 1.0);
+    assert_eq!(Matrix4f::from_scale(2.0, 3.0, 4.0).det(), 24.0);
+    assert_eq!(block_matrix().0.det(), -5.0);
+}
+
+#[test]
+fn matrix_inverse() {
+    let (matrix, expected) = block_matrix();
+    let inverse = matrix.inverse();
+    assert!(inverse.approx_eq(&expected, 1e-6), "{:?}", inverse);
+    assert!((&matrix * &inverse).approx_eq(&Matrix4f::new(), 1e-6));
+}
@@ -0,0 +1,66 @@
+//! Checks the estimate of the GPU memory of a render and the --max-memory
+//! budget, which are both known before a device is acquired.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::{path::{Path, PathBuf}, process::Output};
+
+fn volume(name: &str) -> PathBuf {
+    return common::volume(&common::directory("max_memory"), name, &[0; 8 * 8 * 8]);
+}
+
+fn run(volume: &Path, args: &[&str]) -> Output {
+    return common::command()
+        .args(["--volume-dimensions", "8", "8", "8", "--volume"])
+        .arg(volume)
+        .args(args)
+        .output()
+        .unwrap();
+}
+
+fn estimate(args: &[&str]) -> String {
+    let volume = volume(&format!("estimate_{}", args.len()));
+    let result = run(&volume, &[&["--dry-run", "--quiet", "--out-resolution", "256", "256"], args].concat());
+    assert!(result.status.success());
+    let stdout = String::from_utf8_lossy(&result.stdout).to_string();
+    return stdout.lines().find_map(|l| l.strip_prefix("Estimated GPU memory: ")).unwrap().to_string();
+}
+
+#[test]
+fn estimate_counts_the_textures_and_buffers() {
+    // Fourteen render textures and the readback buffer of 1 MB each,
+    // which the volume and the transfer function round up
+    assert_eq!(estimate(&[]), "16 MB");
+    // The depth adds a readback buffer
+    assert_eq!(estimate(&["--depth", "unused.pfm"]), "17 MB");
+}
+
+#[test]
+fn over_budget() {
+    let volume = volume("over_budget");
+    let output = common::output(&common::directory("max_memory"), "over_budget.ppm");
+    let result = run(&volume, &["--out-resolution", "256", "256", "--max-memory", "10", "--output", output.to_str().unwrap()]);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!result.status.success());
+    assert!(stderr.contains("needs an estimated 16 MB of GPU memory, which exceeds the budget of 10 MB"), "unexpected error output: {}", stderr);
+    assert!(!output.exists());
+}
+
+#[test]
+fn zero_budget() {
+    let volume = volume("zero_budget");
+    let result = run(&volume, &["--dry-run", "--max-memory", "0"]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Memory budget must be positive"));
+}
+
+#[test]
+fn rejects_unit() {
+    let volume = volume("unit");
+    let result = run(&volume, &["--dry-run", "--max-memory", "512MB"]);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!result.status.success());
+    assert!(stderr.contains("Invalid value \"512MB\" for --max-memory"), "unexpected error: {}", stderr);
+}
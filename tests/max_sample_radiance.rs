@@ -0,0 +1,40 @@
+//! Checks that --max-sample-radiance limits the light of single paths
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+/// Mean of the output image, or None when there is no GPU
+fn mean_brightness(name: &str, extra_args: &[&str]) -> Option<f32> {
+    let directory = common::directory("max_sample_radiance");
+    let volume = common::volume(&directory, name, &[64; 8 * 8 * 8]);
+    let output = common::output(&directory, &format!("{}.ppm", name));
+
+    let result = common::run_on_gpu(name, common::command()
+        .args(["--quiet", "--volume-dimensions", "8", "8", "8", "--out-resolution", "8", "8", "--iterations", "4", "--volume"])
+        .arg(&volume)
+        .arg("--output")
+        .arg(&output)
+        .args(extra_args))?;
+    common::assert_success(&result);
+
+    let values = common::ppm_values(&output);
+    return Some(values.iter().sum::<u32>() as f32 / values.len() as f32);
+}
+
+#[test]
+fn darkens_bright_paths() {
+    if let (Some(unclamped), Some(clamped)) = (mean_brightness("unclamped", &[]), mean_brightness("clamped", &["--max-sample-radiance", "0.1"])) {
+        assert!(clamped < 0.5 * unclamped, "clamping to 0.1 gave {} instead of less than half of {}", clamped, unclamped);
+    }
+}
+
+#[test]
+fn rejects_non_positive_limit() {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere", "--max-sample-radiance", "-1"])
+        .output()
+        .unwrap();
+    assert!(!result.status.success(), "a negative limit was accepted");
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Max sample radiance must be a positive number"));
+}
@@ -0,0 +1,45 @@
+//! Checks that --multi-gpu renders on a single GPU when there is only one
+//! adapter, and the combinations it rejects
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::{fs, path::PathBuf, process::Output};
+
+fn run(name: &str, args: &[&str]) -> (Output, PathBuf) {
+    let directory = common::directory("multi_gpu");
+    let volume = common::volume(&directory, name, &[128; 8 * 8 * 8]);
+    let output = common::output(&directory, &format!("{}.ppm", name));
+    let result = common::command()
+        .args(["--multi-gpu", "--volume-dimensions", "8", "8", "8", "--out-resolution", "16", "9", "--volume"])
+        .arg(&volume)
+        .arg("--output")
+        .arg(&output)
+        .args(args)
+        .output()
+        .unwrap();
+    return (result, output);
+}
+
+#[test]
+fn renders_with_the_available_adapters() {
+    let (result, output) = run("render", &[]);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    if common::no_gpu(&stderr) {
+        eprintln!("Skipping, no GPU adapter");
+        return;
+    }
+    common::assert_success(&result);
+    assert!(stderr.contains("found a single adapter") || stderr.contains("Splitting the image among"), "unexpected output: {}", stderr);
+    let contents = fs::read_to_string(&output).unwrap();
+    assert!(contents.starts_with("P3\n16 9\n"));
+}
+
+#[test]
+fn rejected_with_cpu() {
+    let (result, output) = run("cpu", &["--cpu"]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("which --cpu does not use"));
+    assert!(!output.exists());
+}
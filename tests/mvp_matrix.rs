@@ -0,0 +1,58 @@
+//! Checks the validation of --mvp-matrix: non-finite values are rejected
+//! and singular matrices are warned about, whatever their scale
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::process::Output;
+
+fn parse(matrix: [f32; 16]) -> Output {
+    let values: Vec<String> = matrix.iter().map(|v| v.to_string()).collect();
+    return common::command()
+        .args(["--dry-run", "--generate", "sphere", "--mvp-matrix"])
+        .args(&values)
+        .output()
+        .unwrap();
+}
+
+fn scaled_identity(scale: f32) -> [f32; 16] {
+    let mut matrix = [0.0; 16];
+    for i in 0..4 {
+        matrix[i * 5] = scale;
+    }
+    return matrix;
+}
+
+fn warns_degenerate(result: &Output) -> bool {
+    assert!(result.status.success(), "the matrix was rejected: {}", String::from_utf8_lossy(&result.stderr));
+    return String::from_utf8_lossy(&result.stderr).contains("singular");
+}
+
+#[test]
+fn rejects_nan() {
+    let mut matrix = scaled_identity(1.0);
+    matrix[6] = f32::NAN;
+    let result = parse(matrix);
+    assert!(!result.status.success(), "a matrix with NaN was accepted");
+    assert!(String::from_utf8_lossy(&result.stderr).contains("non-finite"), "unexpected error: {}", String::from_utf8_lossy(&result.stderr));
+}
+
+#[test]
+fn identity_is_not_degenerate() {
+    assert!(!warns_degenerate(&parse(scaled_identity(1.0))));
+}
+
+#[test]
+fn scaled_matrix_is_not_degenerate() {
+    // The determinant is 1e-12, but the rows are as short
+    assert!(!warns_degenerate(&parse(scaled_identity(1e-3))));
+}
+
+#[test]
+fn singular_matrix_is_degenerate() {
+    let mut matrix = scaled_identity(1.0);
+    // The third row repeats the second one
+    matrix[8..12].copy_from_slice(&[0.0, 1.0, 0.0, 0.0]);
+    assert!(warns_degenerate(&parse(matrix)));
+}
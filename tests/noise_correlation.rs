@@ -0,0 +1,48 @@
+//! Checks that the random states of neighboring pixels are independent,
+//! so a single iteration of a homogeneous volume shows white noise.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+const SIZE: usize = 64;
+
+#[test]
+fn neighbors_are_uncorrelated() {
+    let directory = common::directory("noise_correlation");
+    let volume = common::volume(&directory, "volume", &[128; 8 * 8 * 8]);
+    let output = common::output(&directory, "output.ppm");
+
+    // From inside the volume every pixel shows it
+    let result = match common::run_on_gpu("neighbors_are_uncorrelated", common::command()
+        .args(["--quiet", "--volume-dimensions", "8", "8", "8", "--out-resolution", "64", "64", "--iterations", "1"])
+        .args(["--camera-position", "0", "0", "0.8", "--volume"])
+        .arg(&volume)
+        .arg("--output")
+        .arg(&output)) {
+        Some(r) => r,
+        None => return
+    };
+    common::assert_success(&result);
+
+    let values = common::ppm_values(&output);
+    let pixels: Vec<f32> = values.chunks_exact(3).map(|p| p.iter().sum::<u32>() as f32).collect();
+    let mean = pixels.iter().sum::<f32>() / pixels.len() as f32;
+    let deviations: Vec<f32> = pixels.iter().map(|p| p - mean).collect();
+    let variance = deviations.iter().map(|d| d * d).sum::<f32>() / deviations.len() as f32;
+    assert!(variance > 0.0, "the render has no noise");
+
+    for (dx, dy) in [(1, 0), (0, 1), (1, 1)] {
+        let mut sum = 0.0;
+        let mut count = 0;
+        for y in 0..SIZE - dy {
+            for x in 0..SIZE - dx {
+                sum += deviations[y * SIZE + x] * deviations[(y + dy) * SIZE + x + dx];
+                count += 1;
+            }
+        }
+        let correlation = sum / count as f32 / variance;
+        // The estimate itself scatters by about 1 / 64 around zero
+        assert!(correlation.abs() < 0.1, "pixels {} apart horizontally and {} vertically correlate by {}", dx, dy, correlation);
+    }
+}
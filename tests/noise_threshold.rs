@@ -0,0 +1,56 @@
+//! Checks that --noise-threshold stops rendering once the estimated noise is
+//! low enough, and keeps rendering up to --iterations otherwise.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+/// Renders with the threshold and returns the log, or None when there is no GPU
+fn render(name: &str, threshold: &str) -> Option<String> {
+    let directory = common::directory("noise_threshold");
+    let volume = common::volume(&directory, name, &[128; 8 * 8 * 8]);
+
+    let result = common::run_on_gpu(name, common::command()
+        .args(["--volume-dimensions", "8", "8", "8", "--out-resolution", "8", "8", "--iterations", "40",
+            "--max-iterations-per-submit", "4", "--noise-threshold", threshold, "--volume"])
+        .arg(&volume)
+        .arg("--output")
+        .arg(directory.join(format!("{}.ppm", name))))?;
+    common::assert_success(&result);
+    return Some(String::from_utf8_lossy(&result.stderr).to_string());
+}
+
+#[test]
+fn stops_early() {
+    if let Some(log) = render("early", "1") {
+        assert!(log.contains("Reached the noise threshold after 4 iterations"), "unexpected log: {}", log);
+    }
+}
+
+#[test]
+fn renders_all_iterations() {
+    if let Some(log) = render("all", "1e-30") {
+        assert!(log.contains("Stopped after 40 iterations without reaching the noise threshold"), "unexpected log: {}", log);
+    }
+}
+
+#[test]
+fn rejects_non_positive_threshold() {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere", "--noise-threshold", "0"])
+        .output()
+        .unwrap();
+    assert!(!result.status.success(), "a threshold of 0 was accepted");
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Noise threshold must be a positive number"));
+}
+
+#[test]
+fn rejects_typo() {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere", "--noise-threshold", "0,01"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!result.status.success(), "a threshold of 0,01 was accepted");
+    assert!(stderr.contains("Invalid value \"0,01\" for --noise-threshold"), "unexpected error: {}", stderr);
+}
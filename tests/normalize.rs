@@ -0,0 +1,27 @@
+//! Checks that --normalize stretches the densities of a volume to the full
+//! 8-bit range before they are used
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+#[test]
+fn normalize() {
+    let directory = common::directory("normalize");
+    let file = common::volume(&directory, "volume", &[40, 60, 60, 80, 80, 100, 100, 120]);
+
+    let result = common::command()
+        .args(["--histogram", "--normalize", "--volume-dimensions", "2", "2", "2", "--volume"])
+        .arg(&file)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "normalizing failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert!(String::from_utf8_lossy(&result.stderr).contains("from 40 to 120"), "the range was not reported");
+
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let counts: Vec<(u32, u32)> = stdout.lines().skip(1).map(|l| {
+        let (value, count) = l.split_once(',').unwrap();
+        return (value.parse().unwrap(), count.parse().unwrap());
+    }).filter(|(_, count)| *count > 0).collect();
+    assert_eq!(counts, [(0, 1), (64, 2), (128, 2), (191, 2), (255, 1)]);
+}
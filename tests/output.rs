@@ -0,0 +1,205 @@
+//! Checks that image content which does not cover the whole image is
+//! rejected before anything is written, and the PFM reader and averaging
+//! used by --accumulate, and the checkpoint files of --resume
+
+#![allow(clippy::needless_return)]
+
+#[path = "../src/output.rs"]
+mod output;
+
+use std::{fs, io::ErrorKind, path::{Path, PathBuf}};
+
+fn output_file(name: &str) -> PathBuf {
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join("output");
+    fs::create_dir_all(&directory).unwrap();
+    let file = directory.join(name);
+    let _ = fs::remove_file(&file);
+    return file;
+}
+
+#[test]
+fn complete_image() {
+    let file = output_file("complete.ppm");
+    output::write_output(file.to_str().unwrap(), 2, 1, 255, vec![255, 0, 0, 0, 0, 255]).unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "P3\n2 1\n255\n255 0 0\n0 0 255\n");
+}
+
+#[test]
+fn sixteen_bit_ppm() {
+    let file = output_file("sixteen_bit.ppm");
+    output::write_output(file.to_str().unwrap(), 1, 1, 65535, vec![65535, 257, 0]).unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "P3\n1 1\n65535\n65535 257 0\n");
+}
+
+#[test]
+fn png() {
+    let pixels = vec![65535, 1000, 0, 0, 0, 65535];
+    let file = output_file("sixteen_bit.png");
+    output::write_output(file.to_str().unwrap(), 1, 2, 65535, pixels.clone()).unwrap();
+    let image = image::open(&file).unwrap();
+    assert_eq!(image.color(), image::ColorType::Rgb16);
+    assert_eq!(image.into_rgb16().into_raw(), pixels);
+
+    let file = output_file("eight_bit.png");
+    output::write_output(file.to_str().unwrap(), 2, 1, 255, vec![255, 0, 0, 0, 0, 255]).unwrap();
+    let image = image::open(&file).unwrap();
+    assert_eq!(image.color(), image::ColorType::Rgb8);
+    assert_eq!(image.into_rgb8().into_raw(), [255, 0, 0, 0, 0, 255]);
+}
+
+#[test]
+fn short_image() {
+    let file = output_file("short.ppm");
+    // The last pixel is missing its blue component
+    let error = output::write_output(file.to_str().unwrap(), 2, 2, 255, vec![0; 11]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert!(error.to_string().contains("11 values") && error.to_string().contains("needs 12"), "unexpected error: {}", error);
+    assert!(!file.exists(), "a partial image was written");
+}
+
+#[test]
+fn short_pfm() {
+    let file = output_file("short.pfm");
+    let error = output::write_pfm(file.to_str().unwrap(), 2, 2, 3, &[0.0; 9]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert!(!file.exists(), "a partial image was written");
+}
+
+#[test]
+fn pfm_round_trip() {
+    let file = output_file("round_trip.pfm");
+    let pixels = [0.0, 0.25, 0.5, 1.0, 2.0, f32::INFINITY];
+    output::write_pfm(file.to_str().unwrap(), 3, 2, 1, &pixels).unwrap();
+    let image = output::read_pfm(&fs::read(&file).unwrap()).unwrap();
+    assert_eq!((image.width, image.height, image.channels), (3, 2, 1));
+    assert_eq!(image.pixels, pixels);
+}
+
+#[test]
+fn big_endian_pfm() {
+    // The bottom row is stored first
+    let mut contents = b"PF\n1 2\n1.0\n".to_vec();
+    for value in [4.0f32, 5.0, 6.0, 1.0, 2.0, 3.0] {
+        contents.extend_from_slice(&value.to_be_bytes());
+    }
+    let image = output::read_pfm(&contents).unwrap();
+    assert_eq!((image.width, image.height, image.channels), (1, 2, 3));
+    assert_eq!(image.pixels, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+}
+
+#[test]
+fn truncated_pfm() {
+    let mut contents = b"Pf\n2 2\n-1.0\n".to_vec();
+    contents.extend_from_slice(&[0; 12]);
+    let error = output::read_pfm(&contents).err().unwrap();
+    assert!(error.contains("needs 16"), "unexpected error: {}", error);
+}
+
+#[test]
+fn weighted_average() {
+    let image = |pixels: Vec<f32>| output::PfmImage { width: 2, height: 1, channels: 1, pixels };
+    let images = [image(vec![1.0, 0.0]), image(vec![3.0, 4.0])];
+    assert_eq!(output::weighted_average(&images, &[1.0, 1.0]), [2.0, 2.0]);
+    assert_eq!(output::weighted_average(&images, &[3.0, 1.0]), [1.5, 1.0]);
+}
+
+#[test]
+fn write_into_memory() {
+    let mut ppm = Vec::new();
+    output::write_ppm_to(&mut ppm, 1, 2, 255, &[1, 2, 3, 4, 5, 6]).unwrap();
+    assert_eq!(String::from_utf8(ppm).unwrap(), "P3\n1 2\n255\n1 2 3\n4 5 6\n");
+
+    let mut pfm = Vec::new();
+    output::write_pfm_to(&mut pfm, 1, 2, 1, &[1.0, 2.0]).unwrap();
+    let image = output::read_pfm(&pfm).unwrap();
+    assert_eq!(image.pixels, [1.0, 2.0]);
+
+    let mut short = Vec::new();
+    assert!(output::write_ppm_to(&mut short, 1, 2, 255, &[1, 2, 3]).is_err());
+    assert!(short.is_empty(), "a partial image was written");
+}
+
+#[test]
+fn upscale() {
+    // Pixel centers stay aligned, so the outer pixels repeat the edge
+    assert_eq!(output::upscale(&[0.0, 4.0], [2, 1], [4, 1], 1), [0.0, 1.0, 3.0, 4.0]);
+    assert_eq!(output::upscale(&[1.0, 2.0, 3.0, 4.0], [1, 2], [1, 2], 2), [1.0, 2.0, 3.0, 4.0]);
+    let upscaled = output::upscale(&[0.0, 2.0, 4.0, 6.0], [2, 2], [1, 1], 1);
+    assert_eq!(upscaled, [3.0]);
+}
+
+#[test]
+fn output_name_templates() {
+    assert!(output::validate_template("frame_{index}_{steps}.png").is_ok());
+    assert!(output::validate_template("plain.ppm").is_ok());
+    assert!(output::validate_template("frame_{seed}.png").unwrap_err().contains("Unknown key \"seed\""));
+    assert!(output::validate_template("frame_{index.png").unwrap_err().contains("opening brace"));
+    assert!(output::validate_template("frame_index}.png").unwrap_err().contains("closing brace"));
+
+    let values = [("steps", "64".to_string()), ("extinction", "100".to_string())];
+    assert_eq!(output::fill_template("e{extinction}_s{steps}_{steps}.png", &values), "e100_s64_64.png");
+}
+
+#[test]
+fn radiance_statistics() {
+    // Two channels of four pixels, with an even count for the median
+    let statistics = output::channel_statistics(&[4.0, 1.0, 1.0, 1.0, 2.0, 1.0, 3.0, 5.0], 2);
+    assert_eq!(statistics[0], output::ChannelStatistics { min: 1.0, max: 4.0, mean: 2.5, median: 2.5 });
+    assert_eq!(statistics[1], output::ChannelStatistics { min: 1.0, max: 5.0, mean: 2.0, median: 1.0 });
+
+    let rgb = output::channel_statistics(&[0.5, 1.0, f32::INFINITY], 3);
+    let parameters = [("width", "1".to_string()), ("gamma", "2.2".to_string())];
+    let json = output_file("statistics.json");
+    output::write_statistics(json.to_str().unwrap(), &rgb, &parameters).unwrap();
+    let json = fs::read_to_string(json).unwrap();
+    assert!(json.contains("\"width\": 1,\n    \"gamma\": 2.2\n"), "unexpected parameters: {}", json);
+    assert!(json.contains("\"r\": { \"min\": 0.5, \"max\": 0.5, \"mean\": 0.5, \"median\": 0.5 },"), "unexpected red: {}", json);
+    // JSON has no infinity
+    assert!(json.contains("\"b\": { \"min\": null, \"max\": null, \"mean\": null, \"median\": null }\n"), "unexpected blue: {}", json);
+
+    let csv = output_file("statistics.csv");
+    output::write_statistics(csv.to_str().unwrap(), &rgb, &parameters).unwrap();
+    let csv = fs::read_to_string(csv).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "channel,min,max,mean,median,width,gamma");
+    assert_eq!(lines[2], "g,1,1,1,1,1,2.2");
+}
+
+#[test]
+fn checkpoint_roundtrip() {
+    let checkpoint = output::Checkpoint {
+        resolution: [2, 1],
+        iterations: 37,
+        textures: [0, 1, 2, 3, 4, 5, 6].map(|t| (0..8).map(|v| (t * 8 + v) as f32 * 0.5).collect())
+    };
+    let file = output_file("state.ckpt");
+    output::write_checkpoint(file.to_str().unwrap(), &checkpoint).unwrap();
+    let contents = fs::read(&file).unwrap();
+    assert_eq!(output::read_checkpoint(&contents).unwrap(), checkpoint);
+
+    // A truncated file is rejected instead of resuming from part of the state
+    assert!(output::read_checkpoint(&contents[..contents.len() - 4]).unwrap_err().contains("bytes of state"));
+    assert_eq!(output::read_checkpoint(b"P3\n2 1\n255\n").unwrap_err(), "not a checkpoint file");
+}
+
+#[test]
+fn srgb_encoding() {
+    // A linear 0.5 is about 188 of 255 in sRGB, not the 128 of a bare scaling
+    assert_eq!((output::linear_to_srgb(0.5) * 255.0).round(), 188.0);
+    assert_eq!(output::linear_to_srgb(0.0), 0.0);
+    assert!((output::linear_to_srgb(1.0) - 1.0).abs() < 1e-6);
+    // The linear segment near black
+    assert!((output::linear_to_srgb(0.002) - 0.02584).abs() < 1e-6);
+}
+
+#[test]
+fn flip_rows() {
+    // Three rows of two RGB pixels, the middle row stays in place
+    let mut image: Vec<u16> = (0..18).collect();
+    output::flip_rows(&mut image, 2, 3);
+    assert_eq!(image, [12, 13, 14, 15, 16, 17, 6, 7, 8, 9, 10, 11, 0, 1, 2, 3, 4, 5]);
+
+    let mut depth = vec![1.0, 2.0, 3.0, 4.0];
+    output::flip_rows(&mut depth, 1, 1);
+    assert_eq!(depth, [4.0, 3.0, 2.0, 1.0]);
+}
@@ -0,0 +1,44 @@
+//! Writes renders into an output directory with a templated file name,
+//! with the CPU renderer so that no GPU is needed.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::{fs, path::Path, process::Output};
+
+fn render(directory: &Path, template: &str) -> Output {
+    let name = directory.file_name().unwrap().to_str().unwrap();
+    let volume = common::volume(directory.parent().unwrap(), name, &[0; 8]);
+    return common::command()
+        .args(["--cpu", "--quiet", "--out-resolution", "4", "2", "--steps", "12", "--volume-dimensions", "2", "2", "2", "--volume"])
+        .arg(&volume)
+        .arg("--output-dir")
+        .arg(directory)
+        .args(["--output-name", template])
+        .output()
+        .unwrap();
+}
+
+#[test]
+fn indexed_names() {
+    let directory = common::directory("output_name").join("renders");
+    let _ = fs::remove_dir_all(&directory);
+    for _ in 0..2 {
+        let result = render(&directory, "frame_{index}_{steps}_{width}x{height}.ppm");
+        common::assert_success(&result);
+    }
+    // The directory is created and the second run does not overwrite the first
+    let mut names: Vec<String> = fs::read_dir(&directory).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().to_string()).collect();
+    names.sort();
+    assert_eq!(names, ["frame_0_12_4x2.ppm", "frame_1_12_4x2.ppm"]);
+}
+
+#[test]
+fn unknown_key() {
+    let directory = common::directory("output_name").join("unknown");
+    let result = render(&directory, "frame_{frame}.ppm");
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Unknown key \"frame\""));
+    assert!(!directory.exists(), "the directory was created for a rejected template");
+}
@@ -0,0 +1,36 @@
+//! Checks that unknown phase functions and parameters of the double-hg
+//! phase function outside of their range are rejected
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+fn error(args: &[&str]) -> String {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(!result.status.success(), "{:?} was accepted", args);
+    return String::from_utf8_lossy(&result.stderr).to_string();
+}
+
+#[test]
+fn rejects_unknown_phase_function() {
+    let stderr = error(&["--phase", "mie"]);
+    assert!(stderr.contains("Unknown phase function \"mie\""), "unexpected error: {}", stderr);
+}
+
+#[test]
+fn rejects_parameters_out_of_range() {
+    for params in ["1.2,0.5,0.5", "0.5,-0.5,1.5", "0.5,0.5"] {
+        let stderr = error(&["--phase", "double-hg", "--phase-params", params]);
+        assert!(stderr.contains("needs three parameters G1,G2,W"), "unexpected error for {}: {}", params, stderr);
+    }
+}
+
+#[test]
+fn rejects_missing_parameters() {
+    let stderr = error(&["--phase", "double-hg"]);
+    assert!(stderr.contains("needs its parameters"), "unexpected error: {}", stderr);
+}
@@ -0,0 +1,118 @@
+//! Renders an empty volume in front of a uniform environment map, so that
+//! every pixel shows a known radiance, and checks the values the tone
+//! mapping operators, --gamma-only and --output-colorspace encode it as.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+/// RGBE colors of a radiance of 0.25, 0.5 and 1.0
+const QUARTER: [u8; 4] = [128, 128, 128, 127];
+const HALF: [u8; 4] = [128, 128, 128, 128];
+const ONE: [u8; 4] = [128, 128, 128, 129];
+
+/// Levels that scale a radiance of 1.0 to 0.5 and 2.0 in front of the tone
+/// mapping, both with a midpoint that leaves the tone curve linear
+const DIM: [&str; 4] = ["--levels", "0", "1", "2"];
+const BRIGHT: [&str; 4] = ["--levels", "0", "0.25", "0.5"];
+
+/// Values of the rendered image, or None when there is no GPU
+fn render(name: &str, rgbe: [u8; 4], args: &[&str]) -> Option<Vec<u32>> {
+    return common::render_envmap(&common::directory("pixel_encoding"), name, rgbe, args);
+}
+
+fn assert_values(values: &[u32], expected: u32, tolerance: u32) {
+    assert_eq!(values.len(), 4 * 4 * 3);
+    assert!(values.iter().all(|v| v.abs_diff(expected) <= tolerance), "expected {}, got {:?}", expected, values);
+}
+
+/// Checks that --tonemap linear clamps the radiance to 1.0, which the other
+/// operators do not, or does nothing when there is no GPU
+fn assert_clamped(name: &str, args: &[&str]) {
+    let args = [args, &["--gamma", "1"]].concat();
+    let dim = match render(&format!("{}_dim", name), ONE, &[&args[..], &["--tonemap", "linear"], &DIM].concat()) {
+        Some(v) => v,
+        None => return
+    };
+    assert_values(&dim, 128, 1);
+    let bright = render(&format!("{}_bright", name), ONE, &[&args[..], &["--tonemap", "linear"], &BRIGHT].concat()).unwrap();
+    assert_values(&bright, 255, 1);
+    // Reinhard maps 2.0 to 2 / 3 instead
+    let reinhard = render(&format!("{}_reinhard", name), ONE, &[&args[..], &["--tonemap", "reinhard"], &BRIGHT].concat()).unwrap();
+    assert_values(&reinhard, 170, 1);
+}
+
+#[test]
+fn gpu_tonemap_linear() {
+    assert_clamped("gpu", &[]);
+}
+
+#[test]
+fn cpu_tonemap_linear() {
+    assert_clamped("cpu", &["--cpu"]);
+}
+
+/// Gamma encoding of a radiance of 0.25
+fn gamma_encoded(gamma: f32) -> u32 {
+    return (0.25f32.powf(1.0 / gamma) * 255.0).round() as u32;
+}
+
+#[test]
+fn cpu_gamma_only() {
+    for gamma in [1.0, 2.2] {
+        let gamma_text = gamma.to_string();
+        let values = render(&format!("cpu_gamma_{}", gamma), QUARTER, &["--cpu", "--gamma-only", "--gamma", &gamma_text]).unwrap();
+        assert_values(&values, gamma_encoded(gamma), 0);
+    }
+    assert_eq!(gamma_encoded(2.2), 136);
+}
+
+#[test]
+fn gpu_gamma_only() {
+    if let Some(values) = render("gpu_gamma", QUARTER, &["--gamma-only", "--gamma", "2.2"]) {
+        assert_values(&values, gamma_encoded(2.2), 1);
+    }
+}
+
+#[test]
+fn gamma_only_rejects_tone_curve() {
+    for tone_curve in [&["--levels", "0", "0.4", "1"][..], &["--saturation", "0.5"], &["--tonemap", "aces"]] {
+        let result = common::command()
+            .args(["--dry-run", "--generate", "sphere", "--gamma-only"])
+            .args(tone_curve)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert!(!result.status.success(), "{:?} was ignored", tone_curve);
+        assert!(stderr.contains("--gamma-only skips the tone curve"), "unexpected error: {}", stderr);
+    }
+}
+
+#[test]
+fn cpu_output_colorspace() {
+    // --gamma no longer applies once the color space is given
+    let srgb = render("cpu_srgb", HALF, &["--cpu", "--output-colorspace", "srgb", "--gamma", "1.5"]).unwrap();
+    assert_values(&srgb, 188, 0);
+    let linear = render("cpu_linear", HALF, &["--cpu", "--output-colorspace", "linear"]).unwrap();
+    assert_values(&linear, 128, 0);
+}
+
+#[test]
+fn gpu_output_colorspace() {
+    if let Some(values) = render("gpu_srgb", HALF, &["--output-colorspace", "srgb"]) {
+        assert_values(&values, 188, 1);
+    }
+    if let Some(values) = render("gpu_linear", HALF, &["--output-colorspace", "linear"]) {
+        assert_values(&values, 128, 1);
+    }
+}
+
+#[test]
+fn unknown_output_colorspace() {
+    let result = common::command()
+        .args(["--dry-run", "--output-colorspace", "rec2020"])
+        .output()
+        .unwrap();
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Unknown output color space \"rec2020\""));
+}
@@ -0,0 +1,56 @@
+//! Renders a small square preview with --preview-resolution and compares
+//! the upscaled image against a render at the full resolution, with the
+//! CPU renderer so that both are free of noise.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::fs;
+
+fn render(name: &str, args: &[&str]) -> (usize, usize, Vec<i32>) {
+    let directory = common::directory("preview_resolution");
+    let output = common::output(&directory, &format!("{}.ppm", name));
+    let result = common::render_sphere(&directory, name, 16, &output,
+        &[&["--cpu", "--out-resolution", "96", "48", "--camera-position", "0", "0", "2"], args].concat());
+    common::assert_success(&result);
+
+    let text = fs::read_to_string(&output).unwrap();
+    let values: Vec<i32> = text.split_whitespace().skip(1).map(|v| v.parse().unwrap()).collect();
+    return (values[0] as usize, values[1] as usize, values[3..].to_vec());
+}
+
+#[test]
+fn upscaled_preview_matches_full_render() {
+    let (width, height, full) = render("full", &[]);
+    let (preview_width, preview_height, preview) = render("preview", &["--preview-resolution", "24"]);
+    assert_eq!((preview_width, preview_height), (width, height));
+
+    // The preview only loses detail, so the sphere stays in the same place
+    // with the same shape instead of being squeezed into a square
+    let mean_difference = full.iter().zip(&preview).map(|(a, b)| (a - b).abs()).sum::<i32>() as f32 / full.len() as f32;
+    assert!(mean_difference < 4.0, "the preview differs from the full render by {} on average", mean_difference);
+}
+
+#[test]
+fn zero_resolution() {
+    let volume = common::volume(&common::directory("preview_resolution"), "zero", &[0; 8]);
+    let result = common::command()
+        .args(["--cpu", "--preview-resolution", "0", "--volume-dimensions", "2", "2", "2", "--volume"])
+        .arg(&volume)
+        .output()
+        .unwrap();
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Preview resolution must be positive"));
+}
+
+#[test]
+fn rejects_typo() {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere", "--preview-resolution", "128x128"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!result.status.success());
+    assert!(stderr.contains("Invalid value \"128x128\" for --preview-resolution"), "unexpected error: {}", stderr);
+}
@@ -0,0 +1,81 @@
+//! Renders on the GPU at resolutions whose rows are and are not aligned to
+//! the 256 bytes required for copying the render targets into buffers, and
+//! checks that the padding of the rows does not leak into the images.
+//! Skipped when no GPU adapter is available.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::fs;
+
+/// Returns the pixels of the image, or None when there is no GPU
+fn render(width: u32, height: u32) -> Option<Vec<u8>> {
+    let directory = common::directory("readback");
+    let name = format!("{}x{}", width, height);
+    let output = common::output(&directory, &format!("{}.ppm", name));
+
+    let result = common::render_sphere(&directory, &name, 16, &output, &[
+        "--camera-position", "0", "0", "2", "--iterations", "16", "--out-resolution", &width.to_string(), &height.to_string()
+    ]);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    if common::no_gpu(&stderr) {
+        eprintln!("Skipping {}: no GPU adapter", name);
+        return None;
+    }
+    assert!(output.exists(), "rendering {} failed: {}", name, stderr);
+
+    let contents = fs::read_to_string(&output).unwrap();
+    let mut tokens = contents.split_whitespace();
+    assert_eq!(tokens.next(), Some("P3"));
+    let size: Vec<u32> = tokens.by_ref().take(3).map(|t| t.parse().unwrap()).collect();
+    assert_eq!(size, [width, height, 255]);
+    let pixels: Vec<u8> = tokens.map(|t| t.parse().unwrap()).collect();
+    assert_eq!(pixels.len(), (width * height * 3) as usize, "{}x{} image has the wrong number of values", width, height);
+    return Some(pixels);
+}
+
+/// The sphere is in the middle of the image, so rows read with the wrong
+/// stride would shift parts of it sideways
+fn check_centered(width: u32, height: u32) {
+    let pixels = match render(width, height) {
+        Some(p) => p,
+        None => return
+    };
+    let mut rows = 0;
+    for y in 0..height {
+        let mut sum = 0.0;
+        let mut count = 0.0;
+        for x in 0..width {
+            // The default transfer function has no green, the background is white
+            if pixels[((y * width + x) * 3 + 1) as usize] < 128 {
+                sum += x as f32 + 0.5;
+                count += 1.0;
+            }
+        }
+        // Rows at the silhouette are too noisy, they might only have a few dark pixels on one side
+        if count >= 4.0 {
+            rows += 1;
+            let center = sum / count;
+            assert!((center - width as f32 / 2.0).abs() < 2.0, "row {} of the {}x{} image is centered at x = {}", y, width, height, center);
+        }
+    }
+    assert!(rows > 0, "{}x{} image does not show the volume", width, height);
+}
+
+#[test]
+fn aligned_rows() {
+    // 100 Rgba32Float texels are 1600 bytes, a multiple of 256
+    check_centered(100, 100);
+}
+
+#[test]
+fn unaligned_rows() {
+    // 50 Rgba32Float texels are 800 bytes, which must be padded
+    check_centered(50, 50);
+}
+
+#[test]
+fn unaligned_non_square() {
+    check_centered(37, 23);
+}
@@ -0,0 +1,56 @@
+//! Checks the swapping and mirroring of volume axes
+
+#![allow(clippy::needless_return)]
+
+#[allow(dead_code)]
+#[path = "../src/volume.rs"]
+mod volume;
+
+/// Volume whose voxels hold their own x, y and z coordinates
+fn coordinates(dimensions: [u32; 3]) -> Vec<u8> {
+    let mut voxels = Vec::new();
+    for z in 0..dimensions[2] {
+        for y in 0..dimensions[1] {
+            for x in 0..dimensions[0] {
+                voxels.extend_from_slice(&[x as u8, y as u8, z as u8]);
+            }
+        }
+    }
+    return voxels;
+}
+
+fn voxel(volume: &[u8], dimensions: [u32; 3], x: u32, y: u32, z: u32) -> [u8; 3] {
+    let index = (((z * dimensions[1] + y) * dimensions[0] + x) * 3) as usize;
+    return [volume[index], volume[index + 1], volume[index + 2]];
+}
+
+#[test]
+fn unchanged() {
+    let volume = coordinates([2, 3, 4]);
+    assert_eq!(volume::reorient(&volume, [2, 3, 4], 3, [0, 1, 2], [false; 3]), (volume, [2, 3, 4]));
+}
+
+#[test]
+fn flip() {
+    let (volume, dimensions) = volume::reorient(&coordinates([2, 3, 4]), [2, 3, 4], 3, [0, 1, 2], [true, false, true]);
+    assert_eq!(dimensions, [2, 3, 4]);
+    assert_eq!(voxel(&volume, dimensions, 0, 0, 0), [1, 0, 3]);
+    assert_eq!(voxel(&volume, dimensions, 1, 2, 1), [0, 2, 2]);
+}
+
+#[test]
+fn swap() {
+    // The x axis of the result is the z axis of the source
+    let (volume, dimensions) = volume::reorient(&coordinates([2, 3, 4]), [2, 3, 4], 3, [2, 1, 0], [false; 3]);
+    assert_eq!(dimensions, [4, 3, 2]);
+    assert_eq!(voxel(&volume, dimensions, 3, 1, 0), [0, 1, 3]);
+    assert_eq!(voxel(&volume, dimensions, 2, 0, 1), [1, 0, 2]);
+}
+
+#[test]
+fn swap_then_flip() {
+    // Flips apply to the axes of the result
+    let (volume, dimensions) = volume::reorient(&coordinates([2, 3, 4]), [2, 3, 4], 3, [1, 0, 2], [true, false, false]);
+    assert_eq!(dimensions, [3, 2, 4]);
+    assert_eq!(voxel(&volume, dimensions, 0, 1, 2), [1, 2, 2]);
+}
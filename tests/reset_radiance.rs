@@ -0,0 +1,47 @@
+//! Checks that --reset-radiance shows in the pixels whose first path has
+//! not finished yet
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+/// Number of pixels that are greener than they are blue, or None when
+/// there is no GPU. The default transfer function is gray, so only the
+/// reset radiance can tint the pixels.
+fn green_pixels(name: &str, reset_radiance: [&str; 3]) -> Option<usize> {
+    let directory = common::directory("reset_radiance");
+    let volume = common::volume(&directory, name, &[255; 8 * 8 * 8]);
+    let output = common::output(&directory, &format!("{}.ppm", name));
+
+    // A single short step in a dense volume leaves most of the paths unfinished
+    let result = common::run_on_gpu(name, common::command()
+        .args(["--quiet", "--volume-dimensions", "8", "8", "8", "--out-resolution", "8", "8", "--iterations", "1", "--steps", "1"])
+        .args(["--extinction", "1000", "--reset-radiance"])
+        .args(reset_radiance)
+        .arg("--volume")
+        .arg(&volume)
+        .arg("--output")
+        .arg(&output))?;
+    common::assert_success(&result);
+
+    let values = common::ppm_values(&output);
+    return Some(values.chunks_exact(3).filter(|p| p[1] > p[2]).count());
+}
+
+#[test]
+fn marks_unfinished_pixels() {
+    if let (Some(black), Some(green)) = (green_pixels("black", ["0", "0", "0"]), green_pixels("green", ["0", "1", "0"])) {
+        assert_eq!(black, 0, "{} pixels are tinted without a reset radiance", black);
+        assert!(green > 0, "no pixel shows the reset radiance");
+    }
+}
+
+#[test]
+fn rejects_negative_radiance() {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere", "--reset-radiance", "0", "-1", "0"])
+        .output()
+        .unwrap();
+    assert!(!result.status.success(), "a negative reset radiance was accepted");
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Reset radiance must be three non-negative numbers"));
+}
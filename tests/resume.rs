@@ -0,0 +1,99 @@
+//! Checks that --checkpoint writes the accumulated state and that --resume
+//! adds new iterations to it, which are skipped without a GPU
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::{fs, path::{Path, PathBuf}, process::Output};
+
+fn volume(name: &str) -> PathBuf {
+    return common::sphere(&common::directory("resume"), name, 8);
+}
+
+fn render(volume: &Path, output: &Path, args: &[&str]) -> Output {
+    let _ = fs::remove_file(output);
+    return common::command()
+        .args(["--volume-dimensions", "8", "8", "8", "--volume"])
+        .arg(volume)
+        .args(args)
+        .arg("--output")
+        .arg(output)
+        .output()
+        .unwrap();
+}
+
+fn no_gpu(result: &Output) -> bool {
+    return common::no_gpu(&String::from_utf8_lossy(&result.stderr));
+}
+
+#[test]
+fn resumes_the_iterations() {
+    let volume = volume("iterations");
+    let checkpoint = common::directory("resume").join("iterations.ckpt");
+    let output = common::directory("resume").join("iterations.ppm");
+    let checkpoint_arg = checkpoint.to_str().unwrap();
+    let _ = fs::remove_file(&checkpoint);
+
+    let first = render(&volume, &output, &["--out-resolution", "8", "8", "--iterations", "6",
+        "--max-iterations-per-submit", "2", "--checkpoint", checkpoint_arg, "--checkpoint-every", "4"]);
+    if no_gpu(&first) {
+        return;
+    }
+    let stderr = String::from_utf8_lossy(&first.stderr);
+    assert!(first.status.success(), "render failed: {}", stderr);
+    assert!(stderr.contains("Checkpoint of 4 iterations written"), "no periodic checkpoint: {}", stderr);
+    assert!(stderr.contains("Checkpoint of 6 iterations written"), "no final checkpoint: {}", stderr);
+
+    let resumed = render(&volume, &output, &["--out-resolution", "8", "8", "--iterations", "3",
+        "--resume", checkpoint_arg, "--checkpoint", checkpoint_arg]);
+    let stderr = String::from_utf8_lossy(&resumed.stderr);
+    assert!(resumed.status.success(), "resumed render failed: {}", stderr);
+    assert!(stderr.contains("Resuming from 6 iterations"), "unexpected output: {}", stderr);
+    assert!(stderr.contains("Checkpoint of 9 iterations written"), "unexpected output: {}", stderr);
+    assert!(output.exists());
+}
+
+#[test]
+fn rejects_another_resolution() {
+    let volume = volume("resolution");
+    let checkpoint = common::directory("resume").join("resolution.ckpt");
+    let output = common::directory("resume").join("resolution.ppm");
+    let checkpoint_arg = checkpoint.to_str().unwrap();
+    let _ = fs::remove_file(&checkpoint);
+
+    let first = render(&volume, &output, &["--out-resolution", "8", "8", "--iterations", "2", "--checkpoint", checkpoint_arg]);
+    if no_gpu(&first) {
+        return;
+    }
+    assert!(first.status.success(), "render failed: {}", String::from_utf8_lossy(&first.stderr));
+
+    let resumed = render(&volume, &output, &["--out-resolution", "4", "8", "--resume", checkpoint_arg]);
+    let stderr = String::from_utf8_lossy(&resumed.stderr);
+    assert!(!resumed.status.success());
+    assert!(stderr.contains("was rendered at 8x8, but this render is 4x8"), "unexpected error output: {}", stderr);
+    assert!(!output.exists());
+}
+
+#[test]
+fn rejects_invalid_options() {
+    let volume = volume("options");
+    let output = common::directory("resume").join("options.ppm");
+    let result = render(&volume, &output, &["--checkpoint-every", "4"]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("--checkpoint-every needs a file"));
+
+    let result = render(&volume, &output, &["--checkpoint", "state.ckpt", "--checkpoint-every", "ten"]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Invalid value \"ten\" for --checkpoint-every"));
+
+    let result = render(&volume, &output, &["--checkpoint", "state.ckpt", "--tile", "4"]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("cannot be combined with --tile"));
+
+    let not_checkpoint = common::directory("resume").join("options.ckpt");
+    fs::write(&not_checkpoint, b"not a checkpoint").unwrap();
+    let result = render(&volume, &output, &["--resume", not_checkpoint.to_str().unwrap()]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("not a checkpoint file"));
+}
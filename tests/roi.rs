@@ -0,0 +1,69 @@
+//! Checks that --roi frames a region of the volume with the camera
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::path::PathBuf;
+
+fn run(args: &[&str]) -> String {
+    let result = common::command()
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "running failed: {}", String::from_utf8_lossy(&result.stderr));
+    return String::from_utf8_lossy(&result.stdout).to_string();
+}
+
+/// Path of a generated 16x16x16 sphere volume, one per test so that
+/// parallel tests do not read a volume while it is written
+fn sphere(name: &str) -> PathBuf {
+    return common::sphere(&common::directory("roi"), name, 16);
+}
+
+/// Inverse MVP matrix printed by --dump-matrix
+fn dump_matrix(extra_args: &[&str]) -> String {
+    let volume = sphere("matrix");
+    let mut args = vec!["--quiet", "--dry-run", "--dump-matrix", "--volume-dimensions", "16", "16", "16", "--volume", volume.to_str().unwrap()];
+    args.extend_from_slice(extra_args);
+    return run(&args).lines().next().unwrap().to_string();
+}
+
+/// Number of pixels of a CPU render of a sphere that show the volume
+fn covered_pixels(name: &str, extra_args: &[&str]) -> usize {
+    let volume = sphere(name);
+    let output = common::output(&common::directory("roi"), &format!("{}.ppm", name));
+    let mut args = vec!["--quiet", "--cpu", "--volume-dimensions", "16", "16", "16", "--out-resolution", "32", "32",
+        "--volume", volume.to_str().unwrap(), "--output", output.to_str().unwrap()];
+    args.extend_from_slice(extra_args);
+    run(&args);
+
+    let values = common::ppm_values(&output);
+    // The corner shows the background in every render
+    return values.chunks_exact(3).filter(|p| p[1].abs_diff(values[1]) > 8).count();
+}
+
+#[test]
+fn whole_volume_keeps_the_camera() {
+    let matrix = dump_matrix(&[]);
+    assert_eq!(matrix.split(' ').count(), 16, "unexpected matrix {:?}", matrix);
+    assert_eq!(matrix, dump_matrix(&["--roi", "0", "0", "0", "1", "1", "1"]));
+    assert_ne!(matrix, dump_matrix(&["--roi", "0", "0", "0", "0.5", "0.5", "0.5"]));
+}
+
+#[test]
+fn zooms_into_the_center() {
+    let whole = covered_pixels("whole", &[]);
+    let zoomed = covered_pixels("zoomed", &["--roi", "0.25", "0.25", "0.25", "0.75", "0.75", "0.75"]);
+    assert!(whole > 0 && zoomed > 2 * whole, "the zoomed render covers {} pixels and the whole volume {}", zoomed, whole);
+}
+
+#[test]
+fn rejects_empty_region() {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere", "--roi", "0.5", "0", "0", "0.5", "1", "1"])
+        .output()
+        .unwrap();
+    assert!(!result.status.success(), "an empty region was accepted");
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Region of interest"));
+}
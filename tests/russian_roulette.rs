@@ -0,0 +1,27 @@
+//! Checks that an invalid --rr-start is rejected instead of panicking
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+#[test]
+fn rejects_invalid_bounce() {
+    for bounce in ["-1", "two"] {
+        let result = common::command()
+            .args(["--dry-run", "--generate", "sphere", "--rr-start", bounce])
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert!(!result.status.success(), "--rr-start {} was accepted", bounce);
+        assert!(stderr.contains(&format!("Invalid value {:?} for --rr-start", bounce)), "unexpected error: {}", stderr);
+    }
+}
+
+#[test]
+fn accepts_bounce() {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere", "--rr-start", "3"])
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "--rr-start 3 was rejected: {}", String::from_utf8_lossy(&result.stderr));
+}
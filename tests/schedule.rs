@@ -0,0 +1,60 @@
+//! Checks how --steps-schedule and --bounces-schedule split the iterations,
+//! through the runs of iterations that -v prints, and that invalid
+//! schedules are rejected
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::{fs, process::Output};
+
+fn dry_run(args: &[&str]) -> Output {
+    return common::command()
+        .args(["--dry-run", "--generate", "sphere"])
+        .args(args)
+        .output()
+        .unwrap();
+}
+
+fn assert_rejected(result: &Output, error: &str) {
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!result.status.success(), "the schedule was accepted");
+    assert!(stderr.contains(error), "unexpected error: {}", stderr);
+}
+
+#[test]
+fn switches_at_the_midpoint() {
+    let directory = common::directory("schedule");
+    let volume = common::volume(&directory, "midpoint", &[0; 8]);
+    let result = match common::run_on_gpu("midpoint", common::command()
+        .args(["-v", "--volume-dimensions", "2", "2", "2", "--out-resolution", "4", "4", "--iterations", "4", "--steps", "8"])
+        .args(["--bounces-schedule", "1,3", "--volume"])
+        .arg(&volume)
+        .arg("--output")
+        .arg(common::output(&directory, "midpoint.ppm"))) {
+        Some(result) => result,
+        None => return
+    };
+    common::assert_success(&result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    let runs: Vec<&str> = stderr.lines().filter_map(|l| l.split("Iterations from ").nth(1)).collect();
+    assert_eq!(runs, ["0: 8 steps, 1 bounces", "2: 8 steps, 3 bounces"]);
+}
+
+#[test]
+fn rejects_empty_schedule() {
+    let config = common::directory("schedule").join("empty.toml");
+    fs::write(&config, "[rendering]\nsteps_schedule = []\n").unwrap();
+    assert_rejected(&dry_run(&["--config", config.to_str().unwrap()]), "Steps schedule is empty");
+}
+
+#[test]
+fn rejects_invalid_entry() {
+    assert_rejected(&dry_run(&["--bounces-schedule", "1,two"]), "Invalid value \"two\" for --bounces-schedule");
+}
+
+#[test]
+fn rejects_sweep_of_scheduled_parameter() {
+    let result = dry_run(&["--output", "sheet.ppm", "--sweep", "steps=8,16", "--steps-schedule", "8,16"]);
+    assert_rejected(&result, "Sweeping steps cannot be combined with a steps schedule");
+}
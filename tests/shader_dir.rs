@@ -0,0 +1,44 @@
+//! Replaces shaders through --shader-dir and checks that shaders with
+//! errors are reported instead of crashing the renderer.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::{fs, process::Output};
+
+/// Renders with the given main fragment shader, or None when there is no GPU
+fn render(name: &str, fragment_shader: &str) -> Option<Output> {
+    let directory = common::directory(&format!("shader_dir/{}", name));
+    let output = common::output(&directory, "output.ppm");
+    fs::write(directory.join("mcm_main_fragment.wgsl"), fragment_shader).unwrap();
+
+    let result = common::render_sphere(&directory, "volume", 8, &output, &[
+        "--out-resolution", "8", "8", "--shader-dir", directory.to_str().unwrap()
+    ]);
+    if common::no_gpu(&String::from_utf8_lossy(&result.stderr)) {
+        eprintln!("Skipping {}: no GPU adapter", name);
+        return None;
+    }
+    return Some(result);
+}
+
+const FRAGMENT_SHADER: &str = include_str!("../src/shaders/mcm_main_fragment.wgsl");
+
+#[test]
+fn unchanged_shader() {
+    if let Some(result) = render("unchanged", FRAGMENT_SHADER) {
+        common::assert_success(&result);
+    }
+}
+
+#[test]
+fn syntax_error() {
+    let broken = FRAGMENT_SHADER.replacen("fn max3(", "fn max3((", 1);
+    if let Some(result) = render("syntax_error", &broken) {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert!(!result.status.success(), "the broken shader did not fail");
+        assert!(stderr.contains("mcm_main_fragment.wgsl") && stderr.contains("parsing error"), "unexpected error output: {}", stderr);
+        assert!(!stderr.contains("panicked"), "the renderer panicked: {}", stderr);
+    }
+}
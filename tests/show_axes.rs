@@ -0,0 +1,35 @@
+//! Checks that --show-axes draws the bounding box of the volume over the
+//! image, through the CPU renderer, in front of a black environment map
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+/// Colors of the pixels of the rendered image
+fn render(name: &str, args: &[&str]) -> Vec<[u32; 3]> {
+    let args = [&["--cpu", "--out-resolution", "32", "24"], args].concat();
+    let values = common::render_envmap(&common::directory("show_axes"), name, [0, 0, 0, 0], &args).unwrap();
+    return values.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect();
+}
+
+#[test]
+fn draws_the_bounding_box() {
+    let plain = render("plain", &[]);
+    assert!(plain.iter().all(|c| *c == [0, 0, 0]), "expected a black image, got {:?}", plain);
+
+    let axes = render("axes", &["--show-axes"]);
+    for color in [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]] {
+        assert!(axes.contains(&color), "no pixel of color {:?}", color);
+    }
+    // Lines only, the inside of the box stays black
+    let drawn = axes.iter().filter(|c| **c != [0, 0, 0]).count();
+    assert!(drawn < axes.len() / 3, "{} of {} pixels drawn", drawn, axes.len());
+
+    // The three axes start at the same corner
+    let corner = |color: [u32; 3]| {
+        return (0..axes.len()).filter(|i| axes[*i] == color).collect::<Vec<usize>>();
+    };
+    let [red, green, blue] = [[255, 0, 0], [0, 255, 0], [0, 0, 255]].map(corner);
+    let near = |a: usize, b: usize| (a % 32).abs_diff(b % 32) <= 1 && (a / 32).abs_diff(b / 32) <= 1;
+    assert!(red.iter().any(|r| green.iter().any(|g| near(*r, *g)) && blue.iter().any(|b| near(*r, *b))));
+}
@@ -0,0 +1,36 @@
+//! Checks that --stats writes the statistics of the radiance before tone
+//! mapping, through the CPU renderer, together with the parameters
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::fs;
+
+fn statistics(name: &str, args: &[&str]) -> String {
+    let directory = common::directory("stats");
+    let stats = common::output(&directory, &format!("{}.json", name));
+    let output = directory.join(format!("{}.ppm", name));
+
+    let result = common::render_sphere(&directory, name, 8, &output,
+        &[&["--cpu", "--out-resolution", "8", "8", "--stats", stats.to_str().unwrap()], args].concat());
+    common::assert_success(&result);
+    return fs::read_to_string(stats).unwrap();
+}
+
+#[test]
+fn records_parameters() {
+    let json = statistics("parameters", &["--extinction", "50"]);
+    assert!(json.contains("\"width\": 8,"), "missing width: {}", json);
+    assert!(json.contains("\"extinction\": 50,"), "missing extinction: {}", json);
+    // The white environment is seen next to the sphere
+    assert!(json.contains("\"max\": 1,"), "unexpected radiance: {}", json);
+}
+
+#[test]
+fn before_tone_mapping() {
+    let plain = statistics("plain", &[]);
+    let leveled = statistics("leveled", &["--levels", "0", "0.2", "4", "--saturation", "0.5"]);
+    let radiance = |json: &str| json[json.find("\"radiance\"").unwrap()..].to_string();
+    assert_eq!(radiance(&plain), radiance(&leveled));
+}
@@ -0,0 +1,55 @@
+//! Checks the steps that are derived from the extinction when --steps is
+//! not given, through the scene that --dry-run prints.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+/// Steps and step length printed by --dry-run for a volume of the given size
+fn dry_run(dimension: u32, args: &[&str]) -> (String, f32) {
+    let dimensions = dimension.to_string();
+    let volume = common::volume(&common::directory("steps"), &dimensions, &vec![0; dimension.pow(3) as usize]);
+    let result = common::command()
+        .args(["--dry-run", "--quiet", "--volume-dimensions", &dimensions, &dimensions, &dimensions, "--volume"])
+        .arg(&volume)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "dry run failed: {}", String::from_utf8_lossy(&result.stderr));
+    let stdout = String::from_utf8_lossy(&result.stdout).to_string();
+    let line = |prefix: &str| stdout.lines().find_map(|l| l.strip_prefix(prefix)).unwrap().to_string();
+    return (line("Steps: "), line("Step length: ").parse().unwrap());
+}
+
+#[test]
+fn mean_free_path() {
+    // 1 / 100 across the diagonal of the unit cube, sqrt(3) / 0.01
+    let (steps, length) = dry_run(16, &["--extinction", "100"]);
+    assert_eq!(steps, "174 (derived from the extinction)");
+    assert!((length - 0.01).abs() < 1e-6, "step length {}", length);
+
+    // Doubling the extinction halves the step length
+    let (steps, length) = dry_run(16, &["--extinction", "200"]);
+    assert_eq!(steps, "347 (derived from the extinction)");
+    assert!((length - 0.005).abs() < 1e-6, "step length {}", length);
+}
+
+#[test]
+fn at_most_a_voxel() {
+    // 1 / 2 would skip voxels of a 16 voxel wide volume
+    let (steps, length) = dry_run(16, &["--extinction", "2"]);
+    assert_eq!(steps, "28 (derived from the extinction)");
+    assert_eq!(length, 1.0 / 16.0);
+}
+
+#[test]
+fn capped() {
+    let (steps, _) = dry_run(16, &["--extinction", "100000"]);
+    assert_eq!(steps, "4096 (derived from the extinction)");
+}
+
+#[test]
+fn explicit_steps() {
+    let (steps, _) = dry_run(16, &["--extinction", "100", "--steps", "50"]);
+    assert_eq!(steps, "50");
+}
@@ -0,0 +1,75 @@
+//! Renders parameter sweeps into contact sheets with the CPU renderer and
+//! checks the layout of the cells and their labels.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::{fs, path::{Path, PathBuf}, process::Output};
+
+fn directory(name: &str) -> PathBuf {
+    let directory = common::directory(&format!("sweep/{}", name));
+    common::sphere(&directory, "volume", 8);
+    return directory;
+}
+
+fn sweep(directory: &Path, parameters: &[&str]) -> Output {
+    return common::command()
+        .args(["--quiet", "--cpu", "--iterations", "2", "--out-resolution", "48", "32", "--volume-dimensions", "8", "8", "8", "--volume"])
+        .arg(directory.join("volume.raw"))
+        .arg("--output")
+        .arg(directory.join("sheet.ppm"))
+        .arg("--sweep")
+        .args(parameters)
+        .output()
+        .unwrap();
+}
+
+/// Width, height and the values of a plain PPM image
+fn read_ppm(path: &Path) -> (usize, usize, Vec<u32>) {
+    let text = fs::read_to_string(path).unwrap();
+    let values: Vec<u32> = text.split_whitespace().skip(1).map(|v| v.parse().unwrap()).collect();
+    return (values[0] as usize, values[1] as usize, values[3..].to_vec());
+}
+
+#[test]
+fn grid_of_cells() {
+    let directory = directory("grid");
+    let result = sweep(&directory, &["extinction=0,400,1000", "anisotropy=-0.5,0.5"]);
+    assert!(result.status.success(), "sweep failed: {}", String::from_utf8_lossy(&result.stderr));
+
+    // Three columns of extinctions and two rows of anisotropies, each
+    // cell with a label strip of 9 pixels below it
+    let (width, height, values) = read_ppm(&directory.join("sheet.ppm"));
+    assert_eq!((width, height), (3 * 48, 2 * (32 + 9)));
+    // The green channel, which the red sphere absorbs
+    let pixel = |x: usize, y: usize| values[(y * width + x) * 3 + 1];
+
+    // Without extinction, the first column only shows the background
+    let center = |column: usize, row: usize| pixel(column * 48 + 24, row * 41 + 16);
+    assert!(center(0, 0) > center(2, 0), "the dense sphere does not absorb light");
+    assert!(center(0, 1) > center(2, 1), "the dense sphere does not absorb light");
+
+    for row in 0..2 {
+        let strip = (row * 41 + 32..row * 41 + 41).flat_map(|y| (0..width).map(move |x| (x, y)));
+        let lit = strip.filter(|(x, y)| pixel(*x, *y) == 255).count();
+        assert!(lit > 0, "the labels of row {} are missing", row);
+    }
+}
+
+#[test]
+fn invalid_parameters() {
+    let directory = directory("invalid");
+    for (parameters, message) in [
+        (&["density=1,2"][..], "Unknown sweep parameter"),
+        (&["extinction=1,x"][..], "is not a number"),
+        (&["bounces=1.5"][..], "must be an integer"),
+        (&["steps=10", "steps=20"][..], "more than once"),
+        (&[][..], "at least one parameter")
+    ] {
+        let result = sweep(&directory, parameters);
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert!(!result.status.success(), "{:?} did not fail", parameters);
+        assert!(stderr.contains(message), "unexpected error for {:?}: {}", parameters, stderr);
+    }
+}
@@ -0,0 +1,62 @@
+//! Checks that --tf-blend mixes the transfer functions of --tf and --tf2,
+//! through the CPU renderer, and that mismatched ones are rejected
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::{fs, path::{Path, PathBuf}, process::Output};
+
+const RED: [u8; 8] = [0, 0, 0, 0, 255, 0, 0, 255];
+const GREEN: [u8; 8] = [0, 0, 0, 0, 0, 255, 0, 255];
+
+fn render(name: &str, second: &[u8], args: &[&str]) -> (Output, PathBuf) {
+    let directory = common::directory("tf_blend");
+    let first_tf = directory.join(format!("{}_first.tf", name));
+    let second_tf = directory.join(format!("{}_second.tf", name));
+    let output = common::output(&directory, &format!("{}.ppm", name));
+    fs::write(&first_tf, RED).unwrap();
+    fs::write(&second_tf, second).unwrap();
+
+    let result = common::render_sphere(&directory, name, 8, &output, &[
+        &["--cpu", "--out-resolution", "8", "8", "--tf", first_tf.to_str().unwrap(), "--tf2", second_tf.to_str().unwrap()], args
+    ].concat());
+    return (result, output);
+}
+
+/// Color of the center pixel of the plain PPM image
+fn center(output: &Path) -> [u32; 3] {
+    let values = common::ppm_values(output);
+    let index = (4 * 8 + 4) * 3;
+    return [values[index], values[index + 1], values[index + 2]];
+}
+
+#[test]
+fn blends_the_colors() {
+    let mut colors = Vec::new();
+    for blend in ["0", "0.5", "1"] {
+        let (result, output) = render(&format!("blend_{}", blend), &GREEN, &["--tf-blend", blend]);
+        common::assert_success(&result);
+        colors.push(center(&output));
+    }
+    let [first, half, second] = [colors[0], colors[1], colors[2]];
+    assert!(first[0] > 0 && first[1] == 0, "the first transfer function is not red: {:?}", first);
+    assert!(second[0] == 0 && second[1] > 0, "the second transfer function is not green: {:?}", second);
+    assert!(half[0] > 0 && half[1] > 0 && half[0] < first[0] && half[1] < second[1], "the blend is not in between: {:?}", half);
+}
+
+#[test]
+fn different_lengths() {
+    let (result, output) = render("different_lengths", &[0, 0, 0, 0, 0, 255, 0, 128, 0, 255, 0, 255], &[]);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!result.status.success());
+    assert!(stderr.contains("has 3 entries, but the first transfer function has 2"), "unexpected error output: {}", stderr);
+    assert!(!output.exists());
+}
+
+#[test]
+fn blend_out_of_range() {
+    let (result, _) = render("out_of_range", &GREEN, &["--tf-blend", "1.5"]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("must be between 0 and 1"));
+}
@@ -0,0 +1,69 @@
+//! Renders an opaque volume whose transfer function is white in red and
+//! gray in green and blue, and checks the output bytes against the decoding
+//! of --tf-colorspace. With a single bounce, every photon that leaves the
+//! volume scattered exactly once, so green is red times the decoded gray in
+//! every sample and the ratio is free of noise.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::fs;
+
+/// Values of the rendered image, or None when there is no GPU
+fn render(name: &str, args: &[&str]) -> Option<Vec<u32>> {
+    let directory = common::directory("tf_colorspace");
+    let volume = common::volume(&directory, name, &[255; 8 * 8 * 8]);
+    let tf = directory.join(format!("{}.tf", name));
+    fs::write(&tf, [255, 188, 188, 255].repeat(256)).unwrap();
+    let output = common::output(&directory, &format!("{}.ppm", name));
+
+    // The camera is close enough for the volume to cover the whole image
+    let result = common::run_on_gpu(name, common::command()
+        .args(["--quiet", "--volume-dimensions", "8", "8", "8", "--out-resolution", "4", "4", "--iterations", "64"])
+        .args(["--bounces", "1", "--extinction", "100", "--gamma", "2.2", "--camera-position", "0", "0", "1.2", "--volume"])
+        .arg(&volume)
+        .arg("--tf")
+        .arg(&tf)
+        .arg("--output")
+        .arg(&output)
+        .args(args))?;
+    common::assert_success(&result);
+    return Some(common::ppm_values(&output));
+}
+
+/// Every green byte is the red byte of the same pixel, scaled by the gamma
+/// encoded gray, up to the rounding of both
+fn assert_gray(values: &[u32], gray: f32) {
+    assert_eq!(values.len(), 4 * 4 * 3);
+    for pixel in values.chunks(3) {
+        assert!(pixel[0] > 0, "the volume is not lit: {:?}", values);
+        let expected = pixel[0] as f32 * gray.powf(1.0 / 2.2);
+        assert!((pixel[1] as f32 - expected).abs() <= 1.0, "expected a green of {} next to a red of {}, got {:?}", expected, pixel[0], values);
+        assert_eq!(pixel[1], pixel[2]);
+    }
+}
+
+#[test]
+fn cpu_srgb() {
+    assert_gray(&render("cpu_srgb", &["--cpu", "--tf-colorspace", "srgb"]).unwrap(), 0.5029);
+}
+
+#[test]
+fn cpu_linear() {
+    assert_gray(&render("cpu_linear", &["--cpu", "--tf-colorspace", "linear"]).unwrap(), 188.0 / 255.0);
+}
+
+#[test]
+fn gpu_srgb() {
+    if let Some(values) = render("gpu_srgb", &["--tf-colorspace", "srgb"]) {
+        assert_gray(&values, 0.5029);
+    }
+}
+
+#[test]
+fn gpu_linear() {
+    if let Some(values) = render("gpu_linear", &["--tf-colorspace", "linear"]) {
+        assert_gray(&values, 188.0 / 255.0);
+    }
+}
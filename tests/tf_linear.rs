@@ -0,0 +1,53 @@
+//! Checks that --tf-linear passes the transfer function bytes unchanged into
+//! shading, by reading the albedo of an opaque volume with a gray transfer
+//! function.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::fs;
+
+/// Returns the albedo in the center of the image, or None when there is no GPU
+fn albedo(name: &str, extra_args: &[&str]) -> Option<f32> {
+    let directory = common::directory("tf_linear");
+    let volume = common::volume(&directory, name, &[255; 8 * 8 * 8]);
+    let tf = directory.join(format!("{}.tf", name));
+    let output = directory.join(format!("{}.ppm", name));
+    let albedo = common::output(&directory, &format!("{}.albedo.pfm", name));
+    fs::write(&tf, [188, 188, 188, 255].repeat(256)).unwrap();
+
+    let result = common::run_on_gpu(name, common::command()
+        .args(["--quiet", "--volume-dimensions", "8", "8", "8", "--out-resolution", "8", "8", "--aov", "albedo", "--volume"])
+        .arg(&volume)
+        .arg("--tf")
+        .arg(&tf)
+        .arg("--output")
+        .arg(&output)
+        .args(extra_args))?;
+    common::assert_success(&result);
+
+    // 8x8 RGB floats after the header, the center pixel is in row 4
+    let contents = fs::read(&albedo).unwrap();
+    let data = &contents[contents.len() - 8 * 8 * 3 * 4..];
+    let index = (4 * 8 + 4) * 3 * 4;
+    return Some(f32::from_le_bytes([data[index], data[index + 1], data[index + 2], data[index + 3]]));
+}
+
+fn assert_close(value: Option<f32>, expected: f32) {
+    if let Some(v) = value {
+        assert!((v - expected).abs() < 0.002, "sampled {} instead of {}", v, expected);
+    }
+}
+
+#[test]
+fn cpu() {
+    assert_close(albedo("cpu_linear", &["--cpu", "--tf-linear"]), 188.0 / 255.0);
+    assert_close(albedo("cpu_srgb", &["--cpu"]), 0.5029);
+}
+
+#[test]
+fn gpu() {
+    assert_close(albedo("gpu_linear", &["--tf-linear"]), 188.0 / 255.0);
+    assert_close(albedo("gpu_srgb", &[]), 0.5029);
+}
@@ -0,0 +1,55 @@
+//! Checks that --tile with a size that does not divide the resolution
+//! stitches the same image as a render in one piece, with every tile in its
+//! place and the right way up, including the narrower tiles at the edges.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+/// Red channel of the render, row by row, or None when there is no GPU
+fn render(name: &str, args: &[&str]) -> Option<Vec<u32>> {
+    let directory = common::directory("tile");
+    // A dense block in one corner of the volume, so that misplaced or
+    // mirrored tiles change the image
+    let mut voxels = vec![0u8; 8 * 8 * 8];
+    for (i, voxel) in voxels.iter_mut().enumerate() {
+        if i % 8 < 5 && (i / 8) % 8 < 3 {
+            *voxel = 255;
+        }
+    }
+    let volume = common::volume(&directory, name, &voxels);
+    let output = common::output(&directory, &format!("{}.ppm", name));
+
+    let result = common::run_on_gpu(name, common::command()
+        .args(["--quiet", "--volume-dimensions", "8", "8", "8", "--out-resolution", "8", "5", "--iterations", "512", "--volume"])
+        .arg(&volume)
+        .arg("--output")
+        .arg(&output)
+        .args(args))?;
+    common::assert_success(&result);
+    return Some(common::ppm_values(&output).chunks_exact(3).map(|c| c[0]).collect());
+}
+
+#[test]
+fn matches_the_untiled_render() {
+    let whole = match render("whole", &[]) {
+        Some(w) => w,
+        None => return
+    };
+    let tiled = render("tiled", &["--tile", "3"]).unwrap();
+    assert!(whole.iter().any(|v| *v < 230), "the block is not visible: {:?}", whole);
+    // Only the noise of the two renders differs
+    let difference = whole.iter().zip(&tiled).map(|(a, b)| a.abs_diff(*b)).max().unwrap();
+    assert!(difference <= 12, "tiled {:?} differs from {:?} by up to {}", tiled, whole, difference);
+}
+
+#[test]
+fn rejects_invalid_size() {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere", "--tile", "64px"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!result.status.success(), "an invalid tile size was accepted");
+    assert!(stderr.contains("Invalid value \"64px\" for --tile"), "unexpected error: {}", stderr);
+}
@@ -0,0 +1,36 @@
+//! Checks how transfer function files whose length is not a multiple
+//! of the 4 bytes of an RGBA entry are handled
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::{fs, path::PathBuf, process::Output};
+
+fn render(name: &str, transfer_function: &[u8]) -> (Output, PathBuf) {
+    let directory = common::directory("transfer_function");
+    let tf = directory.join(format!("{}.tf", name));
+    let output = common::output(&directory, &format!("{}.ppm", name));
+    fs::write(&tf, transfer_function).unwrap();
+
+    let result = common::render_sphere(&directory, name, 8, &output, &["--cpu", "--out-resolution", "8", "8", "--tf", tf.to_str().unwrap()]);
+    return (result, output);
+}
+
+#[test]
+fn shorter_than_one_entry() {
+    let (result, output) = render("three_bytes", &[255, 0, 0]);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("needs at least one RGBA entry"), "unexpected error output: {}", stderr);
+    assert!(!output.exists(), "an image was rendered with an empty transfer function");
+    assert!(!result.status.success(), "the error did not set a failing exit code");
+}
+
+#[test]
+fn partial_last_entry() {
+    let (result, output) = render("six_bytes", &[255, 0, 0, 255, 0, 255]);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("The last 2 bytes are ignored"), "missing warning: {}", stderr);
+    assert!(output.exists(), "no image was rendered: {}", stderr);
+    assert!(result.status.success());
+}
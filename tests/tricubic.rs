@@ -0,0 +1,42 @@
+//! Checks --tricubic against the trilinear filtering. A cubic B-spline
+//! reproduces linear functions exactly, so a density ramp must render
+//! like with --linear, while the nearest lookups differ from both.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+/// Pixel values of the ramp rendered with the given filtering, or None
+/// when there is no GPU
+fn render(name: &str, filter_args: &[&str]) -> Option<Vec<f32>> {
+    let directory = common::directory("tricubic");
+    let ramp: Vec<u8> = (0..8 * 8 * 8).map(|i| (i % 8 * 32) as u8).collect();
+    let volume = common::volume(&directory, name, &ramp);
+    let output = common::output(&directory, &format!("{}.ppm", name));
+
+    let result = common::run_on_gpu(name, common::command()
+        .args(["--quiet", "--volume-dimensions", "8", "8", "8", "--out-resolution", "16", "16", "--iterations", "64"])
+        .args(["--camera-position", "0", "0", "2", "--volume"])
+        .arg(&volume)
+        .arg("--output")
+        .arg(&output)
+        .args(filter_args))?;
+    common::assert_success(&result);
+    return Some(common::ppm_values(&output).into_iter().map(|v| v as f32).collect());
+}
+
+fn mean_difference(a: &[f32], b: &[f32]) -> f32 {
+    return a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum::<f32>() / a.len() as f32;
+}
+
+#[test]
+fn reproduces_linear_ramp() {
+    if let (Some(linear), Some(tricubic), Some(nearest)) = (render("linear", &["--linear"]), render("tricubic", &["--tricubic"]), render("nearest", &[])) {
+        let tricubic_difference = mean_difference(&linear, &tricubic);
+        let nearest_difference = mean_difference(&linear, &nearest);
+        assert!(
+            tricubic_difference < 0.5 * nearest_difference,
+            "the tricubic ramp differs from the linear one by {}, the nearest one only by {}", tricubic_difference, nearest_difference
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Checks that zero volume dimensions are rejected and that a volume of a
+//! single slice renders as a 2D density field.
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::{path::PathBuf, process::Output};
+
+/// Disk of full density in the middle of a 256x256 slice, written for
+/// every test on its own, as they run in parallel
+fn slice(name: &str) -> PathBuf {
+    let mut voxels = vec![0u8; 256 * 256];
+    for y in 0..256 {
+        for x in 0..256 {
+            let (dx, dy) = (x as f32 - 127.5, y as f32 - 127.5);
+            if dx * dx + dy * dy < 64.0 * 64.0 {
+                voxels[y * 256 + x] = 255;
+            }
+        }
+    }
+    return common::volume(&common::directory("volume_dimensions"), name, &voxels);
+}
+
+fn render(name: &str, dimensions: [&str; 3], args: &[&str]) -> Output {
+    let volume = slice(name);
+    return common::command()
+        .args(["--quiet", "--out-resolution", "32", "32", "--camera-position", "0", "0", "2", "--volume-dimensions"])
+        .args(dimensions)
+        .arg("--volume")
+        .arg(&volume)
+        .arg("--output")
+        .arg(volume.with_file_name(format!("{}.ppm", name)))
+        .args(args)
+        .output()
+        .unwrap();
+}
+
+/// Green channel of the pixel, the default transfer function shows the disk in red
+fn green(name: &str, x: usize, y: usize) -> u32 {
+    let values = common::ppm_values(&common::directory("volume_dimensions").join(format!("{}.ppm", name)));
+    return values[(y * 32 + x) * 3 + 1];
+}
+
+#[test]
+fn zero_dimension() {
+    for dimensions in [["0", "256", "256"], ["256", "0", "256"], ["256", "256", "0"]] {
+        let result = render("zero", dimensions, &["--cpu"]);
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert!(!result.status.success(), "{:?} did not fail", dimensions);
+        assert!(stderr.contains("must all be positive") && !stderr.contains("panicked"), "unexpected error for {:?}: {}", dimensions, stderr);
+    }
+}
+
+#[test]
+fn single_slice_cpu() {
+    let result = render("slice_cpu", ["256", "256", "1"], &["--cpu"]);
+    common::assert_success(&result);
+    assert!(green("slice_cpu", 16, 16) < 50, "the disk is missing from the center");
+    assert!(green("slice_cpu", 2, 2) > 200, "the corner is not empty");
+}
+
+#[test]
+fn single_slice_gpu() {
+    let result = render("slice_gpu", ["256", "256", "1"], &["--iterations", "16", "--steps", "64", "--linear"]);
+    if common::no_gpu(&String::from_utf8_lossy(&result.stderr)) {
+        eprintln!("Skipping the GPU render: no GPU adapter");
+        return;
+    }
+    common::assert_success(&result);
+    assert!(green("slice_gpu", 16, 16) < 50, "the disk is missing from the center");
+    assert!(green("slice_gpu", 2, 2) > 200, "the corner is not empty");
+}
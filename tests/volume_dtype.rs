@@ -0,0 +1,53 @@
+//! Reads raw volumes with wider data types and checks that they are
+//! rescaled to the same densities as the equivalent 8-bit volume
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+use std::process::Output;
+
+fn histogram(name: &str, volume: &[u8], dtype: &str) -> Output {
+    let file = common::volume(&common::directory("volume_dtype"), name, volume);
+    return common::command()
+        .args(["--quiet", "--histogram", "--volume-dimensions", "4", "4", "4", "--volume-dtype", dtype, "--volume"])
+        .arg(&file)
+        .output()
+        .unwrap();
+}
+
+/// Densities 0, 17, ..., 255 repeated over the 64 voxels
+fn densities() -> Vec<u8> {
+    return (0..64).map(|i| (i % 16) as u8 * 17).collect();
+}
+
+fn assert_same_histogram(name: &str, volume: &[u8], dtype: &str) {
+    let expected = histogram("u8", &densities(), "u8");
+    let result = histogram(name, volume, dtype);
+    assert!(result.status.success(), "reading {} failed: {}", dtype, String::from_utf8_lossy(&result.stderr));
+    assert_eq!(String::from_utf8_lossy(&result.stdout), String::from_utf8_lossy(&expected.stdout), "{} was rescaled differently", dtype);
+}
+
+#[test]
+fn sixteen_bit() {
+    let values: Vec<u16> = densities().iter().map(|d| 1000 + *d as u16 * 100).collect();
+    assert_same_histogram("u16le", &values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>(), "u16le");
+    assert_same_histogram("u16be", &values.iter().flat_map(|v| v.to_be_bytes()).collect::<Vec<u8>>(), "u16be");
+    let values: Vec<i16> = densities().iter().map(|d| *d as i16 * 100 - 20000).collect();
+    assert_same_histogram("i16le", &values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>(), "i16le");
+}
+
+#[test]
+fn float() {
+    let values: Vec<f32> = densities().iter().map(|d| *d as f32 / 255.0 * 2.0 - 1.0).collect();
+    assert_same_histogram("f32le", &values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>(), "f32le");
+    assert_same_histogram("f32be", &values.iter().flat_map(|v| v.to_be_bytes()).collect::<Vec<u8>>(), "f32be");
+}
+
+#[test]
+fn wrong_size() {
+    // 64 bytes are enough for 8-bit, but not for 16-bit values
+    let result = histogram("short", &densities(), "u16le");
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("require 128"), "unexpected error: {}", String::from_utf8_lossy(&result.stderr));
+}
@@ -0,0 +1,52 @@
+//! Checks that --volume-origin moves the volume in the scene
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+/// Inverse MVP matrix printed by --dump-matrix for a 16x16x16 volume
+fn dump_matrix(extra_args: &[&str]) -> Vec<f32> {
+    let volume = common::volume(&common::directory("volume_origin"), "volume", &[0; 16 * 16 * 16]);
+
+    let result = common::command()
+        .args(["--quiet", "--dry-run", "--dump-matrix", "--volume-dimensions", "16", "16", "16", "--volume"])
+        .arg(&volume)
+        .args(extra_args)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "dumping the matrix failed: {}", String::from_utf8_lossy(&result.stderr));
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    return stdout.lines().next().unwrap().split(' ').map(|v| v.parse().unwrap()).collect();
+}
+
+fn assert_close(a: &[f32], b: &[f32]) {
+    assert_eq!(a.len(), 16);
+    assert!(a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-4), "{:?} differs from {:?}", a, b);
+}
+
+#[test]
+fn defaults_to_the_center() {
+    assert_eq!(dump_matrix(&[]), dump_matrix(&["--volume-origin", "0.5", "0.5", "0.5"]));
+}
+
+#[test]
+fn moves_the_volume() {
+    // Moving the camera and its target along with the volume gives the same view
+    let centered = dump_matrix(&[]);
+    let corner = dump_matrix(&["--volume-origin", "0", "0", "0", "--camera-position", "-0.5", "-0.5", "1.5", "--look-at", "0.5", "0.5", "0.5"]);
+    assert_close(&centered, &corner);
+    let moved = dump_matrix(&["--volume-origin", "0", "0", "0"]);
+    assert!(centered.iter().zip(&moved).any(|(a, b)| (a - b).abs() > 0.1), "moving the volume did not change the view");
+}
+
+#[test]
+fn rejects_mvp_matrix() {
+    let identity = ["1", "0", "0", "0", "0", "1", "0", "0", "0", "0", "1", "0", "0", "0", "0", "1"];
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere", "--volume-origin", "0", "0", "0", "--mvp-matrix"])
+        .args(identity)
+        .output()
+        .unwrap();
+    assert!(!result.status.success(), "--volume-origin was accepted with --mvp-matrix");
+    assert!(String::from_utf8_lossy(&result.stderr).contains("cannot be combined with --mvp-matrix"));
+}
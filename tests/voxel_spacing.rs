@@ -0,0 +1,48 @@
+//! Checks that the size of the volume in the scene follows both its
+//! dimensions and --voxel-spacing, through the matrix of --dump-matrix
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+/// Inverse MVP matrix printed by --dump-matrix for a volume of the given size
+fn dump_matrix(dimensions: [u32; 3], spacing: Option<[f32; 3]>) -> Vec<f32> {
+    let directory = common::directory("voxel_spacing");
+    let [w, h, d] = dimensions.map(|v| v.to_string());
+    let volume = common::volume(&directory, &format!("{}x{}x{}", w, h, d), &vec![0; dimensions.iter().product::<u32>() as usize]);
+
+    let mut command = common::command();
+    command.args(["--quiet", "--dry-run", "--dump-matrix", "--volume-dimensions", &w, &h, &d, "--volume"]).arg(&volume);
+    if let Some(spacing) = spacing {
+        command.arg("--voxel-spacing").args(spacing.map(|v| v.to_string()));
+    }
+    let result = command.output().unwrap();
+    assert!(result.status.success(), "dumping the matrix failed: {}", String::from_utf8_lossy(&result.stderr));
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    return stdout.lines().next().unwrap().split(' ').map(|v| v.parse().unwrap()).collect();
+}
+
+fn assert_close(a: &[f32], b: &[f32]) {
+    assert_eq!(a.len(), 16);
+    assert!(a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-4), "{:?} differs from {:?}", a, b);
+}
+
+#[test]
+fn thick_slices_of_a_flat_volume() {
+    // 16 x 16 x 4 voxels with slices three times as thick span 16 x 16 x 12,
+    // so z stays the shortest side, the same as a cube at a spacing of 0.75
+    let ct = dump_matrix([16, 16, 4], Some([1.0, 1.0, 3.0]));
+    assert_close(&ct, &dump_matrix([8, 8, 8], Some([1.0, 1.0, 0.75])));
+    assert_close(&ct, &dump_matrix([4, 4, 3], Some([1.0, 1.0, 1.0])));
+}
+
+#[test]
+fn unit_cube_without_spacing() {
+    // Without a spacing, every volume fills the unit cube
+    let flat = dump_matrix([16, 16, 4], None);
+    assert_close(&flat, &dump_matrix([8, 8, 8], None));
+    assert_close(&flat, &dump_matrix([8, 8, 8], Some([1.0, 1.0, 1.0])));
+    // With one, the number of voxels sets the proportions
+    let proportional = dump_matrix([16, 16, 4], Some([1.0, 1.0, 1.0]));
+    assert!(flat.iter().zip(&proportional).any(|(a, b)| (a - b).abs() > 0.1), "the spacing did not change the framing");
+}
@@ -0,0 +1,34 @@
+//! Checks that --window stretches a range of densities over the transfer
+//! function and clamps the densities outside of it
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+#[allow(dead_code)]
+#[path = "../src/volume.rs"]
+mod volume;
+
+#[test]
+fn remaps_densities() {
+    let mut densities = vec![0, 40, 60, 80, 100, 120, 200, 255];
+    volume::apply_window(&mut densities, 1, [40, 120]);
+    assert_eq!(densities, [0, 0, 64, 128, 191, 255, 255, 255]);
+}
+
+#[test]
+fn only_remaps_alpha() {
+    let mut voxels = vec![10, 20, 30, 50, 10, 20, 30, 150];
+    volume::apply_window(&mut voxels, 4, [50, 150]);
+    assert_eq!(voxels, [10, 20, 30, 0, 10, 20, 30, 255]);
+}
+
+#[test]
+fn rejects_empty_window() {
+    let result = common::command()
+        .args(["--dry-run", "--generate", "sphere", "--window", "100", "100"])
+        .output()
+        .unwrap();
+    assert!(!result.status.success(), "an empty window was accepted");
+    assert!(String::from_utf8_lossy(&result.stderr).contains("low end below the high end"));
+}
@@ -0,0 +1,36 @@
+//! Checks that a render without iterations writes the image of the reset
+//! radiance instead of failing
+
+#![allow(clippy::needless_return)]
+
+pub mod common;
+
+fn render(name: &str, args: &[&str]) -> Option<Vec<u32>> {
+    let directory = common::directory("zero_iterations");
+    let volume = common::volume(&directory, name, &[128; 8 * 8 * 8]);
+    let output = common::output(&directory, &format!("{}.ppm", name));
+
+    let result = common::run_on_gpu(name, common::command()
+        .args(["--quiet", "--volume-dimensions", "8", "8", "8", "--out-resolution", "8", "8", "--iterations", "0", "--volume"])
+        .arg(&volume)
+        .arg("--output")
+        .arg(&output)
+        .args(args))?;
+    common::assert_success(&result);
+    return Some(common::ppm_values(&output));
+}
+
+#[test]
+fn black_image() {
+    if let Some(values) = render("black", &[]) {
+        assert!(values.iter().all(|v| *v == 0), "expected a black image, got {:?}", values);
+    }
+}
+
+#[test]
+fn tiled() {
+    // Every tile resets its pixels before its iterations
+    if let Some(values) = render("tiled", &["--tile", "4"]) {
+        assert!(values.iter().all(|v| *v == 0), "expected a black image, got {:?}", values);
+    }
+}